@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS monarch_db_repeatable_migrations (
+    monarch_schema STRING NOT NULL,
+    name STRING NOT NULL,
+    checksum STRING NOT NULL,
+    PRIMARY KEY (monarch_schema, name)
+)
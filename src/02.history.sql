@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS monarch_db_migration_history (
+    monarch_schema TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    checksum TEXT NOT NULL,
+    status TEXT NOT NULL,
+    error TEXT,
+    description TEXT,
+    applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    duration_ms INTEGER,
+    PRIMARY KEY (monarch_schema, version)
+)
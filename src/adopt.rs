@@ -0,0 +1,315 @@
+//! Taking over databases migrated by another migration tool.
+//!
+//! A database migrated by refinery, sqlx or diesel records the migrations it ran in that tool's
+//! own table. [`MonarchDB::adopt_history`] matches those records against Monarch's migrations
+//! by the number each migration's name starts with, and records the version reached, so
+//! switching tools does not re-run or require faking every migration by hand.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use rusqlite::Connection;
+
+use crate::backend::{Rusqlite, block_on};
+use crate::{
+    Error, MigrationStatus, MonarchDB, Result, engine, select_schema_version, set_schema_version,
+};
+
+/// A migration tool whose history [`MonarchDB::adopt_history`] can read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationTool {
+    /// [refinery](https://docs.rs/refinery), which records migrations in
+    /// `refinery_schema_history`.
+    Refinery,
+    /// [sqlx](https://docs.rs/sqlx), which records migrations in `_sqlx_migrations`.
+    Sqlx,
+    /// [diesel](https://diesel.rs), which records migrations in `__diesel_schema_migrations`.
+    Diesel,
+}
+
+impl MigrationTool {
+    /// The table the tool records its migrations in.
+    pub fn history_table(self) -> &'static str {
+        match self {
+            MigrationTool::Refinery => "refinery_schema_history",
+            MigrationTool::Sqlx => "_sqlx_migrations",
+            MigrationTool::Diesel => "__diesel_schema_migrations",
+        }
+    }
+
+    /// Reads the versions of the migrations the tool applied.
+    fn applied_versions(self, connection: &Connection) -> Result<BTreeSet<u64>> {
+        let table = self.history_table();
+        let query = match self {
+            MigrationTool::Sqlx => {
+                let failed: i64 = connection.query_row(
+                    &format!("SELECT COUNT(*) FROM {table} WHERE NOT success"),
+                    [],
+                    |row| row.get(0),
+                )?;
+                if failed > 0 {
+                    return Err(Error::AdoptHistory(format!(
+                        "{table} records {failed} failed migration(s)"
+                    )));
+                }
+                format!("SELECT CAST(version AS TEXT) FROM {table}")
+            }
+            MigrationTool::Refinery | MigrationTool::Diesel => {
+                format!("SELECT CAST(version AS TEXT) FROM {table}")
+            }
+        };
+        let mut stmt = connection.prepare(&query)?;
+        let versions = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        versions
+            .iter()
+            .map(|version| {
+                version_number(version).ok_or_else(|| {
+                    Error::AdoptHistory(format!("{table} records unrecognized version {version}"))
+                })
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for MigrationTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MigrationTool::Refinery => "refinery",
+            MigrationTool::Sqlx => "sqlx",
+            MigrationTool::Diesel => "diesel",
+        })
+    }
+}
+
+impl std::str::FromStr for MigrationTool {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "refinery" => Ok(MigrationTool::Refinery),
+            "sqlx" => Ok(MigrationTool::Sqlx),
+            "diesel" => Ok(MigrationTool::Diesel),
+            _ => Err(format!(
+                "unknown migration tool {s}, expected refinery, sqlx or diesel"
+            )),
+        }
+    }
+}
+
+impl MonarchDB {
+    /// Records the migrations which `tool` applied to `connection` as applied by Monarch, and
+    /// returns the schema version reached.
+    ///
+    /// Each of Monarch's migrations is matched with the tool's record of the version its name
+    /// starts with, ignoring leading zeros and the dashes of diesel's timestamps, so
+    /// `V3__users.sql` for refinery, `0003_users.sql` and `2024-01-31-120000_users` all match
+    /// their Monarch counterparts named `003_users.sql` or `20240131120000_users.sql`.
+    /// Migrations from a [`StaticMonarchConfiguration`](crate::StaticMonarchConfiguration) are
+    /// numbered from 1. The matched migrations are recorded in Monarch's history, and the
+    /// tool's table is left in place.
+    ///
+    /// ```rust
+    /// use monarch_db::{MigrationTool, MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    ///         "ALTER TABLE users ADD COLUMN name TEXT;",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// // A database which refinery migrated to its first version.
+    /// let connection = rusqlite::Connection::open_in_memory()?;
+    /// connection.execute_batch(
+    ///     "CREATE TABLE users (id INTEGER PRIMARY KEY);
+    ///      CREATE TABLE refinery_schema_history (version INT4 PRIMARY KEY, name VARCHAR(255),
+    ///          applied_on VARCHAR(255), checksum VARCHAR(255));
+    ///      INSERT INTO refinery_schema_history VALUES (1, 'users', '2024-01-31', '0');",
+    /// )?;
+    ///
+    /// assert_eq!(monarch_db.adopt_history(&connection, MigrationTool::Refinery)?, 1);
+    /// let connection = monarch_db.migrate(connection)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::AdoptHistory`] if Monarch already records a version for this
+    /// schema, if the tool's table records a migration Monarch does not have or one which
+    /// failed, or if the applied migrations are not the first migrations in order.
+    pub fn adopt_history(&self, connection: &Connection, tool: MigrationTool) -> Result<u32> {
+        let tx = engine::begin(self, connection)?;
+        let table = tool.history_table();
+        let exists: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?1)",
+            [table],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Err(Error::AdoptHistory(format!(
+                "the database has no {table} table"
+            )));
+        }
+        let mut driver = Rusqlite(&tx);
+        let recorded = block_on(select_schema_version(&mut driver, &self.name))?;
+        if recorded > 0 {
+            return Err(Error::AdoptHistory(format!(
+                "{} is already at version {recorded}",
+                self.name
+            )));
+        }
+
+        let mut applied = tool.applied_versions(&tx)?;
+        let mut version = 0;
+        for (next, migration) in self.versions() {
+            let number = version_number(&migration.name);
+            if !number.is_some_and(|number| applied.remove(&number)) {
+                break;
+            }
+            block_on(self.record_history(
+                &mut driver,
+                next,
+                migration,
+                &MigrationStatus::Applied,
+                None,
+            ))?;
+            version = next;
+        }
+        if let Some(unmatched) = applied.first() {
+            return Err(Error::AdoptHistory(format!(
+                "{table} records version {unmatched}, which is not a migration of {} after \
+                 version {version}",
+                self.name
+            )));
+        }
+        block_on(set_schema_version(&mut driver, &self.name, version))?;
+        block_on(self.record_identifier(&mut driver, version))?;
+        tx.commit()?;
+        tracing::debug!(%tool, version, "Adopted migration history");
+        Ok(version)
+    }
+}
+
+/// The version number a migration name starts with, skipping a refinery `V` or `U` prefix and
+/// the dashes in diesel's timestamps.
+fn version_number(name: &str) -> Option<u64> {
+    let name = name.strip_prefix(['V', 'U']).unwrap_or(name);
+    let digits: String = name
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '-')
+        .filter(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    fn sqlx_database(versions: &[(i64, bool)]) -> rusqlite::Result<Connection> {
+        let connection = Connection::open_in_memory()?;
+        connection.execute_batch(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+             CREATE TABLE _sqlx_migrations (version BIGINT PRIMARY KEY, description TEXT NOT NULL,
+                 installed_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                 success BOOLEAN NOT NULL, checksum BLOB NOT NULL, execution_time BIGINT NOT NULL);",
+        )?;
+        for (version, success) in versions {
+            connection.execute(
+                "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+                 VALUES (?1, 'migration', ?2, x'00', 0)",
+                rusqlite::params![version, success],
+            )?;
+        }
+        Ok(connection)
+    }
+
+    #[test]
+    fn test_adopt_sqlx_history() -> Result<()> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "adopt",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                "ALTER TABLE users ADD COLUMN name TEXT;",
+                "CREATE INDEX users_name ON users (name);",
+            ],
+        });
+        let connection = sqlx_database(&[(1, true), (2, true)])?;
+        assert_eq!(
+            monarch_db.adopt_history(&connection, MigrationTool::Sqlx)?,
+            2
+        );
+
+        let history = monarch_db.history(&connection)?;
+        assert_eq!(history.len(), 2);
+        let connection = monarch_db.migrate(connection)?;
+        assert_eq!(monarch_db.plan(&connection)?.from_version, 3);
+
+        let error = monarch_db
+            .adopt_history(&connection, MigrationTool::Sqlx)
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("already at version 3"),
+            "{error}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_adopt_rejects_unmatched_history() -> Result<()> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "adopt",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                "ALTER TABLE users ADD COLUMN name TEXT;",
+                "CREATE INDEX users_name ON users (name);",
+            ],
+        });
+        let gap = sqlx_database(&[(1, true), (3, true)])?;
+        let error = monarch_db
+            .adopt_history(&gap, MigrationTool::Sqlx)
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("version 3, which is not"),
+            "{error}"
+        );
+        assert_eq!(monarch_db.plan(&gap)?.from_version, 0);
+
+        let failed = sqlx_database(&[(1, true), (2, false)])?;
+        let error = monarch_db
+            .adopt_history(&failed, MigrationTool::Sqlx)
+            .unwrap_err();
+        assert!(error.to_string().contains("1 failed migration"), "{error}");
+
+        let missing = Connection::open_in_memory()?;
+        assert!(
+            monarch_db
+                .adopt_history(&missing, MigrationTool::Diesel)
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_number() {
+        assert_eq!(version_number("003_users.sql"), Some(3));
+        assert_eq!(version_number("V3__users.sql"), Some(3));
+        assert_eq!(
+            version_number("2024-01-31-120000_users"),
+            Some(20240131120000)
+        );
+        assert_eq!(version_number("20240131120000"), Some(20240131120000));
+        assert_eq!(version_number("users.sql"), None);
+    }
+}
@@ -0,0 +1,138 @@
+//! Databases migrated by a newer version of the application.
+//!
+//! After a rollback, an older binary may open a database whose recorded version is past its
+//! last migration. Migrating has nothing to do, but the schema may differ from what the binary
+//! expects. [`MonarchDB::with_version_ahead_policy`] decides whether that is ignored, logged or
+//! an error.
+
+use crate::{Error, MonarchDB, Result};
+
+/// How a database whose recorded version is past the last migration is treated when migrating.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum VersionAheadPolicy {
+    /// Use the database as it is, e.g. so the previous release keeps running during a
+    /// blue/green deploy.
+    #[default]
+    Ignore,
+    /// Log a warning, then use the database as it is.
+    Warn,
+    /// Fail migrating with [`Error::DatabaseAhead`].
+    Error,
+}
+
+impl MonarchDB {
+    /// Sets how a database whose recorded version is past the
+    /// [`current_version`](Self::current_version) is treated when migrating, see
+    /// [`VersionAheadPolicy`]. Defaults to [`VersionAheadPolicy::Ignore`].
+    ///
+    /// With [`VersionAheadPolicy::Error`], migrating fails with [`Error::DatabaseAhead`], so a
+    /// downgraded binary stops before it misreads a newer schema.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration, VersionAheadPolicy};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let new: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    ///         "ALTER TABLE users ADD COLUMN name TEXT;",
+    ///     ],
+    /// }
+    /// .into();
+    /// let old = MonarchDB::from(StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+    /// })
+    /// .with_version_ahead_policy(VersionAheadPolicy::Error);
+    ///
+    /// let connection = new.open_in_memory()?;
+    /// assert!(old.migrate(connection).is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_version_ahead_policy(mut self, policy: VersionAheadPolicy) -> Self {
+        self.version_ahead_policy = policy;
+        self
+    }
+
+    /// Applies the version ahead policy to the version recorded in a database being migrated.
+    pub(crate) fn check_version_ahead(&self, version: u32) -> Result<()> {
+        let current = self.current_version();
+        if version <= current {
+            return Ok(());
+        }
+        match self.version_ahead_policy {
+            VersionAheadPolicy::Ignore => Ok(()),
+            VersionAheadPolicy::Warn => {
+                tracing::warn!(
+                    version,
+                    current,
+                    "Database was migrated past the last known migration"
+                );
+                Ok(())
+            }
+            VersionAheadPolicy::Error => Err(Error::DatabaseAhead { version, current }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::{StaticMonarchConfiguration, TransactionMode};
+
+    use super::*;
+
+    fn old(policy: VersionAheadPolicy) -> MonarchDB {
+        MonarchDB::from(StaticMonarchConfiguration {
+            name: "ahead",
+            enable_foreign_keys: false,
+            migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+        })
+        .with_version_ahead_policy(policy)
+    }
+
+    #[test]
+    fn test_version_ahead_policy() -> Result<()> {
+        let new = MonarchDB::from(StaticMonarchConfiguration {
+            name: "ahead",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                "ALTER TABLE users ADD COLUMN name TEXT;",
+            ],
+        });
+        let mut connection = Connection::open_in_memory()?;
+        new.migrate_to(&mut connection, 2)?;
+
+        for policy in [VersionAheadPolicy::Ignore, VersionAheadPolicy::Warn] {
+            let report = old(policy).migrate_to(&mut connection, 1)?;
+            assert_eq!((report.from_version, report.to_version), (2, 2));
+        }
+
+        for mode in [
+            TransactionMode::Single,
+            TransactionMode::PerMigration,
+            TransactionMode::Savepoint,
+        ] {
+            let error = old(VersionAheadPolicy::Error)
+                .with_transaction_mode(mode)
+                .migrate_to(&mut connection, 1)
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "database is at version 2, past the last migration at version 1"
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,270 @@
+//! Assertions checked after a migration runs.
+//!
+//! A data migration, such as rebuilding a table to change a column's type, can silently lose
+//! rows. A migration declares queries which must hold once it has run, and if one does not, the
+//! migration fails and its transaction is rolled back:
+//!
+//! - `-- monarch:assert <query>` must return a true value, a nonzero number, in the first column
+//!   of its first row, e.g. `-- monarch:assert SELECT count(*) > 0 FROM countries`.
+//! - `-- monarch:assert-unchanged <query>` must return the same rows after the migration as
+//!   before it, e.g. `-- monarch:assert-unchanged SELECT count(*) FROM users` around a rebuild of
+//!   `users`.
+//!
+//! Assertions may also live in a companion file next to a migration loaded from a directory,
+//! `003_rebuild_users.verify.sql` for `003_rebuild_users.sql`. Each statement of that file is an
+//! `assert` query, and it may start with directives of its own. The companion file is not part
+//! of the migration's checksum.
+
+use rusqlite::Connection;
+use rusqlite::types::Value;
+
+use crate::migration::{Migration, directives};
+use crate::sql::statements;
+use crate::{Error, Result};
+
+/// The suffix of a file holding assertions for the migration of the same name.
+pub(crate) const VERIFY_SUFFIX: &str = ".verify.sql";
+
+/// A query checked after a migration runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Assertion {
+    pub(crate) query: String,
+    /// Whether the query must return the same rows as before the migration, rather than true.
+    pub(crate) unchanged: bool,
+}
+
+/// The assertions declared by directives at the start of `sql`.
+pub(crate) fn assertion_directives(sql: &str) -> impl Iterator<Item = Assertion> + '_ {
+    directives(sql).filter_map(|(directive, query)| {
+        let unchanged = match directive {
+            "assert" => false,
+            "assert-unchanged" => true,
+            _ => return None,
+        };
+        (!query.is_empty()).then(|| Assertion {
+            query: query.trim_end_matches(';').trim_end().to_owned(),
+            unchanged,
+        })
+    })
+}
+
+impl Migration {
+    /// Adds the assertions of a companion `.verify.sql` file: its directives, and each of its
+    /// statements as an `assert` query.
+    pub(crate) fn with_verification(mut self, sql: &str) -> Self {
+        self.assertions.extend(assertion_directives(sql));
+        self.assertions
+            .extend(statements(sql).into_iter().map(|tokens| {
+                let (first, last) = (&tokens[0], &tokens[tokens.len() - 1]);
+                Assertion {
+                    query: sql[first.offset..last.offset + last.text.len()].to_owned(),
+                    unchanged: false,
+                }
+            }));
+        self
+    }
+
+    /// Runs the migration's statements and imports, calling `on_statement` like
+    /// [`execute`](Self::execute), then checks its assertions.
+    ///
+    /// A failed assertion is reported as [`Error::AssertionFailed`].
+    pub(crate) fn run(
+        &self,
+        connection: &Connection,
+        on_statement: impl FnMut(usize),
+    ) -> Result<()> {
+        let before = self
+            .assertions
+            .iter()
+            .filter(|assertion| assertion.unchanged)
+            .map(|assertion| self.rows(connection, assertion))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.execute(connection, on_statement)?;
+        self.run_imports(connection)?;
+
+        let mut before = before.into_iter();
+        for assertion in &self.assertions {
+            let rows = self.rows(connection, assertion)?;
+            let failure = if assertion.unchanged {
+                let before = before
+                    .next()
+                    .expect("rows are read for each unchanged assertion");
+                (rows != before).then(|| {
+                    format!(
+                        "returned {} before the migration and {} after it",
+                        show(&before),
+                        show(&rows)
+                    )
+                })
+            } else {
+                match rows.first().and_then(|row| row.first()) {
+                    Some(Value::Integer(value)) if *value != 0 => None,
+                    Some(Value::Real(value)) if *value != 0.0 => None,
+                    _ => Some(format!("returned {}", show(&rows))),
+                }
+            };
+            if let Some(reason) = failure {
+                return Err(Error::AssertionFailed {
+                    migration: self.name.to_string(),
+                    query: assertion.query.clone(),
+                    reason,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Every row `assertion` returns, failing with the migration's name if the query does.
+    fn rows(&self, connection: &Connection, assertion: &Assertion) -> Result<Vec<Vec<Value>>> {
+        let query = || -> rusqlite::Result<Vec<Vec<Value>>> {
+            let mut statement = connection.prepare(&assertion.query)?;
+            let columns = statement.column_count();
+            statement
+                .query_map([], |row| (0..columns).map(|index| row.get(index)).collect())?
+                .collect()
+        };
+        query().map_err(|error| Error::AssertionFailed {
+            migration: self.name.to_string(),
+            query: assertion.query.clone(),
+            reason: error.to_string(),
+        })
+    }
+}
+
+/// Renders query results for an error message, e.g. `(3)` or `no rows`.
+fn show(rows: &[Vec<Value>]) -> String {
+    if rows.is_empty() {
+        return "no rows".to_owned();
+    }
+    let rows: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            let values: Vec<String> = row
+                .iter()
+                .map(|value| match value {
+                    Value::Null => "NULL".to_owned(),
+                    Value::Integer(value) => value.to_string(),
+                    Value::Real(value) => value.to_string(),
+                    Value::Text(value) => format!("'{value}'"),
+                    Value::Blob(value) => format!("<{} byte blob>", value.len()),
+                })
+                .collect();
+            format!("({})", values.join(", "))
+        })
+        .collect();
+    rows.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{MonarchDB, SourceMigration};
+
+    use super::*;
+
+    const USERS: &str = "CREATE TABLE users (id INTEGER PRIMARY KEY, age TEXT);
+        INSERT INTO users (age) VALUES ('31'), ('42');";
+
+    fn assertion_error(error: Error) -> (String, String, String) {
+        match error {
+            Error::AssertionFailed {
+                migration,
+                query,
+                reason,
+            } => (migration, query, reason),
+            error => panic!("unexpected error: {error}"),
+        }
+    }
+
+    #[test]
+    fn test_assertion_directives() {
+        let migration = Migration::new(
+            "002_rebuild.sql",
+            "-- monarch:assert SELECT count(*) = 2 FROM users;\n\
+             -- monarch:assert-unchanged SELECT count(*) FROM users\n\
+             -- monarch:assert\n\
+             UPDATE users SET age = age;",
+        );
+        assert_eq!(
+            migration.assertions,
+            [
+                Assertion {
+                    query: "SELECT count(*) = 2 FROM users".into(),
+                    unchanged: false,
+                },
+                Assertion {
+                    query: "SELECT count(*) FROM users".into(),
+                    unchanged: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assertions_pass() -> crate::Result<()> {
+        let rebuild = "-- monarch:assert-unchanged SELECT count(*), sum(age) FROM users
+            CREATE TABLE users_new (id INTEGER PRIMARY KEY, age INTEGER);
+            INSERT INTO users_new SELECT id, CAST(age AS INTEGER) FROM users;
+            DROP TABLE users;
+            ALTER TABLE users_new RENAME TO users;";
+        let monarch_db = MonarchDB::from_migrations(
+            "assertions".into(),
+            true,
+            [
+                SourceMigration::new("001_users.sql", USERS),
+                SourceMigration::new("002_rebuild.sql", rebuild)
+                    .with_verify_sql("SELECT count(*) = 2 FROM users; SELECT max(age) FROM users;"),
+            ],
+        )?;
+        let connection = monarch_db.open_in_memory()?;
+        let ages: i64 = connection.query_row("SELECT sum(age) FROM users", [], |row| row.get(0))?;
+        assert_eq!(ages, 73);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_assertion_rolls_back() -> crate::Result<()> {
+        let lossy = "-- monarch:assert-unchanged SELECT count(*) FROM users
+            DELETE FROM users WHERE id = 1;";
+        let monarch_db = MonarchDB::from_migrations(
+            "assertions".into(),
+            true,
+            [
+                SourceMigration::new("001_users.sql", USERS),
+                SourceMigration::new("002_lossy.sql", lossy),
+            ],
+        )?;
+        let mut connection = Connection::open_in_memory()?;
+        monarch_db.migrate_to(&mut connection, 1)?;
+
+        let error = monarch_db.migrate_to(&mut connection, 2).unwrap_err();
+        assert_eq!(
+            assertion_error(error),
+            (
+                "002_lossy.sql".to_owned(),
+                "SELECT count(*) FROM users".to_owned(),
+                "returned (2) before the migration and (1) after it".to_owned(),
+            )
+        );
+        let count: i64 =
+            connection.query_row("SELECT count(*) FROM users", [], |row| row.get(0))?;
+        assert_eq!(count, 2);
+
+        let monarch_db = MonarchDB::from_migrations(
+            "assertions".into(),
+            true,
+            [
+                SourceMigration::new("001_users.sql", USERS),
+                SourceMigration::new("002_check.sql", "SELECT 1;")
+                    .with_verify_sql("SELECT count(*) FROM users WHERE age > '50';"),
+            ],
+        )?;
+        let error = monarch_db
+            .migrate(Connection::open_in_memory()?)
+            .unwrap_err();
+        let (_, query, reason) = assertion_error(error);
+        assert_eq!(query, "SELECT count(*) FROM users WHERE age > '50'");
+        assert_eq!(reason, "returned (0)");
+        Ok(())
+    }
+}
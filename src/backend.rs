@@ -0,0 +1,377 @@
+//! Migrating through drivers other than `rusqlite`.
+//!
+//! [`SqliteBackend`] is the handful of operations migrating needs from a connection: running a
+//! batch of SQL, and running a statement with parameters and reading back its rows. A driver
+//! which implements it, such as a shim over sql.js or wa-sqlite, or a blocking wrapper around an
+//! asynchronous driver, can be migrated with [`MonarchDB::migrate_backend`] without forking the
+//! migration logic.
+//!
+//! Every driver, `rusqlite` and libSQL included, runs the same migration core: the version,
+//! history and repeatable migration tables, the checksum and identifier checks, out-of-order
+//! migrations, the transaction mode, metrics, the write-ahead log checkpoint and the schema file.
+//! What needs a `rusqlite` connection only runs on one: connection setup hooks, progress
+//! reporters, quarantine remediations, the migration timeout, the foreign key checks of relaxed
+//! migrations, and retrying while another connection holds the lock. A migration with an import
+//! or assertion fails on other drivers.
+
+use std::fmt;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+use rusqlite::Connection;
+use rusqlite::types::{FromSql, Value, ValueRef};
+
+use crate::migration::Migration;
+use crate::{Error, MigrationReport, MonarchDB, Result, wait_for_lock};
+
+/// A connection to an SQLite database which migrations can run through, see
+/// [`MonarchDB::migrate_backend`].
+pub trait SqliteBackend {
+    /// The error the driver fails with. Its message is recorded in the history when a
+    /// best-effort migration fails.
+    type Error: From<Error> + fmt::Display;
+
+    /// Runs `sql`, which may hold several statements, discarding any rows they return.
+    fn execute_batch(&mut self, sql: &str) -> Result<(), Self::Error>;
+
+    /// Runs a single statement with positional parameters `?1`, `?2`, ... bound to `params`,
+    /// returning every row it produces, which is none for statements other than queries.
+    fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Vec<Value>>, Self::Error>;
+}
+
+impl SqliteBackend for Connection {
+    type Error = Error;
+
+    fn execute_batch(&mut self, sql: &str) -> Result<()> {
+        Ok(Connection::execute_batch(self, sql)?)
+    }
+
+    fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Vec<Value>>> {
+        Ok(query_rows(self, sql, params)?)
+    }
+}
+
+/// Runs `sql` with `params` on `connection`, returning every row.
+fn query_rows(
+    connection: &Connection,
+    sql: &str,
+    params: &[Value],
+) -> rusqlite::Result<Vec<Vec<Value>>> {
+    let mut stmt = connection.prepare(sql)?;
+    let columns = stmt.column_count();
+    let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+        (0..columns).map(|index| row.get(index)).collect()
+    })?;
+    rows.collect()
+}
+
+/// The operations the migration core runs against a connection.
+///
+/// Implemented for `rusqlite` connections by [`Rusqlite`], which runs migrations with every
+/// feature that needs one, for each [`SqliteBackend`], and for libSQL connections. The methods
+/// are asynchronous for libSQL; the other drivers finish each call the first time it is polled,
+/// so the core runs on them through [`block_on`].
+pub(crate) trait Driver {
+    type Error: From<Error> + fmt::Display;
+
+    /// Runs `sql`, which may hold several statements, discarding any rows they return.
+    async fn execute_batch(&mut self, sql: &str) -> Result<(), Self::Error>;
+
+    /// Runs a single statement with `params`, returning every row it produces.
+    async fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Vec<Value>>, Self::Error>;
+
+    /// Runs a single statement with `params`, discarding any rows it returns.
+    async fn execute(&mut self, sql: &str, params: &[Value]) -> Result<(), Self::Error> {
+        self.query(sql, params).await?;
+        Ok(())
+    }
+
+    /// The first column of the first row a query returns, or `None` if it returns no rows.
+    async fn query_value<T: FromSql>(
+        &mut self,
+        sql: &str,
+        params: &[Value],
+    ) -> Result<Option<T>, Self::Error> {
+        let rows = self.query(sql, params).await?;
+        Ok(rows.first().map(|row| column(row, 0)).transpose()?)
+    }
+
+    /// Whether the `main` schema has a table named `table`.
+    async fn table_exists(&mut self, table: &str) -> Result<bool, Self::Error> {
+        let exists = self
+            .query_value(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?1)",
+                &[table.to_owned().into()],
+            )
+            .await?;
+        Ok(exists.unwrap_or(false))
+    }
+
+    /// Starts the transaction migrations run in, taking the database's write lock.
+    async fn begin(&mut self, _monarch: &MonarchDB) -> Result<(), Self::Error> {
+        self.execute_batch("BEGIN IMMEDIATE").await
+    }
+
+    /// Runs the statements of `migration`, which upgrades the schema to `version` on the way
+    /// to `target`.
+    async fn run_migration(
+        &mut self,
+        monarch: &MonarchDB,
+        migration: &Migration,
+        _version: u32,
+        _target: u32,
+    ) -> Result<(), Self::Error> {
+        check_supported(migration)?;
+        if monarch.enable_foreign_keys && migration.relax_foreign_keys {
+            self.execute_batch("PRAGMA defer_foreign_keys = ON").await?;
+        }
+        self.execute_batch(migration.sql()?).await
+    }
+
+    /// Runs the statements of the repeatable `migration`.
+    async fn run_repeatable(
+        &mut self,
+        _monarch: &MonarchDB,
+        migration: &Migration,
+    ) -> Result<(), Self::Error> {
+        check_supported(migration)?;
+        self.execute_batch(migration.sql()?).await
+    }
+
+    /// Whether `error` stopped a migration from outside rather than the migration failing by
+    /// itself, so a best-effort migration fails migrating instead of being skipped.
+    fn is_interruption(_error: &Self::Error) -> bool {
+        false
+    }
+}
+
+/// Converts column `index` of `row`, a missing column reading as `NULL`.
+pub(crate) fn column<T: FromSql>(row: &[Value], index: usize) -> Result<T> {
+    let value = row.get(index).map_or(ValueRef::Null, ValueRef::from);
+    T::column_result(value).map_err(|error| {
+        rusqlite::Error::FromSqlConversionFailure(index, value.data_type(), Box::new(error)).into()
+    })
+}
+
+/// Runs `future` to completion on a driver which never waits, such as a `rusqlite` connection.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(output) => output,
+        Poll::Pending => unreachable!("synchronous drivers finish every call when first polled"),
+    }
+}
+
+/// Drives the migration core through a `rusqlite` connection, or a transaction or savepoint on
+/// one.
+pub(crate) struct Rusqlite<'c>(pub(crate) &'c Connection);
+
+impl Driver for Rusqlite<'_> {
+    type Error = Error;
+
+    async fn execute_batch(&mut self, sql: &str) -> Result<()> {
+        Ok(self.0.execute_batch(sql)?)
+    }
+
+    async fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Vec<Value>>> {
+        Ok(query_rows(self.0, sql, params)?)
+    }
+
+    async fn begin(&mut self, monarch: &MonarchDB) -> Result<()> {
+        Ok(monarch.retry_policy.retry(|| {
+            wait_for_lock(monarch.lock_timeout, || {
+                self.0.execute_batch("BEGIN IMMEDIATE")
+            })
+        })?)
+    }
+
+    async fn run_migration(
+        &mut self,
+        monarch: &MonarchDB,
+        migration: &Migration,
+        version: u32,
+        target: u32,
+    ) -> Result<()> {
+        monarch.execute_quarantined(self.0, migration, version, target)
+    }
+
+    async fn run_repeatable(&mut self, monarch: &MonarchDB, migration: &Migration) -> Result<()> {
+        monarch.run_within_timeout(self.0, migration, || migration.run(self.0, |_| {}))
+    }
+
+    fn is_interruption(error: &Error) -> bool {
+        crate::is_interruption(error)
+    }
+}
+
+/// Drives the migration core through a [`SqliteBackend`].
+struct Backend<'b, B>(&'b mut B);
+
+impl<B: SqliteBackend> Driver for Backend<'_, B> {
+    type Error = B::Error;
+
+    async fn execute_batch(&mut self, sql: &str) -> Result<(), B::Error> {
+        self.0.execute_batch(sql)
+    }
+
+    async fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Vec<Value>>, B::Error> {
+        self.0.query(sql, params)
+    }
+}
+
+impl MonarchDB {
+    /// Applies pending migrations through `backend`, in the configured
+    /// [`TransactionMode`](crate::TransactionMode).
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, SqliteBackend, StaticMonarchConfiguration};
+    /// use rusqlite::types::Value;
+    ///
+    /// /// A driver which only knows how to run SQL.
+    /// struct Driver(rusqlite::Connection);
+    ///
+    /// impl SqliteBackend for Driver {
+    ///     type Error = monarch_db::Error;
+    ///
+    ///     fn execute_batch(&mut self, sql: &str) -> monarch_db::Result<()> {
+    ///         Ok(self.0.execute_batch(sql)?)
+    ///     }
+    ///
+    ///     fn query(&mut self, sql: &str, params: &[Value]) -> monarch_db::Result<Vec<Vec<Value>>> {
+    ///         let mut stmt = self.0.prepare(sql)?;
+    ///         let columns = stmt.column_count();
+    ///         let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+    ///             (0..columns).map(|index| row.get(index)).collect()
+    ///         })?;
+    ///         Ok(rows.collect::<rusqlite::Result<_>>()?)
+    ///     }
+    /// }
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);"],
+    /// }
+    /// .into();
+    ///
+    /// let mut driver = Driver(rusqlite::Connection::open_in_memory()?);
+    /// let report = monarch_db.migrate_backend(&mut driver)?;
+    /// assert_eq!(report.to_version, 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with the backend's error if a statement fails, in which case the transaction is
+    /// rolled back, and like [`migrate`](Self::migrate) otherwise.
+    pub fn migrate_backend<B: SqliteBackend>(
+        &self,
+        backend: &mut B,
+    ) -> Result<MigrationReport, B::Error> {
+        if self.enable_foreign_keys {
+            backend.execute_batch("PRAGMA foreign_keys = ON")?;
+        }
+        block_on(self.migrate_through(&mut Backend(backend), self.current_version(), true))
+    }
+}
+
+/// Fails if the migration has imports or assertions, which need a `rusqlite` connection.
+fn check_supported(migration: &Migration) -> Result<()> {
+    if let Some(import) = migration.imports.first() {
+        return Err(Error::UnsupportedImport {
+            migration: migration.name.to_string(),
+            path: import.path.clone(),
+        });
+    }
+    if !migration.assertions.is_empty() {
+        return Err(Error::UnsupportedAssertion {
+            migration: migration.name.to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{MigrationStatus, StaticMonarchConfiguration, TransactionMode};
+
+    use super::*;
+
+    #[test]
+    fn test_backend_matches_rusqlite() -> Result<()> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "it's",
+            enable_foreign_keys: true,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);",
+                "-- monarch:best-effort\nCREATE INDEX broken ON users (missing);",
+                "ALTER TABLE users ADD COLUMN email TEXT;",
+            ],
+        })
+        .with_repeatable_migration(
+            "users_view",
+            "CREATE VIEW IF NOT EXISTS names AS SELECT name FROM users;",
+        );
+        let mut connection = Connection::open_in_memory()?;
+        let report = monarch_db.migrate_backend(&mut connection)?;
+        assert_eq!((report.from_version, report.to_version), (0, 3));
+        assert_eq!(report.skipped, ["2"]);
+        assert_eq!(report.repeatable, ["users_view"]);
+
+        // The rusqlite path reads the same bookkeeping, so nothing is left to run.
+        let report = monarch_db.migrate_to(&mut connection, 3)?;
+        assert_eq!(report.applied(), 0);
+        assert!(report.repeatable.is_empty());
+        let history = monarch_db.history(&connection)?;
+        assert_eq!(history.len(), 3);
+        assert!(matches!(history[1].status, MigrationStatus::Skipped(_)));
+        assert!(history.iter().all(|entry| entry.duration.is_some()));
+
+        let report = monarch_db.migrate_backend(&mut connection)?;
+        assert_eq!((report.applied(), report.repeatable.len()), (0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_migration_rolls_back() -> Result<()> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "backend",
+            enable_foreign_keys: false,
+            migrations: ["CREATE TABLE t (x);", "CREATE TABLE t (x);"],
+        });
+        let mut connection = Connection::open_in_memory()?;
+        assert!(monarch_db.migrate_backend(&mut connection).is_err());
+        assert_eq!(
+            connection.query("SELECT name FROM sqlite_master", &[])?,
+            Vec::<Vec<Value>>::new()
+        );
+
+        // Committing each migration keeps the ones before the failure.
+        let monarch_db = monarch_db.with_transaction_mode(TransactionMode::PerMigration);
+        assert!(monarch_db.migrate_backend(&mut connection).is_err());
+        assert_eq!(crate::plan::recorded_version(&connection, "backend")?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_assertions_need_rusqlite() -> Result<()> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "backend",
+            enable_foreign_keys: false,
+            migrations: ["-- monarch:assert SELECT count(*) = 0 FROM t\nCREATE TABLE t (x);"],
+        });
+        let error = monarch_db
+            .migrate_backend(&mut Connection::open_in_memory()?)
+            .unwrap_err();
+        assert!(
+            matches!(error, Error::UnsupportedAssertion { .. }),
+            "{error}"
+        );
+        Ok(())
+    }
+}
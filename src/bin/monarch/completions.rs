@@ -0,0 +1,236 @@
+//! Shell completion scripts for `monarch completions <shell>`.
+//!
+//! The scripts complete command names, the flags of each command and the options which fill in
+//! configured arguments, and fall back to file names for positional arguments.
+
+use std::fmt::Write;
+
+use crate::CliResult;
+
+/// A command, as offered for completion.
+struct Command {
+    name: &'static str,
+    description: &'static str,
+    flags: &'static [&'static str],
+    /// The values of the command's argument, or empty to complete file names.
+    values: &'static [&'static str],
+}
+
+impl Command {
+    const fn new(name: &'static str, description: &'static str) -> Self {
+        Command {
+            name,
+            description,
+            flags: &[],
+            values: &[],
+        }
+    }
+
+    const fn flags(mut self, flags: &'static [&'static str]) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    const fn values(mut self, values: &'static [&'static str]) -> Self {
+        self.values = values;
+        self
+    }
+}
+
+/// The shells completion scripts can be written for.
+const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+/// Flags every command accepts, which replace the configured arguments.
+const GLOBAL_FLAGS: &[&str] = &["--config", "--migrations-dir", "--app-name", "--database"];
+
+fn commands() -> Vec<Command> {
+    let mut commands = vec![
+        Command::new("init", "Create a migrations directory and monarch.toml"),
+        Command::new("migrate", "Run migrations").flags(&["--to", "--step", "--yes"]),
+        Command::new("watch", "Apply migrations as their files change"),
+        Command::new("version", "Show current migration version").flags(&["--exit-code"]),
+        Command::new("check", "Fail unless the database matches the migrations")
+            .flags(&["--format"]),
+        Command::new("adopt", "Record migrations applied by another tool").flags(&["--from"]),
+        Command::new(
+            "export-metadata",
+            "Export tables and columns per version as JSON",
+        )
+        .flags(&["--output"]),
+        Command::new(
+            "codegen",
+            "Write Rust constants and row structs for the schema",
+        )
+        .flags(&["--output"]),
+        Command::new("lint", "Fail if migrations are nondeterministic or unsafe"),
+        Command::new("blame", "Show the migrations which created schema objects"),
+        Command::new("changelog", "Write release notes of schema changes")
+            .flags(&["--since", "--format", "--output"]),
+        Command::new("squash", "Collapse migrations into a baseline").flags(&["--output"]),
+        Command::new(
+            "make-migration",
+            "Write a migration to the schema in a file",
+        )
+        .flags(&["--target", "--output"]),
+        Command::new("dump-schema", "Write the schema of a database as SQL").flags(&["--output"]),
+        Command::new("history", "Show the migrations applied to a database")
+            .flags(&["--name", "--format"]),
+        Command::new("compare", "Fail unless both databases hold the same data")
+            .flags(&["--table"]),
+        Command::new("advise-indexes", "Flag indexes which may be worth dropping")
+            .flags(&["--workload", "--output"]),
+    ];
+    #[cfg(feature = "arrow")]
+    commands.push(
+        Command::new("export", "Export tables to Parquet or Arrow files").flags(&[
+            "--format",
+            "--output-dir",
+            "--table",
+        ]),
+    );
+    commands.push(Command::new(
+        "batch",
+        "Run commands read line by line, printing NDJSON",
+    ));
+    #[cfg(feature = "tui")]
+    commands.push(Command::new(
+        "tui",
+        "Browse the history, schema and pending migrations",
+    ));
+    #[cfg(unix)]
+    commands.push(Command::new("serve", "Serve commands on a Unix socket").flags(&["--socket"]));
+    commands.push(Command::new("completions", "Write a shell completion script").values(SHELLS));
+    commands.push(Command::new("help", "Show the help message"));
+    commands
+}
+
+/// The completion script for `shell`.
+pub(crate) fn script(shell: &str) -> CliResult<String> {
+    let commands = commands();
+    Ok(match shell {
+        "bash" => bash(&commands),
+        "zsh" => zsh(&commands),
+        "fish" => fish(&commands),
+        _ => {
+            return Err(format!(
+                "unsupported shell: {shell}, expected one of {}",
+                SHELLS.join(", ")
+            )
+            .into());
+        }
+    })
+}
+
+/// The flags `command` accepts, including the global flags.
+fn all_flags(command: &Command) -> Vec<&'static str> {
+    command.flags.iter().chain(GLOBAL_FLAGS).copied().collect()
+}
+
+fn bash(commands: &[Command]) -> String {
+    let names: Vec<&str> = commands.iter().map(|command| command.name).collect();
+    let mut script = format!(
+        "_monarch() {{\n    \
+         local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"\n    \
+         if [ \"$COMP_CWORD\" -eq 1 ]; then\n        \
+         COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n        \
+         return\n    \
+         fi\n    \
+         case \"${{COMP_WORDS[1]}}\" in\n",
+        names.join(" ")
+    );
+    for command in commands {
+        let words = if command.values.is_empty() {
+            String::new()
+        } else {
+            format!(" {}", command.values.join(" "))
+        };
+        let _ = writeln!(
+            script,
+            "        {}) local words=\"{}{words}\" ;;",
+            command.name,
+            all_flags(command).join(" "),
+        );
+    }
+    script.push_str(
+        "        *) local words=\"\" ;;\n    \
+         esac\n    \
+         if [[ \"$cur\" == -* ]]; then\n        \
+         COMPREPLY=($(compgen -W \"$words\" -- \"$cur\"))\n    \
+         else\n        \
+         COMPREPLY=($(compgen -W \"$words\" -- \"$cur\" | grep -v '^-') $(compgen -f -- \"$cur\"))\n    \
+         fi\n\
+         }\n\
+         complete -o filenames -F _monarch monarch\n",
+    );
+    script
+}
+
+fn zsh(commands: &[Command]) -> String {
+    let mut script = String::from(
+        "#compdef monarch\n\n\
+         _monarch() {\n    \
+         local -a commands\n    \
+         commands=(\n",
+    );
+    for command in commands {
+        let _ = writeln!(script, "        '{}:{}'", command.name, command.description);
+    }
+    script.push_str(
+        "    )\n    \
+         if (( CURRENT == 2 )); then\n        \
+         _describe 'command' commands\n        \
+         return\n    \
+         fi\n    \
+         case $words[2] in\n",
+    );
+    for command in commands {
+        let values = if command.values.is_empty() {
+            "_files".to_owned()
+        } else {
+            format!("compadd -- {}", command.values.join(" "))
+        };
+        let _ = writeln!(
+            script,
+            "        {})\n            \
+             if [[ $PREFIX == -* ]]; then compadd -- {}; else {values}; fi ;;",
+            command.name,
+            all_flags(command).join(" "),
+        );
+    }
+    script.push_str(
+        "        *) _files ;;\n    \
+         esac\n\
+         }\n\n\
+         _monarch \"$@\"\n",
+    );
+    script
+}
+
+fn fish(commands: &[Command]) -> String {
+    let mut script = String::new();
+    for command in commands {
+        let _ = writeln!(
+            script,
+            "complete -c monarch -n __fish_use_subcommand -f -a {} -d '{}'",
+            command.name, command.description,
+        );
+    }
+    for command in commands {
+        let condition = format!("'__fish_seen_subcommand_from {}'", command.name);
+        for flag in all_flags(command) {
+            let _ = writeln!(
+                script,
+                "complete -c monarch -n {condition} -l {}",
+                flag.trim_start_matches('-')
+            );
+        }
+        if !command.values.is_empty() {
+            let _ = writeln!(
+                script,
+                "complete -c monarch -n {condition} -f -a '{}'",
+                command.values.join(" ")
+            );
+        }
+    }
+    script
+}
@@ -0,0 +1,231 @@
+//! The `monarch.toml` file, which supplies the arguments every command would otherwise repeat.
+//!
+//! ```toml
+//! migrations_dir = "./migrations"
+//! app_name = "my_app"
+//! database = "./app.db"
+//! schema_file = "./schema.sql"
+//!
+//! [pragmas]
+//! journal_mode = "WAL"
+//! busy_timeout = 5000
+//! ```
+//!
+//! The file is read from the working directory, or from the path given with `--config`. The
+//! `--migrations-dir`, `--app-name` and `--database` flags, or the positional arguments, override
+//! the values in the file.
+//!
+//! When `schema_file` is set, `monarch migrate` rewrites that file with the migrated schema, and
+//! `monarch check` fails if it does not match the schema the migrations produce.
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use crate::CliResult;
+
+/// The file read when no `--config` flag is given.
+pub(crate) const DEFAULT_PATH: &str = "monarch.toml";
+
+/// `PRAGMA` statements from the configuration, run on each connection which may migrate.
+static PRAGMAS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// The schema file from the configuration, kept in step with the migrations.
+static SCHEMA_FILE: OnceLock<Option<String>> = OnceLock::new();
+
+/// The contents of a `monarch.toml` file.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CliConfig {
+    migrations_dir: Option<String>,
+    app_name: Option<String>,
+    database: Option<String>,
+    schema_file: Option<String>,
+    #[serde(default)]
+    pragmas: BTreeMap<String, toml::Value>,
+}
+
+/// A positional argument which the configuration can supply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    MigrationsDir,
+    AppName,
+    Database,
+}
+
+impl Slot {
+    fn flag(self) -> &'static str {
+        match self {
+            Slot::MigrationsDir => "--migrations-dir",
+            Slot::AppName => "--app-name",
+            Slot::Database => "--database",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            Slot::MigrationsDir => "migrations_dir",
+            Slot::AppName => "app_name",
+            Slot::Database => "database",
+        }
+    }
+
+    fn configured(self, config: &CliConfig) -> Option<&String> {
+        match self {
+            Slot::MigrationsDir => config.migrations_dir.as_ref(),
+            Slot::AppName => config.app_name.as_ref(),
+            Slot::Database => config.database.as_ref(),
+        }
+    }
+}
+
+/// The leading positional arguments of `command` which the configuration can supply, and the
+/// least and most positional arguments which follow them.
+fn slots(command: &str) -> Option<(&'static [Slot], usize, usize)> {
+    use Slot::*;
+
+    Some(match command {
+        "migrate" | "version" | "check" | "adopt" | "advise-indexes" | "export" | "watch" => {
+            (&[MigrationsDir, AppName, Database], 0, 0)
+        }
+        "export-metadata" | "codegen" | "lint" | "changelog" | "make-migration" => {
+            (&[MigrationsDir, AppName], 0, 0)
+        }
+        "blame" => (&[MigrationsDir, AppName], 0, 1),
+        "squash" => (&[MigrationsDir, AppName], 1, 1),
+        "dump-schema" | "history" => (&[Database], 0, 0),
+        "tui" => (&[Database], 0, 2),
+        _ => return None,
+    })
+}
+
+/// The `PRAGMA` statements to run on connections which may migrate.
+pub(crate) fn pragmas() -> &'static [String] {
+    PRAGMAS.get().map_or(&[], Vec::as_slice)
+}
+
+/// The schema file to write after migrating and compare when checking.
+pub(crate) fn schema_file() -> Option<&'static str> {
+    SCHEMA_FILE.get().and_then(Option::as_deref)
+}
+
+/// Rewrites `args` into the full positional form of their command, filling in the arguments
+/// given by `--migrations-dir`, `--app-name` and `--database` flags or by the configuration file.
+///
+/// Commands whose arguments cannot be configured are returned unchanged, as are commands missing
+/// arguments which nothing supplies, so they report their own usage.
+pub(crate) fn expand_args(args: Vec<String>) -> CliResult<Vec<String>> {
+    let Some((slots, min_extra, max_extra)) = args.get(1).and_then(|command| slots(command)) else {
+        return Ok(args);
+    };
+
+    let mut config_path = None;
+    let mut flags: BTreeMap<&'static str, String> = BTreeMap::new();
+    let mut rest = Vec::new();
+    let mut words = args[2..].iter().cloned();
+    while let Some(word) = words.next() {
+        let slot = slots.iter().find(|slot| slot.flag() == word);
+        if word != "--config" && slot.is_none() {
+            rest.push(word);
+            continue;
+        }
+        let value = words
+            .next()
+            .ok_or_else(|| format!("{word} requires a value"))?;
+        match slot {
+            Some(slot) => flags.insert(slot.flag(), value),
+            None => config_path.replace(value),
+        };
+    }
+
+    let config = load(config_path.as_deref())?;
+    PRAGMAS
+        .set(pragma_statements(&config.pragmas)?)
+        .expect("arguments are expanded once");
+    SCHEMA_FILE
+        .set(config.schema_file.clone())
+        .expect("arguments are expanded once");
+
+    let leading = rest
+        .iter()
+        .take_while(|word| !word.starts_with('-'))
+        .count();
+    let positional = leading >= slots.len() + min_extra;
+    if !positional && !(min_extra..=max_extra).contains(&leading) {
+        return Ok(args[..2].iter().cloned().chain(rest).collect());
+    }
+
+    let mut expanded = args[..2].to_vec();
+    for (index, slot) in slots.iter().enumerate() {
+        let value = flags
+            .remove(slot.flag())
+            .or_else(|| positional.then(|| rest[index].clone()))
+            .or_else(|| slot.configured(&config).cloned())
+            .ok_or_else(|| {
+                format!(
+                    "missing {}: pass {} or set {} in {}",
+                    slot.key(),
+                    slot.flag(),
+                    slot.key(),
+                    config_path.as_deref().unwrap_or(DEFAULT_PATH)
+                )
+            })?;
+        expanded.push(value);
+    }
+    let skip = if positional { slots.len() } else { 0 };
+    expanded.extend(rest.into_iter().skip(skip));
+    Ok(expanded)
+}
+
+/// Reads the configuration from `path`, or from `monarch.toml` if it exists.
+fn load(path: Option<&str>) -> CliResult<CliConfig> {
+    let source = match path {
+        Some(path) => std::fs::read_to_string(path).map_err(|e| format!("reading {path}: {e}"))?,
+        None => match std::fs::read_to_string(DEFAULT_PATH) {
+            Ok(source) => source,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(CliConfig::default()),
+            Err(e) => return Err(format!("reading {DEFAULT_PATH}: {e}").into()),
+        },
+    };
+    toml::from_str(&source)
+        .map_err(|e| format!("parsing {}: {e}", path.unwrap_or(DEFAULT_PATH)).into())
+}
+
+/// The `monarch.toml` written by `monarch init` for a new project.
+pub(crate) fn starter(migrations_dir: &str, app_name: &str, database: &str) -> String {
+    let quote = |value: &str| toml::Value::String(value.to_owned()).to_string();
+    format!(
+        "migrations_dir = {}\n\
+         app_name = {}\n\
+         database = {}\n\
+         # schema_file = \"./schema.sql\"\n\
+         \n\
+         [pragmas]\n\
+         journal_mode = \"WAL\"\n\
+         busy_timeout = 5000\n",
+        quote(migrations_dir),
+        quote(app_name),
+        quote(database),
+    )
+}
+
+/// Renders each configured pragma as a `PRAGMA` statement.
+fn pragma_statements(pragmas: &BTreeMap<String, toml::Value>) -> CliResult<Vec<String>> {
+    pragmas
+        .iter()
+        .map(|(name, value)| {
+            if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format!("invalid pragma name: {name}").into());
+            }
+            let value = match value {
+                toml::Value::String(value) => format!("'{}'", value.replace('\'', "''")),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) => if *value { "ON" } else { "OFF" }.to_owned(),
+                _ => {
+                    return Err(format!("pragma {name} must be a string, number or boolean").into());
+                }
+            };
+            Ok(format!("PRAGMA {name} = {value};"))
+        })
+        .collect()
+}
@@ -0,0 +1,1799 @@
+use camino::Utf8PathBuf;
+use monarch_db::{
+    ChangelogEntry, ChecksumPolicy, HistoryEntry, MigrationReport, MigrationStatus, MigrationTool,
+    MonarchConfiguration, MonarchDB, SchemaDiff, SchemaObject, TableDigest, VersionMetadata,
+};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead};
+use std::process;
+use std::time::{Duration, SystemTime};
+
+mod completions;
+mod config;
+#[cfg(unix)]
+mod serve;
+#[cfg(feature = "tui")]
+mod tui;
+
+type CliResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {e}");
+        process::exit(1);
+    }
+}
+
+fn run() -> CliResult<()> {
+    let args = config::expand_args(std::env::args().collect())?;
+
+    if args.len() < 2 {
+        print_usage(&args[0]);
+        return Ok(());
+    }
+
+    match args[1].as_str() {
+        "migrate" => {
+            let mut flags: Vec<&str> = args.iter().skip(5).map(String::as_str).collect();
+            let yes = flags.iter().any(|flag| matches!(*flag, "--yes" | "-y"));
+            flags.retain(|flag| !matches!(*flag, "--yes" | "-y"));
+            let target = match parse_migrate_target(&flags) {
+                Ok(target) if args.len() >= 5 => target,
+                _ => {
+                    eprintln!(
+                        "Usage: {} migrate <migrations_dir> <app_name> <sqlite_url> [--to <version> | --step <count>] [--yes]",
+                        args[0]
+                    );
+                    process::exit(1);
+                }
+            };
+            migrate_command(&args[2], &args[3], &args[4], target, yes)?;
+        }
+        "watch" => {
+            if args.len() != 5 {
+                eprintln!(
+                    "Usage: {} watch <migrations_dir> <app_name> <sqlite_url>",
+                    args[0]
+                );
+                process::exit(1);
+            }
+            watch_command(&args[2], &args[3], &args[4])?;
+        }
+        "version" => {
+            let exit_code = match args.get(5..) {
+                Some([]) => false,
+                Some([flag]) if flag == "--exit-code" => true,
+                _ => {
+                    eprintln!(
+                        "Usage: {} version <migrations_dir> <app_name> <sqlite_url> [--exit-code]",
+                        args[0]
+                    );
+                    process::exit(1);
+                }
+            };
+            let pending = version_command(&args[2], &args[3], &args[4])?;
+            if exit_code && pending {
+                process::exit(1);
+            }
+        }
+        "check" => {
+            let json = match args.len() {
+                5 => false,
+                7 if args[5] == "--format" && matches!(args[6].as_str(), "text" | "json") => {
+                    args[6] == "json"
+                }
+                _ => {
+                    eprintln!(
+                        "Usage: {} check <migrations_dir> <app_name> <sqlite_url> [--format text|json]",
+                        args[0]
+                    );
+                    process::exit(1);
+                }
+            };
+            if !check_command(&args[2], &args[3], &args[4], json)? {
+                process::exit(1);
+            }
+        }
+        "adopt" => {
+            let tool = match args.get(5..) {
+                Some([flag, tool]) if flag == "--from" => tool.parse()?,
+                _ => {
+                    eprintln!(
+                        "Usage: {} adopt <migrations_dir> <app_name> <sqlite_url> --from <refinery|sqlx|diesel>",
+                        args[0]
+                    );
+                    process::exit(1);
+                }
+            };
+            adopt_command(&args[2], &args[3], &args[4], tool)?;
+        }
+        "export-metadata" => {
+            let output = match args.len() {
+                4 => None,
+                6 if args[4] == "--output" => Some(args[5].as_str()),
+                _ => {
+                    eprintln!(
+                        "Usage: {} export-metadata <migrations_dir> <app_name> [--output <file>]",
+                        args[0]
+                    );
+                    process::exit(1);
+                }
+            };
+            export_metadata_command(&args[2], &args[3], output)?;
+        }
+        "init" => {
+            let database = match args.len() {
+                4 => None,
+                5 => Some(args[4].as_str()),
+                _ => {
+                    eprintln!(
+                        "Usage: {} init <migrations_dir> <app_name> [<sqlite_url>]",
+                        args[0]
+                    );
+                    process::exit(1);
+                }
+            };
+            init_command(&args[2], &args[3], database)?;
+        }
+        "codegen" => {
+            let output = match args.len() {
+                4 => None,
+                6 if args[4] == "--output" => Some(args[5].as_str()),
+                _ => {
+                    eprintln!(
+                        "Usage: {} codegen <migrations_dir> <app_name> [--output <file>]",
+                        args[0]
+                    );
+                    process::exit(1);
+                }
+            };
+            codegen_command(&args[2], &args[3], output)?;
+        }
+        "lint" => {
+            if args.len() != 4 {
+                eprintln!("Usage: {} lint <migrations_dir> <app_name>", args[0]);
+                process::exit(1);
+            }
+            if !lint_command(&args[2], &args[3])? {
+                process::exit(1);
+            }
+        }
+        "blame" => {
+            let object = match args.len() {
+                4 => None,
+                5 => Some(args[4].as_str()),
+                _ => {
+                    eprintln!(
+                        "Usage: {} blame <migrations_dir> <app_name> [<object>]",
+                        args[0]
+                    );
+                    process::exit(1);
+                }
+            };
+            blame_command(&args[2], &args[3], object)?;
+        }
+        "changelog" => {
+            let usage = || {
+                eprintln!(
+                    "Usage: {} changelog <migrations_dir> <app_name> [--since <version>] [--format markdown|json] [--output <file>]",
+                    args[0]
+                );
+                process::exit(1);
+            };
+            if args.len() < 4 {
+                usage();
+            }
+            let mut since = 0;
+            let mut json = false;
+            let mut output = None;
+            let mut options = args[4..].iter();
+            while let Some(option) = options.next() {
+                match (option.as_str(), options.next()) {
+                    ("--since", Some(version)) => match version.parse() {
+                        Ok(version) => since = version,
+                        Err(_) => usage(),
+                    },
+                    ("--format", Some(format))
+                        if matches!(format.as_str(), "markdown" | "json") =>
+                    {
+                        json = format == "json";
+                    }
+                    ("--output", Some(path)) => output = Some(path.as_str()),
+                    _ => usage(),
+                }
+            }
+            changelog_command(&args[2], &args[3], since, json, output)?;
+        }
+        "squash" => {
+            let output = match args.len() {
+                5 => None,
+                7 if args[5] == "--output" => Some(args[6].as_str()),
+                _ => {
+                    eprintln!(
+                        "Usage: {} squash <migrations_dir> <app_name> <through_version> [--output <file>]",
+                        args[0]
+                    );
+                    process::exit(1);
+                }
+            };
+            let through = args[4]
+                .parse()
+                .map_err(|_| format!("invalid version: {}", args[4]))?;
+            squash_command(&args[2], &args[3], through, output)?;
+        }
+        "make-migration" => {
+            let usage = || {
+                eprintln!(
+                    "Usage: {} make-migration <migrations_dir> <app_name> --target <schema.sql> [--output <file>]",
+                    args[0]
+                );
+                process::exit(1);
+            };
+            if args.len() < 4 {
+                usage();
+            }
+            let mut target = None;
+            let mut output = None;
+            let mut options = args[4..].iter();
+            while let Some(option) = options.next() {
+                match (option.as_str(), options.next()) {
+                    ("--target", Some(path)) => target = Some(path.as_str()),
+                    ("--output", Some(path)) => output = Some(path.as_str()),
+                    _ => usage(),
+                }
+            }
+            let Some(target) = target else {
+                usage();
+                return Ok(());
+            };
+            make_migration_command(&args[2], &args[3], target, output)?;
+        }
+        "dump-schema" => {
+            let output = match args.len() {
+                3 => None,
+                5 if args[3] == "--output" => Some(args[4].as_str()),
+                _ => {
+                    eprintln!(
+                        "Usage: {} dump-schema <sqlite_url> [--output <file>]",
+                        args[0]
+                    );
+                    process::exit(1);
+                }
+            };
+            dump_schema_command(&args[2], output)?;
+        }
+        "history" => {
+            let usage = || {
+                eprintln!(
+                    "Usage: {} history <sqlite_url> [--name <app_name>] [--format table|json]",
+                    args[0]
+                );
+                process::exit(1);
+            };
+            if args.len() < 3 {
+                usage();
+            }
+            let mut name = None;
+            let mut json = false;
+            let mut options = args[3..].iter();
+            while let Some(option) = options.next() {
+                match (option.as_str(), options.next()) {
+                    ("--name", Some(value)) => name = Some(value.as_str()),
+                    ("--format", Some(format)) if matches!(format.as_str(), "table" | "json") => {
+                        json = format == "json";
+                    }
+                    _ => usage(),
+                }
+            }
+            history_command(&args[2], name, json)?;
+        }
+        "compare" => {
+            let usage = || {
+                eprintln!(
+                    "Usage: {} compare <sqlite_url> <other_sqlite_url> [--table <name>]...",
+                    args[0]
+                );
+                process::exit(1);
+            };
+            if args.len() < 4 {
+                usage();
+            }
+            let mut tables = Vec::new();
+            let mut options = args[4..].iter();
+            while let Some(option) = options.next() {
+                match (option.as_str(), options.next()) {
+                    ("--table", Some(value)) => tables.push(value.as_str()),
+                    _ => usage(),
+                }
+            }
+            if !compare_command(&args[2], &args[3], &tables)? {
+                process::exit(1);
+            }
+        }
+        "advise-indexes" => {
+            let usage = || {
+                eprintln!(
+                    "Usage: {} advise-indexes <migrations_dir> <app_name> <sqlite_url> [--workload <file>] [--output <file>]",
+                    args[0]
+                );
+                process::exit(1);
+            };
+            if args.len() < 5 {
+                usage();
+            }
+            let mut workload = None;
+            let mut output = None;
+            let mut options = args[5..].iter();
+            while let Some(option) = options.next() {
+                match (option.as_str(), options.next()) {
+                    ("--workload", Some(path)) => workload = Some(path.as_str()),
+                    ("--output", Some(path)) => output = Some(path.as_str()),
+                    _ => usage(),
+                }
+            }
+            advise_indexes_command(&args[2], &args[3], &args[4], workload, output)?;
+        }
+        #[cfg(feature = "arrow")]
+        "export" => {
+            let usage = || {
+                eprintln!(
+                    "Usage: {} export <migrations_dir> <app_name> <sqlite_url> --format <parquet|arrow> --output-dir <dir> [--table <name>]...",
+                    args[0]
+                );
+                process::exit(1);
+            };
+            if args.len() < 5 {
+                usage();
+            }
+            let mut format = None;
+            let mut output_dir = None;
+            let mut tables = Vec::new();
+            let mut options = args[5..].iter();
+            while let Some(option) = options.next() {
+                match (option.as_str(), options.next()) {
+                    ("--format", Some(value)) => format = Some(value.parse()?),
+                    ("--output-dir", Some(value)) => output_dir = Some(value.as_str()),
+                    ("--table", Some(value)) => tables.push(value.as_str()),
+                    _ => usage(),
+                }
+            }
+            let (Some(format), Some(output_dir)) = (format, output_dir) else {
+                usage();
+                return Ok(());
+            };
+            export_command(&args[2], &args[3], &args[4], format, output_dir, &tables)?;
+        }
+        #[cfg(feature = "tui")]
+        "tui" => {
+            let migrations = match args.len() {
+                3 => None,
+                5 => Some((args[3].as_str(), args[4].as_str())),
+                _ => {
+                    eprintln!(
+                        "Usage: {} tui <sqlite_url> [<migrations_dir> <app_name>]",
+                        args[0]
+                    );
+                    process::exit(1);
+                }
+            };
+            tui::tui_command(&args[2], migrations)?;
+        }
+        "completions" => {
+            if args.len() != 3 {
+                eprintln!("Usage: {} completions <bash|zsh|fish>", args[0]);
+                process::exit(1);
+            }
+            print!("{}", completions::script(&args[2])?);
+        }
+        "batch" => {
+            if args.len() != 3 {
+                eprintln!("Usage: {} batch <file|->", args[0]);
+                process::exit(1);
+            }
+            if !batch_command(&args[2])? {
+                process::exit(1);
+            }
+        }
+        #[cfg(unix)]
+        "serve" => {
+            if args.len() != 4 || args[2] != "--socket" {
+                eprintln!("Usage: {} serve --socket <path>", args[0]);
+                process::exit(1);
+            }
+            serve::serve_command(&args[3])?;
+        }
+        "help" | "--help" | "-h" => {
+            print_usage(&args[0]);
+        }
+        _ => {
+            eprintln!("Unknown command: {}", args[1]);
+            print_usage(&args[0]);
+            process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_usage(program_name: &str) {
+    println!("Monarch-DB Migration Tool");
+    println!();
+    println!("USAGE:");
+    println!("    {program_name} <COMMAND> <ARGS>");
+    println!();
+    println!("COMMANDS:");
+    println!(
+        "    init <migrations_dir> <app_name> [<sqlite_url>]     Create a migrations directory and monarch.toml"
+    );
+    println!(
+        "    migrate <migrations_dir> <app_name> <sqlite_url> [--to <version> | --step <count>] [--yes]\n                                                        Run migrations, asking first for a file database"
+    );
+    println!(
+        "    watch <migrations_dir> <app_name> <sqlite_url>      Apply migrations as their files change"
+    );
+    println!(
+        "    version <migrations_dir> <app_name> <sqlite_url> [--exit-code]\n                                                        Show current migration version"
+    );
+    println!(
+        "    check <migrations_dir> <app_name> <sqlite_url> [--format text|json]\n                                                        Fail unless the database matches the migrations"
+    );
+    println!(
+        "    adopt <migrations_dir> <app_name> <sqlite_url> --from <refinery|sqlx|diesel>\n                                                        Record migrations applied by another tool"
+    );
+    println!(
+        "    export-metadata <migrations_dir> <app_name> [--output <file>]\n                                                        Export tables and columns per version as JSON"
+    );
+    println!(
+        "    codegen <migrations_dir> <app_name> [--output <file>]\n                                                        Write Rust constants and row structs for the schema"
+    );
+    println!(
+        "    lint <migrations_dir> <app_name>                    Fail if migrations write nondeterministic data\n                                                        or break their transaction"
+    );
+    println!(
+        "    blame <migrations_dir> <app_name> [<object>]        Show the migrations which created schema objects"
+    );
+    println!(
+        "    changelog <migrations_dir> <app_name> [--since <version>] [--format markdown|json] [--output <file>]\n                                                        Write release notes of schema changes"
+    );
+    println!(
+        "    squash <migrations_dir> <app_name> <through_version> [--output <file>]\n                                                        Collapse migrations 1 to <through_version> into a baseline"
+    );
+    println!(
+        "    make-migration <migrations_dir> <app_name> --target <schema.sql> [--output <file>]\n                                                        Write a migration to the schema in a file"
+    );
+    println!(
+        "    dump-schema <sqlite_url> [--output <file>]          Write the database's schema as SQL"
+    );
+    println!(
+        "    history <sqlite_url> [--name <app_name>] [--format table|json]\n                                                        Show the migrations applied to a database"
+    );
+    println!(
+        "    compare <sqlite_url> <other_sqlite_url> [--table <name>]...\n                                                        Fail unless both databases hold the same data"
+    );
+    println!(
+        "    advise-indexes <migrations_dir> <app_name> <sqlite_url> [--workload <file>] [--output <file>]\n                                                        Flag indexes which may be worth dropping"
+    );
+    #[cfg(feature = "arrow")]
+    println!(
+        "    export <migrations_dir> <app_name> <sqlite_url> --format <parquet|arrow> --output-dir <dir> [--table <name>]...\n                                                        Migrate, then export tables to Parquet or Arrow files"
+    );
+    println!(
+        "    completions <bash|zsh|fish>                         Write a shell completion script"
+    );
+    println!(
+        "    batch <file|->                                      Run commands read line by line, printing NDJSON"
+    );
+    #[cfg(feature = "tui")]
+    println!(
+        "    tui <sqlite_url> [<migrations_dir> <app_name>]      Browse the history, schema and pending migrations"
+    );
+    #[cfg(unix)]
+    println!(
+        "    serve --socket <path>                               Serve JSON-RPC requests on a Unix socket"
+    );
+    println!("    help                                                Show this help message");
+    println!();
+    println!("OPTIONS:");
+    println!("    --config <file>            Read arguments from <file> instead of ./monarch.toml");
+    println!("    --migrations-dir <dir>     Use <dir> instead of the configured <migrations_dir>");
+    println!("    --app-name <name>          Use <name> instead of the configured <app_name>");
+    println!("    --database <url>           Use <url> instead of the configured <sqlite_url>");
+    println!();
+    println!("ARGS:");
+    println!("    <migrations_dir>    Path to directory containing migration files");
+    println!("    <app_name>          Name of the application (used for version tracking)");
+    println!("    <sqlite_url>        SQLite database URL (file path or ':memory:')");
+    println!();
+    println!("    These may be left out when set in monarch.toml or given by the options above.");
+    println!();
+    println!("EXAMPLES:");
+    println!("    {program_name} init ./migrations my_app");
+    println!("    {program_name} migrate ./migrations my_app ./database.db");
+    println!("    {program_name} migrate ./migrations my_app ./database.db --yes");
+    println!("    {program_name} migrate --database ./staging.db");
+    println!("    {program_name} version ./migrations my_app ./database.db");
+    println!("    {program_name} version ./migrations my_app ./staging.db --exit-code");
+    println!("    {program_name} watch ./migrations my_app ./dev.db");
+    println!("    {program_name} migrate ./migrations my_app :memory:");
+    println!("    {program_name} migrate ./migrations my_app ./database.db --step 1");
+    println!("    {program_name} check ./migrations my_app ./database.db --format json");
+    println!("    {program_name} adopt ./migrations my_app ./database.db --from sqlx");
+    println!("    {program_name} export-metadata ./migrations my_app --output schema.json");
+    println!("    {program_name} codegen ./migrations my_app --output src/schema.rs");
+    println!("    {program_name} lint ./migrations my_app");
+    println!("    {program_name} blame ./migrations my_app users");
+    println!("    {program_name} changelog ./migrations my_app --since 12 --output CHANGES.md");
+    println!("    {program_name} squash ./migrations my_app 40 --output 000_baseline.sql");
+    println!(
+        "    {program_name} make-migration ./migrations my_app --target schema.sql --output 004_next.sql"
+    );
+    println!("    {program_name} dump-schema ./database.db --output schema.sql");
+    println!("    {program_name} history ./database.db --name my_app");
+    #[cfg(feature = "tui")]
+    println!("    {program_name} tui ./database.db ./migrations my_app");
+    println!("    {program_name} compare ./replica-a.db ./replica-b.db --table users");
+    println!(
+        "    {program_name} advise-indexes ./migrations my_app ./database.db --workload queries.sql"
+    );
+    println!("    {program_name} batch - < commands.txt");
+    println!("    {program_name} completions bash > /etc/bash_completion.d/monarch");
+}
+
+fn load_monarch(migrations_dir: &str, app_name: &str) -> CliResult<MonarchDB> {
+    let config = MonarchConfiguration {
+        name: app_name.to_string(),
+        enable_foreign_keys: true,
+        migration_directory: Utf8PathBuf::from(migrations_dir),
+        ..Default::default()
+    };
+
+    Ok(MonarchDB::from_configuration(config)?)
+}
+
+#[cfg(feature = "arrow")]
+fn connection_configuration(sqlite_url: &str) -> monarch_db::ConnectionConfiguration {
+    let database = if sqlite_url == ":memory:" {
+        monarch_db::Database::Memory
+    } else {
+        monarch_db::Database::File(Utf8PathBuf::from(sqlite_url))
+    };
+    monarch_db::ConnectionConfiguration {
+        database,
+        init_sql: config::pragmas().to_vec(),
+        ..Default::default()
+    }
+}
+
+/// How far the `migrate` command migrates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MigrateTarget {
+    /// Apply every pending migration.
+    Latest,
+    /// Apply migrations up to this version.
+    Version(u32),
+    /// Apply this many pending migrations.
+    Step(usize),
+}
+
+/// Parses the optional `--to <version>` or `--step <count>` flags of the `migrate` command.
+fn parse_migrate_target(flags: &[&str]) -> CliResult<MigrateTarget> {
+    match flags {
+        [] => Ok(MigrateTarget::Latest),
+        ["--to", version] => Ok(MigrateTarget::Version(
+            version
+                .parse()
+                .map_err(|_| format!("invalid version: {version}"))?,
+        )),
+        ["--step", count] => match count.parse() {
+            Ok(count) if count > 0 => Ok(MigrateTarget::Step(count)),
+            _ => Err(format!("invalid step count: {count}").into()),
+        },
+        _ => Err("expected --to <version> or --step <count>".into()),
+    }
+}
+
+/// The outcome of running migrations against a database.
+struct MigrateOutcome {
+    available_migrations: u32,
+    from_version: u32,
+    version: u32,
+    skipped: Vec<String>,
+}
+
+impl MigrateOutcome {
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "available_migrations": self.available_migrations,
+            "from_version": self.from_version,
+            "version": self.version,
+            "skipped": self.skipped,
+            "up_to_date": self.version == self.available_migrations,
+        })
+    }
+}
+
+/// Opens a database which will be migrated, running the configured pragmas.
+fn open_for_migrating(sqlite_url: &str) -> CliResult<rusqlite::Connection> {
+    let connection = if sqlite_url == ":memory:" {
+        rusqlite::Connection::open_in_memory()?
+    } else {
+        rusqlite::Connection::open(sqlite_url)?
+    };
+    for pragma in config::pragmas() {
+        connection.execute_batch(pragma)?;
+    }
+    Ok(connection)
+}
+
+fn run_migrations(
+    migrations_dir: &str,
+    app_name: &str,
+    sqlite_url: &str,
+    target: MigrateTarget,
+) -> CliResult<MigrateOutcome> {
+    let mut monarch_db = load_monarch(migrations_dir, app_name)?;
+    if let Some(path) = config::schema_file() {
+        monarch_db = monarch_db.with_schema_file(path);
+    }
+    let available_migrations = monarch_db.current_version();
+
+    let mut connection = open_for_migrating(sqlite_url)?;
+    let target = match target {
+        MigrateTarget::Latest => available_migrations,
+        MigrateTarget::Version(version) => version,
+        MigrateTarget::Step(count) => {
+            let plan = monarch_db.plan(&connection)?;
+            plan.migrations
+                .get(count - 1)
+                .map_or(plan.to_version, |migration| migration.version)
+        }
+    };
+    let report = monarch_db.migrate_to(&mut connection, target)?;
+
+    Ok(MigrateOutcome {
+        available_migrations,
+        from_version: report.from_version,
+        version: report.to_version,
+        skipped: report.skipped,
+    })
+}
+
+fn migrate_command(
+    migrations_dir: &str,
+    app_name: &str,
+    sqlite_url: &str,
+    target: MigrateTarget,
+    yes: bool,
+) -> CliResult<()> {
+    if !yes
+        && sqlite_url != ":memory:"
+        && !confirm_migrate(migrations_dir, app_name, sqlite_url, target)?
+    {
+        return Err(
+            "migrations were not confirmed; pass --yes to apply them without asking".into(),
+        );
+    }
+
+    println!("Running migrations...");
+    println!("  Migrations directory: {migrations_dir}");
+    println!("  Application name: {app_name}");
+    println!("  Database: {sqlite_url}");
+    println!();
+
+    let outcome = run_migrations(migrations_dir, app_name, sqlite_url, target)?;
+    let final_version = outcome.version;
+
+    println!("Found {} migration(s)", outcome.available_migrations);
+    println!("Migration completed successfully!");
+    println!("Current schema version: {final_version}");
+
+    if final_version > outcome.from_version {
+        println!(
+            "Applied {} new migration(s)",
+            final_version - outcome.from_version
+        );
+    }
+    for name in &outcome.skipped {
+        println!("Warning: best-effort migration {name} failed and was skipped");
+    }
+    if final_version >= outcome.available_migrations {
+        println!("Database is up to date.");
+    } else {
+        println!(
+            "{} version(s) remain pending.",
+            outcome.available_migrations - final_version
+        );
+    }
+
+    Ok(())
+}
+
+/// Lists the migrations the `migrate` command would apply and asks whether to apply them,
+/// returning whether they were confirmed. Nothing is asked when nothing is pending.
+///
+/// Anything but `y` or `yes` declines, including the end of input, so a script which does not pass
+/// `--yes` stops rather than migrating unattended.
+fn confirm_migrate(
+    migrations_dir: &str,
+    app_name: &str,
+    sqlite_url: &str,
+    target: MigrateTarget,
+) -> CliResult<bool> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let connection = if std::path::Path::new(sqlite_url).exists() {
+        open_read_only(sqlite_url)?
+    } else {
+        rusqlite::Connection::open_in_memory()?
+    };
+    let mut plan = monarch_db.plan(&connection)?;
+    match target {
+        MigrateTarget::Latest => {}
+        MigrateTarget::Version(version) => plan.migrations.retain(|m| m.version <= version),
+        MigrateTarget::Step(count) => plan.migrations.truncate(count),
+    }
+    if plan.is_empty() {
+        return Ok(true);
+    }
+
+    println!(
+        "{} migration(s) and {} repeatable migration(s) will run for {app_name} on {sqlite_url}:",
+        plan.migrations.len(),
+        plan.repeatable.len()
+    );
+    for migration in &plan.migrations {
+        let note = if migration.skipped {
+            " (skipped in this environment)"
+        } else if migration.destructive {
+            " (destructive: drops tables or columns, or deletes rows)"
+        } else {
+            ""
+        };
+        match &migration.description {
+            Some(description) => println!(
+                "  {} -> version {}: {description}{note}",
+                migration.name, migration.version
+            ),
+            None => println!(
+                "  {} -> version {}{note}",
+                migration.name, migration.version
+            ),
+        }
+    }
+    for name in &plan.repeatable {
+        println!("  {name} (repeatable)");
+    }
+    print!("Apply them? [y/N] ");
+    io::Write::flush(&mut io::stdout())?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    println!();
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// How often the `watch` command checks the migration directory for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Applies pending migrations each time a file in the migration directory changes, until the
+/// process is interrupted.
+///
+/// The database stays open between changes, so `:memory:` can be watched too. A migration which
+/// changes after it was applied is reported rather than applied again.
+fn watch_command(migrations_dir: &str, app_name: &str, sqlite_url: &str) -> CliResult<()> {
+    let mut connection = open_for_migrating(sqlite_url)?;
+    println!(
+        "Watching {migrations_dir} for changes to apply to {sqlite_url}, press Ctrl-C to stop"
+    );
+
+    let mut last = None;
+    loop {
+        let fingerprint = directory_fingerprint(migrations_dir);
+        if last.as_ref() != Some(&fingerprint) {
+            last = Some(fingerprint);
+            match watch_migrate(&mut connection, migrations_dir, app_name) {
+                Ok(report) => {
+                    if report.applied() > 0 {
+                        println!(
+                            "Applied {} migration(s), now at version {}",
+                            report.applied(),
+                            report.to_version
+                        );
+                    } else {
+                        println!("Up to date at version {}", report.to_version);
+                    }
+                    for name in &report.repeatable {
+                        println!("Ran repeatable migration {name}");
+                    }
+                    for name in &report.skipped {
+                        println!("Warning: best-effort migration {name} failed and was skipped");
+                    }
+                }
+                Err(error) => println!("Error: {error}"),
+            }
+        }
+        std::thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Loads the migrations and applies those which are pending, failing if an applied migration
+/// changed.
+fn watch_migrate(
+    connection: &mut rusqlite::Connection,
+    migrations_dir: &str,
+    app_name: &str,
+) -> CliResult<MigrationReport> {
+    let monarch_db =
+        load_monarch(migrations_dir, app_name)?.with_checksum_policy(ChecksumPolicy::Error);
+    Ok(monarch_db.migrate_to(connection, monarch_db.current_version())?)
+}
+
+/// The names, sizes and modification times of the files in `directory`, which change whenever
+/// a migration is added, edited or removed. Unreadable entries are left out, and an unreadable
+/// directory has an empty fingerprint.
+fn directory_fingerprint(directory: &str) -> BTreeMap<String, (u64, Option<SystemTime>)> {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return BTreeMap::new();
+    };
+    entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok()?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            Some((name, (metadata.len(), metadata.modified().ok())))
+        })
+        .collect()
+}
+
+/// The schema version recorded in a database, as seen by the `version` command.
+enum RecordedVersion {
+    /// The database records this version for the application.
+    Version(u32),
+    /// The database has no version recorded for the application.
+    Uninitialized,
+    /// The database could not be opened.
+    Unavailable(String),
+}
+
+/// The outcome of comparing a database's version with the available migrations.
+struct VersionOutcome {
+    available_migrations: u32,
+    recorded: RecordedVersion,
+}
+
+impl VersionOutcome {
+    fn to_json(&self) -> serde_json::Value {
+        let (current_version, status, error) = match &self.recorded {
+            RecordedVersion::Version(version) if *version < self.available_migrations => {
+                (Some(*version), "pending", None)
+            }
+            RecordedVersion::Version(version) if *version == self.available_migrations => {
+                (Some(*version), "up_to_date", None)
+            }
+            RecordedVersion::Version(version) => (Some(*version), "ahead", None),
+            RecordedVersion::Uninitialized => (None, "uninitialized", None),
+            RecordedVersion::Unavailable(error) => (None, "unavailable", Some(error)),
+        };
+        json!({
+            "available_migrations": self.available_migrations,
+            "current_version": current_version,
+            "status": status,
+            "error": error,
+        })
+    }
+
+    /// Whether fewer migrations have been applied than are available.
+    fn is_pending(&self) -> bool {
+        match self.recorded {
+            RecordedVersion::Version(version) => version < self.available_migrations,
+            RecordedVersion::Uninitialized | RecordedVersion::Unavailable(_) => {
+                self.available_migrations > 0
+            }
+        }
+    }
+
+    fn is_up_to_date(&self) -> bool {
+        matches!(self.recorded, RecordedVersion::Version(version) if version == self.available_migrations)
+    }
+}
+
+fn check_version(
+    migrations_dir: &str,
+    app_name: &str,
+    sqlite_url: &str,
+) -> CliResult<VersionOutcome> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let available_migrations = monarch_db.current_version();
+
+    // Open the database read-only, so checking it never applies the pending migrations
+    let connection = match open_read_only(sqlite_url) {
+        Ok(conn) => conn,
+        Err(e) => {
+            return Ok(VersionOutcome {
+                available_migrations,
+                recorded: RecordedVersion::Unavailable(e.to_string()),
+            });
+        }
+    };
+
+    // Query current version
+    let mut stmt = connection
+        .prepare("SELECT version FROM monarch_db_schema_version WHERE monarch_schema = ?1")?;
+    let recorded = match stmt.query_row([app_name], |row| row.get(0)) {
+        Ok(version) => RecordedVersion::Version(version),
+        Err(_) => RecordedVersion::Uninitialized,
+    };
+
+    Ok(VersionOutcome {
+        available_migrations,
+        recorded,
+    })
+}
+
+/// The outcome of checking a database against the migrations, without changing it.
+struct CheckOutcome {
+    version: VersionOutcome,
+    /// Schema differences, or `None` if the database could not be opened.
+    drift: Option<SchemaDiff>,
+    /// The configured schema file and whether it is stale, see [`config::schema_file`].
+    schema_file: Option<(&'static str, bool)>,
+}
+
+impl CheckOutcome {
+    /// Whether the database is at the latest version with no schema drift, and the schema file,
+    /// if any, is up to date.
+    fn is_consistent(&self) -> bool {
+        self.version.is_up_to_date()
+            && self.drift.as_ref().is_some_and(SchemaDiff::is_empty)
+            && !self.schema_file.is_some_and(|(_, stale)| stale)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let names = |objects: &[SchemaObject]| -> Vec<String> {
+            objects
+                .iter()
+                .map(|object| format!("{} {}", object.kind, object.name))
+                .collect()
+        };
+        let drift = self.drift.as_ref().map(|diff| {
+            json!({
+                "missing": names(&diff.missing),
+                "extra": names(&diff.extra),
+                "modified": diff
+                    .modified
+                    .iter()
+                    .map(|object| json!({
+                        "object": format!("{} {}", object.kind, object.name),
+                        "expected": object.expected,
+                        "actual": object.actual,
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        });
+
+        let mut value = self.version.to_json();
+        value["drift"] = drift.unwrap_or(serde_json::Value::Null);
+        value["schema_file"] = self.schema_file.map_or(
+            serde_json::Value::Null,
+            |(path, stale)| json!({ "path": path, "stale": stale }),
+        );
+        value["consistent"] = self.is_consistent().into();
+        value
+    }
+}
+
+/// Compares a database's recorded version and schema with the migrations, opening it read-only.
+fn check_database(
+    migrations_dir: &str,
+    app_name: &str,
+    sqlite_url: &str,
+) -> CliResult<CheckOutcome> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let available_migrations = monarch_db.current_version();
+    let schema_file = match config::schema_file() {
+        Some(path) => Some((path, monarch_db.schema_file_is_stale(path)?)),
+        None => None,
+    };
+
+    let connection = match open_read_only(sqlite_url) {
+        Ok(connection) => connection,
+        Err(e) => {
+            return Ok(CheckOutcome {
+                version: VersionOutcome {
+                    available_migrations,
+                    recorded: RecordedVersion::Unavailable(e.to_string()),
+                },
+                drift: None,
+                schema_file,
+            });
+        }
+    };
+
+    let recorded = match connection.query_row(
+        "SELECT version FROM monarch_db_schema_version WHERE monarch_schema = ?1",
+        [app_name],
+        |row| row.get(0),
+    ) {
+        Ok(version) => RecordedVersion::Version(version),
+        Err(_) => RecordedVersion::Uninitialized,
+    };
+    let drift = monarch_db.diff(&connection)?;
+
+    Ok(CheckOutcome {
+        version: VersionOutcome {
+            available_migrations,
+            recorded,
+        },
+        drift: Some(drift),
+        schema_file,
+    })
+}
+
+/// Prints whether a database is consistent with the migrations, returning the verdict.
+fn check_command(
+    migrations_dir: &str,
+    app_name: &str,
+    sqlite_url: &str,
+    json: bool,
+) -> CliResult<bool> {
+    let outcome = check_database(migrations_dir, app_name, sqlite_url)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&outcome.to_json())?);
+        return Ok(outcome.is_consistent());
+    }
+
+    let available_migrations = outcome.version.available_migrations;
+    match &outcome.version.recorded {
+        RecordedVersion::Version(version) => {
+            println!("Schema version: {version} of {available_migrations}")
+        }
+        RecordedVersion::Uninitialized => {
+            println!("Schema version: not initialized ({available_migrations} available)")
+        }
+        RecordedVersion::Unavailable(e) => println!("Database unavailable: {e}"),
+    }
+    match &outcome.drift {
+        Some(diff) if diff.is_empty() => println!("Schema drift: none"),
+        Some(diff) => print!("Schema drift:\n{diff}"),
+        None => {}
+    }
+    match outcome.schema_file {
+        Some((path, true)) => {
+            println!("Schema file: {path} is stale, migrate a database to refresh it")
+        }
+        Some((path, false)) => println!("Schema file: {path} is up to date"),
+        None => {}
+    }
+
+    if outcome.is_consistent() {
+        println!("OK: database is consistent with migrations");
+    } else {
+        println!("FAILED: database is not consistent with migrations");
+    }
+    Ok(outcome.is_consistent())
+}
+
+/// Prints the version of a database and the migrations pending for it, returning whether any
+/// are.
+fn version_command(migrations_dir: &str, app_name: &str, sqlite_url: &str) -> CliResult<bool> {
+    println!("Checking migration version...");
+    println!("  Migrations directory: {migrations_dir}");
+    println!("  Application name: {app_name}");
+    println!("  Database: {sqlite_url}");
+    println!();
+
+    let outcome = check_version(migrations_dir, app_name, sqlite_url)?;
+    let available_migrations = outcome.available_migrations;
+
+    let pending = outcome.is_pending();
+    println!("Available migrations: {available_migrations}");
+
+    match outcome.recorded {
+        RecordedVersion::Version(version) => {
+            println!("Current schema version: {version}");
+            if version < available_migrations {
+                println!(
+                    "Migrations pending: {} -> {} ({} new migration(s))",
+                    version,
+                    available_migrations,
+                    available_migrations - version
+                );
+            } else if version == available_migrations {
+                println!("Database is up to date.");
+            } else {
+                println!(
+                    "Warning: Current version ({version}) is higher than available migrations ({available_migrations})"
+                );
+            }
+        }
+        RecordedVersion::Uninitialized => {
+            println!("Current schema version: 0 (schema not initialized for this app)");
+            if available_migrations > 0 {
+                println!(
+                    "Migrations pending: 0 -> {available_migrations} ({available_migrations} new migration(s))"
+                );
+            }
+        }
+        RecordedVersion::Unavailable(e) => {
+            eprintln!("Failed to connect to database: {e}");
+            println!("Current schema version: 0 (database not initialized)");
+        }
+    }
+
+    Ok(pending)
+}
+
+/// Records the migrations another tool applied to a database as applied by Monarch.
+fn adopt_command(
+    migrations_dir: &str,
+    app_name: &str,
+    sqlite_url: &str,
+    tool: MigrationTool,
+) -> CliResult<()> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let connection = rusqlite::Connection::open(sqlite_url)?;
+    let version = monarch_db.adopt_history(&connection, tool)?;
+    println!("Adopted {tool} history of {app_name} at schema version {version}");
+    let pending = monarch_db.current_version() - version;
+    if pending > 0 {
+        println!("{pending} migration(s) remain pending.");
+    }
+    Ok(())
+}
+
+fn export_metadata_command(
+    migrations_dir: &str,
+    app_name: &str,
+    output: Option<&str>,
+) -> CliResult<()> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let versions = monarch_db.schema_metadata()?;
+
+    let document = json!({
+        "name": app_name,
+        "current_version": monarch_db.current_version(),
+        "versions": versions.iter().map(version_json).collect::<Vec<_>>(),
+    });
+    let rendered = serde_json::to_string_pretty(&document)?;
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, rendered + "\n")?;
+            eprintln!("Wrote metadata for {} version(s) to {path}", versions.len());
+        }
+        None => println!("{rendered}"),
+    }
+
+    Ok(())
+}
+
+/// The first migration of a new project.
+const STARTER_MIGRATION: &str = "\
+-- Create the initial schema.
+--
+-- Migrations run once each, in order of the number their file names start with. Once this
+-- migration has been applied, add further changes in new files, such as 002_add_posts.sql,
+-- rather than editing it.
+
+-- CREATE TABLE users (
+--     id INTEGER PRIMARY KEY,
+--     name TEXT NOT NULL
+-- );
+";
+
+/// Creates a migrations directory with a first migration, and a `monarch.toml` naming it.
+///
+/// Nothing is written if the configuration file or the first migration already exists.
+fn init_command(migrations_dir: &str, app_name: &str, database: Option<&str>) -> CliResult<()> {
+    let migration = Utf8PathBuf::from(migrations_dir).join("001_init.sql");
+    for path in [config::DEFAULT_PATH, migration.as_str()] {
+        if Utf8PathBuf::from(path).exists() {
+            return Err(format!("{path} already exists").into());
+        }
+    }
+
+    let database = database.map_or_else(|| format!("./{app_name}.db"), str::to_owned);
+    std::fs::create_dir_all(migrations_dir)?;
+    std::fs::write(&migration, STARTER_MIGRATION)?;
+    std::fs::write(
+        config::DEFAULT_PATH,
+        config::starter(migrations_dir, app_name, &database),
+    )?;
+
+    println!("Created {migration}");
+    println!("Created {}", config::DEFAULT_PATH);
+    println!();
+    println!("Write the first migration in {migration}, then apply it with `monarch migrate`.");
+    Ok(())
+}
+
+/// Writes Rust definitions of the tables and columns the migrations create.
+fn codegen_command(migrations_dir: &str, app_name: &str, output: Option<&str>) -> CliResult<()> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let code = monarch_db.rust_code()?;
+    match output {
+        Some(path) => {
+            std::fs::write(path, code)?;
+            eprintln!(
+                "Wrote definitions for schema version {} to {path}",
+                monarch_db.current_version()
+            );
+        }
+        None => print!("{code}"),
+    }
+    Ok(())
+}
+
+/// Prints nondeterministic data changes in the migrations, returning whether there were none.
+fn lint_command(migrations_dir: &str, app_name: &str) -> CliResult<bool> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let findings = monarch_db.lint_determinism()?;
+    let hazards = monarch_db.lint_transactions()?;
+    for finding in &findings {
+        println!("{finding}");
+    }
+    for hazard in &hazards {
+        println!("{hazard}");
+    }
+    if findings.is_empty() && hazards.is_empty() {
+        println!("Migrations are deterministic and safe to run in a transaction.");
+    }
+    Ok(findings.is_empty() && hazards.is_empty())
+}
+
+/// Prints the migrations which created and last changed each schema object, or only `object`.
+fn blame_command(migrations_dir: &str, app_name: &str, object: Option<&str>) -> CliResult<()> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let blame = monarch_db.schema_blame()?;
+
+    let Some(object) = object else {
+        for entry in &blame {
+            println!("{entry}");
+        }
+        return Ok(());
+    };
+    let entry = blame
+        .iter()
+        .find(|entry| entry.name == object)
+        .ok_or_else(|| {
+            format!("the migrations create no table, index, view or trigger named {object}")
+        })?;
+    let path = |name: &str| Utf8PathBuf::from(migrations_dir).join(name);
+    println!("{entry}");
+    println!("  created in: {}", path(&entry.created_by.name));
+    if entry.modified_by != entry.created_by {
+        println!("  changed in: {}", path(&entry.modified_by.name));
+    }
+    Ok(())
+}
+
+/// Writes the schema changes made by each migration after `since` as Markdown or JSON, to stdout
+/// or `output`.
+fn changelog_command(
+    migrations_dir: &str,
+    app_name: &str,
+    since: u32,
+    json: bool,
+    output: Option<&str>,
+) -> CliResult<()> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let changelog = monarch_db.changelog(since)?;
+
+    let rendered = if json {
+        let document = json!({
+            "name": app_name,
+            "since": since,
+            "versions": changelog.iter().map(changelog_json).collect::<Vec<_>>(),
+        });
+        serde_json::to_string_pretty(&document)? + "\n"
+    } else {
+        changelog_markdown(app_name, &changelog)
+    };
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, rendered)?;
+            eprintln!("Wrote changes for {} version(s) to {path}", changelog.len());
+        }
+        None => print!("{rendered}"),
+    }
+
+    Ok(())
+}
+
+fn changelog_json(entry: &ChangelogEntry) -> serde_json::Value {
+    let objects = |objects: &[SchemaObject]| -> Vec<serde_json::Value> {
+        objects
+            .iter()
+            .map(|object| json!({ "kind": object.kind, "name": object.name, "sql": object.sql }))
+            .collect()
+    };
+    let altered: Vec<_> = entry
+        .altered
+        .iter()
+        .map(|object| {
+            json!({
+                "kind": object.kind,
+                "name": object.name,
+                "before": object.before,
+                "after": object.after,
+            })
+        })
+        .collect();
+    json!({
+        "version": entry.version,
+        "name": entry.name,
+        "description": entry.description,
+        "created": objects(&entry.created),
+        "dropped": objects(&entry.dropped),
+        "altered": altered,
+    })
+}
+
+fn changelog_markdown(app_name: &str, changelog: &[ChangelogEntry]) -> String {
+    let mut markdown = format!("# Schema changes for {app_name}\n");
+    for entry in changelog {
+        markdown.push_str(&format!(
+            "\n## Version {}: {}\n\n",
+            entry.version, entry.name
+        ));
+        if let Some(description) = &entry.description {
+            markdown.push_str(&format!("{description}\n\n"));
+        }
+        let changes: Vec<String> = entry
+            .created
+            .iter()
+            .map(|object| format!("- Created {} `{}`", object.kind, object.name))
+            .chain(
+                entry
+                    .altered
+                    .iter()
+                    .map(|object| format!("- Altered {} `{}`", object.kind, object.name)),
+            )
+            .chain(
+                entry
+                    .dropped
+                    .iter()
+                    .map(|object| format!("- Dropped {} `{}`", object.kind, object.name)),
+            )
+            .collect();
+        if changes.is_empty() {
+            markdown.push_str("No schema changes.\n");
+        } else {
+            markdown.push_str(&(changes.join("\n") + "\n"));
+        }
+    }
+    markdown
+}
+
+/// Writes a baseline replacing migrations 1 to `through` to stdout or `output`.
+fn squash_command(
+    migrations_dir: &str,
+    app_name: &str,
+    through: u32,
+    output: Option<&str>,
+) -> CliResult<()> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let baseline = monarch_db.squash(1..=through)?;
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, baseline)?;
+            eprintln!(
+                "Wrote baseline for versions 1 to {through} to {path}; \
+                 move it into {migrations_dir} in place of the migrations it replaces"
+            );
+        }
+        None => print!("{baseline}"),
+    }
+
+    Ok(())
+}
+
+/// Writes a candidate migration from the schema the migrations produce to the schema in `target`,
+/// to stdout or `output`. Nothing is written when the schemas already match.
+fn make_migration_command(
+    migrations_dir: &str,
+    app_name: &str,
+    target: &str,
+    output: Option<&str>,
+) -> CliResult<()> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let target_schema = std::fs::read_to_string(target)
+        .map_err(|error| format!("reading target schema {target}: {error}"))?;
+    let Some(migration) = monarch_db.make_migration(&target_schema)? else {
+        eprintln!("The migrations in {migrations_dir} already produce the schema in {target}");
+        return Ok(());
+    };
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, migration)?;
+            eprintln!(
+                "Wrote a migration to the schema in {target} to {path}; \
+                 review it, then move it into {migrations_dir}"
+            );
+        }
+        None => print!("{migration}"),
+    }
+
+    Ok(())
+}
+
+fn version_json(version: &VersionMetadata) -> serde_json::Value {
+    let tables: Vec<_> = version
+        .tables
+        .iter()
+        .map(|table| {
+            let columns: Vec<_> = table
+                .columns
+                .iter()
+                .map(|column| {
+                    json!({
+                        "name": column.name,
+                        "type": column.declared_type,
+                        "not_null": column.not_null,
+                        "primary_key": column.primary_key,
+                        "default": column.default,
+                    })
+                })
+                .collect();
+            json!({ "name": table.name, "kind": table.kind, "columns": columns })
+        })
+        .collect();
+    json!({ "version": version.version, "tables": tables })
+}
+
+/// Opens an existing database read-only, without applying migrations.
+fn open_read_only(sqlite_url: &str) -> CliResult<rusqlite::Connection> {
+    if sqlite_url == ":memory:" {
+        return Ok(rusqlite::Connection::open_in_memory()?);
+    }
+    Ok(rusqlite::Connection::open_with_flags(
+        sqlite_url,
+        rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_URI,
+    )?)
+}
+
+/// Writes the schema DDL of an existing database to stdout or `output`.
+fn dump_schema_command(sqlite_url: &str, output: Option<&str>) -> CliResult<()> {
+    let connection = open_read_only(sqlite_url)?;
+    let sql = MonarchDB::schema_sql(&connection)?;
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, sql)?;
+            eprintln!("Wrote schema of {sqlite_url} to {path}");
+        }
+        None => print!("{sql}"),
+    }
+
+    Ok(())
+}
+
+/// Prints the migrations recorded as run against an existing database, for the application
+/// `name` or for every application with a history, as a table or as JSON.
+///
+/// The database is opened read-only, so no migration directory is needed.
+fn history_command(sqlite_url: &str, name: Option<&str>, json: bool) -> CliResult<()> {
+    let connection = open_read_only(sqlite_url)?;
+    let rows = recorded_history(&connection, name)?;
+
+    if json {
+        let entries: Vec<_> = rows
+            .iter()
+            .map(|(name, entry)| {
+                let error = match &entry.status {
+                    MigrationStatus::Applied => None,
+                    MigrationStatus::Skipped(error) => Some(error),
+                };
+                json!({
+                    "app_name": name,
+                    "version": entry.version,
+                    "name": entry.name,
+                    "description": entry.description,
+                    "checksum": entry.checksum,
+                    "status": match entry.status {
+                        MigrationStatus::Applied => "applied",
+                        MigrationStatus::Skipped(_) => "skipped",
+                    },
+                    "error": error,
+                    "applied_at": entry.applied_at,
+                    "duration_ms": entry.duration.map(|duration| duration.as_millis() as u64),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    if rows.is_empty() {
+        println!("No migrations recorded in {sqlite_url}");
+        return Ok(());
+    }
+    let mut table = vec![[
+        "APP".to_owned(),
+        "VERSION".to_owned(),
+        "NAME".to_owned(),
+        "APPLIED AT".to_owned(),
+        "DURATION".to_owned(),
+        "STATUS".to_owned(),
+    ]];
+    for (name, entry) in &rows {
+        table.push([
+            name.clone(),
+            entry.version.to_string(),
+            entry.name.clone(),
+            entry.applied_at.clone(),
+            entry.duration.map_or_else(
+                || "-".to_owned(),
+                |duration| format!("{}ms", duration.as_millis()),
+            ),
+            entry.status.to_string(),
+        ]);
+    }
+    let mut widths = [0; 6];
+    for row in &table {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in &table {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+    Ok(())
+}
+
+/// The history of the application `name`, or of every application with a history, in
+/// `connection`, with the name of the application of each entry.
+fn recorded_history(
+    connection: &rusqlite::Connection,
+    name: Option<&str>,
+) -> CliResult<Vec<(String, HistoryEntry)>> {
+    let names = match name {
+        Some(name) => vec![name.to_owned()],
+        None => recorded_schemas(connection)?,
+    };
+    let mut rows = Vec::new();
+    for name in names {
+        let monarch_db = MonarchDB::from_iter(name.clone(), true, Vec::new())?;
+        for entry in monarch_db.history(connection)? {
+            rows.push((name.clone(), entry));
+        }
+    }
+    Ok(rows)
+}
+
+/// The applications with migrations recorded in the history table of `connection`, in order of
+/// name. Empty if the database has no history table.
+fn recorded_schemas(connection: &rusqlite::Connection) -> CliResult<Vec<String>> {
+    let exists: bool = connection.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'monarch_db_migration_history')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Ok(Vec::new());
+    }
+    let mut stmt = connection.prepare(
+        "SELECT DISTINCT monarch_schema FROM monarch_db_migration_history ORDER BY monarch_schema",
+    )?;
+    let names = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(names)
+}
+
+/// Compares the content digests of two databases, returning whether their tables all match.
+///
+/// Both databases are opened read-only. Every table in either database is compared unless
+/// `tables` names some.
+fn compare_command(sqlite_url: &str, other_sqlite_url: &str, tables: &[&str]) -> CliResult<bool> {
+    let digests = |url: &str| -> CliResult<BTreeMap<String, TableDigest>> {
+        let connection = open_read_only(url)?;
+        Ok(MonarchDB::content_digest(&connection, tables)?
+            .into_iter()
+            .map(|digest| (digest.table.clone(), digest))
+            .collect())
+    };
+    let ours = digests(sqlite_url)?;
+    let mut theirs = digests(other_sqlite_url)?;
+
+    let mut identical = true;
+    for (table, digest) in ours {
+        match theirs.remove(&table) {
+            Some(other) if other.digest == digest.digest => {
+                println!("{table}: identical ({} rows)", digest.rows);
+            }
+            Some(other) => {
+                identical = false;
+                println!(
+                    "{table}: differs ({} rows in {sqlite_url}, {} rows in {other_sqlite_url})",
+                    digest.rows, other.rows
+                );
+            }
+            None => {
+                identical = false;
+                println!("{table}: only in {sqlite_url}");
+            }
+        }
+    }
+    for table in theirs.keys() {
+        identical = false;
+        println!("{table}: only in {other_sqlite_url}");
+    }
+    Ok(identical)
+}
+
+/// Reports indexes which may be worth dropping, optionally writing a migration which drops them.
+///
+/// The database is opened read-only and is not migrated. The workload file holds the queries the
+/// application runs, separated by semicolons.
+fn advise_indexes_command(
+    migrations_dir: &str,
+    app_name: &str,
+    sqlite_url: &str,
+    workload: Option<&str>,
+    output: Option<&str>,
+) -> CliResult<()> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let connection = open_read_only(sqlite_url)?;
+
+    let workload = match workload {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => String::new(),
+    };
+    let queries: Vec<&str> = workload
+        .split(';')
+        .map(str::trim)
+        .filter(|query| !query.is_empty())
+        .collect();
+
+    let advice = monarch_db.advise_indexes(&connection, &queries)?;
+    if advice.is_empty() {
+        println!("No indexes flagged.");
+        return Ok(());
+    }
+    for item in &advice {
+        println!("{} on {}: {}", item.index, item.table, item.reason);
+    }
+
+    if let Some(path) = output {
+        let mut migration = String::from("-- Generated by `monarch advise-indexes`.\n");
+        for item in &advice {
+            migration.push_str(&format!("\n-- {}\n{}\n", item.reason, item.drop_sql()));
+        }
+        std::fs::write(path, migration)?;
+        eprintln!(
+            "Wrote migration dropping {} index(es) to {path}",
+            advice.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Migrates a database, then writes each table to `<output_dir>/<table>.<format>`.
+///
+/// All tables other than SQLite's and Monarch's own are exported unless `tables` names some.
+#[cfg(feature = "arrow")]
+fn export_command(
+    migrations_dir: &str,
+    app_name: &str,
+    sqlite_url: &str,
+    format: monarch_db::ExportFormat,
+    output_dir: &str,
+    tables: &[&str],
+) -> CliResult<()> {
+    let monarch_db = load_monarch(migrations_dir, app_name)?;
+    let connection = monarch_db.create_connection(&connection_configuration(sqlite_url))?;
+
+    let tables: Vec<String> = if tables.is_empty() {
+        let mut stmt = connection.prepare(
+            "SELECT name FROM sqlite_master \
+             WHERE type = 'table' AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' \
+               AND name NOT LIKE 'monarch\\_db\\_%' ESCAPE '\\' \
+             ORDER BY name",
+        )?;
+        stmt.query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?
+    } else {
+        tables.iter().map(|table| table.to_string()).collect()
+    };
+
+    std::fs::create_dir_all(output_dir)?;
+    for table in &tables {
+        let path = Utf8PathBuf::from(output_dir).join(format!("{table}.{}", format.extension()));
+        let mut file = io::BufWriter::new(std::fs::File::create(&path)?);
+        let rows = monarch_db::TableExport::new(table, format).run(&connection, &mut file)?;
+        io::Write::flush(&mut file)?;
+        println!("Exported {rows} row(s) from {table} to {path}");
+    }
+
+    Ok(())
+}
+
+/// Runs newline-delimited commands from `source` (a file path, or `-` for stdin).
+///
+/// Each non-empty line not starting with `#` is split on whitespace into a command and its
+/// arguments, using the same argument order as the corresponding subcommand. One JSON object is
+/// printed per command. A failing command does not stop the batch; the return value reports
+/// whether every command succeeded.
+fn batch_command(source: &str) -> CliResult<bool> {
+    let reader: Box<dyn BufRead> = if source == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(io::BufReader::new(std::fs::File::open(source)?))
+    };
+
+    let mut all_ok = true;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() || words[0].starts_with('#') {
+            continue;
+        }
+
+        let record = match batch_dispatch(&words) {
+            // A check which ran but found problems fails the batch, like a failed command.
+            Ok(result) if result["consistent"] == false => {
+                all_ok = false;
+                json!({
+                    "line": index + 1,
+                    "command": words[0],
+                    "ok": false,
+                    "error": "database is not consistent with migrations",
+                    "result": result,
+                })
+            }
+            Ok(result) => json!({
+                "line": index + 1,
+                "command": words[0],
+                "ok": true,
+                "result": result,
+            }),
+            Err(e) => {
+                all_ok = false;
+                json!({
+                    "line": index + 1,
+                    "command": words[0],
+                    "ok": false,
+                    "error": e.to_string(),
+                })
+            }
+        };
+        println!("{record}");
+    }
+
+    Ok(all_ok)
+}
+
+fn batch_dispatch(words: &[&str]) -> CliResult<serde_json::Value> {
+    match words {
+        ["migrate", migrations_dir, app_name, sqlite_url, flags @ ..] => {
+            let target = parse_migrate_target(flags)?;
+            Ok(run_migrations(migrations_dir, app_name, sqlite_url, target)?.to_json())
+        }
+        ["version", migrations_dir, app_name, sqlite_url] => {
+            Ok(check_version(migrations_dir, app_name, sqlite_url)?.to_json())
+        }
+        ["check", migrations_dir, app_name, sqlite_url] => {
+            Ok(check_database(migrations_dir, app_name, sqlite_url)?.to_json())
+        }
+        ["migrate" | "version" | "check", ..] => Err(format!(
+            "usage: {} <migrations_dir> <app_name> <sqlite_url>",
+            words[0]
+        )
+        .into()),
+        [command, ..] => Err(format!("unsupported batch command: {command}").into()),
+        [] => unreachable!("blank lines are skipped"),
+    }
+}
@@ -0,0 +1,229 @@
+//! A resident control process speaking JSON-RPC over a Unix socket.
+//!
+//! Each connection may send any number of requests, one JSON object per line, and receives one
+//! response per line:
+//!
+//! ```text
+//! {"jsonrpc":"2.0","id":1,"method":"migrate","params":{"migrations_dir":"./migrations","app_name":"my_app","database":"./app.db"}}
+//! {"jsonrpc":"2.0","id":1,"result":{"available_migrations":3,"from_version":0,"up_to_date":true,"version":3}}
+//! ```
+//!
+//! Access control relies on the socket's file permissions: the socket is bound inside a private
+//! directory and made readable and writable by its owner only before it is moved into place.
+
+use std::fs::DirBuilder;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use serde_json::{Value, json};
+
+use crate::{CliResult, MigrateTarget, check_database, check_version, run_migrations};
+
+/// JSON-RPC error code for a request which is not valid JSON.
+const PARSE_ERROR: i64 = -32700;
+/// JSON-RPC error code for a request which is not a valid request object.
+const INVALID_REQUEST: i64 = -32600;
+/// JSON-RPC error code for an unknown method.
+const METHOD_NOT_FOUND: i64 = -32601;
+/// JSON-RPC error code for missing or malformed parameters.
+const INVALID_PARAMS: i64 = -32602;
+/// JSON-RPC error code for a method which failed while running.
+const SERVER_ERROR: i64 = -32000;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+pub(crate) fn serve_command(socket: &str) -> CliResult<()> {
+    let path = Path::new(socket);
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!("socket {socket} is already in use").into());
+        }
+        // Nothing is listening, so this is left over from a previous run.
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = bind_private(path)?;
+    eprintln!("Listening on {socket}");
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = handle_client(stream) {
+                    eprintln!("Client error: {e}");
+                }
+            }
+            Err(e) => eprintln!("Failed to accept connection: {e}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Binds the socket inside a fresh directory only the owner can enter, restricts the socket to
+/// its owner, and only then moves it to `path`, so no other user can connect in between.
+fn bind_private(path: &Path) -> CliResult<UnixListener> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| format!("socket {} has no file name", path.display()))?;
+    let mut staging_name = std::ffi::OsString::from(".");
+    staging_name.push(name);
+    staging_name.push(format!(".{}", std::process::id()));
+    let staging = path.with_file_name(staging_name);
+
+    // Creating the directory fails if it already exists, so nobody else can have prepared it.
+    DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join(name);
+    let result = UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&staged);
+    }
+    std::fs::remove_dir(&staging)?;
+    Ok(result?)
+}
+
+fn handle_client(stream: UnixStream) -> CliResult<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = respond(&line);
+        writeln!(writer, "{response}")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn respond(line: &str) -> Value {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string())),
+    };
+
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        return error_response(id, RpcError::new(INVALID_REQUEST, "missing method"));
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+    match dispatch(method, &params) {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_response(id, error),
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+fn dispatch(method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+        "status" => {
+            let outcome = check_version(
+                param(params, "migrations_dir")?,
+                param(params, "app_name")?,
+                param(params, "database")?,
+            )
+            .map_err(server_error)?;
+            Ok(outcome.to_json())
+        }
+        "check" => {
+            let outcome = check_database(
+                param(params, "migrations_dir")?,
+                param(params, "app_name")?,
+                param(params, "database")?,
+            )
+            .map_err(server_error)?;
+            Ok(outcome.to_json())
+        }
+        "migrate" => {
+            let outcome = run_migrations(
+                param(params, "migrations_dir")?,
+                param(params, "app_name")?,
+                param(params, "database")?,
+                migrate_target(params)?,
+            )
+            .map_err(server_error)?;
+            Ok(outcome.to_json())
+        }
+        "backup" => {
+            let destination = param(params, "destination")?;
+            backup(param(params, "database")?, destination).map_err(server_error)?;
+            Ok(json!({ "destination": destination }))
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method: {method}"),
+        )),
+    }
+}
+
+fn param<'p>(params: &'p Value, name: &str) -> Result<&'p str, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("missing string param: {name}")))
+}
+
+/// The target of a `migrate` request, from its optional integer `to` or `step` param.
+fn migrate_target(params: &Value) -> Result<MigrateTarget, RpcError> {
+    let integer = |name: &str| match params.get(name) {
+        None => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("invalid integer param: {name}"))),
+    };
+    match (integer("to")?, integer("step")?) {
+        (None, None) => Ok(MigrateTarget::Latest),
+        (Some(version), None) => u32::try_from(version)
+            .map(MigrateTarget::Version)
+            .map_err(|_| RpcError::new(INVALID_PARAMS, "invalid integer param: to")),
+        (None, Some(count)) if count > 0 => Ok(MigrateTarget::Step(count as usize)),
+        (None, Some(_)) => Err(RpcError::new(INVALID_PARAMS, "invalid integer param: step")),
+        (Some(_), Some(_)) => Err(RpcError::new(
+            INVALID_PARAMS,
+            "params to and step are mutually exclusive",
+        )),
+    }
+}
+
+fn server_error(error: Box<dyn std::error::Error>) -> RpcError {
+    RpcError::new(SERVER_ERROR, error.to_string())
+}
+
+/// Writes a consistent copy of `database` to `destination` with `VACUUM INTO`.
+fn backup(database: &str, destination: &str) -> CliResult<()> {
+    if Path::new(destination).exists() {
+        return Err(format!("backup destination {destination} already exists").into());
+    }
+    let connection = rusqlite::Connection::open_with_flags(
+        database,
+        rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_URI,
+    )?;
+    connection.execute("VACUUM INTO ?1", [destination])?;
+    Ok(())
+}
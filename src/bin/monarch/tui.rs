@@ -0,0 +1,333 @@
+//! A read-only terminal UI for `monarch tui`, to browse a database's migrations and schema.
+//!
+//! Three tabs list the migration history of every application recorded in the database, the
+//! tables, indexes, views and triggers of its schema, and, given a migration directory and an
+//! application name, the migrations which have yet to run. The database is opened read-only and
+//! read once when the UI starts, so exploring it never changes it.
+
+use monarch_db::{HistoryEntry, MigrationPlan, MigrationStatus, MonarchDB, SchemaObject};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState, Tabs, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::{CliResult, load_monarch, open_read_only, recorded_history};
+
+/// A tab of the explorer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tab {
+    History,
+    Schema,
+    Pending,
+}
+
+impl Tab {
+    const ALL: [Tab; 3] = [Tab::History, Tab::Schema, Tab::Pending];
+
+    fn title(self) -> &'static str {
+        match self {
+            Tab::History => "1 History",
+            Tab::Schema => "2 Schema",
+            Tab::Pending => "3 Pending",
+        }
+    }
+}
+
+/// The history, schema and pending migrations of a database, and what is selected in each.
+struct Explorer {
+    tab: Tab,
+    history: Vec<(String, HistoryEntry)>,
+    objects: Vec<SchemaObject>,
+    /// The migrations to run, if a migration directory was given.
+    plan: Option<MigrationPlan>,
+    /// The selected row of each tab, in the order of [`Tab::ALL`].
+    selected: [TableState; 3],
+}
+
+/// Browses the database at `sqlite_url`, and the pending migrations of `migrations`, a migration
+/// directory and application name, until the user quits.
+pub(crate) fn tui_command(sqlite_url: &str, migrations: Option<(&str, &str)>) -> CliResult<()> {
+    let connection = open_read_only(sqlite_url)?;
+    let plan = match migrations {
+        Some((migrations_dir, app_name)) => {
+            Some(load_monarch(migrations_dir, app_name)?.plan(&connection)?)
+        }
+        None => None,
+    };
+    let mut explorer = Explorer {
+        tab: Tab::History,
+        history: recorded_history(&connection, None)?,
+        objects: MonarchDB::schema_objects(&connection)?,
+        plan,
+        selected: Default::default(),
+    };
+    for tab in Tab::ALL {
+        explorer.select(tab, 0);
+    }
+
+    let mut terminal = ratatui::init();
+    let result = explorer.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl Explorer {
+    /// Redraws the explorer after each key press until `q` or escape is pressed.
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> CliResult<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let selected = self.selected[self.tab as usize].selected().unwrap_or(0);
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Tab | KeyCode::Right | KeyCode::Char('l') => {
+                    self.tab = Tab::ALL[(self.tab as usize + 1) % Tab::ALL.len()];
+                }
+                KeyCode::BackTab | KeyCode::Left | KeyCode::Char('h') => {
+                    self.tab = Tab::ALL[(self.tab as usize + Tab::ALL.len() - 1) % Tab::ALL.len()];
+                }
+                KeyCode::Char(digit @ '1'..='3') => {
+                    self.tab = Tab::ALL[digit as usize - '1' as usize];
+                }
+                KeyCode::Down | KeyCode::Char('j') => self.select(self.tab, selected + 1),
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.select(self.tab, selected.saturating_sub(1));
+                }
+                KeyCode::PageDown => self.select(self.tab, selected + 10),
+                KeyCode::PageUp => self.select(self.tab, selected.saturating_sub(10)),
+                KeyCode::Home | KeyCode::Char('g') => self.select(self.tab, 0),
+                KeyCode::End | KeyCode::Char('G') => self.select(self.tab, usize::MAX),
+                _ => {}
+            }
+        }
+    }
+
+    /// The number of rows `tab` lists.
+    fn rows(&self, tab: Tab) -> usize {
+        match tab {
+            Tab::History => self.history.len(),
+            Tab::Schema => self.objects.len(),
+            Tab::Pending => self
+                .plan
+                .as_ref()
+                .map_or(0, |plan| plan.migrations.len() + plan.repeatable.len()),
+        }
+    }
+
+    /// Selects row `index` of `tab`, or its last row if it has fewer.
+    fn select(&mut self, tab: Tab, index: usize) {
+        let rows = self.rows(tab);
+        let selected = (rows > 0).then(|| index.min(rows - 1));
+        self.selected[tab as usize].select(selected);
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [tabs, body, help] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list, detail] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(body);
+
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+        frame.render_widget(
+            Tabs::new(Tab::ALL.map(Tab::title))
+                .select(self.tab as usize)
+                .highlight_style(highlight),
+            tabs,
+        );
+        frame.render_widget(
+            Line::from("q quit  tab/←/→ switch tabs  ↑/↓ select  home/end first/last"),
+            help,
+        );
+
+        let selected = self.selected[self.tab as usize].selected();
+        let (table, text) = match self.tab {
+            Tab::History => self.history_tab(selected),
+            Tab::Schema => self.schema_tab(selected),
+            Tab::Pending => self.pending_tab(selected),
+        };
+        let table = table
+            .block(Block::default().borders(Borders::ALL))
+            .row_highlight_style(highlight);
+        frame.render_stateful_widget(table, list, &mut self.selected[self.tab as usize]);
+        frame.render_widget(
+            Paragraph::new(text)
+                .block(Block::default().borders(Borders::ALL))
+                .wrap(Wrap { trim: false }),
+            detail,
+        );
+    }
+
+    /// The migrations recorded in the history, and the details of the selected one.
+    fn history_tab(&self, selected: Option<usize>) -> (Table<'static>, Text<'static>) {
+        let rows = self.history.iter().map(|(app, entry)| {
+            let status = match entry.status {
+                MigrationStatus::Applied => "applied",
+                MigrationStatus::Skipped(_) => "skipped",
+            };
+            Row::new([
+                app.clone(),
+                entry.version.to_string(),
+                entry.name.clone(),
+                status.to_owned(),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(7),
+                Constraint::Fill(2),
+                Constraint::Length(7),
+            ],
+        )
+        .header(header(["APP", "VERSION", "NAME", "STATUS"]));
+
+        let text = match selected.and_then(|index| self.history.get(index)) {
+            Some((app, entry)) => Text::from(vec![
+                field("Application", app),
+                field("Version", &entry.version.to_string()),
+                field("Name", &entry.name),
+                field("Description", entry.description.as_deref().unwrap_or("-")),
+                field("Applied at", &entry.applied_at),
+                field(
+                    "Duration",
+                    &entry.duration.map_or_else(
+                        || "-".to_owned(),
+                        |duration| format!("{}ms", duration.as_millis()),
+                    ),
+                ),
+                field("Checksum", &entry.checksum),
+                field("Status", &entry.status.to_string()),
+            ]),
+            None => Text::from("No migrations are recorded in this database."),
+        };
+        (table, text)
+    }
+
+    /// The objects in the schema, and the SQL of the selected one.
+    fn schema_tab(&self, selected: Option<usize>) -> (Table<'static>, Text<'static>) {
+        let rows = self
+            .objects
+            .iter()
+            .map(|object| Row::new([object.kind.clone(), object.name.clone()]));
+        let table = Table::new(rows, [Constraint::Length(7), Constraint::Fill(1)])
+            .header(header(["KIND", "NAME"]));
+
+        let text = match selected.and_then(|index| self.objects.get(index)) {
+            Some(object) => Text::from(object.sql.clone()),
+            None => Text::from("The schema has no tables, indexes, views or triggers."),
+        };
+        (table, text)
+    }
+
+    /// The migrations which have yet to run, and the details of the selected one.
+    fn pending_tab(&self, selected: Option<usize>) -> (Table<'static>, Text<'static>) {
+        let table = |rows: Vec<Row<'static>>| {
+            Table::new(
+                rows,
+                [
+                    Constraint::Length(7),
+                    Constraint::Fill(1),
+                    Constraint::Length(11),
+                ],
+            )
+            .header(header(["VERSION", "NAME", "NOTE"]))
+        };
+        let Some(plan) = &self.plan else {
+            let text = Text::from(
+                "Pass a migration directory and an application name to list the migrations \
+                 which have yet to run: monarch tui <sqlite_url> <migrations_dir> <app_name>",
+            );
+            return (table(Vec::new()), text);
+        };
+
+        let mut rows: Vec<Row<'static>> = plan
+            .migrations
+            .iter()
+            .map(|migration| {
+                let note = if migration.skipped {
+                    "skipped"
+                } else if migration.destructive {
+                    "destructive"
+                } else {
+                    ""
+                };
+                Row::new([
+                    migration.version.to_string(),
+                    migration.name.clone(),
+                    note.to_owned(),
+                ])
+            })
+            .collect();
+        rows.extend(
+            plan.repeatable
+                .iter()
+                .map(|name| Row::new(["-".to_owned(), name.clone(), "repeatable".to_owned()])),
+        );
+
+        let text = match selected {
+            Some(index) if index < plan.migrations.len() => {
+                let migration = &plan.migrations[index];
+                let mut lines = vec![
+                    field("Version", &migration.version.to_string()),
+                    field("Name", &migration.name),
+                    field(
+                        "Description",
+                        migration.description.as_deref().unwrap_or("-"),
+                    ),
+                    field("Size", &format!("{} bytes", migration.size)),
+                ];
+                if migration.skipped {
+                    lines.push(Line::from(
+                        "Skipped, since it does not run in the selected environment.",
+                    ));
+                }
+                if migration.destructive {
+                    lines.push(Line::from("Drops tables or columns, or deletes rows."));
+                }
+                for (directive, args) in &migration.directives {
+                    lines.push(field("Directive", &format!("{directive} {args}")));
+                }
+                Text::from(lines)
+            }
+            Some(index) => Text::from(format!(
+                "The repeatable migration {} changed since it last ran, and runs after the \
+                 versioned migrations.",
+                plan.repeatable[index - plan.migrations.len()]
+            )),
+            None => Text::from(format!(
+                "{} is up to date at version {}.",
+                plan.name, plan.from_version
+            )),
+        };
+        (table(rows), text)
+    }
+}
+
+/// A bold header row with `titles`.
+fn header<const N: usize>(titles: [&'static str; N]) -> Row<'static> {
+    Row::new(titles).style(Style::default().add_modifier(Modifier::BOLD))
+}
+
+/// A line of the details pane, with its label in bold.
+fn field(label: &str, value: &str) -> Line<'static> {
+    Line::from(vec![
+        Span::styled(
+            format!("{label}: "),
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(value.to_owned()),
+    ])
+}
@@ -0,0 +1,206 @@
+//! Finding the migration responsible for each schema object.
+//!
+//! [`MonarchDB::schema_blame`] replays the configured migrations against a scratch in-memory
+//! database, comparing the schema after each one with the schema before it, to find the migration
+//! which created each table, index, view and trigger and the one which last changed it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use rusqlite::Connection;
+
+use crate::migration::Migration;
+use crate::schema::{SchemaObject, kind_order, schema_objects};
+use crate::{MonarchDB, Result};
+
+/// The migrations responsible for a schema object, see [`MonarchDB::schema_blame`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectBlame {
+    /// One of `table`, `index`, `view` or `trigger`, as reported by `sqlite_master`.
+    pub kind: String,
+    /// The name of the object.
+    pub name: String,
+    /// The migration which created the object, or last re-created it after it was dropped.
+    pub created_by: MigrationRef,
+    /// The migration which last changed the object's definition, e.g. with `ALTER TABLE`. The
+    /// same as `created_by` if it has not changed since.
+    pub modified_by: MigrationRef,
+}
+
+impl fmt::Display for ObjectBlame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: created by {}",
+            self.kind, self.name, self.created_by
+        )?;
+        if self.modified_by != self.created_by {
+            write!(f, ", last changed by {}", self.modified_by)?;
+        }
+        Ok(())
+    }
+}
+
+/// A migration, identified by name and by the version it reaches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationRef {
+    /// The name of the migration, its file name for migrations loaded from a directory.
+    pub name: String,
+    /// The version the migration upgrades the database to, or `None` for a repeatable migration.
+    pub version: Option<u32>,
+}
+
+impl fmt::Display for MigrationRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version {
+            Some(version) => write!(f, "{} (version {version})", self.name),
+            None => write!(f, "{} (repeatable)", self.name),
+        }
+    }
+}
+
+/// The schema after a migration was replayed.
+pub(crate) struct Snapshot<'m> {
+    /// The version reached, or `None` for a repeatable migration.
+    pub(crate) version: Option<u32>,
+    pub(crate) migration: &'m Migration,
+    pub(crate) objects: Vec<SchemaObject>,
+}
+
+impl MonarchDB {
+    /// Finds the migrations which created and last changed each object in the schema the
+    /// migrations produce, ordered like [`schema_sql`](Self::schema_sql).
+    ///
+    /// Migrations, including repeatable migrations, are replayed against a scratch in-memory
+    /// database, skipping those outside the configured environment. No real database is
+    /// touched.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> monarch_db::Result<()> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    ///         "ALTER TABLE users ADD COLUMN name TEXT;",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// let blame = monarch_db.schema_blame()?;
+    /// assert_eq!(
+    ///     blame[0].to_string(),
+    ///     "table users: created by 1 (version 1), last changed by 2 (version 2)"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn schema_blame(&self) -> Result<Vec<ObjectBlame>> {
+        let mut blame: BTreeMap<(String, String), ObjectBlame> = BTreeMap::new();
+        let mut previous: BTreeMap<(String, String), String> = BTreeMap::new();
+        for snapshot in self.schema_snapshots()? {
+            let migration = MigrationRef {
+                name: snapshot.migration.name.to_string(),
+                version: snapshot.version,
+            };
+            let current: BTreeMap<_, _> = snapshot
+                .objects
+                .into_iter()
+                .map(|object| ((object.kind, object.name), object.sql))
+                .collect();
+            for (key, sql) in &current {
+                match previous.get(key) {
+                    None => {
+                        blame.insert(
+                            key.clone(),
+                            ObjectBlame {
+                                kind: key.0.clone(),
+                                name: key.1.clone(),
+                                created_by: migration.clone(),
+                                modified_by: migration.clone(),
+                            },
+                        );
+                    }
+                    Some(before) if before != sql => {
+                        if let Some(object) = blame.get_mut(key) {
+                            object.modified_by = migration.clone();
+                        }
+                    }
+                    Some(_) => {}
+                }
+            }
+            blame.retain(|key, _| current.contains_key(key));
+            previous = current;
+        }
+
+        // Order like schema_objects: by kind, then name.
+        let mut blame: Vec<_> = blame.into_values().collect();
+        blame.sort_by_key(|object| (kind_order(&object.kind), object.name.clone()));
+        Ok(blame)
+    }
+
+    /// Replays the migrations on a scratch database, returning the schema after each one.
+    pub(crate) fn schema_snapshots(&self) -> Result<Vec<Snapshot<'_>>> {
+        let connection = Connection::open_in_memory()?;
+        self.setup_connection(&connection)?;
+        let versioned = self
+            .versions()
+            .map(|(version, migration)| (Some(version), migration));
+        let repeatable = self.repeatables.iter().map(|migration| (None, migration));
+
+        let mut snapshots = Vec::new();
+        for (version, migration) in versioned.chain(repeatable) {
+            if self.applies(migration) {
+                migration.execute(&connection, |_| {})?;
+            }
+            snapshots.push(Snapshot {
+                version,
+                migration,
+                objects: schema_objects(&connection)?,
+            });
+        }
+        Ok(snapshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_blame_tracks_creation_and_changes() -> Result<()> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "blame",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY); CREATE TABLE old (id);",
+                "CREATE INDEX users_id ON users (id); DROP TABLE old;",
+                "ALTER TABLE users ADD COLUMN name TEXT; CREATE TABLE old (id, name);",
+            ],
+        })
+        .with_repeatable_migration(
+            "R__views.sql",
+            "CREATE VIEW IF NOT EXISTS names AS SELECT name FROM users;",
+        );
+
+        let blame: Vec<_> = monarch_db
+            .schema_blame()?
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            blame,
+            [
+                "table old: created by 3 (version 3)",
+                "table users: created by 1 (version 1), last changed by 3 (version 3)",
+                "index users_id: created by 2 (version 2)",
+                "view names: created by R__views.sql (repeatable)",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+//! Streaming large BLOB columns between tables.
+//!
+//! Restructuring a media-library-style schema often means moving a BLOB column into a new
+//! table. A plain `INSERT ... SELECT` materializes each value in memory, which can exhaust it
+//! when values are hundreds of megabytes. [`BlobCopy`] instead allocates each value in the target
+//! with `zeroblob()` and streams the contents across in fixed-size chunks using SQLite's
+//! incremental BLOB I/O, reporting progress as it goes.
+//!
+//! Enable the `blob` feature to use these helpers.
+
+use rusqlite::Connection;
+
+use crate::Result;
+use crate::sql::quote;
+
+/// The default number of bytes copied per read and write.
+pub const DEFAULT_BLOB_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Copies a BLOB column into another table, row by row, without loading whole values.
+///
+/// Values are copied to the target row with the same `rowid` as the source row, so the target
+/// rows must already exist, typically inserted with everything but the BLOB column in the same
+/// migration. Rows whose source value is `NULL` are skipped. Run the copy inside the migration's
+/// transaction to keep it atomic.
+///
+/// ```rust
+/// use monarch_db::BlobCopy;
+///
+/// # fn main() -> monarch_db::Result<()> {
+/// let connection = rusqlite::Connection::open_in_memory()?;
+/// connection.execute_batch(
+///     "CREATE TABLE photos (id INTEGER PRIMARY KEY, data BLOB);
+///      INSERT INTO photos (data) VALUES (randomblob(3000000));
+///      CREATE TABLE photo_data (photo_id INTEGER PRIMARY KEY, data BLOB);
+///      INSERT INTO photo_data (photo_id) SELECT id FROM photos;",
+/// )?;
+///
+/// let progress = BlobCopy::new("photos", "data", "photo_data", "data").run(&connection, |p| {
+///     println!("{} of {} bytes", p.bytes_copied, p.bytes_total);
+/// })?;
+/// assert_eq!(progress.bytes_copied, 3_000_000);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct BlobCopy<'a> {
+    source_table: &'a str,
+    source_column: &'a str,
+    target_table: &'a str,
+    target_column: &'a str,
+    chunk_size: usize,
+}
+
+/// How far a [`BlobCopy`] has progressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlobProgress {
+    /// The number of values copied so far.
+    pub rows_copied: u64,
+    /// The number of non-`NULL` values to copy.
+    pub rows_total: u64,
+    /// The number of bytes copied so far.
+    pub bytes_copied: u64,
+    /// The total size of the values to copy.
+    pub bytes_total: u64,
+}
+
+impl<'a> BlobCopy<'a> {
+    /// Copies `source_table.source_column` into `target_table.target_column`.
+    pub fn new(
+        source_table: &'a str,
+        source_column: &'a str,
+        target_table: &'a str,
+        target_column: &'a str,
+    ) -> Self {
+        BlobCopy {
+            source_table,
+            source_column,
+            target_table,
+            target_column,
+            chunk_size: DEFAULT_BLOB_CHUNK_SIZE,
+        }
+    }
+
+    /// Sets the number of bytes copied per read and write, [`DEFAULT_BLOB_CHUNK_SIZE`] by
+    /// default. This bounds the memory used by the copy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "BLOB chunk size must be positive");
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Runs the copy, calling `progress` after each chunk is written.
+    ///
+    /// Returns the final progress. Fails with [`Error::Sqlite`](crate::Error::Sqlite) wrapping
+    /// [`rusqlite::Error::QueryReturnedNoRows`] if a source row has no matching target row.
+    #[tracing::instrument(level = "debug", skip_all, fields(source = self.source_table, target = self.target_table))]
+    pub fn run(
+        &self,
+        connection: &Connection,
+        mut progress: impl FnMut(&BlobProgress),
+    ) -> Result<BlobProgress> {
+        let rows = {
+            let mut stmt = connection.prepare(&format!(
+                "SELECT rowid, length({column}) FROM {table} WHERE {column} IS NOT NULL ORDER BY rowid",
+                column = quote(self.source_column),
+                table = quote(self.source_table),
+            ))?;
+            stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?
+                .collect::<rusqlite::Result<Vec<_>>>()?
+        };
+
+        let mut state = BlobProgress {
+            rows_total: rows.len() as u64,
+            bytes_total: rows.iter().map(|(_, length)| *length as u64).sum(),
+            ..Default::default()
+        };
+
+        let mut allocate = connection.prepare(&format!(
+            "UPDATE {table} SET {column} = zeroblob(?2) WHERE rowid = ?1",
+            table = quote(self.target_table),
+            column = quote(self.target_column),
+        ))?;
+        let mut buffer = vec![0; self.chunk_size];
+
+        for (rowid, length) in rows {
+            if allocate.execute((rowid, length))? != 1 {
+                return Err(rusqlite::Error::QueryReturnedNoRows.into());
+            }
+
+            let source =
+                connection.blob_open("main", self.source_table, self.source_column, rowid, true)?;
+            let mut target = connection.blob_open(
+                "main",
+                self.target_table,
+                self.target_column,
+                rowid,
+                false,
+            )?;
+
+            let length = length as usize;
+            let mut offset = 0;
+            loop {
+                // Empty values take a single, empty chunk so they are still reported.
+                let chunk = &mut buffer[..self.chunk_size.min(length - offset)];
+                if !chunk.is_empty() {
+                    source.read_at_exact(chunk, offset)?;
+                    target.write_at(chunk, offset)?;
+                }
+                offset += chunk.len();
+                state.bytes_copied += chunk.len() as u64;
+
+                let done = offset == length;
+                if done {
+                    state.rows_copied += 1;
+                }
+                progress(&state);
+                if done {
+                    break;
+                }
+            }
+        }
+
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copies_blobs_in_chunks() -> Result<()> {
+        let connection = Connection::open_in_memory()?;
+        connection.execute_batch(
+            "CREATE TABLE media (id INTEGER PRIMARY KEY, data BLOB);
+             INSERT INTO media (data) VALUES (randomblob(10000)), (NULL), (x''), (randomblob(2500));
+             CREATE TABLE media_data (id INTEGER PRIMARY KEY, data BLOB);
+             INSERT INTO media_data (id) SELECT id FROM media;",
+        )?;
+
+        let mut updates = Vec::new();
+        let progress = BlobCopy::new("media", "data", "media_data", "data")
+            .with_chunk_size(4096)
+            .run(&connection, |p| updates.push(*p))?;
+
+        assert_eq!(
+            progress,
+            BlobProgress {
+                rows_copied: 3,
+                rows_total: 3,
+                bytes_copied: 12500,
+                bytes_total: 12500,
+            }
+        );
+        // 3 chunks for the first value, 1 for the empty value, 1 for the last.
+        assert_eq!(updates.len(), 5);
+        assert_eq!(updates.last(), Some(&progress));
+
+        let mismatched: i64 = connection.query_row(
+            "SELECT COUNT(*) FROM media JOIN media_data USING (id) WHERE media.data IS NOT media_data.data",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(mismatched, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_target_row() -> Result<()> {
+        let connection = Connection::open_in_memory()?;
+        connection.execute_batch(
+            "CREATE TABLE media (id INTEGER PRIMARY KEY, data BLOB);
+             INSERT INTO media (data) VALUES (randomblob(10));
+             CREATE TABLE media_data (id INTEGER PRIMARY KEY, data BLOB);",
+        )?;
+
+        let result = BlobCopy::new("media", "data", "media_data", "data").run(&connection, |_| {});
+        assert!(matches!(
+            result,
+            Err(crate::Error::Sqlite(rusqlite::Error::QueryReturnedNoRows))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+//! Migrating within a time budget at startup, deferring the rest.
+//!
+//! A desktop application which migrates while it starts cannot show its window until migrating
+//! finishes. [`MonarchDB::create_connection_within`] instead applies pending migrations one
+//! transaction at a time until a time budget runs out, and returns the connection at the
+//! version reached together with a [`DeferredMigrations`] handle which finishes migrating later,
+//! e.g. on a background thread.
+
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+
+use crate::{ConnectionConfiguration, MigrationReport, MonarchDB, Result, engine, open_connection};
+
+/// Migrations left pending by [`MonarchDB::create_connection_within`].
+#[derive(Debug, Clone)]
+pub struct DeferredMigrations {
+    configuration: ConnectionConfiguration,
+    version: u32,
+    target: u32,
+}
+
+impl DeferredMigrations {
+    /// The version the database was left at when the budget ran out.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The number of versions left to apply.
+    pub fn pending(&self) -> u32 {
+        self.target - self.version
+    }
+
+    /// Opens a new connection to the database and applies the remaining migrations.
+    ///
+    /// `monarch` should be the `MonarchDB` which deferred the migrations. Migrating takes the
+    /// database's write lock, so other connections wait for each migration like they would for
+    /// any other write.
+    pub fn finish(self, monarch: &MonarchDB) -> Result<MigrationReport> {
+        let mut connection = open_connection(
+            &self.configuration,
+            &monarch.retry_policy,
+            monarch.busy_handler,
+        )?;
+        monarch.migrate_to(&mut connection, self.target)
+    }
+}
+
+impl MonarchDB {
+    /// Opens a connection like [`create_connection`](Self::create_connection), but stops
+    /// applying migrations once `budget` has elapsed.
+    ///
+    /// Each migration runs in its own transaction, as with
+    /// [`TransactionMode::PerMigration`](crate::TransactionMode::PerMigration), and no further
+    /// migration starts once the budget is spent, although one which is running finishes. If
+    /// migrations remain, the connection is returned at the version reached along with a
+    /// [`DeferredMigrations`] handle to apply them later, so the application must be able to
+    /// work with the database at that version. Private in-memory databases cannot be reopened, so
+    /// they are always migrated in full.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use monarch_db::{ConnectionConfiguration, Database, MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let directory = tempfile::tempdir()?;
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    ///         "CREATE INDEX users_id ON users (id);",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// let configuration = ConnectionConfiguration {
+    ///     database: Database::File(directory.path().join("app.db").try_into()?),
+    ///     ..Default::default()
+    /// };
+    /// let (connection, deferred) =
+    ///     monarch_db.create_connection_within(&configuration, Duration::from_millis(200))?;
+    /// if let Some(deferred) = deferred {
+    ///     // e.g. on a background thread, while the application starts.
+    ///     deferred.finish(&monarch_db)?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn create_connection_within(
+        &self,
+        configuration: &ConnectionConfiguration,
+        budget: Duration,
+    ) -> Result<(Connection, Option<DeferredMigrations>)> {
+        let start = Instant::now();
+        let mut connection = open_connection(configuration, &self.retry_policy, self.busy_handler)?;
+        let target = self.current_version();
+        let mut version = engine::recorded_version(&connection, &self.name)?;
+        loop {
+            if configuration.database.is_shared() && start.elapsed() >= budget {
+                break;
+            }
+            // Repeatable migrations run with the last step, or on their own if nothing else is due.
+            let step = if version < target {
+                self.get_migration(version)?.1
+            } else {
+                target
+            };
+            version = self.migrate_to(&mut connection, step)?.to_version;
+            if version >= target {
+                break;
+            }
+        }
+
+        self.after_open(&connection)?;
+        if version >= target {
+            return Ok((connection, None));
+        }
+        tracing::debug!(
+            version,
+            target,
+            budget_ms = budget.as_millis() as u64,
+            "Deferring migrations past the startup budget"
+        );
+        let deferred = DeferredMigrations {
+            configuration: configuration.clone(),
+            version,
+            target,
+        };
+        Ok((connection, Some(deferred)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::{Database, StaticMonarchConfiguration};
+
+    use super::*;
+
+    #[test]
+    fn test_spent_budget_defers_migrations() -> Result<(), Box<dyn std::error::Error>> {
+        let directory = TempDir::new()?;
+        let configuration = ConnectionConfiguration {
+            database: Database::File(directory.path().join("budget.db").try_into()?),
+            ..Default::default()
+        };
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "budget",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                "ALTER TABLE users ADD COLUMN name TEXT;",
+            ],
+        });
+
+        let (connection, deferred) =
+            monarch_db.create_connection_within(&configuration, Duration::ZERO)?;
+        let deferred = deferred.expect("migrations are deferred");
+        assert_eq!((deferred.version(), deferred.pending()), (0, 2));
+        drop(connection);
+
+        let report = deferred.finish(&monarch_db)?;
+        assert_eq!((report.from_version, report.to_version), (0, 2));
+
+        let (_, deferred) = monarch_db.create_connection_within(&configuration, Duration::ZERO)?;
+        assert!(deferred.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_relaxed_migration_keeps_referring_rows() -> Result<(), Box<dyn std::error::Error>> {
+        let directory = TempDir::new()?;
+        let configuration = ConnectionConfiguration {
+            database: Database::File(directory.path().join("budget.db").try_into()?),
+            ..Default::default()
+        };
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "budget",
+            enable_foreign_keys: true,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);
+                 CREATE TABLE posts (user_id INTEGER REFERENCES users (id) ON DELETE CASCADE);
+                 INSERT INTO users (id) VALUES (1);
+                 INSERT INTO posts (user_id) VALUES (1);",
+                "-- monarch:relax-foreign-keys
+                 CREATE TABLE users_new (id INTEGER PRIMARY KEY, name TEXT);
+                 INSERT INTO users_new (id) SELECT id FROM users;
+                 DROP TABLE users;
+                 ALTER TABLE users_new RENAME TO users;",
+            ],
+        });
+
+        let (connection, deferred) =
+            monarch_db.create_connection_within(&configuration, Duration::from_secs(60))?;
+        assert!(deferred.is_none());
+        let posts: i64 =
+            connection.query_row("SELECT count(*) FROM posts", [], |row| row.get(0))?;
+        assert_eq!(posts, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_migrates_in_full() -> Result<()> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "budget",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                "ALTER TABLE users ADD COLUMN name TEXT;",
+            ],
+        });
+        let (connection, deferred) = monarch_db
+            .create_connection_within(&ConnectionConfiguration::default(), Duration::ZERO)?;
+        assert!(deferred.is_none());
+        connection.execute("INSERT INTO users (name) VALUES ('alice')", [])?;
+        Ok(())
+    }
+}
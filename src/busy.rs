@@ -0,0 +1,66 @@
+//! Waiting for locks held by other connections.
+
+use std::time::Duration;
+
+use rusqlite::Connection;
+
+use crate::MonarchDB;
+
+/// What a connection does when another connection holds a lock it needs.
+///
+/// Installed with [`MonarchDB::with_busy_handler`] on every connection
+/// [`create_connection`](MonarchDB::create_connection) opens, before anything else runs on it,
+/// so a long migration in one process makes the others wait rather than fail with
+/// `SQLITE_BUSY`. The connection keeps the handler after it is returned.
+#[derive(Debug, Clone, Copy)]
+pub enum BusyHandler {
+    /// Sleep and retry until the lock has been waited on for this long in total, SQLite's
+    /// default handler, set with `sqlite3_busy_timeout`. `Duration::ZERO` fails immediately.
+    Timeout(Duration),
+    /// Call this function with the number of times it has already been called for the same lock,
+    /// retrying while it returns `true`. The function may sleep before returning to pace the
+    /// retries, and a panic is treated as `false`.
+    Custom(fn(i32) -> bool),
+}
+
+impl BusyHandler {
+    /// Installs the handler on `connection`, replacing its current one.
+    pub(crate) fn install(self, connection: &Connection) -> rusqlite::Result<()> {
+        match self {
+            BusyHandler::Timeout(timeout) => connection.busy_timeout(timeout),
+            BusyHandler::Custom(handler) => connection.busy_handler(Some(handler)),
+        }
+    }
+}
+
+impl MonarchDB {
+    /// Sets the busy handler installed on the connections
+    /// [`create_connection`](Self::create_connection) opens, see [`BusyHandler`].
+    ///
+    /// Without one, connections keep rusqlite's default five second busy timeout. The handler
+    /// is not installed on connections passed to [`migrate`](Self::migrate), which keep their
+    /// own. It also governs each attempt to take the migration lock, which
+    /// [`with_lock_timeout`](Self::with_lock_timeout) retries in turn.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use monarch_db::{BusyHandler, MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// fn patiently(attempts: i32) -> bool {
+    ///     std::thread::sleep(Duration::from_millis(10 << attempts.min(6)));
+    ///     attempts < 20
+    /// }
+    ///
+    /// let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+    /// })
+    /// .with_busy_handler(BusyHandler::Custom(patiently));
+    /// ```
+    pub fn with_busy_handler(mut self, handler: BusyHandler) -> Self {
+        self.busy_handler = Some(handler);
+        self
+    }
+}
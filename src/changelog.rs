@@ -0,0 +1,184 @@
+//! Release notes of schema changes, generated from the migrations.
+//!
+//! [`MonarchDB::changelog`] replays the migrations against a scratch in-memory database and
+//! records, for each version, the migration's description and the tables, indexes, views and
+//! triggers it created, dropped or altered.
+
+use std::collections::BTreeMap;
+
+use crate::schema::{SchemaObject, kind_order};
+use crate::{MonarchDB, Result};
+
+/// The schema changes made by one migration, see [`MonarchDB::changelog`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangelogEntry {
+    /// The schema version the migration upgrades the database to.
+    pub version: u32,
+    /// The name of the migration, its file name for migrations loaded from a directory.
+    pub name: String,
+    /// The migration's `-- monarch:description` directives, or else its leading comment or file
+    /// name.
+    pub description: Option<String>,
+    /// Objects the migration created.
+    pub created: Vec<SchemaObject>,
+    /// Objects the migration dropped.
+    pub dropped: Vec<SchemaObject>,
+    /// Objects whose definitions the migration changed.
+    pub altered: Vec<AlteredObject>,
+}
+
+/// An object whose definition a migration changed, e.g. with `ALTER TABLE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlteredObject {
+    /// One of `table`, `index`, `view` or `trigger`, as reported by `sqlite_master`.
+    pub kind: String,
+    /// The name of the object.
+    pub name: String,
+    /// The object's SQL before the migration.
+    pub before: String,
+    /// The object's SQL after the migration.
+    pub after: String,
+}
+
+impl MonarchDB {
+    /// Lists the schema changes made by each migration after version `since`, in order.
+    ///
+    /// Migrations are replayed against a scratch in-memory database, skipping those outside the
+    /// configured environment, which therefore change nothing. Repeatable migrations are not
+    /// included. Within each entry, objects are ordered like [`schema_sql`](Self::schema_sql).
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> monarch_db::Result<()> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    ///         "-- monarch:description Index users by name.\n\
+    ///          ALTER TABLE users ADD COLUMN name TEXT;\n\
+    ///          CREATE INDEX users_name ON users (name);",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// let changelog = monarch_db.changelog(1)?;
+    /// assert_eq!(changelog.len(), 1);
+    /// assert_eq!(changelog[0].description.as_deref(), Some("Index users by name."));
+    /// assert_eq!(changelog[0].created[0].name, "users_name");
+    /// assert_eq!(changelog[0].altered[0].name, "users");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn changelog(&self, since: u32) -> Result<Vec<ChangelogEntry>> {
+        let mut entries = Vec::new();
+        let mut previous: BTreeMap<(u8, String), SchemaObject> = BTreeMap::new();
+        for snapshot in self.schema_snapshots()? {
+            let Some(version) = snapshot.version else {
+                break;
+            };
+            let current: BTreeMap<_, _> = snapshot
+                .objects
+                .into_iter()
+                .map(|object| ((kind_order(&object.kind), object.name.clone()), object))
+                .collect();
+
+            if version > since {
+                let mut entry = ChangelogEntry {
+                    version,
+                    name: snapshot.migration.name.to_string(),
+                    description: snapshot.migration.description.clone(),
+                    created: Vec::new(),
+                    dropped: Vec::new(),
+                    altered: Vec::new(),
+                };
+                for (key, object) in &current {
+                    match previous.get(key) {
+                        None => entry.created.push(object.clone()),
+                        Some(before) if before.sql != object.sql => {
+                            entry.altered.push(AlteredObject {
+                                kind: object.kind.clone(),
+                                name: object.name.clone(),
+                                before: before.sql.clone(),
+                                after: object.sql.clone(),
+                            });
+                        }
+                        Some(_) => {}
+                    }
+                }
+                entry.dropped = previous
+                    .iter()
+                    .filter(|(key, _)| !current.contains_key(*key))
+                    .map(|(_, object)| object.clone())
+                    .collect();
+                entries.push(entry);
+            }
+            previous = current;
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_changelog_since_version() -> Result<()> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "changelog",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id); CREATE TABLE legacy (id);",
+                "-- monarch:description Drop legacy data.\nDROP TABLE legacy;",
+                "CREATE VIEW user_ids AS SELECT id FROM users; ALTER TABLE users ADD COLUMN name;",
+            ],
+        });
+
+        let changelog = monarch_db.changelog(1)?;
+        let summary: Vec<_> = changelog
+            .iter()
+            .map(|entry| {
+                let names = |objects: &[SchemaObject]| {
+                    objects.iter().map(|o| o.name.clone()).collect::<Vec<_>>()
+                };
+                (
+                    entry.version,
+                    entry.description.clone(),
+                    names(&entry.created),
+                    names(&entry.dropped),
+                    entry
+                        .altered
+                        .iter()
+                        .map(|o| o.name.clone())
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    2,
+                    Some("Drop legacy data.".to_owned()),
+                    vec![],
+                    vec!["legacy".to_owned()],
+                    vec![]
+                ),
+                (
+                    3,
+                    None,
+                    vec!["user_ids".to_owned()],
+                    vec![],
+                    vec!["users".to_owned()]
+                ),
+            ]
+        );
+        assert_eq!(changelog[1].altered[0].before, "CREATE TABLE users (id)");
+        assert!(monarch_db.changelog(3)?.is_empty());
+        Ok(())
+    }
+}
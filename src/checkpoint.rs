@@ -0,0 +1,93 @@
+//! Checkpointing the write-ahead log after migrations.
+//!
+//! In WAL mode, a migration which rebuilds a large table writes every changed page to the
+//! write-ahead log, which SQLite only truncates once a checkpoint has copied them into the
+//! database and no reader still needs them. On space-constrained devices the log can outgrow the
+//! database itself. [`MonarchDB::with_wal_checkpoint`] runs `PRAGMA wal_checkpoint(TRUNCATE)`
+//! after migrations apply, returning that space right away.
+
+use crate::MonarchDB;
+use crate::backend::{Driver, column};
+
+impl MonarchDB {
+    /// Sets whether to checkpoint and truncate the write-ahead log after migrations apply.
+    ///
+    /// The checkpoint only runs when a versioned or repeatable migration ran and the database
+    /// is in WAL mode. The migrations have committed by then, so a checkpoint which fails, or
+    /// which cannot finish because another connection is reading, is logged as a warning
+    /// rather than failing the migration. Defaults to `false`.
+    pub fn with_wal_checkpoint(mut self, checkpoint: bool) -> Self {
+        self.wal_checkpoint = checkpoint;
+        self
+    }
+
+    /// Checkpoints the write-ahead log through `driver`, if configured and in WAL mode.
+    pub(crate) async fn checkpoint<D: Driver>(&self, driver: &mut D) {
+        if !self.wal_checkpoint {
+            return;
+        }
+        let checkpoint = async {
+            let journal_mode: Option<String> =
+                driver.query_value("PRAGMA journal_mode", &[]).await?;
+            if !journal_mode.is_some_and(|mode| mode.eq_ignore_ascii_case("wal")) {
+                return Ok(None);
+            }
+            let rows = driver.query("PRAGMA wal_checkpoint(TRUNCATE)", &[]).await?;
+            let Some(row) = rows.first() else {
+                return Ok(None);
+            };
+            let result: (bool, i64, i64) = (column(row, 0)?, column(row, 1)?, column(row, 2)?);
+            Ok::<_, D::Error>(Some(result))
+        };
+        match checkpoint.await {
+            Ok(None) => {}
+            Ok(Some((false, _, pages))) => {
+                tracing::debug!(pages, "Checkpointed the write-ahead log");
+            }
+            Ok(Some((true, log, pages))) => tracing::warn!(
+                log,
+                pages,
+                "Could not finish checkpointing the write-ahead log, another connection is using it"
+            ),
+            Err(error) => tracing::warn!(%error, "Failed to checkpoint the write-ahead log"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_truncates_log() -> crate::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let path = directory.path().join("checkpoint.db");
+        let wal = directory.path().join("checkpoint.db-wal");
+        let monarch = |checkpoint| {
+            MonarchDB::from(StaticMonarchConfiguration {
+                name: "checkpoint",
+                enable_foreign_keys: true,
+                migrations: [
+                    "CREATE TABLE blobs (data BLOB);",
+                    "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 64)
+                     INSERT INTO blobs SELECT randomblob(4096) FROM n;",
+                ],
+            })
+            .with_wal_checkpoint(checkpoint)
+        };
+
+        let mut connection = Connection::open(&path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        monarch(false).migrate_to(&mut connection, 1)?;
+        assert!(std::fs::metadata(&wal)?.len() > 0);
+
+        let connection = monarch(true).migrate(connection)?;
+        assert_eq!(std::fs::metadata(&wal)?.len(), 0);
+        drop(connection);
+        Ok(())
+    }
+}
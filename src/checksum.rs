@@ -0,0 +1,279 @@
+//! Detecting migrations which changed after they were applied.
+//!
+//! The migration history records a checksum of each migration's SQL. Editing a migration which
+//! already ran usually means databases migrated before and after the edit have different
+//! schemas, so [`MonarchDB::with_checksum_policy`] decides what migrating does when a recorded
+//! checksum no longer matches: nothing, log a warning, fail, or accept the new SQL and record its
+//! checksum. A server can refuse to start, while an application on a user's device usually has
+//! to keep working.
+//!
+//! [`MonarchDB::with_checksum_algorithm`] chooses the hash. Checksums of either algorithm can be
+//! compared against each other's databases, since each recorded checksum is checked with the
+//! algorithm which produced it.
+
+use crate::backend::Driver;
+use crate::digest::Fnv64;
+use crate::history::HISTORY_TABLE;
+use crate::{Error, MonarchDB, Result};
+
+/// What migrating does when an applied migration's SQL no longer matches its recorded checksum.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ChecksumPolicy {
+    /// Don't compare checksums.
+    #[default]
+    Ignore,
+    /// Log a warning for each changed migration, then migrate.
+    Warn,
+    /// Fail migrating with [`Error::ChecksumMismatch`].
+    Error,
+    /// Accept the changed migration, recording its new checksum in the history, then migrate.
+    Update,
+}
+
+/// The hash used for the checksums of migrations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ChecksumAlgorithm {
+    /// The 64-bit FNV-1a hash, as 16 hex digits. Fast and stable, but not cryptographic.
+    #[default]
+    Fnv1a64,
+    /// SHA-256, as 64 hex digits, for checksums which must also detect deliberate edits.
+    /// Requires the `sha256` feature.
+    #[cfg(feature = "sha256")]
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    /// The checksum of `sql`, as hex digits.
+    pub fn checksum(self, sql: &str) -> String {
+        match self {
+            ChecksumAlgorithm::Fnv1a64 => {
+                let mut hasher = Fnv64::new();
+                hasher.write(sql.as_bytes());
+                hasher.finish_hex()
+            }
+            #[cfg(feature = "sha256")]
+            ChecksumAlgorithm::Sha256 => sha256_hex(sql.as_bytes()),
+        }
+    }
+
+    /// The algorithm which produced `checksum`, told apart by its length, or `None` for a
+    /// SHA-256 checksum without the `sha256` feature.
+    fn of(checksum: &str) -> Option<Self> {
+        if checksum.len() != 64 {
+            return Some(ChecksumAlgorithm::Fnv1a64);
+        }
+        #[cfg(feature = "sha256")]
+        return Some(ChecksumAlgorithm::Sha256);
+        #[cfg(not(feature = "sha256"))]
+        return None;
+    }
+}
+
+/// Whether `recorded`, a checksum of either algorithm, is the checksum of `sql`. A checksum which
+/// cannot be computed without the `sha256` feature does not match.
+pub(crate) fn checksum_matches(recorded: &str, sql: &str) -> bool {
+    ChecksumAlgorithm::of(recorded).is_some_and(|algorithm| algorithm.checksum(sql) == recorded)
+}
+
+impl MonarchDB {
+    /// Sets what migrating does when a migration recorded in the history has changed since it
+    /// ran, see [`ChecksumPolicy`]. Defaults to [`ChecksumPolicy::Ignore`].
+    ///
+    /// Migrations are compared with the history entry of the same version and name, so a
+    /// migration which was renamed, or replaced by a baseline, is not compared. With
+    /// [`ChecksumPolicy::Error`], migrating fails with [`Error::ChecksumMismatch`].
+    ///
+    /// ```rust
+    /// use monarch_db::{ChecksumPolicy, MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let original: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+    /// }
+    /// .into();
+    /// let edited = MonarchDB::from(StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);"],
+    /// })
+    /// .with_checksum_policy(ChecksumPolicy::Error);
+    ///
+    /// let connection = original.open_in_memory()?;
+    /// assert!(edited.migrate(connection).is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_checksum_policy(mut self, policy: ChecksumPolicy) -> Self {
+        self.checksum_policy = policy;
+        self
+    }
+
+    /// Sets the hash used for the checksums recorded from now on, see [`ChecksumAlgorithm`].
+    /// Defaults to [`ChecksumAlgorithm::Fnv1a64`].
+    ///
+    /// Checksums recorded with the other algorithm are still compared correctly, so the
+    /// algorithm can be changed for an existing database.
+    pub fn with_checksum_algorithm(mut self, algorithm: ChecksumAlgorithm) -> Self {
+        self.checksum_algorithm = algorithm;
+        self
+    }
+
+    /// The checksum of `sql` with the configured algorithm.
+    pub(crate) fn checksum(&self, sql: &str) -> String {
+        self.checksum_algorithm.checksum(sql)
+    }
+
+    /// Applies the checksum policy to the migrations recorded in the history of a database
+    /// being migrated.
+    pub(crate) async fn check_checksums<D: Driver>(&self, driver: &mut D) -> Result<(), D::Error> {
+        if self.checksum_policy == ChecksumPolicy::Ignore {
+            return Ok(());
+        }
+
+        for entry in self.read_history(driver).await? {
+            let Some((_, migration)) = self.versions().find(|(version, migration)| {
+                *version == entry.version && migration.name == entry.name
+            }) else {
+                continue;
+            };
+            if ChecksumAlgorithm::of(&entry.checksum).is_none() {
+                tracing::warn!(
+                    migration = %migration.name,
+                    version = entry.version,
+                    "Cannot verify a SHA-256 checksum without the `sha256` feature"
+                );
+                continue;
+            }
+            if checksum_matches(&entry.checksum, migration.sql()?) {
+                continue;
+            }
+
+            let current = self.checksum(migration.sql()?);
+            match self.checksum_policy {
+                ChecksumPolicy::Ignore => {}
+                ChecksumPolicy::Warn => tracing::warn!(
+                    migration = %migration.name,
+                    version = entry.version,
+                    recorded = %entry.checksum,
+                    %current,
+                    "Migration changed since it was applied"
+                ),
+                ChecksumPolicy::Error => {
+                    return Err(Error::ChecksumMismatch {
+                        migration: migration.name.to_string(),
+                        version: entry.version,
+                        recorded: entry.checksum,
+                        current,
+                    }
+                    .into());
+                }
+                ChecksumPolicy::Update => {
+                    tracing::debug!(
+                        migration = %migration.name,
+                        version = entry.version,
+                        "Recording the checksum of a changed migration"
+                    );
+                    driver
+                        .execute(
+                            &format!(
+                                "UPDATE {HISTORY_TABLE} SET checksum = ?1 \
+                                 WHERE monarch_schema = ?2 AND version = ?3"
+                            ),
+                            &[
+                                current.into(),
+                                self.name.to_string().into(),
+                                entry.version.into(),
+                            ],
+                        )
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The SHA-256 hash of `data`, as 64 hex digits.
+#[cfg(feature = "sha256")]
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "sha256")]
+    fn test_sha256() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let long = "a".repeat(1000);
+        assert_eq!(
+            sha256_hex(long.as_bytes()),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    fn test_policies() -> Result<()> {
+        let monarch = |migration| {
+            MonarchDB::from(StaticMonarchConfiguration {
+                name: "checksum",
+                enable_foreign_keys: false,
+                migrations: [migration],
+            })
+        };
+        let connection = monarch("CREATE TABLE t (x);").open_in_memory()?;
+        let edited = "CREATE TABLE t (x, y);";
+
+        let mut connection = monarch(edited).migrate(connection)?;
+        let error = monarch(edited)
+            .with_checksum_policy(ChecksumPolicy::Error)
+            .migrate_to(&mut connection, 1)
+            .unwrap_err();
+        assert!(matches!(error, Error::ChecksumMismatch { version: 1, .. }));
+
+        #[cfg(feature = "sha256")]
+        let algorithm = ChecksumAlgorithm::Sha256;
+        #[cfg(not(feature = "sha256"))]
+        let algorithm = ChecksumAlgorithm::Fnv1a64;
+        let updating = monarch(edited)
+            .with_checksum_policy(ChecksumPolicy::Update)
+            .with_checksum_algorithm(algorithm);
+        updating.migrate_to(&mut connection, 1)?;
+        let history = updating.history(&connection)?;
+        assert_eq!(history[0].checksum, algorithm.checksum(edited));
+
+        // The updated checksum matches, whichever algorithm checks it.
+        monarch(edited)
+            .with_checksum_policy(ChecksumPolicy::Error)
+            .migrate_to(&mut connection, 1)?;
+        Ok(())
+    }
+}
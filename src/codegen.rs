@@ -0,0 +1,344 @@
+//! Generating Rust definitions of the migrated schema.
+//!
+//! Hand-written queries name tables and columns in string literals, which drift out of step with
+//! the migrations unnoticed. [`MonarchDB::rust_code`] migrates a scratch database and emits a
+//! Rust module describing the schema it ends up with: a module of name constants for each table
+//! and view, and a struct for its rows. Checking the generated file into a repository, and
+//! regenerating it in CI, turns a renamed column into a compile error.
+//!
+//! Column types follow SQLite's [type affinity] rules for the declared type: `INTEGER` affinity
+//! becomes `i64`, `TEXT` becomes `String`, `REAL` becomes `f64` and a `BLOB` declaration becomes
+//! `Vec<u8>`. Columns with `NUMERIC` affinity, such as `BOOLEAN` or `DATETIME`, or no declared
+//! type can hold values of any type and become [`rusqlite::types::Value`]. Columns which may be
+//! `NULL` are wrapped in `Option`.
+//!
+//! [type affinity]: https://www.sqlite.org/datatype3.html#determination_of_column_affinity
+
+use std::fmt::Write;
+
+use crate::metadata::{ColumnMetadata, TableMetadata, tables};
+use crate::{MonarchDB, Result};
+
+/// Names which are keywords in Rust and need a raw identifier, `r#type`.
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "box", "break", "const", "continue", "do", "dyn", "else", "enum",
+    "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "macro",
+    "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "static", "struct",
+    "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual", "where",
+    "while", "yield",
+];
+
+impl MonarchDB {
+    /// Generates Rust definitions of the tables and views the migrations create.
+    ///
+    /// The migrations, including repeatable migrations, are applied to a scratch in-memory
+    /// database, and each table and view in it becomes a module of `&str` constants, `TABLE`
+    /// and one per column, and a struct with a field per column and a `from_row` constructor.
+    /// Monarch's own tables are left out. The output is formatted like `rustfmt` would, so it
+    /// can be written to a file and checked in.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT NOT NULL);"],
+    /// }
+    /// .into();
+    ///
+    /// let code = monarch_db.rust_code()?;
+    /// assert!(code.contains("pub const EMAIL: &str = \"email\";"));
+    /// assert!(code.contains("pub struct Users {"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rust_code(&self) -> Result<String> {
+        let connection = self.migrate_scratch()?;
+        let tables: Vec<TableMetadata> = tables(&connection)?
+            .into_iter()
+            .filter(|table| !table.name.starts_with("monarch_db_"))
+            .collect();
+
+        let mut code = format!(
+            "// @generated by Monarch from the migrations of `{}`. Do not edit by hand.\n\n\
+             /// The schema version these definitions describe.\n\
+             pub const SCHEMA_VERSION: u32 = {};\n",
+            self.name,
+            self.current_version()
+        );
+        for table in &tables {
+            write_table(&mut code, table);
+        }
+        Ok(code)
+    }
+}
+
+/// Writes the constants module and row struct of `table`.
+fn write_table(code: &mut String, table: &TableMetadata) {
+    let module = identifier(&table.name);
+    let row = type_name(&table.name);
+    let kind = table.kind.as_str();
+
+    let _ = write!(
+        code,
+        "\n/// Names in the `{name}` {kind}.\n\
+         pub mod {module} {{\n    \
+         /// The name of the {kind}.\n    \
+         pub const TABLE: &str = {literal};\n",
+        name = table.name,
+        literal = literal(&table.name),
+    );
+    for column in &table.columns {
+        let _ = write!(
+            code,
+            "\n    /// The `{name}` column.\n    pub const {constant}: &str = {literal};\n",
+            name = column.name,
+            constant = constant_name(&column.name),
+            literal = literal(&column.name),
+        );
+    }
+    code.push_str("}\n");
+
+    let _ = write!(
+        code,
+        "\n/// A row of the `{name}` {kind}.\n\
+         #[derive(Debug, Clone, PartialEq)]\n\
+         pub struct {row} {{\n",
+        name = table.name,
+    );
+    for column in &table.columns {
+        let _ = writeln!(
+            code,
+            "    pub {field}: {ty},",
+            field = identifier(&column.name),
+            ty = rust_type(column),
+        );
+    }
+    let _ = write!(
+        code,
+        "}}\n\nimpl {row} {{\n    \
+         /// Reads a row which selected every column of the {kind} by name.\n    \
+         pub fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {{\n        \
+         Ok({row} {{\n",
+    );
+    for column in &table.columns {
+        let _ = writeln!(
+            code,
+            "            {field}: row.get({module}::{constant})?,",
+            field = identifier(&column.name),
+            constant = constant_name(&column.name),
+        );
+    }
+    code.push_str("        })\n    }\n}\n");
+}
+
+/// The Rust type of a column's values, by the affinity of its declared type.
+fn rust_type(column: &ColumnMetadata) -> String {
+    let declared = column.declared_type.to_uppercase();
+    let base = if declared.contains("INT") {
+        "i64"
+    } else if ["CHAR", "CLOB", "TEXT"]
+        .iter()
+        .any(|text| declared.contains(text))
+    {
+        "String"
+    } else if declared.contains("BLOB") {
+        "Vec<u8>"
+    } else if ["REAL", "FLOA", "DOUB"]
+        .iter()
+        .any(|real| declared.contains(real))
+    {
+        "f64"
+    } else {
+        // Any value fits, NULL included.
+        return "rusqlite::types::Value".to_owned();
+    };
+    // An INTEGER PRIMARY KEY is the rowid, which is never NULL.
+    let rowid = column.primary_key && declared == "INTEGER";
+    if column.not_null || rowid {
+        base.to_owned()
+    } else {
+        format!("Option<{base}>")
+    }
+}
+
+/// `name` with every character which cannot appear in an identifier replaced by `_`.
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{name}")
+    } else {
+        name
+    }
+}
+
+/// A field or module name for `name`, as a raw identifier if it is a keyword.
+fn identifier(name: &str) -> String {
+    let name = snake_case(name);
+    match name.as_str() {
+        "self" | "super" | "crate" => format!("{name}_"),
+        _ if KEYWORDS.contains(&name.as_str()) => format!("r#{name}"),
+        _ => name,
+    }
+}
+
+/// `name` in snake case, e.g. `created_at` for `createdAt`.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    let mut previous_lower = false;
+    for c in sanitize(name).chars() {
+        if c.is_ascii_uppercase() && previous_lower {
+            snake.push('_');
+        }
+        previous_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+/// A constant name for `name`, e.g. `CREATED_AT` for `createdAt`.
+fn constant_name(name: &str) -> String {
+    let constant = snake_case(name).to_uppercase();
+    if constant == "TABLE" {
+        format!("{constant}_")
+    } else {
+        constant
+    }
+}
+
+/// A type name for `name`, e.g. `UserAccounts` for `user_accounts`.
+fn type_name(name: &str) -> String {
+    let name = sanitize(name);
+    let mut type_name: String = name
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().map(|c| c.to_ascii_uppercase());
+            first.into_iter().chain(chars).collect::<String>()
+        })
+        .collect();
+    if type_name.starts_with(|c: char| c.is_ascii_digit()) || type_name == "Self" {
+        type_name.insert(0, '_');
+    }
+    type_name
+}
+
+/// `value` as a Rust string literal.
+fn literal(value: &str) -> String {
+    format!("{value:?}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rust_code() -> Result<()> {
+        let monarch_db = MonarchDB::from_static(
+            "codegen",
+            true,
+            &["CREATE TABLE user_accounts (
+                    id INTEGER PRIMARY KEY,
+                    email TEXT NOT NULL,
+                    createdAt DATETIME,
+                    score REAL,
+                    avatar BLOB,
+                    type VARCHAR(10)
+                );"],
+        )
+        .with_repeatable_migration(
+            "R__views.sql",
+            "CREATE VIEW IF NOT EXISTS emails AS SELECT email FROM user_accounts;",
+        );
+
+        let code = monarch_db.rust_code()?;
+        assert_eq!(
+            code,
+            r#"// @generated by Monarch from the migrations of `codegen`. Do not edit by hand.
+
+/// The schema version these definitions describe.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Names in the `emails` view.
+pub mod emails {
+    /// The name of the view.
+    pub const TABLE: &str = "emails";
+
+    /// The `email` column.
+    pub const EMAIL: &str = "email";
+}
+
+/// A row of the `emails` view.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Emails {
+    pub email: Option<String>,
+}
+
+impl Emails {
+    /// Reads a row which selected every column of the view by name.
+    pub fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Emails {
+            email: row.get(emails::EMAIL)?,
+        })
+    }
+}
+
+/// Names in the `user_accounts` table.
+pub mod user_accounts {
+    /// The name of the table.
+    pub const TABLE: &str = "user_accounts";
+
+    /// The `id` column.
+    pub const ID: &str = "id";
+
+    /// The `email` column.
+    pub const EMAIL: &str = "email";
+
+    /// The `createdAt` column.
+    pub const CREATED_AT: &str = "createdAt";
+
+    /// The `score` column.
+    pub const SCORE: &str = "score";
+
+    /// The `avatar` column.
+    pub const AVATAR: &str = "avatar";
+
+    /// The `type` column.
+    pub const TYPE: &str = "type";
+}
+
+/// A row of the `user_accounts` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserAccounts {
+    pub id: i64,
+    pub email: String,
+    pub created_at: rusqlite::types::Value,
+    pub score: Option<f64>,
+    pub avatar: Option<Vec<u8>>,
+    pub r#type: Option<String>,
+}
+
+impl UserAccounts {
+    /// Reads a row which selected every column of the table by name.
+    pub fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(UserAccounts {
+            id: row.get(user_accounts::ID)?,
+            email: row.get(user_accounts::EMAIL)?,
+            created_at: row.get(user_accounts::CREATED_AT)?,
+            score: row.get(user_accounts::SCORE)?,
+            avatar: row.get(user_accounts::AVATAR)?,
+            r#type: row.get(user_accounts::TYPE)?,
+        })
+    }
+}
+"#
+        );
+        Ok(())
+    }
+}
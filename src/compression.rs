@@ -0,0 +1,334 @@
+//! Migrations stored compressed.
+//!
+//! Years of migrations can add megabytes of SQL text to a binary. With the `compression` feature,
+//! migrations may be stored compressed with [Zstandard], as written by the `zstd` command line
+//! tool or crate:
+//!
+//! - A migration directory may hold `001_create_users.sql.zst` in place of
+//!   `001_create_users.sql`. The migration keeps the name `001_create_users.sql`, so compressing
+//!   a migration which has already run does not change its name or checksum.
+//! - [`CompressedMonarchConfiguration`] embeds compressed migrations with `include_bytes!`.
+//! - [`SourceMigration::compressed`] creates compressed migrations for other
+//!   [`MigrationSource`](crate::MigrationSource)s.
+//!
+//! Building a `MonarchDB` only decompresses the comments and directives at the start of each
+//! compressed migration. The rest is decompressed the first time its SQL is needed, usually when
+//! the migration runs, so the migrations a database has already applied stay compressed in
+//! memory. Comparing [checksums](crate::ChecksumPolicy) or reading the SQL with
+//! [`MonarchDB::migration_sql`](crate::MonarchDB::migration_sql) decompresses it too.
+//!
+//! A migration which cannot be decompressed fails with [`Error::CompressedMigration`] when that
+//! happens. Without the feature, loading a compressed migration fails with it rather than
+//! skipping the migration.
+//!
+//! [Zstandard]: https://facebook.github.io/zstd/
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::OnceLock;
+
+use crate::source::{MigrationSource, SourceMigration};
+use crate::{Error, Result, template};
+
+/// The suffix of a compressed migration file, following its `.sql` extension.
+pub(crate) const COMPRESSED_SUFFIX: &str = ".zst";
+
+/// The SQL of a compressed migration, decompressed the first time it is read.
+#[derive(Debug, Clone)]
+pub(crate) struct CompressedSql {
+    data: Cow<'static, [u8]>,
+    /// Template variables the SQL is rendered with, for a migration loaded from a directory.
+    variables: Option<BTreeMap<String, String>>,
+    sql: OnceLock<String>,
+}
+
+/// Compares the compressed data, whether or not either has been decompressed.
+impl PartialEq for CompressedSql {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data && self.variables == other.variables
+    }
+}
+
+impl Eq for CompressedSql {}
+
+impl CompressedSql {
+    pub(crate) fn new(data: Cow<'static, [u8]>) -> Self {
+        CompressedSql {
+            data,
+            variables: None,
+            sql: OnceLock::new(),
+        }
+    }
+
+    /// Renders the SQL as a template with `variables` once it is decompressed.
+    pub(crate) fn with_variables(mut self, variables: BTreeMap<String, String>) -> Self {
+        self.variables = Some(variables);
+        self
+    }
+
+    /// The blank and comment lines at the start of the SQL of the migration called `migration`,
+    /// which hold its directives, decompressing the data only up to its first statement.
+    pub(crate) fn header(&self, migration: &str) -> Result<String> {
+        let mut reader = BufReader::new(decoder(migration, &self.data)?);
+        let mut header = String::new();
+        loop {
+            let start = header.len();
+            let read = reader
+                .read_line(&mut header)
+                .map_err(|error| corrupt(migration, error))?;
+            let line = header[start..].trim_start_matches('\u{feff}').trim();
+            if read == 0 {
+                break;
+            }
+            if !line.is_empty() && !line.starts_with("--") {
+                header.truncate(start);
+                break;
+            }
+        }
+        self.render(migration, header)
+    }
+
+    /// The SQL of the migration called `migration`, decompressing it the first time it is read.
+    pub(crate) fn sql(&self, migration: &str) -> Result<&str> {
+        if let Some(sql) = self.sql.get() {
+            return Ok(sql);
+        }
+        let mut sql = String::new();
+        decoder(migration, &self.data)?
+            .read_to_string(&mut sql)
+            .map_err(|error| corrupt(migration, error))?;
+        let sql = self.render(migration, sql)?;
+        Ok(self.sql.get_or_init(|| sql))
+    }
+
+    /// Whether the SQL has been decompressed.
+    #[cfg(all(test, feature = "compression"))]
+    pub(crate) fn is_decompressed(&self) -> bool {
+        self.sql.get().is_some()
+    }
+
+    /// Drops a byte order mark from decompressed SQL and renders its template variables.
+    fn render(&self, migration: &str, sql: String) -> Result<String> {
+        let sql = match sql.strip_prefix('\u{feff}') {
+            Some(sql) => sql.to_owned(),
+            None => sql,
+        };
+        let Some(variables) = &self.variables else {
+            return Ok(sql);
+        };
+        template::render(&sql, variables).map_err(|variable| Error::UndefinedVariable {
+            migration: migration.to_owned(),
+            variable,
+        })
+    }
+}
+
+/// Reads the decompressed SQL of the migration called `migration`.
+#[cfg(feature = "compression")]
+fn decoder<'d>(migration: &str, data: &'d [u8]) -> Result<impl Read + 'd> {
+    zstd::stream::read::Decoder::with_buffer(data).map_err(|error| corrupt(migration, error))
+}
+
+/// Fails, since reading compressed migrations needs the `compression` feature.
+#[cfg(not(feature = "compression"))]
+fn decoder(migration: &str, _: &[u8]) -> Result<std::io::Empty> {
+    Err(Error::CompressedMigration {
+        migration: migration.to_owned(),
+        reason: "reading compressed migrations requires the `compression` feature".to_owned(),
+    })
+}
+
+fn corrupt(migration: &str, error: std::io::Error) -> Error {
+    Error::CompressedMigration {
+        migration: migration.to_owned(),
+        reason: error.to_string(),
+    }
+}
+
+impl SourceMigration {
+    /// Creates a migration from SQL compressed with Zstandard. Building a `MonarchDB` only
+    /// decompresses the comments and directives at the start of the SQL, and the rest is
+    /// decompressed the first time it is needed, usually when the migration runs.
+    ///
+    /// Building a `MonarchDB` from the migration fails with [`Error::CompressedMigration`] if the
+    /// start of the data cannot be decompressed or the `compression` feature is not enabled, and
+    /// reading its SQL fails with it if the rest cannot be.
+    pub fn compressed(
+        name: impl Into<Cow<'static, str>>,
+        compressed: impl Into<Cow<'static, [u8]>>,
+    ) -> Self {
+        let mut migration = SourceMigration::new(name, "");
+        migration.compressed = Some(CompressedSql::new(compressed.into()));
+        migration
+    }
+}
+
+/// Configuration for MonarchDB with compressed migrations embedded at compile time, typically
+/// with `include_bytes!`. Requires the `compression` feature.
+///
+/// Migrations are compressed with Zstandard, as by the `zstd` command line tool, and
+/// decompressed when they are needed, like [`SourceMigration::compressed`], so those a database
+/// has already applied stay compressed.
+///
+/// Like [`StaticMonarchConfiguration`](crate::StaticMonarchConfiguration), migrations are named
+/// by their versions, so a static configuration can switch to compressed migrations without
+/// changing their names. Build a `MonarchDB` from it with
+/// [`MonarchDB::from_source`](crate::MonarchDB::from_source).
+///
+/// ```rust,ignore
+/// use monarch_db::{CompressedMonarchConfiguration, MonarchDB};
+///
+/// let monarch_db = MonarchDB::from_source(CompressedMonarchConfiguration {
+///     name: "my_app",
+///     enable_foreign_keys: true,
+///     migrations: [
+///         include_bytes!("migrations/001_create_users.sql.zst"),
+///         include_bytes!("migrations/002_create_posts.sql.zst"),
+///     ],
+/// })?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompressedMonarchConfiguration<const N: usize> {
+    /// The name of the database schema, used for tracking migration versions.
+    pub name: &'static str,
+    /// Whether to enable foreign key constraints in SQLite.
+    pub enable_foreign_keys: bool,
+    /// Compressed migration SQL, ordered from oldest to newest.
+    pub migrations: [&'static [u8]; N],
+}
+
+/// Uses the embedded compressed migrations, named by their versions.
+impl<const N: usize> MigrationSource for CompressedMonarchConfiguration<N> {
+    fn name(&self) -> Cow<'static, str> {
+        self.name.into()
+    }
+
+    fn enable_foreign_keys(&self) -> bool {
+        self.enable_foreign_keys
+    }
+
+    fn migrations(&self) -> Result<Vec<SourceMigration>> {
+        Ok(self
+            .migrations
+            .iter()
+            .enumerate()
+            .map(|(index, compressed)| {
+                SourceMigration::compressed((index + 1).to_string(), *compressed)
+            })
+            .collect())
+    }
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use camino::Utf8PathBuf;
+
+    use crate::{MonarchConfiguration, MonarchDB};
+
+    use super::*;
+
+    fn compress(sql: &str) -> &'static [u8] {
+        zstd::encode_all(sql.as_bytes(), 0).unwrap().leak()
+    }
+
+    #[test]
+    fn test_compressed_configuration() -> Result<()> {
+        let users = compress("CREATE TABLE users (id INTEGER PRIMARY KEY);");
+        let posts = compress(
+            "-- monarch:depends-on 1\nCREATE TABLE posts (user_id INTEGER REFERENCES users (id));",
+        );
+        let monarch_db = MonarchDB::from_source(CompressedMonarchConfiguration {
+            name: "compressed",
+            enable_foreign_keys: true,
+            migrations: [users, posts],
+        })?;
+        assert_eq!(monarch_db.migrations[1].dependencies, ["1"]);
+        let compressed = |version: usize| match &monarch_db.migrations[version].sql {
+            crate::migration::Sql::Compressed(compressed) => compressed.is_decompressed(),
+            crate::migration::Sql::Text(_) => unreachable!("the migration is compressed"),
+        };
+        assert!(!compressed(0) && !compressed(1));
+
+        assert_eq!(
+            monarch_db.migration_sql(2)?,
+            Some(
+                "-- monarch:depends-on 1\n\
+                 CREATE TABLE posts (user_id INTEGER REFERENCES users (id));"
+            )
+        );
+        assert!(!compressed(0) && compressed(1));
+        monarch_db.open_in_memory()?;
+
+        let error = MonarchDB::from_source(CompressedMonarchConfiguration {
+            name: "compressed",
+            enable_foreign_keys: true,
+            migrations: [b"not zstd".as_slice()],
+        })
+        .unwrap_err();
+        assert!(
+            matches!(error, Error::CompressedMigration { .. }),
+            "{error}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_migration_fails_when_run() -> Result<()> {
+        let sql = format!(
+            "-- Users\nCREATE TABLE users (id);\n{}",
+            "SELECT 1;\n".repeat(30_000)
+        );
+        let compressed = compress(&sql);
+        let truncated = &compressed[..compressed.len() - 8];
+        let monarch_db = MonarchDB::from_source(CompressedMonarchConfiguration {
+            name: "compressed",
+            enable_foreign_keys: true,
+            migrations: [truncated],
+        })?;
+        assert_eq!(
+            monarch_db.migrations[0].description.as_deref(),
+            Some("Users")
+        );
+
+        let error = monarch_db.open_in_memory().unwrap_err();
+        assert!(
+            matches!(error, Error::CompressedMigration { .. }),
+            "{error}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_directory() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let path = Utf8PathBuf::try_from(directory.path().to_owned()).unwrap();
+        std::fs::write(
+            path.join("001_users.sql"),
+            "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+        )?;
+        std::fs::write(
+            path.join("002_posts.sql.zst"),
+            compress("CREATE TABLE {{prefix}}posts (id INTEGER PRIMARY KEY);"),
+        )?;
+
+        let configuration = MonarchConfiguration {
+            name: "compressed".into(),
+            enable_foreign_keys: true,
+            migration_directory: path.clone(),
+            variables: [("prefix".to_owned(), "app_".to_owned())].into(),
+            ..Default::default()
+        };
+        let monarch_db = MonarchDB::from_configuration(configuration.clone())?;
+        let names: Vec<&str> = monarch_db.migrations.iter().map(|m| &*m.name).collect();
+        assert_eq!(names, ["001_users.sql", "002_posts.sql"]);
+        let connection = monarch_db.open_in_memory()?;
+        connection.execute_batch("SELECT id FROM app_posts")?;
+
+        // A migration may not be both compressed and not.
+        std::fs::write(path.join("002_posts.sql"), "SELECT 1;")?;
+        let error = MonarchDB::from_configuration(configuration).unwrap_err();
+        assert!(matches!(error, Error::DuplicateMigration { .. }));
+        Ok(())
+    }
+}
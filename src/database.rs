@@ -0,0 +1,245 @@
+//! Where a connection's database lives.
+
+#[cfg(feature = "testing")]
+use std::sync::Arc;
+
+#[cfg(feature = "testing")]
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::RetryPolicy;
+
+/// The database a [`ConnectionConfiguration`](crate::ConnectionConfiguration) opens.
+///
+/// With the `serde` feature, a database deserializes from a path string, from a map with a
+/// single `shared_memory` key naming a shared in-memory database, or, when absent or null, as
+/// a private in-memory database. It serializes to the same forms, and a temporary file database
+/// serializes to its path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Database {
+    /// A private in-memory database, which disappears when its connection closes.
+    #[default]
+    Memory,
+    /// An in-memory database shared by every connection in the process which opens it by the
+    /// same name, through SQLite's shared cache.
+    ///
+    /// The database is migrated once, and lives until the last connection to it closes, so a
+    /// test or a connection pool can share it by keeping one connection open.
+    SharedMemory(String),
+    /// A database file, which is created if it does not exist.
+    ///
+    /// The path may also be an SQLite [URI filename], such as
+    /// `file:data.db?mode=ro&immutable=1`, whose query parameters set options for opening the
+    /// database. A read-only database can be opened this way as long as it needs no migrations.
+    ///
+    /// [URI filename]: https://www.sqlite.org/uri.html
+    File(Utf8PathBuf),
+    /// A database file in a unique temporary directory, which is removed along with the
+    /// database once every clone of the [`TempDatabase`] has been dropped.
+    ///
+    /// Unlike an in-memory database, this exercises the journal and `fsync` behaviour of a real
+    /// file, e.g. for integration tests of WAL mode. Requires the `testing` feature.
+    #[cfg(feature = "testing")]
+    TempFile(TempDatabase),
+}
+
+/// The handle which owns a [`Database::TempFile`].
+///
+/// Clones share one directory, so a [`ConnectionConfiguration`](crate::ConnectionConfiguration)
+/// holding the handle can be cloned freely; close the database's connections before dropping
+/// the last clone, since some platforms cannot remove open files.
+#[cfg(feature = "testing")]
+#[derive(Debug, Clone)]
+pub struct TempDatabase {
+    path: Utf8PathBuf,
+    _directory: Arc<tempfile::TempDir>,
+}
+
+#[cfg(feature = "testing")]
+impl TempDatabase {
+    /// Creates a unique temporary directory for a database named `database.db`.
+    pub fn new() -> std::io::Result<Self> {
+        let directory = tempfile::Builder::new().prefix("monarch-db-").tempdir()?;
+        let path = Utf8Path::from_path(directory.path())
+            .ok_or_else(|| std::io::Error::other("temporary directory path is not UTF-8"))?
+            .join("database.db");
+        Ok(TempDatabase {
+            path,
+            _directory: Arc::new(directory),
+        })
+    }
+
+    /// The path of the database file.
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+}
+
+#[cfg(feature = "testing")]
+impl PartialEq for TempDatabase {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+#[cfg(feature = "testing")]
+impl Eq for TempDatabase {}
+
+impl Database {
+    /// Opens a connection to the database.
+    pub(crate) fn open(&self, retry_policy: &RetryPolicy) -> rusqlite::Result<Connection> {
+        match self {
+            Database::Memory => Connection::open_in_memory(),
+            Database::SharedMemory(name) => {
+                let uri = format!("file:{}?mode=memory&cache=shared", uri_escape(name));
+                retry_policy.retry(|| Connection::open(&uri))
+            }
+            #[cfg(feature = "testing")]
+            Database::TempFile(temp) => retry_policy.retry(|| Connection::open(temp.path())),
+            Database::File(path) => retry_policy.retry(|| {
+                Connection::open_with_flags(path, OpenFlags::default() | OpenFlags::SQLITE_OPEN_URI)
+            }),
+        }
+    }
+
+    /// Whether a new connection to the database sees the same data as an existing one.
+    pub(crate) fn is_shared(&self) -> bool {
+        !matches!(self, Database::Memory)
+    }
+}
+
+impl From<Utf8PathBuf> for Database {
+    fn from(path: Utf8PathBuf) -> Self {
+        Database::File(path)
+    }
+}
+
+impl From<&str> for Database {
+    fn from(path: &str) -> Self {
+        Database::File(path.into())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Database {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        match self {
+            Database::Memory => serializer.serialize_none(),
+            Database::SharedMemory(name) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("shared_memory", name)?;
+                map.end()
+            }
+            Database::File(path) => path.serialize(serializer),
+            #[cfg(feature = "testing")]
+            Database::TempFile(temp) => temp.path().serialize(serializer),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Database {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "snake_case", deny_unknown_fields)]
+        struct Shared {
+            shared_memory: String,
+        }
+
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            File(Utf8PathBuf),
+            Shared(Shared),
+        }
+
+        Ok(match Option::<Repr>::deserialize(deserializer)? {
+            None => Database::Memory,
+            Some(Repr::File(path)) => Database::File(path),
+            Some(Repr::Shared(shared)) => Database::SharedMemory(shared.shared_memory),
+        })
+    }
+}
+
+/// Escapes the characters which would end the path of a `file:` URI.
+fn uri_escape(name: &str) -> String {
+    name.replace('%', "%25")
+        .replace('?', "%3f")
+        .replace('#', "%23")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_temp_file_is_removed_with_its_handle() -> Result<(), Box<dyn std::error::Error>> {
+        let temp = TempDatabase::new()?;
+        let path = temp.path().to_owned();
+        let database = Database::TempFile(temp);
+        let connection = database.open(&RetryPolicy::NONE)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (1);")?;
+        assert!(path.exists());
+
+        let copy = database.clone();
+        drop(database);
+        assert!(path.exists());
+
+        drop(connection);
+        drop(copy);
+        assert!(!path.exists());
+        assert!(!path.parent().unwrap().exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_memory_is_shared_by_name() -> crate::Result<()> {
+        let shared = Database::SharedMemory("database_test_shared?#%".to_owned());
+        let first = shared.open(&RetryPolicy::NONE)?;
+        first.execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (1);")?;
+
+        let second = shared.open(&RetryPolicy::NONE)?;
+        let x: i64 = second.query_row("SELECT x FROM t", [], |row| row.get(0))?;
+        assert_eq!(x, 1);
+
+        let other = Database::SharedMemory("database_test_other".to_owned());
+        let tables: i64 = other.open(&RetryPolicy::NONE)?.query_row(
+            "SELECT COUNT(*) FROM sqlite_master",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(tables, 0);
+        Ok(())
+    }
+
+    #[cfg(all(feature = "serde", feature = "cli"))]
+    #[test]
+    fn test_serialize_round_trip() -> serde_json::Result<()> {
+        for database in [
+            Database::Memory,
+            Database::SharedMemory("app".to_owned()),
+            Database::File("./app.db".into()),
+        ] {
+            let json = serde_json::to_string(&database)?;
+            assert_eq!(serde_json::from_str::<Database>(&json)?, database, "{json}");
+        }
+        Ok(())
+    }
+
+    #[cfg(all(feature = "serde", feature = "cli"))]
+    #[test]
+    fn test_deserialize() -> serde_json::Result<()> {
+        let database: Database = serde_json::from_str(r#""./app.db""#)?;
+        assert_eq!(database, Database::File("./app.db".into()));
+        let database: Database = serde_json::from_str(r#"{"shared_memory": "app"}"#)?;
+        assert_eq!(database, Database::SharedMemory("app".to_owned()));
+        let database: Database = serde_json::from_str("null")?;
+        assert_eq!(database, Database::Memory);
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+//! Ordering migrations by the dependencies they declare.
+//!
+//! When independent modules contribute migrations, numbering alone cannot say that a module's
+//! migration needs a table another module creates. A migration may name the migrations it needs
+//! with a `-- monarch:depends-on` directive, and is then moved after them if it would otherwise
+//! run first. Migrations otherwise keep their order, so adding a dependency which already holds
+//! changes nothing.
+
+use std::collections::BTreeMap;
+
+use crate::migration::Migration;
+use crate::{Error, Result};
+
+/// Orders `migrations` so that each runs after its dependencies, keeping their order otherwise.
+///
+/// Fails with [`Error::MissingDependency`] if a migration depends on one which does not exist,
+/// and with [`Error::DependencyCycle`] if migrations depend on each other.
+pub(crate) fn order_by_dependencies(migrations: Vec<Migration>) -> Result<Vec<Migration>> {
+    if migrations.iter().all(|m| m.dependencies.is_empty()) {
+        return Ok(migrations);
+    }
+
+    let positions: BTreeMap<&str, usize> = migrations
+        .iter()
+        .enumerate()
+        .map(|(index, migration)| (&*migration.name, index))
+        .collect();
+    let mut dependencies = Vec::with_capacity(migrations.len());
+    for migration in &migrations {
+        let mut indices = Vec::new();
+        for dependency in &migration.dependencies {
+            let index =
+                positions
+                    .get(dependency.as_str())
+                    .ok_or_else(|| Error::MissingDependency {
+                        migration: migration.name.to_string(),
+                        dependency: dependency.clone(),
+                    })?;
+            indices.push(*index);
+        }
+        dependencies.push(indices);
+    }
+
+    // Repeatedly take the earliest migration whose dependencies have all been taken.
+    let mut order = Vec::with_capacity(migrations.len());
+    let mut taken = vec![false; migrations.len()];
+    while order.len() < migrations.len() {
+        let next = (0..migrations.len())
+            .find(|&index| !taken[index] && dependencies[index].iter().all(|&d| taken[d]));
+        let Some(next) = next else {
+            return Err(Error::DependencyCycle {
+                migrations: cycle(&migrations, &dependencies, &taken),
+            });
+        };
+        taken[next] = true;
+        order.push(next);
+    }
+
+    let mut migrations: Vec<Option<Migration>> = migrations.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .filter_map(|index| migrations[index].take())
+        .collect())
+}
+
+/// The names of migrations which depend on each other in a cycle, among those not `taken`.
+fn cycle(migrations: &[Migration], dependencies: &[Vec<usize>], taken: &[bool]) -> Vec<String> {
+    // Every migration left has a dependency left, so following them must come back around.
+    let mut path = Vec::new();
+    let mut index = (0..migrations.len())
+        .find(|&index| !taken[index])
+        .expect("a migration is left");
+    while !path.contains(&index) {
+        path.push(index);
+        index = *dependencies[index]
+            .iter()
+            .find(|&&dependency| !taken[dependency])
+            .expect("a dependency is left");
+    }
+    let start = path.iter().position(|&i| i == index).unwrap_or(0);
+    path[start..]
+        .iter()
+        .map(|&index| migrations[index].name.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(migrations: &[Migration]) -> Vec<&str> {
+        migrations.iter().map(|m| &*m.name).collect()
+    }
+
+    #[test]
+    fn test_order_by_dependencies() -> Result<()> {
+        let migrations = vec![
+            Migration::new("001_users.sql", "CREATE TABLE users (id);"),
+            Migration::new(
+                "002_invoices.sql",
+                "-- monarch:depends-on 003_accounts.sql, 001_users.sql\nCREATE TABLE invoices (id);",
+            ),
+            Migration::new("003_accounts.sql", "CREATE TABLE accounts (id);"),
+            Migration::new("004_tags.sql", "CREATE TABLE tags (id);"),
+        ];
+        let ordered = order_by_dependencies(migrations)?;
+        assert_eq!(
+            names(&ordered),
+            [
+                "001_users.sql",
+                "003_accounts.sql",
+                "002_invoices.sql",
+                "004_tags.sql"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dependency_errors() {
+        let missing = vec![Migration::new(
+            "001_users.sql",
+            "-- monarch:depends-on 000_setup.sql\nSELECT 1;",
+        )];
+        assert!(matches!(
+            order_by_dependencies(missing),
+            Err(Error::MissingDependency { .. })
+        ));
+
+        let cyclic = vec![
+            Migration::new("001_users.sql", "SELECT 1;"),
+            Migration::new("002_a.sql", "-- monarch:depends-on 003_b.sql\nSELECT 2;"),
+            Migration::new("003_b.sql", "-- monarch:depends-on 002_a.sql\nSELECT 3;"),
+        ];
+        match order_by_dependencies(cyclic) {
+            Err(Error::DependencyCycle { migrations }) => {
+                assert_eq!(migrations, ["002_a.sql", "003_b.sql"]);
+            }
+            other => panic!("expected a dependency cycle, found {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! A plain description of a [`MonarchDB`], e.g. to log at startup or to emit for debugging.
+
+use std::time::Duration;
+
+use crate::{
+    ChecksumAlgorithm, ChecksumPolicy, DeterminismPolicy, MonarchDB, Result, RetryPolicy,
+    TransactionMode, VersionAheadPolicy,
+};
+
+/// The migrations and settings of a [`MonarchDB`], see [`MonarchDB::describe`].
+///
+/// With the `serde` feature, a description can be serialized and deserialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MonarchDescription {
+    /// The name of the schema.
+    pub name: String,
+    /// Whether foreign key constraints are enabled.
+    pub enable_foreign_keys: bool,
+    /// The version the migrations reach, see [`MonarchDB::current_version`].
+    pub current_version: u32,
+    /// The environment migrations run in, see [`MonarchDB::with_environment`].
+    pub environment: Option<String>,
+    /// The versioned migrations, in the order they run.
+    pub migrations: Vec<MigrationDescription>,
+    /// The repeatable migrations, in the order they run.
+    pub repeatable: Vec<MigrationDescription>,
+    /// How long to wait for the migration lock, see [`MonarchDB::with_lock_timeout`].
+    pub lock_timeout: Duration,
+    /// How lock errors are retried, see [`MonarchDB::with_retry_policy`].
+    pub retry_policy: RetryPolicy,
+    /// How migrations are grouped into transactions, see [`MonarchDB::with_transaction_mode`].
+    pub transaction_mode: TransactionMode,
+    /// How nondeterministic data changes are treated, see
+    /// [`MonarchDB::with_determinism_policy`].
+    pub determinism_policy: DeterminismPolicy,
+    /// How databases past the last migration are treated, see
+    /// [`MonarchDB::with_version_ahead_policy`].
+    pub version_ahead_policy: VersionAheadPolicy,
+    /// How migrations changed after they were applied are treated, see
+    /// [`MonarchDB::with_checksum_policy`].
+    pub checksum_policy: ChecksumPolicy,
+    /// The hash of the recorded checksums, see [`MonarchDB::with_checksum_algorithm`].
+    pub checksum_algorithm: ChecksumAlgorithm,
+}
+
+/// A migration in a [`MonarchDescription`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MigrationDescription {
+    /// The version the migration reaches, or `None` for a repeatable migration.
+    pub version: Option<u32>,
+    /// The name of the migration, its file name for migrations loaded from a directory.
+    pub name: String,
+    /// The checksum of the migration's SQL, as recorded in [`MonarchDB::history`].
+    pub checksum: String,
+}
+
+impl MonarchDB {
+    /// Describes the migrations and settings of this `MonarchDB`.
+    ///
+    /// Hooks, such as those registered with [`with_connection_setup`](Self::with_connection_setup),
+    /// are code rather than settings and are left out.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+    /// }
+    /// .into();
+    ///
+    /// let description = monarch_db.describe()?;
+    /// assert_eq!(description.current_version, 1);
+    /// assert_eq!(description.migrations[0].version, Some(1));
+    /// # Ok::<(), monarch_db::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::CompressedMigration`](crate::Error::CompressedMigration) if a
+    /// compressed migration cannot be decompressed to compute its checksum.
+    pub fn describe(&self) -> Result<MonarchDescription> {
+        let first = self.baseline().unwrap_or(1);
+        let describe = |version, migration: &crate::migration::Migration| {
+            Ok(MigrationDescription {
+                version,
+                name: migration.name.to_string(),
+                checksum: self.checksum(migration.sql()?),
+            })
+        };
+        Ok(MonarchDescription {
+            name: self.name.to_string(),
+            enable_foreign_keys: self.enable_foreign_keys,
+            current_version: self.current_version(),
+            environment: self.environment().map(str::to_owned),
+            migrations: self
+                .migrations
+                .iter()
+                .enumerate()
+                .map(|(index, migration)| describe(Some(first + index as u32), migration))
+                .collect::<Result<_>>()?,
+            repeatable: self
+                .repeatables
+                .iter()
+                .map(|migration| describe(None, migration))
+                .collect::<Result<_>>()?,
+            lock_timeout: self.lock_timeout,
+            retry_policy: self.retry_policy,
+            transaction_mode: self.transaction_mode,
+            determinism_policy: self.determinism_policy,
+            version_ahead_policy: self.version_ahead_policy,
+            checksum_policy: self.checksum_policy,
+            checksum_algorithm: self.checksum_algorithm,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "serde", feature = "cli"))]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_description_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "describe",
+            enable_foreign_keys: true,
+            migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+        })
+        .with_repeatable_migration("R__views.sql", "CREATE VIEW IF NOT EXISTS v AS SELECT 1;")
+        .with_transaction_mode(TransactionMode::PerMigration)
+        .with_environment("dev");
+
+        let description = monarch_db.describe()?;
+        let json = serde_json::to_value(&description)?;
+        assert_eq!(json["transaction_mode"], "per_migration");
+        assert_eq!(json["repeatable"][0]["version"], serde_json::Value::Null);
+        assert_eq!(json["migrations"][0]["checksum"], "6a8f4358b233fe97");
+        assert_eq!(
+            serde_json::from_value::<MonarchDescription>(json)?,
+            description
+        );
+        Ok(())
+    }
+}
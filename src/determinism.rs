@@ -0,0 +1,314 @@
+//! Linting migrations for nondeterministic data changes.
+//!
+//! A migration which writes data using `random()`, the current time, or a UUID generator produces
+//! different databases each time it runs, so databases built from the same migrations cannot be
+//! reproduced or compared across environments. [`MonarchDB::lint_determinism`] flags such
+//! expressions in statements which write data, and [`MonarchDB::with_determinism_policy`] can
+//! warn about or reject them when migrating. Schema definitions, such as a column's
+//! `DEFAULT CURRENT_TIMESTAMP` or a trigger body, are not flagged.
+
+use std::fmt;
+
+use crate::migration::Migration;
+use crate::sql::{Token, TokenKind, statements};
+use crate::{Error, MonarchDB, Result};
+
+/// Functions which return a different value on every call.
+const RANDOM_FUNCTIONS: &[&str] = &[
+    "random",
+    "randomblob",
+    "uuid",
+    "uuid_str",
+    "uuid_blob",
+    "gen_random_uuid",
+];
+
+/// Date and time functions, which use the current time when given `'now'` or no time value.
+const TIME_FUNCTIONS: &[&str] = &[
+    "date",
+    "time",
+    "datetime",
+    "julianday",
+    "unixepoch",
+    "strftime",
+];
+
+/// Keywords which evaluate to the current time.
+const TIME_KEYWORDS: &[&str] = &["CURRENT_TIMESTAMP", "CURRENT_DATE", "CURRENT_TIME"];
+
+/// How nondeterministic data changes are treated when migrating.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum DeterminismPolicy {
+    /// Run migrations without checking them.
+    #[default]
+    Allow,
+    /// Log a warning for each nondeterministic expression in a migration before running it.
+    Warn,
+    /// Refuse to run a migration with a nondeterministic expression, failing the migration.
+    Reject,
+}
+
+/// A nondeterministic expression in a statement which writes data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nondeterminism {
+    /// The name of the migration, its file name for migrations loaded from a directory.
+    pub migration: String,
+    /// The 1-based line of the expression within the migration.
+    pub line: usize,
+    /// The expression, as written in the migration.
+    pub expression: String,
+}
+
+impl fmt::Display for Nondeterminism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "migration {} line {}: {} makes a data change nondeterministic",
+            self.migration, self.line, self.expression
+        )
+    }
+}
+
+impl std::error::Error for Nondeterminism {}
+
+impl MonarchDB {
+    /// Sets how nondeterministic data changes are treated when migrating, see
+    /// [`DeterminismPolicy`]. Defaults to [`DeterminismPolicy::Allow`].
+    ///
+    /// With [`DeterminismPolicy::Reject`], migrating fails with
+    /// [`Error::Nondeterministic`] for the first [`Nondeterminism`] found, and the transaction is
+    /// rolled back.
+    pub fn with_determinism_policy(mut self, policy: DeterminismPolicy) -> Self {
+        self.determinism_policy = policy;
+        self
+    }
+
+    /// Finds nondeterministic expressions in statements which write data, in every migration
+    /// including repeatable migrations and those outside the configured environment.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE tokens (value TEXT, created_at TEXT DEFAULT CURRENT_TIMESTAMP);",
+    ///         "INSERT INTO tokens (value) VALUES (hex(randomblob(16)));",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// let findings = monarch_db.lint_determinism()?;
+    /// assert_eq!(findings.len(), 1);
+    /// assert_eq!(findings[0].migration, "2");
+    /// assert_eq!(findings[0].expression, "randomblob(16)");
+    /// # Ok::<(), monarch_db::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::CompressedMigration`] if a compressed migration cannot be
+    /// decompressed.
+    pub fn lint_determinism(&self) -> Result<Vec<Nondeterminism>> {
+        let mut findings = Vec::new();
+        for migration in self.migrations.iter().chain(&self.repeatables) {
+            findings.extend(lint(migration)?);
+        }
+        Ok(findings)
+    }
+
+    /// Applies the determinism policy to a migration which is about to run.
+    pub(crate) fn check_determinism(&self, migration: &Migration) -> Result<()> {
+        if self.determinism_policy == DeterminismPolicy::Allow {
+            return Ok(());
+        }
+        for finding in lint(migration)? {
+            if self.determinism_policy == DeterminismPolicy::Reject {
+                return Err(Error::Nondeterministic(finding));
+            }
+            tracing::warn!(%finding, "Nondeterministic migration");
+        }
+        Ok(())
+    }
+}
+
+/// Finds nondeterministic expressions in the statements of `migration` which write data.
+fn lint(migration: &Migration) -> Result<Vec<Nondeterminism>> {
+    let sql = migration.sql()?;
+    let mut findings = Vec::new();
+    for statement in statements(sql) {
+        if !writes_data(&statement) {
+            continue;
+        }
+        for (index, token) in statement.iter().enumerate() {
+            let Some(end) = nondeterministic(&statement, index) else {
+                continue;
+            };
+            let last = &statement[end];
+            findings.push(Nondeterminism {
+                migration: migration.name.to_string(),
+                line: token.line,
+                expression: sql[token.offset..last.offset + last.text.len()].to_owned(),
+            });
+        }
+    }
+    Ok(findings)
+}
+
+/// Whether a statement inserts, updates or deletes rows.
+fn writes_data(statement: &[Token<'_>]) -> bool {
+    let writes = |token: &Token<'_>| {
+        ["INSERT", "REPLACE", "UPDATE", "DELETE"]
+            .iter()
+            .any(|keyword| token.is_keyword(keyword))
+    };
+    match statement.first() {
+        Some(first) if writes(first) => true,
+        // A common table expression followed by a write.
+        Some(first) if first.is_keyword("WITH") => statement.iter().any(writes),
+        // CREATE TABLE ... AS SELECT.
+        Some(first) if first.is_keyword("CREATE") => {
+            statement.iter().any(|token| token.is_keyword("TABLE"))
+                && statement.windows(2).any(|pair| {
+                    pair[0].is_keyword("AS")
+                        && (pair[1].is_keyword("SELECT")
+                            || pair[1].is_keyword("WITH")
+                            || pair[1].is_keyword("VALUES"))
+                })
+        }
+        _ => false,
+    }
+}
+
+/// If a nondeterministic expression starts at `statement[index]`, the index of its last token.
+fn nondeterministic(statement: &[Token<'_>], index: usize) -> Option<usize> {
+    let token = &statement[index];
+    if token.kind != TokenKind::Word {
+        return None;
+    }
+    let is_call = statement
+        .get(index + 1)
+        .is_some_and(|next| next.is_punctuation('('));
+
+    if !is_call {
+        return TIME_KEYWORDS
+            .iter()
+            .any(|keyword| token.is_keyword(keyword))
+            .then_some(index);
+    }
+
+    let close = closing_parenthesis(statement, index + 1)?;
+    let arguments = &statement[index + 2..close];
+    if RANDOM_FUNCTIONS.iter().any(|name| token.is_keyword(name)) {
+        return Some(close);
+    }
+    if TIME_FUNCTIONS.iter().any(|name| token.is_keyword(name)) {
+        let uses_now = arguments.iter().any(|argument| {
+            argument.kind == TokenKind::String && argument.text.eq_ignore_ascii_case("'now'")
+        });
+        // strftime() requires a format, so only its second argument defaults to now.
+        let defaults_to_now = if token.is_keyword("strftime") {
+            !arguments
+                .iter()
+                .any(|argument| argument.is_punctuation(','))
+        } else {
+            arguments.is_empty()
+        };
+        if uses_now || defaults_to_now {
+            return Some(close);
+        }
+    }
+    None
+}
+
+/// The index of the parenthesis closing the one at `statement[open]`.
+fn closing_parenthesis(statement: &[Token<'_>], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (index, token) in statement.iter().enumerate().skip(open) {
+        if token.is_punctuation('(') {
+            depth += 1;
+        } else if token.is_punctuation(')') {
+            depth -= 1;
+            if depth == 0 {
+                return Some(index);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expressions(sql: &'static str) -> Vec<String> {
+        lint(&Migration::new("test.sql", sql))
+            .unwrap()
+            .into_iter()
+            .map(|finding| finding.expression)
+            .collect()
+    }
+
+    #[test]
+    fn test_flags_nondeterministic_writes() {
+        let sql = "CREATE TABLE t (id, token, created DEFAULT CURRENT_TIMESTAMP);
+            INSERT INTO t (id, token) VALUES (1, hex(randomblob(16)));
+            UPDATE t SET created = datetime('now'), token = uuid();
+            UPDATE t SET created = strftime('%s'), id = random () % 10;
+            DELETE FROM t WHERE created < date('now', '-1 day');
+            CREATE TABLE snapshot AS SELECT *, CURRENT_TIME AS taken FROM t;";
+        assert_eq!(
+            expressions(sql),
+            [
+                "randomblob(16)",
+                "datetime('now')",
+                "uuid()",
+                "strftime('%s')",
+                "random ()",
+                "date('now', '-1 day')",
+                "CURRENT_TIME",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ignores_schema_and_fixed_times() {
+        let sql = "CREATE TABLE t (id, created TEXT DEFAULT (datetime('now')));
+            CREATE TRIGGER t_created AFTER INSERT ON t BEGIN
+                UPDATE t SET created = CURRENT_TIMESTAMP WHERE id = NEW.id;
+            END;
+            INSERT INTO t (id, created) VALUES (1, datetime('2024-01-01')), (2, 'random()');
+            UPDATE t SET created = strftime('%Y', created);
+            SELECT random();";
+        assert!(expressions(sql).is_empty());
+    }
+
+    #[test]
+    fn test_reject_policy() -> Result<()> {
+        use crate::StaticMonarchConfiguration;
+
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "determinism",
+            enable_foreign_keys: false,
+            migrations: ["CREATE TABLE t (id);", "INSERT INTO t VALUES (random());"],
+        });
+        let findings = monarch_db.lint_determinism()?;
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].line, 1);
+        assert!(monarch_db.open_in_memory().is_ok());
+
+        let monarch_db = monarch_db.with_determinism_policy(DeterminismPolicy::Reject);
+        let error = monarch_db.open_in_memory().unwrap_err();
+        let Error::Nondeterministic(finding) = error else {
+            panic!("unexpected error {error:?}");
+        };
+        assert_eq!(finding, findings[0]);
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Hashing table contents to compare databases.
+//!
+//! After a migration runs on several replicas, [`MonarchDB::content_digest`] confirms they hold
+//! the same data without copying it anywhere: each table's rows are read in a stable order and
+//! hashed with a canonical encoding, so equal digests mean equal contents.
+
+use std::fmt;
+
+use rusqlite::Connection;
+use rusqlite::types::ValueRef;
+
+use crate::sql::quote;
+use crate::{MonarchDB, Result};
+
+/// The 64-bit FNV-1a hash, which is stable across platforms and releases.
+///
+/// It is not cryptographic: digests detect accidental differences, not tampering.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fnv64(u64);
+
+impl Fnv64 {
+    pub(crate) fn new() -> Self {
+        Fnv64(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    /// The hash as 16 hex digits.
+    pub(crate) fn finish_hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+/// The digest of one table's contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableDigest {
+    /// The name of the table.
+    pub table: String,
+    /// The number of rows in the table.
+    pub rows: u64,
+    /// A hash of the table's column names and rows, as 16 hex digits.
+    pub digest: String,
+}
+
+impl fmt::Display for TableDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ({} rows)", self.table, self.digest, self.rows)
+    }
+}
+
+impl MonarchDB {
+    /// Computes a digest of the contents of each of `tables` in the connection's `main` schema,
+    /// or of every table when `tables` is empty.
+    ///
+    /// Rows are hashed in order of all their columns, with each value encoded with its storage
+    /// class, so the digest depends only on the column names and the set of rows, not on rowids
+    /// or the order rows were inserted. Two databases whose tables have equal digests hold the
+    /// same data. Digests are returned sorted by table name, and exclude SQLite's internal tables
+    /// and Monarch's own `monarch_db_` tables unless they are named explicitly.
+    ///
+    /// ```rust
+    /// use monarch_db::MonarchDB;
+    ///
+    /// # fn main() -> monarch_db::Result<()> {
+    /// let a = rusqlite::Connection::open_in_memory()?;
+    /// a.execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (1), (2);")?;
+    /// let b = rusqlite::Connection::open_in_memory()?;
+    /// b.execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (2), (1);")?;
+    ///
+    /// assert_eq!(MonarchDB::content_digest(&a, &[])?, MonarchDB::content_digest(&b, &["t"])?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn content_digest(connection: &Connection, tables: &[&str]) -> Result<Vec<TableDigest>> {
+        let mut names: Vec<String> = if tables.is_empty() {
+            let mut stmt = connection.prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'table' \
+                 AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' \
+                 AND name NOT LIKE 'monarch\\_db\\_%' ESCAPE '\\'",
+            )?;
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?
+        } else {
+            tables.iter().map(|table| table.to_string()).collect()
+        };
+        names.sort();
+        names.dedup();
+
+        Ok(names
+            .into_iter()
+            .map(|table| table_digest(connection, table))
+            .collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+fn table_digest(connection: &Connection, table: String) -> rusqlite::Result<TableDigest> {
+    let columns: Vec<String> = {
+        let mut stmt = connection.prepare("SELECT name FROM pragma_table_info(?1) ORDER BY cid")?;
+        stmt.query_map([&table], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?
+    };
+    if columns.is_empty() {
+        // Let SQLite report the missing table.
+        connection.prepare(&format!("SELECT * FROM {}", quote(&table)))?;
+    }
+
+    let mut hasher = Fnv64::new();
+    for column in &columns {
+        write_bytes(&mut hasher, column.as_bytes());
+    }
+
+    let quoted: Vec<String> = columns.iter().map(|column| quote(column)).collect();
+    let mut stmt = connection.prepare(&format!(
+        "SELECT {columns} FROM {table} ORDER BY {columns}",
+        columns = quoted.join(", "),
+        table = quote(&table),
+    ))?;
+    let mut rows = stmt.query([])?;
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        for index in 0..columns.len() {
+            match row.get_ref(index)? {
+                ValueRef::Null => hasher.write(&[0]),
+                ValueRef::Integer(value) => {
+                    hasher.write(&[1]);
+                    hasher.write(&value.to_be_bytes());
+                }
+                ValueRef::Real(value) => {
+                    hasher.write(&[2]);
+                    hasher.write(&value.to_bits().to_be_bytes());
+                }
+                ValueRef::Text(value) => {
+                    hasher.write(&[3]);
+                    write_bytes(&mut hasher, value);
+                }
+                ValueRef::Blob(value) => {
+                    hasher.write(&[4]);
+                    write_bytes(&mut hasher, value);
+                }
+            }
+        }
+        count += 1;
+    }
+
+    Ok(TableDigest {
+        table,
+        rows: count,
+        digest: hasher.finish_hex(),
+    })
+}
+
+/// Writes a length-prefixed byte string, so adjacent values cannot run together.
+fn write_bytes(hasher: &mut Fnv64, bytes: &[u8]) {
+    hasher.write(&(bytes.len() as u64).to_be_bytes());
+    hasher.write(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest_ignores_row_order_and_detects_changes() -> Result<()> {
+        let schema =
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, score REAL, avatar BLOB);
+                      CREATE TABLE empty (x);";
+        let a = Connection::open_in_memory()?;
+        a.execute_batch(schema)?;
+        a.execute_batch(
+            "INSERT INTO users VALUES (1, 'alice', 1.5, x'00'), (2, 'bob', NULL, NULL);",
+        )?;
+        let b = Connection::open_in_memory()?;
+        b.execute_batch(schema)?;
+        b.execute_batch(
+            "INSERT INTO users VALUES (2, 'bob', NULL, NULL), (1, 'alice', 1.5, x'00');",
+        )?;
+
+        let digests = MonarchDB::content_digest(&a, &[])?;
+        let tables: Vec<_> = digests.iter().map(|d| (d.table.as_str(), d.rows)).collect();
+        assert_eq!(tables, [("empty", 0), ("users", 2)]);
+        assert_eq!(digests, MonarchDB::content_digest(&b, &[])?);
+
+        // The same text stored as a blob is different content.
+        b.execute(
+            "UPDATE users SET name = CAST(name AS BLOB) WHERE id = 1",
+            [],
+        )?;
+        assert_ne!(
+            MonarchDB::content_digest(&a, &["users"])?,
+            MonarchDB::content_digest(&b, &["users"])?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_table() -> Result<()> {
+        let connection = Connection::open_in_memory()?;
+        let error = MonarchDB::content_digest(&connection, &["missing"]).unwrap_err();
+        assert!(error.to_string().contains("no such table"), "{error}");
+        Ok(())
+    }
+}
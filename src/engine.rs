@@ -0,0 +1,96 @@
+//! The steps of migrating a database, for tools which provide their own frontend.
+//!
+//! [`MonarchDB::migrate`] runs every step of a migration at once. Tools which embed Monarch,
+//! such as a database manager which asks before each upgrade or a framework's own command line,
+//! can instead drive the same steps themselves and keep Monarch's semantics for the parts they
+//! do not customize:
+//!
+//! 1. [`prepare_connection`] runs the connection setup hooks and enables foreign keys, which
+//!    must happen outside of a transaction.
+//! 2. [`begin`] takes the migration lock with a `BEGIN IMMEDIATE` transaction, waiting and
+//!    retrying as configured.
+//! 3. [`MonarchDB::plan`] lists the pending migrations in the order they run, and
+//!    [`recorded_version`] reads the version recorded for a schema.
+//! 4. [`apply`] runs the pending migrations up to a target version inside the transaction, and
+//!    records the version reached.
+//! 5. The caller commits the transaction, or drops it to roll back.
+//!
+//! The functions in this module follow semantic versioning like the rest of the public API:
+//! their signatures and the meaning of each step only change in a major release.
+//!
+//! ```rust
+//! use monarch_db::{MonarchDB, StaticMonarchConfiguration, engine};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let monarch_db: MonarchDB = StaticMonarchConfiguration {
+//!     name: "my_app",
+//!     enable_foreign_keys: true,
+//!     migrations: [
+//!         "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+//!         "ALTER TABLE users ADD COLUMN name TEXT;",
+//!     ],
+//! }
+//! .into();
+//!
+//! let connection = rusqlite::Connection::open_in_memory()?;
+//! engine::prepare_connection(&monarch_db, &connection)?;
+//! let tx = engine::begin(&monarch_db, &connection)?;
+//! for migration in monarch_db.plan(&tx)?.migrations {
+//!     println!("Upgrading to {} ({})", migration.version, migration.name);
+//!     engine::apply(&monarch_db, &tx, migration.version)?;
+//! }
+//! tx.commit()?;
+//!
+//! assert_eq!(engine::recorded_version(&connection, "my_app")?, 2);
+//! # Ok(())
+//! # }
+//! ```
+
+use rusqlite::{Connection, Transaction};
+
+use crate::{MigrationReport, MonarchDB, Result, begin_immediate};
+
+/// Runs the connection setup hooks registered with [`MonarchDB::with_connection_setup`], then
+/// enables foreign keys if the schema asks for them.
+///
+/// Call this before [`begin`], since SQLite ignores the `foreign_keys` pragma inside a
+/// transaction.
+pub fn prepare_connection(monarch: &MonarchDB, connection: &Connection) -> Result<()> {
+    monarch.setup_connection(connection)?;
+    if monarch.enable_foreign_keys {
+        monarch
+            .retry_policy
+            .retry(|| connection.pragma_update(None, "foreign_keys", true))?;
+    }
+    Ok(())
+}
+
+/// Starts the transaction migrations run in, taking the database's write lock.
+///
+/// While another connection holds the lock, this waits for up to the lock timeout (see
+/// [`MonarchDB::with_lock_timeout`]), and the whole attempt is retried according to the retry
+/// policy (see [`MonarchDB::with_retry_policy`]).
+pub fn begin<'c>(monarch: &MonarchDB, connection: &'c Connection) -> Result<Transaction<'c>> {
+    Ok(monarch
+        .retry_policy
+        .retry(|| begin_immediate(connection, monarch.lock_timeout))?)
+}
+
+/// The schema version recorded in `connection` for the schema named `schema`, or 0 if none is.
+///
+/// Nothing is written to the database, so this works on a read-only connection.
+pub fn recorded_version(connection: &Connection, schema: &str) -> Result<u32> {
+    crate::plan::recorded_version(connection, schema)
+}
+
+/// Runs the pending migrations of `monarch` up to version `target` in `tx`, and records the
+/// version reached.
+///
+/// Repeatable migrations run too when `target` is the current version. The caller commits `tx`.
+///
+/// # Errors
+///
+/// Fails like [`MonarchDB::migrate_to`] if `target` cannot be reached, or if a migration fails.
+pub fn apply(monarch: &MonarchDB, tx: &Transaction<'_>, target: u32) -> Result<MigrationReport> {
+    monarch.apply_pending_to(tx, target)
+}
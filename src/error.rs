@@ -0,0 +1,572 @@
+//! Errors raised while configuring or applying migrations.
+
+use std::{fmt, io};
+
+use camino::Utf8PathBuf;
+
+/// An error raised by Monarch-DB.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading migrations from disk failed.
+    Io(io::Error),
+    /// A migration directory could not be read.
+    ReadDirectory {
+        /// The path of the directory.
+        path: Utf8PathBuf,
+        /// Why it could not be read.
+        source: io::Error,
+    },
+    /// An entry of a migration directory could not be listed, e.g. because its name is not
+    /// UTF-8.
+    ReadEntry {
+        /// The path of the directory.
+        directory: Utf8PathBuf,
+        /// Why the entry could not be listed.
+        source: io::Error,
+    },
+    /// A migration file could not be read.
+    ReadMigration {
+        /// The path of the file.
+        path: Utf8PathBuf,
+        /// Why it could not be read.
+        source: io::Error,
+    },
+    /// A migration file is not UTF-8 text.
+    InvalidEncoding {
+        /// The path of the file.
+        path: Utf8PathBuf,
+        /// What is wrong with its encoding.
+        reason: String,
+    },
+    /// A compressed migration could not be decompressed, see
+    /// [`SourceMigration::compressed`](crate::SourceMigration::compressed).
+    CompressedMigration {
+        /// The name of the migration.
+        migration: String,
+        /// Why it could not be decompressed.
+        reason: String,
+    },
+    /// A database operation failed.
+    Sqlite(rusqlite::Error),
+    /// A statement of a migration failed.
+    Statement {
+        /// The name of the migration.
+        migration: String,
+        /// The 1-based index of the statement in the migration.
+        statement: usize,
+        /// The 1-based line of the migration the statement starts on.
+        line: usize,
+        /// Why the statement failed.
+        source: rusqlite::Error,
+    },
+    /// A migration failed to import a file into a table.
+    #[cfg(feature = "import")]
+    Import {
+        /// The name of the migration.
+        migration: String,
+        /// Why the import failed.
+        source: crate::ImportError,
+    },
+    /// A migration changes data nondeterministically and the
+    /// [`DeterminismPolicy`](crate::DeterminismPolicy) rejects it.
+    Nondeterministic(crate::Nondeterminism),
+    /// A statement of a migration cannot run as intended inside the migration's transaction,
+    /// see [`MonarchDB::lint_transactions`](crate::MonarchDB::lint_transactions).
+    TransactionHazard(crate::TransactionHazard),
+    /// A database operation on a libSQL connection failed, see
+    /// [`MonarchDB::migrate_libsql`](crate::MonarchDB::migrate_libsql).
+    #[cfg(feature = "libsql")]
+    Libsql(::libsql::Error),
+    /// A migration run through a driver other than `rusqlite` imports a file, which needs a
+    /// `rusqlite` connection, see [`SqliteBackend`](crate::SqliteBackend).
+    UnsupportedImport {
+        /// The name of the migration.
+        migration: String,
+        /// The path of the imported file.
+        path: camino::Utf8PathBuf,
+    },
+    /// A migration run through a driver other than `rusqlite` has assertions, which need a
+    /// `rusqlite` connection, see [`SqliteBackend`](crate::SqliteBackend).
+    UnsupportedAssertion {
+        /// The name of the migration.
+        migration: String,
+    },
+    /// Fetching migrations from a server failed, see [`HttpSource`](crate::HttpSource).
+    #[cfg(feature = "http")]
+    Fetch {
+        /// The URL which was fetched.
+        url: String,
+        /// Why fetching it failed.
+        reason: String,
+    },
+    /// Migrations fetched from a server failed verification, see
+    /// [`HttpSource`](crate::HttpSource).
+    #[cfg(feature = "http")]
+    Untrusted {
+        /// The URL of the manifest or migration which failed verification.
+        url: String,
+        /// Why it is not trusted.
+        reason: String,
+    },
+    /// A migration refers to a template variable which has no value.
+    UndefinedVariable {
+        /// The name of the migration, e.g. its file name.
+        migration: String,
+        /// The name of the variable.
+        variable: String,
+    },
+    /// A migration depends on a migration which does not exist.
+    MissingDependency {
+        /// The name of the migration.
+        migration: String,
+        /// The name of the missing dependency.
+        dependency: String,
+    },
+    /// Migrations depend on each other, so none of them can run first.
+    DependencyCycle {
+        /// The names of the migrations in the cycle, each depending on the next and the last on
+        /// the first.
+        migrations: Vec<String>,
+    },
+    /// The configuration or a migration needs a crate feature which is not enabled, such as
+    /// `load_extension` to load extensions or `import` for a migration which imports a file.
+    MissingFeature {
+        /// The name of the feature.
+        feature: &'static str,
+        /// What needs the feature, e.g. `migration 003_users.sql imports users.csv`.
+        needed_by: String,
+    },
+    /// With timestamp versions, a versioned migration's name does not start with a timestamp.
+    MissingTimestamp {
+        /// The name of the migration.
+        migration: String,
+    },
+    /// With timestamp versions, two migrations have the same timestamp.
+    DuplicateVersion {
+        /// The timestamp the migrations share.
+        version: u64,
+        /// The name of the first migration.
+        first: String,
+        /// The name of the second migration.
+        second: String,
+    },
+    /// With timestamp versions, the timestamp recorded for the database's version is not the
+    /// one of the migration which now reaches it, because a migration was added before ones
+    /// already applied, see [`MonarchDB::with_out_of_order`](crate::MonarchDB::with_out_of_order).
+    IdentifierMismatch {
+        /// The version recorded in the database.
+        version: u32,
+        /// The timestamp recorded with the version.
+        recorded: u64,
+        /// The timestamp of the migration which now reaches the version.
+        current: u64,
+    },
+    /// A file in a migration directory is neither a migration nor ignored, in strict mode.
+    UnexpectedFile {
+        /// The path of the file.
+        path: Utf8PathBuf,
+    },
+    /// Two migration directories contain a migration with the same file name.
+    DuplicateMigration {
+        /// The file name of the migration.
+        migration: String,
+    },
+    /// A baseline migration is not the first migration.
+    MisplacedBaseline {
+        /// The name of the migration with the baseline directive.
+        migration: String,
+    },
+    /// The database's version falls inside the range of migrations replaced by a baseline, so
+    /// neither the baseline nor the migrations after it can be applied.
+    SquashedVersion {
+        /// The version recorded in the database.
+        version: u32,
+        /// The last version the baseline replaces.
+        baseline: u32,
+    },
+    /// Schemas migrated together in a [`MonarchSet`](crate::MonarchSet) disagree on a setting
+    /// which applies to the whole connection, and the set does not decide it.
+    ConflictingSetting {
+        /// The setting, e.g. `enable_foreign_keys`.
+        setting: &'static str,
+        /// The names of the schemas which enable it.
+        enabled: Vec<String>,
+        /// The names of the schemas which do not.
+        disabled: Vec<String>,
+    },
+    /// A target version is past the last migration, or inside the versions replaced by a
+    /// baseline, so migrating cannot stop there.
+    UnreachableVersion {
+        /// The target version.
+        version: u32,
+    },
+    /// The database was migrated past the last migration, by a newer version of the
+    /// application, see [`VersionAheadPolicy`](crate::VersionAheadPolicy).
+    DatabaseAhead {
+        /// The version recorded in the database.
+        version: u32,
+        /// The current version of the migrations.
+        current: u32,
+    },
+    /// A migration changed after it was applied, see
+    /// [`ChecksumPolicy`](crate::ChecksumPolicy).
+    ChecksumMismatch {
+        /// The name of the migration.
+        migration: String,
+        /// The version the migration upgraded the database to.
+        version: u32,
+        /// The checksum recorded when the migration ran.
+        recorded: String,
+        /// The checksum of the migration's SQL now.
+        current: String,
+    },
+    /// Migrating stopped because shutdown was requested, see
+    /// [`ShutdownGuard`](crate::ShutdownGuard).
+    Shutdown {
+        /// The version the database was left at.
+        version: u32,
+    },
+    /// A migration ran for longer than its timeout and was interrupted, see
+    /// [`MonarchDB::with_migration_timeout`](crate::MonarchDB::with_migration_timeout).
+    MigrationTimedOut {
+        /// The name of the migration.
+        migration: String,
+        /// The timeout it ran past.
+        timeout: std::time::Duration,
+    },
+    /// Migrating was cancelled, interrupting the migration which was running, see
+    /// [`ShutdownGuard::cancel`](crate::ShutdownGuard::cancel).
+    Cancelled {
+        /// The version the database was left at.
+        version: u32,
+    },
+    /// A query asserted by a migration does not hold after it runs, see
+    /// [`SourceMigration::with_verify_sql`](crate::SourceMigration::with_verify_sql).
+    AssertionFailed {
+        /// The name of the migration.
+        migration: String,
+        /// The assertion query.
+        query: String,
+        /// What the query returned, or why it failed.
+        reason: String,
+    },
+    /// A migration which relaxes foreign keys left a row referring to a missing row.
+    ForeignKeyViolation {
+        /// The name of the migration.
+        migration: String,
+        /// The table of the row.
+        table: String,
+        /// The rowid of the row, unless the table is `WITHOUT ROWID`.
+        rowid: Option<i64>,
+        /// The table the row refers to.
+        parent: String,
+    },
+    /// Migrations could not be squashed into a baseline.
+    Squash(String),
+    /// A migration to a target schema could not be written, see
+    /// [`MonarchDB::make_migration`](crate::MonarchDB::make_migration).
+    #[cfg(feature = "generate")]
+    MakeMigration(String),
+    /// The history of another migration tool could not be adopted, see
+    /// [`MonarchDB::adopt_history`](crate::MonarchDB::adopt_history).
+    AdoptHistory(String),
+    /// A database opened with [`MonarchDB::open_verified`](crate::MonarchDB::open_verified) is
+    /// not at exactly the current version.
+    VersionMismatch {
+        /// The current version of the migrations.
+        expected: u32,
+        /// The version recorded in the database.
+        found: u32,
+    },
+    /// A database opened with [`MonarchDB::open_verified`](crate::MonarchDB::open_verified) has
+    /// repeatable migrations which changed since they last ran.
+    PendingRepeatable {
+        /// The names of the repeatable migrations.
+        migrations: Vec<String>,
+    },
+}
+
+/// A `Result` whose error defaults to [`Error`].
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// The SQLite error code of a failed database operation, including a failed statement of a
+    /// migration, like [`rusqlite::Error::sqlite_error_code`].
+    pub fn sqlite_error_code(&self) -> Option<rusqlite::ErrorCode> {
+        match self {
+            Error::Sqlite(error) | Error::Statement { source: error, .. } => {
+                error.sqlite_error_code()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "reading migrations: {error}"),
+            Error::ReadDirectory { path, source } => {
+                write!(f, "reading migration directory {path}: {source}")
+            }
+            Error::ReadEntry { directory, source } => {
+                write!(
+                    f,
+                    "listing an entry of migration directory {directory}: {source}"
+                )
+            }
+            Error::ReadMigration { path, source } => {
+                write!(f, "reading migration {path}: {source}")
+            }
+            Error::InvalidEncoding { path, reason } => {
+                write!(f, "reading migration {path}: {reason}")
+            }
+            Error::CompressedMigration { migration, reason } => {
+                write!(f, "decompressing migration {migration}: {reason}")
+            }
+            Error::Sqlite(error) => write!(f, "{error}"),
+            Error::Statement {
+                migration,
+                statement,
+                line,
+                source,
+            } => write!(
+                f,
+                "migration {migration}, statement {statement} at line {line}: {source}"
+            ),
+            #[cfg(feature = "import")]
+            Error::Import { migration, source } => {
+                write!(f, "migration {migration} importing a file: {source}")
+            }
+            Error::Nondeterministic(finding) => write!(f, "{finding}"),
+            Error::TransactionHazard(hazard) => write!(f, "{hazard}"),
+            #[cfg(feature = "libsql")]
+            Error::Libsql(error) => write!(f, "{error}"),
+            Error::UnsupportedImport { migration, path } => write!(
+                f,
+                "migration {migration} imports {path}, which needs a rusqlite connection"
+            ),
+            Error::UnsupportedAssertion { migration } => write!(
+                f,
+                "migration {migration} has assertions, which need a rusqlite connection"
+            ),
+            #[cfg(feature = "http")]
+            Error::Fetch { url, reason } => write!(f, "fetching {url}: {reason}"),
+            #[cfg(feature = "http")]
+            Error::Untrusted { url, reason } => {
+                write!(f, "refusing migrations from {url}: {reason}")
+            }
+            Error::UndefinedVariable {
+                migration,
+                variable,
+            } => write!(
+                f,
+                "migration {migration} uses undefined template variable {{{{{variable}}}}}"
+            ),
+            Error::MissingDependency {
+                migration,
+                dependency,
+            } => write!(
+                f,
+                "migration {migration} depends on {dependency}, which is not a migration"
+            ),
+            Error::DependencyCycle { migrations } => write!(
+                f,
+                "migrations depend on each other: {} -> {}",
+                migrations.join(" -> "),
+                migrations.first().map_or("", String::as_str)
+            ),
+            Error::MissingFeature { feature, needed_by } => {
+                write!(f, "{needed_by} requires the `{feature}` feature")
+            }
+            Error::MissingTimestamp { migration } => {
+                write!(f, "migration {migration} does not start with a timestamp")
+            }
+            Error::DuplicateVersion {
+                version,
+                first,
+                second,
+            } => write!(
+                f,
+                "migrations {first} and {second} have the same version {version}"
+            ),
+            Error::IdentifierMismatch {
+                version,
+                recorded,
+                current,
+            } => write!(
+                f,
+                "database is at version {version}, recorded as migration {recorded}, but \
+                 migration {current} now reaches it; a migration was added before applied ones"
+            ),
+            Error::UnexpectedFile { path } => write!(
+                f,
+                "unexpected file {path} in migration directory, which is neither a migration nor ignored"
+            ),
+            Error::DuplicateMigration { migration } => write!(
+                f,
+                "migration {migration} is in more than one migration directory"
+            ),
+            Error::MisplacedBaseline { migration } => {
+                write!(
+                    f,
+                    "baseline migration {migration} must be the first migration"
+                )
+            }
+            Error::SquashedVersion { version, baseline } => write!(
+                f,
+                "database is at version {version}, inside versions 1 to {baseline} which were \
+                 squashed into a baseline; apply the original migrations first"
+            ),
+            Error::ConflictingSetting {
+                setting,
+                enabled,
+                disabled,
+            } => write!(
+                f,
+                "{setting} is enabled by {} but not by {}; decide it for the set",
+                enabled.join(", "),
+                disabled.join(", ")
+            ),
+            Error::UnreachableVersion { version } => {
+                write!(
+                    f,
+                    "cannot migrate to version {version}, no migration ends there"
+                )
+            }
+            Error::DatabaseAhead { version, current } => write!(
+                f,
+                "database is at version {version}, past the last migration at version {current}"
+            ),
+            Error::ChecksumMismatch {
+                migration,
+                version,
+                recorded,
+                current,
+            } => write!(
+                f,
+                "migration {migration} to version {version} changed after it was applied: \
+                 recorded checksum {recorded}, now {current}"
+            ),
+            Error::Shutdown { version } => {
+                write!(f, "migrations stopped for shutdown at version {version}")
+            }
+            Error::MigrationTimedOut { migration, timeout } => write!(
+                f,
+                "migration {migration} timed out after {timeout:?} and was rolled back"
+            ),
+            Error::Cancelled { version } => {
+                write!(f, "migrations cancelled at version {version}")
+            }
+            Error::AssertionFailed {
+                migration,
+                query,
+                reason,
+            } => write!(
+                f,
+                "migration {migration} failed assertion `{query}`: {reason}"
+            ),
+            Error::ForeignKeyViolation {
+                migration,
+                table,
+                rowid,
+                parent,
+            } => match rowid {
+                Some(rowid) => write!(
+                    f,
+                    "migration {migration} left row {rowid} of {table} referring to a missing row \
+                     of {parent}"
+                ),
+                None => write!(
+                    f,
+                    "migration {migration} left a row of {table} referring to a missing row of \
+                     {parent}"
+                ),
+            },
+            Error::Squash(reason) => write!(f, "squashing migrations: {reason}"),
+            #[cfg(feature = "generate")]
+            Error::MakeMigration(reason) => write!(f, "making a migration: {reason}"),
+            Error::AdoptHistory(reason) => write!(f, "adopting migration history: {reason}"),
+            Error::VersionMismatch { expected, found } => write!(
+                f,
+                "database is at version {found}, but version {expected} is required and \
+                 migrations may not run"
+            ),
+            Error::PendingRepeatable { migrations } => write!(
+                f,
+                "repeatable migrations {} are pending, but migrations may not run",
+                migrations.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::ReadDirectory { source, .. }
+            | Error::ReadEntry { source, .. }
+            | Error::ReadMigration { source, .. } => Some(source),
+            Error::Sqlite(error) | Error::Statement { source: error, .. } => Some(error),
+            #[cfg(feature = "import")]
+            Error::Import { source, .. } => Some(source),
+            Error::Nondeterministic(finding) => Some(finding),
+            Error::TransactionHazard(hazard) => Some(hazard),
+            #[cfg(feature = "libsql")]
+            Error::Libsql(error) => Some(error),
+            #[cfg(feature = "http")]
+            Error::Fetch { .. } | Error::Untrusted { .. } => None,
+            #[cfg(feature = "generate")]
+            Error::MakeMigration(_) => None,
+            Error::InvalidEncoding { .. }
+            | Error::CompressedMigration { .. }
+            | Error::UndefinedVariable { .. }
+            | Error::UnexpectedFile { .. }
+            | Error::MissingDependency { .. }
+            | Error::DependencyCycle { .. }
+            | Error::MissingFeature { .. }
+            | Error::MissingTimestamp { .. }
+            | Error::DuplicateVersion { .. }
+            | Error::IdentifierMismatch { .. }
+            | Error::DuplicateMigration { .. }
+            | Error::MisplacedBaseline { .. }
+            | Error::SquashedVersion { .. }
+            | Error::ConflictingSetting { .. }
+            | Error::UnreachableVersion { .. }
+            | Error::DatabaseAhead { .. }
+            | Error::ChecksumMismatch { .. }
+            | Error::Shutdown { .. }
+            | Error::Cancelled { .. }
+            | Error::MigrationTimedOut { .. }
+            | Error::AssertionFailed { .. }
+            | Error::ForeignKeyViolation { .. }
+            | Error::Squash(_)
+            | Error::UnsupportedImport { .. }
+            | Error::UnsupportedAssertion { .. }
+            | Error::AdoptHistory(_)
+            | Error::VersionMismatch { .. }
+            | Error::PendingRepeatable { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Self {
+        Error::Sqlite(error)
+    }
+}
+
+#[cfg(feature = "libsql")]
+impl From<::libsql::Error> for Error {
+    fn from(error: ::libsql::Error) -> Self {
+        Error::Libsql(error)
+    }
+}
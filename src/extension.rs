@@ -0,0 +1,79 @@
+//! Loading SQLite extensions before migrations run.
+//!
+//! Migrations which create virtual tables or call functions provided by a loadable extension,
+//! such as `spellfix1`, fail to prepare unless the extension is loaded first. Extensions listed
+//! in [`ConnectionConfiguration::extensions`](crate::ConnectionConfiguration::extensions) are
+//! loaded into each connection [`MonarchDB::create_connection`](crate::MonarchDB::create_connection)
+//! opens, before anything else runs. Loading them requires the `load_extension` feature.
+
+use camino::Utf8PathBuf;
+use rusqlite::Connection;
+
+#[cfg(not(feature = "load_extension"))]
+use crate::Error;
+use crate::Result;
+
+/// A loadable SQLite extension, see [`ConnectionConfiguration::extensions`].
+///
+/// [`ConnectionConfiguration::extensions`]: crate::ConnectionConfiguration::extensions
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SqliteExtension {
+    /// Path to the extension's shared library. SQLite tries the platform's library suffix
+    /// (e.g. `.so`) if the path has none, so `./mod_spellfix` loads `./mod_spellfix.so` on Linux.
+    pub path: Utf8PathBuf,
+    /// The extension's entry point, if it is not the name SQLite derives from the file name.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub entry_point: Option<String>,
+}
+
+/// Loads `extensions` into `connection`, disabling extension loading again afterwards.
+#[cfg(feature = "load_extension")]
+pub(crate) fn load_extensions(
+    connection: &Connection,
+    extensions: &[SqliteExtension],
+) -> Result<()> {
+    if extensions.is_empty() {
+        return Ok(());
+    }
+    // SAFETY: the extensions come from the application's own configuration, and no SQL runs
+    // while loading is enabled, so only these trusted libraries can be loaded.
+    let _guard = unsafe { rusqlite::LoadExtensionGuard::new(connection)? };
+    for extension in extensions {
+        tracing::trace!(path = %extension.path, "Load extension");
+        // SAFETY: as above, the extension is trusted by the application which configured it.
+        unsafe { connection.load_extension(&extension.path, extension.entry_point.as_deref())? };
+    }
+    Ok(())
+}
+
+/// Fails if there are extensions to load, which needs the `load_extension` feature.
+#[cfg(not(feature = "load_extension"))]
+pub(crate) fn load_extensions(_: &Connection, extensions: &[SqliteExtension]) -> Result<()> {
+    match extensions.first() {
+        Some(extension) => Err(Error::MissingFeature {
+            feature: "load_extension",
+            needed_by: format!("loading extension {}", extension.path),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_extension_fails() -> Result<()> {
+        let connection = Connection::open_in_memory()?;
+        load_extensions(&connection, &[])?;
+
+        let missing = SqliteExtension {
+            path: "./does_not_exist".into(),
+            entry_point: None,
+        };
+        let error = load_extensions(&connection, &[missing]).unwrap_err();
+        assert!(error.to_string().contains("does_not_exist"), "{error}");
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+//! Choosing which files of a migration directory are migrations.
+//!
+//! A migration directory often holds more than migrations: a `.gitkeep`, a README describing the
+//! conventions, or an editor's swap file. Files are matched against the
+//! [`include`](MonarchConfiguration::include) patterns, `*.sql` by default, and those which do
+//! not match are skipped. Files matching an [`ignore`](MonarchConfiguration::ignore) pattern are
+//! always skipped, as are hidden files. In [`strict`](MonarchConfiguration::strict) mode, any
+//! other file is an error rather than silently left out, so a misnamed migration cannot go
+//! unnoticed.
+//!
+//! Patterns are matched against the file name, less any compression suffix. `*` matches any run
+//! of characters and `?` matches a single character.
+
+use camino::Utf8Path;
+
+use crate::{Error, MonarchConfiguration, Result};
+
+/// The pattern migration files match when no `include` patterns are configured.
+const DEFAULT_INCLUDE: &str = "*.sql";
+
+impl MonarchConfiguration {
+    /// Whether the file at `path`, whose migration would be called `name`, is a migration.
+    ///
+    /// Fails with [`Error::UnexpectedFile`] for a file which is neither included nor ignored in
+    /// strict mode.
+    pub(crate) fn is_migration(&self, name: &str, path: &Utf8Path) -> Result<bool> {
+        if name.starts_with('.') || self.ignore.iter().any(|pattern| glob(pattern, name)) {
+            tracing::trace!(%path, "Skip ignored file");
+            return Ok(false);
+        }
+
+        let included = if self.include.is_empty() {
+            glob(DEFAULT_INCLUDE, name)
+        } else {
+            self.include.iter().any(|pattern| glob(pattern, name))
+        };
+        if !included {
+            if self.strict {
+                return Err(Error::UnexpectedFile {
+                    path: path.to_owned(),
+                });
+            }
+            tracing::trace!(%path, "Skip file which is not a migration");
+        }
+        Ok(included)
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters and `?` any one.
+fn glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // The position after the last `*`, and the position in `name` it has matched up to.
+    let mut star = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` match one more character.
+                Some((after, matched)) => {
+                    star = Some((after, matched + 1));
+                    p = after;
+                    n = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("*.sql", "001_users.sql"));
+        assert!(glob("*.sql", ".sql"));
+        assert!(!glob("*.sql", "001_users.sql.swp"));
+        assert!(glob("0??_*.sql", "001_users.sql"));
+        assert!(!glob("0??_*.sql", "1_users.sql"));
+        assert!(glob("*_*_*", "a_b_c"));
+        assert!(glob("README*", "README"));
+        assert!(!glob("README", "README.md"));
+    }
+
+    #[test]
+    fn test_is_migration() -> Result<()> {
+        let path = Utf8Path::new("migrations/notes.txt");
+        let mut configuration = MonarchConfiguration {
+            ignore: vec!["*.md".to_owned()],
+            ..Default::default()
+        };
+        assert!(configuration.is_migration("001_users.sql", path)?);
+        assert!(!configuration.is_migration(".gitkeep", path)?);
+        assert!(!configuration.is_migration("README.md", path)?);
+        assert!(!configuration.is_migration("notes.txt", path)?);
+
+        configuration.strict = true;
+        assert!(!configuration.is_migration("README.md", path)?);
+        let error = configuration.is_migration("notes.txt", path).unwrap_err();
+        assert!(matches!(error, Error::UnexpectedFile { .. }));
+
+        configuration.include = vec!["*.sql".to_owned(), "*.txt".to_owned()];
+        assert!(configuration.is_migration("notes.txt", path)?);
+        Ok(())
+    }
+}
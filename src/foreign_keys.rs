@@ -0,0 +1,151 @@
+//! Relaxing foreign keys for migrations which rebuild tables.
+//!
+//! SQLite cannot alter most parts of a table, so the usual way to change one is to create a new
+//! table, copy the rows across, drop the old table and rename the new one. With foreign keys
+//! enforced, dropping a table which other tables refer to fails, or cascades and deletes their
+//! rows. A migration starting with `-- monarch:relax-foreign-keys` runs with enforcement turned
+//! off, and once it has run, `PRAGMA foreign_key_check` must find no violations or the migration
+//! fails and is rolled back.
+//!
+//! SQLite ignores `PRAGMA foreign_keys` inside a transaction, so enforcement is turned off before
+//! the migration transaction starts, for every migration in it, and back on after it ends. While
+//! enforcement is off, every migration in the transaction is checked, not only relaxed ones.
+//! Through the [`engine`](crate::engine) functions and other drivers, which do not
+//! manage the transaction this way, relaxed migrations run with `PRAGMA defer_foreign_keys`
+//! instead, which defers the checks to the end of the migration but does not prevent cascades.
+
+use rusqlite::Connection;
+
+use crate::migration::Migration;
+use crate::{Error, MonarchDB, Result, plan};
+
+impl MonarchDB {
+    /// Whether foreign keys are enforced and a migration pending up to `target` relaxes them.
+    pub(crate) fn relaxes_foreign_keys(
+        &self,
+        connection: &Connection,
+        target: u32,
+    ) -> Result<bool> {
+        if !self.enable_foreign_keys {
+            return Ok(false);
+        }
+        let from = plan::recorded_version(connection, &self.name)?;
+        Ok(self.versions().any(|(version, migration)| {
+            version > from
+                && version <= target
+                && migration.relax_foreign_keys
+                && self.applies(migration)
+        }))
+    }
+
+    /// Defers foreign key checks before a relaxed migration runs, in case they are enforced.
+    pub(crate) fn relax_foreign_keys(&self, tx: &Connection, migration: &Migration) -> Result<()> {
+        if self.enable_foreign_keys && migration.relax_foreign_keys {
+            tx.pragma_update(None, "defer_foreign_keys", true)?;
+        }
+        Ok(())
+    }
+
+    /// Fails if a relaxed migration, or any migration while enforcement is off, left rows
+    /// referring to missing rows.
+    pub(crate) fn check_foreign_keys(&self, tx: &Connection, migration: &Migration) -> Result<()> {
+        if !self.enable_foreign_keys {
+            return Ok(());
+        }
+        let enforced: bool = tx.pragma_query_value(None, "foreign_keys", |row| row.get(0))?;
+        if enforced && !migration.relax_foreign_keys {
+            return Ok(());
+        }
+        let violation = tx
+            .prepare("PRAGMA foreign_key_check")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .next()
+            .transpose()?;
+        match violation {
+            Some((table, rowid, parent)) => Err(Error::ForeignKeyViolation {
+                migration: migration.name.to_string(),
+                table,
+                rowid,
+                parent,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(connection: &Connection, table: &str) -> rusqlite::Result<i64> {
+        connection.query_row(&format!("SELECT count(*) FROM {table}"), [], |row| {
+            row.get(0)
+        })
+    }
+
+    #[test]
+    fn test_rebuild_keeps_referring_rows() -> Result<()> {
+        let monarch = |rebuild: &str| {
+            let users = "CREATE TABLE users (id INTEGER PRIMARY KEY);
+                CREATE TABLE posts (
+                    id INTEGER PRIMARY KEY,
+                    user_id INTEGER REFERENCES users (id) ON DELETE CASCADE
+                );
+                INSERT INTO users (id) VALUES (1), (2);
+                INSERT INTO posts (user_id) VALUES (1), (2);";
+            let migrations = [
+                ("001_users.sql".to_owned(), users.to_owned()),
+                ("002_rebuild.sql".to_owned(), rebuild.to_owned()),
+            ];
+            MonarchDB::from_iter("foreign_keys", true, migrations).expect("migrations load")
+        };
+        let rebuild = "-- monarch:relax-foreign-keys
+            CREATE TABLE users_new (id INTEGER PRIMARY KEY, email TEXT NOT NULL DEFAULT '');
+            INSERT INTO users_new (id) SELECT id FROM users;
+            DROP TABLE users;
+            ALTER TABLE users_new RENAME TO users;";
+        let connection = monarch(rebuild).open_in_memory()?;
+        assert_eq!(count(&connection, "posts")?, 2);
+        let enforced: bool =
+            connection.pragma_query_value(None, "foreign_keys", |row| row.get(0))?;
+        assert!(enforced);
+
+        // Without the directive, dropping the table cascades to its posts.
+        let cascading = rebuild.trim_start_matches("-- monarch:relax-foreign-keys");
+        let connection = monarch(cascading).open_in_memory()?;
+        assert_eq!(count(&connection, "posts")?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_violations_roll_back() -> Result<()> {
+        let users = "CREATE TABLE users (id INTEGER PRIMARY KEY);
+            CREATE TABLE posts (
+                id INTEGER PRIMARY KEY,
+                user_id INTEGER REFERENCES users (id) ON DELETE CASCADE
+            );
+            INSERT INTO users (id) VALUES (1), (2);
+            INSERT INTO posts (user_id) VALUES (1), (2);";
+        let lossy = "-- monarch:relax-foreign-keys
+            CREATE TABLE users_new (id INTEGER PRIMARY KEY);
+            INSERT INTO users_new (id) SELECT id FROM users WHERE id = 1;
+            DROP TABLE users;
+            ALTER TABLE users_new RENAME TO users;";
+        let migrations = [
+            ("001_users.sql".to_owned(), users.to_owned()),
+            ("002_rebuild.sql".to_owned(), lossy.to_owned()),
+        ];
+        let monarch_db = MonarchDB::from_iter("foreign_keys", true, migrations)?;
+        let mut connection = Connection::open_in_memory()?;
+        monarch_db.migrate_to(&mut connection, 1)?;
+
+        let error = monarch_db.migrate_to(&mut connection, 2).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "migration 002_rebuild.sql left row 2 of posts referring to a missing row of users"
+        );
+        assert_eq!(monarch_db.plan(&connection)?.from_version, 1);
+        assert_eq!(count(&connection, "users")?, 2);
+        Ok(())
+    }
+}
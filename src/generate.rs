@@ -0,0 +1,544 @@
+//! Writing a migration which moves the schema to a target schema file.
+//!
+//! Projects which keep a canonical `schema.sql` alongside their migrations otherwise write every
+//! change twice. [`MonarchDB::make_migration`] compares the schema the migrations produce with the
+//! one a target schema file creates, and writes the DDL which turns the first into the second,
+//! as a candidate migration to review before saving it.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rusqlite::Connection;
+
+use crate::schema::{kind_order, normalize};
+use crate::sql::{Token, TokenKind, quote, tokenize};
+use crate::{Error, MonarchDB, Result};
+
+/// The suffix of the temporary name a rebuilt table is created under.
+const REBUILD_SUFFIX: &str = "__monarch_new";
+
+impl MonarchDB {
+    /// Writes a migration which turns the schema the migrations produce into the schema
+    /// `target_schema` creates, or returns `None` if they already match.
+    ///
+    /// Both schemas are built on scratch in-memory databases and compared object by object, as
+    /// [`diff`](Self::diff) does. Objects missing from the target are dropped and new ones are
+    /// created. A table which only gains columns at its end is altered with `ADD COLUMN` where
+    /// SQLite allows it; any other change to a table rebuilds it, copying the columns it keeps
+    /// into a new table which replaces it, after which its indexes are created again. When a table
+    /// is rebuilt, views and triggers are dropped first and created again at the end, since SQLite
+    /// refuses to rename a table while a view or trigger refers to one which is missing.
+    ///
+    /// A renamed table or column looks like one dropped and another created, losing its data, so
+    /// the migration is a candidate to review rather than one to apply blindly. Dropping a table
+    /// which other tables reference with `ON DELETE` actions runs those actions, as SQLite deletes
+    /// the table's rows first when foreign keys are enforced. A migration which rebuilds tables
+    /// relaxes foreign keys while it runs, so rebuilding does not run them.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+    /// }
+    /// .into();
+    ///
+    /// let target = "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);";
+    /// let migration = monarch_db.make_migration(target)?.expect("the schemas differ");
+    /// assert!(migration.contains("ALTER TABLE \"users\" ADD COLUMN name TEXT;"));
+    /// assert_eq!(monarch_db.make_migration("CREATE TABLE users (id INTEGER PRIMARY KEY);")?, None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::MakeMigration`] if the target schema does not run, or if the migration
+    /// written does not run or does not reach the target's tables and columns, and otherwise if
+    /// the migrations fail.
+    pub fn make_migration(&self, target_schema: &str) -> Result<Option<String>> {
+        let current = self.migrate_scratch()?;
+        let target = self.scratch()?;
+        target.execute_batch(target_schema).map_err(|error| {
+            Error::MakeMigration(format!("the target schema does not run: {error}"))
+        })?;
+
+        let (sql, rebuilds) = migration_sql(&objects(&current)?, &objects(&target)?);
+        if sql.is_empty() {
+            return Ok(None);
+        }
+        let directive = if rebuilds && self.enable_foreign_keys {
+            "-- monarch:relax-foreign-keys\n"
+        } else {
+            ""
+        };
+        let sql = format!(
+            "{directive}-- Moves the {} schema to the target schema.\n\
+             --\n\
+             -- Generated by comparing schemas. Review it before applying: renamed tables and\n\
+             -- columns are dropped and created anew, losing their data.\n\n{sql}",
+            self.name
+        );
+
+        let tx = current.unchecked_transaction()?;
+        tx.execute_batch(&sql).map_err(|error| {
+            Error::MakeMigration(format!("the migration written does not run: {error}"))
+        })?;
+        if shape(&tx)? != shape(&target)? {
+            return Err(Error::MakeMigration(
+                "the migration written does not reach the target schema".to_owned(),
+            ));
+        }
+        Ok(Some(sql))
+    }
+}
+
+/// A schema object, with the table an index or trigger belongs to.
+#[derive(Debug)]
+struct Object {
+    kind: String,
+    name: String,
+    table: String,
+    sql: String,
+}
+
+/// The objects of the `main` schema by kind and name, excluding SQLite's internal objects and
+/// Monarch's own tables.
+fn objects(connection: &Connection) -> rusqlite::Result<BTreeMap<(u8, String), Object>> {
+    let mut stmt = connection.prepare(
+        "SELECT type, name, tbl_name, sql FROM sqlite_master \
+         WHERE sql IS NOT NULL \
+           AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' \
+           AND tbl_name NOT LIKE 'monarch\\_db\\_%' ESCAPE '\\'",
+    )?;
+    stmt.query_map([], |row| {
+        Ok(Object {
+            kind: row.get(0)?,
+            name: row.get(1)?,
+            table: row.get(2)?,
+            sql: row.get(3)?,
+        })
+    })?
+    .map(|object| object.map(|object| ((kind_order(&object.kind), object.name.clone()), object)))
+    .collect()
+}
+
+/// The objects of a schema by kind and name, with the columns of each table, to check that a
+/// migration reached the target.
+fn shape(connection: &Connection) -> rusqlite::Result<BTreeMap<(u8, String), Vec<String>>> {
+    let mut shape = BTreeMap::new();
+    for (key, object) in objects(connection)? {
+        let mut stmt = connection.prepare("SELECT name FROM pragma_table_info(?1) ORDER BY cid")?;
+        let columns = stmt
+            .query_map([&object.name], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        shape.insert(key, columns);
+    }
+    Ok(shape)
+}
+
+/// The DDL which turns the `current` objects into the `target` objects, or an empty string if
+/// they match, and whether it rebuilds a table.
+fn migration_sql(
+    current: &BTreeMap<(u8, String), Object>,
+    target: &BTreeMap<(u8, String), Object>,
+) -> (String, bool) {
+    let unchanged = |key: &(u8, String)| {
+        current
+            .get(key)
+            .zip(target.get(key))
+            .is_some_and(|(current, target)| same_sql(&current.sql, &target.sql))
+    };
+
+    let mut tables = String::new();
+    let mut rebuilt = BTreeSet::new();
+    for (key, object) in current.iter().filter(|(key, _)| key.0 == 0) {
+        if !target.contains_key(key) {
+            tables.push_str(&format!("DROP TABLE {};\n\n", quote(&object.name)));
+        }
+    }
+    for (key, object) in target.iter().filter(|(key, _)| key.0 == 0) {
+        match current.get(key) {
+            None => tables.push_str(&normalize(&object.sql)),
+            Some(_) if unchanged(key) => continue,
+            Some(existing) => match add_columns(&existing.sql, &object.sql) {
+                Some(columns) => {
+                    for column in columns {
+                        tables.push_str(&format!(
+                            "ALTER TABLE {} ADD COLUMN {column};\n",
+                            quote(&object.name)
+                        ));
+                    }
+                }
+                None => {
+                    tables.push_str(&rebuild_table(&existing.sql, &object.sql, &object.name));
+                    rebuilt.insert(object.name.clone());
+                }
+            },
+        }
+        tables.push('\n');
+    }
+
+    // Rebuilding a table drops its indexes and triggers, and renaming the new table fails while
+    // a view or trigger refers to the table it replaces, so rebuilds recreate all of those.
+    let recreate = |key: &(u8, String), object: &Object| {
+        !unchanged(key)
+            || (!rebuilt.is_empty() && key.0 >= 2)
+            || (key.0 == 1 && rebuilt.contains(&object.table))
+    };
+    let mut drops = String::new();
+    for (key, object) in current.iter().rev().filter(|(key, _)| key.0 > 0) {
+        // Indexes and triggers go with their tables, views do not.
+        let dropped_with_table = key.0 != 2 && !target.contains_key(&(0, object.table.clone()));
+        if recreate(key, object) && !dropped_with_table && !rebuilt.contains(&object.table) {
+            drops.push_str(&format!(
+                "DROP {} {};\n",
+                object.kind.to_ascii_uppercase(),
+                quote(&object.name)
+            ));
+        }
+    }
+    let mut creates = String::new();
+    for (key, object) in target.iter().filter(|(key, _)| key.0 > 0) {
+        if recreate(key, object) {
+            creates.push_str(&normalize(&object.sql));
+            creates.push('\n');
+        }
+    }
+
+    let mut sql = String::new();
+    if !drops.is_empty() {
+        sql.push_str(&drops);
+        sql.push('\n');
+    }
+    sql.push_str(&tables);
+    sql.push_str(&creates);
+    sql.truncate(sql.trim_end().len());
+    if !sql.is_empty() {
+        sql.push('\n');
+    }
+    (sql, !rebuilt.is_empty())
+}
+
+/// The definitions of the columns `target` adds to the end of `current`, if `ADD COLUMN` can add
+/// them and nothing else about the table changes.
+fn add_columns(current: &str, target: &str) -> Option<Vec<String>> {
+    let current = TableDefinition::parse(current)?;
+    let target = TableDefinition::parse(target)?;
+    if current.virtual_table
+        || target.virtual_table
+        || !same_sql(&current.options, &target.options)
+        || current.constraints.len() != target.constraints.len()
+        || target.columns.len() <= current.columns.len()
+    {
+        return None;
+    }
+    let constraints = current.constraints.iter().zip(&target.constraints);
+    if !constraints.into_iter().all(|(a, b)| same_sql(a, b)) {
+        return None;
+    }
+    let (kept, added) = target.columns.split_at(current.columns.len());
+    if !current
+        .columns
+        .iter()
+        .zip(kept)
+        .all(|((a_name, a), (b_name, b))| a_name == b_name && same_sql(a, b))
+    {
+        return None;
+    }
+
+    added
+        .iter()
+        .map(|(_, definition)| can_add(definition).then(|| definition.clone()))
+        .collect()
+}
+
+/// Whether `ADD COLUMN` accepts a column definition: SQLite refuses primary keys, unique and
+/// stored generated columns, non-constant defaults, and `NOT NULL` without a default.
+fn can_add(definition: &str) -> bool {
+    let tokens = tokenize(definition);
+    let keyword = |keyword| tokens.iter().any(|token| token.is_keyword(keyword));
+    if keyword("PRIMARY") || keyword("UNIQUE") || keyword("STORED") {
+        return false;
+    }
+    let default = tokens.iter().position(|token| token.is_keyword("DEFAULT"));
+    if let Some(value) = default.and_then(|index| tokens.get(index + 1)) {
+        let constant = !value.is_punctuation('(')
+            && !["CURRENT_TIME", "CURRENT_DATE", "CURRENT_TIMESTAMP"]
+                .iter()
+                .any(|keyword| value.is_keyword(keyword));
+        if !constant {
+            return false;
+        }
+    }
+    let not_null = tokens
+        .windows(2)
+        .any(|pair| pair[0].is_keyword("NOT") && pair[1].is_keyword("NULL"));
+    !not_null || default.is_some()
+}
+
+/// The SQL which replaces a table with a copy created from its `target` definition, keeping the
+/// rows of the columns both definitions have.
+fn rebuild_table(current: &str, target: &str, name: &str) -> String {
+    let current_columns = TableDefinition::parse(current).map(|table| table.columns);
+    let target_table = TableDefinition::parse(target);
+    let (Some(current_columns), Some(target_table)) = (current_columns, target_table) else {
+        return format!(
+            "-- Recreate {name}, whose rows are lost.\nDROP TABLE {};\n{}",
+            quote(name),
+            normalize(target)
+        );
+    };
+    if target_table.virtual_table {
+        return format!(
+            "-- Recreate virtual table {name}, whose rows are lost.\nDROP TABLE {};\n{}",
+            quote(name),
+            normalize(target)
+        );
+    }
+
+    let kept: Vec<String> = target_table
+        .columns
+        .iter()
+        .filter(|(column, _)| current_columns.iter().any(|(c, _)| c == column))
+        .map(|(_, definition)| column_name(definition))
+        .collect();
+    let new_name = format!("{name}{REBUILD_SUFFIX}");
+    let create = format!(
+        "{}{}{}",
+        &target[..target_table.name_range.0],
+        quote(&new_name),
+        &target[target_table.name_range.1..]
+    );
+    let mut sql = if kept.is_empty() {
+        format!("-- Rebuild {name}, whose rows are lost since it keeps none of its columns.\n")
+    } else {
+        format!("-- Rebuild {name}, copying columns {}.\n", kept.join(", "))
+    };
+    sql.push_str(&normalize(&create));
+    if !kept.is_empty() {
+        sql.push_str(&format!(
+            "INSERT INTO {} ({columns}) SELECT {columns} FROM {};\n",
+            quote(&new_name),
+            quote(name),
+            columns = kept.join(", ")
+        ));
+    }
+    sql.push_str(&format!(
+        "DROP TABLE {};\nALTER TABLE {} RENAME TO {};\n",
+        quote(name),
+        quote(&new_name),
+        quote(name)
+    ));
+    sql
+}
+
+/// A `CREATE TABLE` statement split into its parts.
+struct TableDefinition {
+    /// The byte range of the table's name.
+    name_range: (usize, usize),
+    /// Whether this is a `CREATE VIRTUAL TABLE`.
+    virtual_table: bool,
+    /// Each column's lowercase, unquoted name, with its definition.
+    columns: Vec<(String, String)>,
+    /// The table constraints, such as a `PRIMARY KEY` over several columns.
+    constraints: Vec<String>,
+    /// Table options after the definitions, such as `WITHOUT ROWID` or `STRICT`.
+    options: String,
+}
+
+impl TableDefinition {
+    fn parse(sql: &str) -> Option<Self> {
+        let tokens = tokenize(sql);
+        let table = tokens.iter().position(|token| token.is_keyword("TABLE"))?;
+        let virtual_table = tokens[..table]
+            .iter()
+            .any(|token| token.is_keyword("VIRTUAL"));
+        let mut name = table + 1;
+        if tokens.get(name).is_some_and(|token| token.is_keyword("IF")) {
+            name += 3;
+        }
+        if tokens
+            .get(name + 1)
+            .is_some_and(|token| token.is_punctuation('.'))
+        {
+            name += 2;
+        }
+        let name_token = tokens.get(name)?;
+        let name_range = (name_token.offset, name_token.offset + name_token.text.len());
+
+        let mut columns = Vec::new();
+        let mut constraints = Vec::new();
+        let mut depth = 0;
+        let mut item: Vec<Token<'_>> = Vec::new();
+        let mut end = None;
+        for (index, token) in tokens.iter().enumerate().skip(name + 1) {
+            if token.is_punctuation('(') {
+                depth += 1;
+                if depth == 1 {
+                    continue;
+                }
+            } else if token.is_punctuation(')') {
+                depth -= 1;
+                if depth == 0 {
+                    end = Some(index);
+                    push_item(sql, &mut item, &mut columns, &mut constraints);
+                    break;
+                }
+            } else if depth == 1 && token.is_punctuation(',') {
+                push_item(sql, &mut item, &mut columns, &mut constraints);
+                continue;
+            }
+            if depth >= 1 {
+                item.push(*token);
+            }
+        }
+        let options = match tokens.get(end? + 1) {
+            Some(token) => sql[token.offset..].trim().to_owned(),
+            None => String::new(),
+        };
+        Some(TableDefinition {
+            name_range,
+            virtual_table,
+            columns,
+            constraints,
+            options,
+        })
+    }
+}
+
+/// Files the definition in `item` as a column or a table constraint, and clears it.
+fn push_item(
+    sql: &str,
+    item: &mut Vec<Token<'_>>,
+    columns: &mut Vec<(String, String)>,
+    constraints: &mut Vec<String>,
+) {
+    let (Some(first), Some(last)) = (item.first(), item.last()) else {
+        return;
+    };
+    let definition = sql[first.offset..last.offset + last.text.len()].to_owned();
+    let constraint = ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"]
+        .iter()
+        .any(|keyword| first.is_keyword(keyword));
+    if constraint {
+        constraints.push(definition);
+    } else {
+        columns.push((unquote(first).to_lowercase(), definition));
+    }
+    item.clear();
+}
+
+/// The name of the column a definition defines, as written.
+fn column_name(definition: &str) -> String {
+    tokenize(definition)
+        .first()
+        .map_or_else(String::new, |token| token.text.to_owned())
+}
+
+/// An identifier without its quotes.
+fn unquote<'a>(token: &Token<'a>) -> &'a str {
+    match token.kind {
+        TokenKind::QuotedIdentifier | TokenKind::String if token.text.len() >= 2 => {
+            &token.text[1..token.text.len() - 1]
+        }
+        _ => token.text,
+    }
+}
+
+/// Whether two statements are the same, ignoring differences in whitespace.
+fn same_sql(a: &str, b: &str) -> bool {
+    a.split_whitespace().eq(b.split_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    fn users() -> MonarchDB {
+        StaticMonarchConfiguration {
+            name: "generate",
+            enable_foreign_keys: true,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, legacy TEXT);
+                 CREATE INDEX users_name ON users (name);
+                 CREATE VIEW user_names AS SELECT name FROM users;",
+                "CREATE TABLE posts (id INTEGER PRIMARY KEY, user_id INTEGER REFERENCES users (id));",
+            ],
+        }
+        .into()
+    }
+
+    /// Applies the migrations and then `migration`, returning the schema.
+    fn apply(monarch_db: &MonarchDB, migration: &str) -> Result<String> {
+        let connection = monarch_db.open_in_memory()?;
+        connection.execute_batch(migration)?;
+        MonarchDB::schema_sql(&connection)
+    }
+
+    #[test]
+    fn test_make_migration_adds_columns_and_objects() -> Result<()> {
+        let target = "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, legacy TEXT, email TEXT NOT NULL DEFAULT '');
+             CREATE INDEX users_name ON users (name);
+             CREATE VIEW user_names AS SELECT name FROM users;
+             CREATE TABLE posts (id INTEGER PRIMARY KEY, user_id INTEGER REFERENCES users (id));
+             CREATE TABLE tags (id INTEGER PRIMARY KEY, label TEXT);
+             CREATE INDEX tags_label ON tags (label);";
+        let migration = users().make_migration(target)?.unwrap();
+        assert!(
+            migration
+                .contains("ALTER TABLE \"users\" ADD COLUMN email TEXT NOT NULL DEFAULT '';\n")
+        );
+        assert!(migration.contains("CREATE TABLE tags (id INTEGER PRIMARY KEY, label TEXT);\n"));
+        assert!(!migration.contains("DROP"));
+        assert!(!migration.contains("user_names"));
+
+        let schema = apply(&users(), &migration)?;
+        let scratch = Connection::open_in_memory()?;
+        scratch.execute_batch(target)?;
+        assert_eq!(schema, MonarchDB::schema_sql(&scratch)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_make_migration_rebuilds_tables() -> Result<()> {
+        let target = "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+             CREATE INDEX users_name ON users (name);
+             CREATE VIEW user_names AS SELECT name FROM users;";
+        let migration = users().make_migration(target)?.unwrap();
+        assert!(migration.starts_with("-- monarch:relax-foreign-keys\n"));
+        assert!(migration.contains("-- Rebuild users, copying columns id, name.\n"));
+        assert!(migration.contains("DROP TABLE \"posts\";\n"));
+        assert!(migration.contains("DROP VIEW \"user_names\";\n"));
+        assert!(!migration.contains("DROP INDEX"));
+
+        let connection = users().open_in_memory()?;
+        connection.execute("INSERT INTO users (name, legacy) VALUES ('alice', 'x')", [])?;
+        connection.execute_batch(&migration)?;
+        let name: String =
+            connection.query_row("SELECT name FROM user_names", [], |row| row.get(0))?;
+        assert_eq!(name, "alice");
+        let indexes: i64 = connection.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = 'users_name'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(indexes, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_make_migration_matching_schema() -> Result<()> {
+        let connection = users().open_in_memory()?;
+        let target = MonarchDB::schema_sql(&connection)?;
+        assert_eq!(users().make_migration(&target)?, None);
+
+        let error = users().make_migration("CREATE TABLE (").unwrap_err();
+        assert!(matches!(error, Error::MakeMigration(_)), "{error}");
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+//! The record of each versioned migration run against a database.
+//!
+//! Alongside the schema version, Monarch records every versioned migration it runs in the
+//! `monarch_db_migration_history` table: its name and description, the checksum of its SQL, when
+//! it ran and for how long, and whether it was applied. A migration marked with a
+//! `-- monarch:best-effort` directive which fails is rolled back and recorded as
+//! [`MigrationStatus::Skipped`], together with its error, and the migrations after it still run.
+
+use std::fmt;
+use std::time::Duration;
+
+use rusqlite::Connection;
+use rusqlite::types::Value;
+
+use crate::backend::{Driver, Rusqlite, block_on, column};
+use crate::migration::Migration;
+use crate::{MonarchDB, Result};
+
+pub(crate) const HISTORY_TABLE: &str = "monarch_db_migration_history";
+
+/// Columns added to the history table after it was first released, which older tables lack, with
+/// the statement adding each.
+pub(crate) const HISTORY_UPGRADES: [(&str, &str); 2] = [
+    (
+        "description",
+        "ALTER TABLE monarch_db_migration_history ADD COLUMN description TEXT",
+    ),
+    (
+        "duration_ms",
+        "ALTER TABLE monarch_db_migration_history ADD COLUMN duration_ms INTEGER",
+    ),
+];
+
+/// A query returning a row if the history table has `column`.
+fn history_column(column: &str) -> String {
+    format!("SELECT name FROM pragma_table_info('{HISTORY_TABLE}') WHERE name = '{column}'")
+}
+
+/// Whether the history table, which must exist, has `column`.
+async fn has_history_column<D: Driver>(driver: &mut D, column: &str) -> Result<bool, D::Error> {
+    Ok(!driver.query(&history_column(column), &[]).await?.is_empty())
+}
+
+/// What happened when a versioned migration ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationStatus {
+    /// The migration ran successfully.
+    Applied,
+    /// The migration was best-effort and failed, so its changes were rolled back and the
+    /// migrations after it ran anyway. Holds the error it failed with.
+    Skipped(String),
+}
+
+impl MigrationStatus {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            MigrationStatus::Applied => "applied",
+            MigrationStatus::Skipped(_) => "skipped",
+        }
+    }
+}
+
+impl fmt::Display for MigrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationStatus::Applied => f.write_str("applied"),
+            MigrationStatus::Skipped(error) => write!(f, "skipped: {error}"),
+        }
+    }
+}
+
+/// A versioned migration recorded in a database's migration history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// The schema version the migration upgraded the database to.
+    pub version: u32,
+    /// The name of the migration, its file name for migrations loaded from a directory.
+    pub name: String,
+    /// What the migration does, from its `-- monarch:description` directives, its leading
+    /// comment or its file name. `None` for migrations recorded before descriptions were.
+    pub description: Option<String>,
+    /// The checksum of the migration's SQL when it ran, as hex digits, see
+    /// [`ChecksumAlgorithm`](crate::ChecksumAlgorithm).
+    pub checksum: String,
+    /// Whether the migration was applied.
+    pub status: MigrationStatus,
+    /// When the migration ran, as an SQLite timestamp in UTC (`YYYY-MM-DD HH:MM:SS`).
+    pub applied_at: String,
+    /// How long the migration took to run, to the millisecond. `None` for migrations recorded
+    /// before durations were, or adopted from another tool, see
+    /// [`MonarchDB::adopt_history`].
+    pub duration: Option<Duration>,
+}
+
+impl MonarchDB {
+    /// Lists the versioned migrations recorded as run against `connection` for this schema, in
+    /// order of version.
+    ///
+    /// Migrations which were skipped because they are restricted to other environments are not
+    /// recorded, and neither are migrations applied before Monarch kept a history. Nothing is
+    /// written to the database, so this works on a read-only connection.
+    ///
+    /// ```rust
+    /// use monarch_db::{MigrationStatus, MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT);",
+    ///         "-- monarch:best-effort\nCREATE INDEX users_email ON missing_table (email);",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// let connection = monarch_db.open_in_memory()?;
+    /// let history = monarch_db.history(&connection)?;
+    /// assert_eq!(history[0].status, MigrationStatus::Applied);
+    /// assert!(matches!(history[1].status, MigrationStatus::Skipped(_)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn history(&self, connection: &Connection) -> Result<Vec<HistoryEntry>> {
+        block_on(self.read_history(&mut Rusqlite(connection)))
+    }
+
+    /// Reads the history through `driver`, like [`history`](Self::history).
+    pub(crate) async fn read_history<D: Driver>(
+        &self,
+        driver: &mut D,
+    ) -> Result<Vec<HistoryEntry>, D::Error> {
+        if !driver.table_exists(HISTORY_TABLE).await? {
+            return Ok(Vec::new());
+        }
+
+        let mut upgraded = Vec::new();
+        for (column, _) in HISTORY_UPGRADES {
+            upgraded.push(match has_history_column(driver, column).await? {
+                true => column,
+                false => "NULL",
+            });
+        }
+        let rows = driver
+            .query(
+                &format!(
+                    "SELECT version, name, checksum, status, error, applied_at, {} \
+                     FROM {HISTORY_TABLE} WHERE monarch_schema = ?1 ORDER BY version",
+                    upgraded.join(", ")
+                ),
+                &[self.name.to_string().into()],
+            )
+            .await?;
+        let entry = |row: &[Value]| -> Result<HistoryEntry> {
+            let status = match column::<String>(row, 3)?.as_str() {
+                "skipped" => {
+                    MigrationStatus::Skipped(column::<Option<String>>(row, 4)?.unwrap_or_default())
+                }
+                _ => MigrationStatus::Applied,
+            };
+            Ok(HistoryEntry {
+                version: column(row, 0)?,
+                name: column(row, 1)?,
+                description: column(row, 6)?,
+                checksum: column(row, 2)?,
+                status,
+                applied_at: column(row, 5)?,
+                duration: column::<Option<u64>>(row, 7)?.map(Duration::from_millis),
+            })
+        };
+        Ok(rows.iter().map(|row| entry(row)).collect::<Result<_>>()?)
+    }
+
+    /// Records that `migration`, which upgrades the schema to `version`, ran with `status` and
+    /// took `duration`, if it was timed.
+    pub(crate) async fn record_history<D: Driver>(
+        &self,
+        driver: &mut D,
+        version: u32,
+        migration: &Migration,
+        status: &MigrationStatus,
+        duration: Option<Duration>,
+    ) -> Result<(), D::Error> {
+        driver.execute_batch(include_str!("02.history.sql")).await?;
+        for (column, upgrade) in HISTORY_UPGRADES {
+            if !has_history_column(driver, column).await? {
+                driver.execute_batch(upgrade).await?;
+            }
+        }
+        let error = match status {
+            MigrationStatus::Applied => None,
+            MigrationStatus::Skipped(error) => Some(error.clone()),
+        };
+        driver
+            .execute(
+                &format!(
+                    "INSERT OR REPLACE INTO {HISTORY_TABLE} \
+                     (monarch_schema, version, name, description, checksum, status, error, \
+                     duration_ms) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+                ),
+                &[
+                    self.name.to_string().into(),
+                    version.into(),
+                    migration.name.to_string().into(),
+                    migration.description.clone().into(),
+                    self.checksum(migration.sql()?).into(),
+                    status.as_str().to_owned().into(),
+                    error.into(),
+                    duration.map(|duration| duration.as_millis() as i64).into(),
+                ],
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_best_effort_failure_is_skipped() -> Result<()> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "history",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT);",
+                "-- monarch:best-effort\nCREATE TABLE partial (x);\nCREATE INDEX broken ON users (missing);",
+                "ALTER TABLE users ADD COLUMN name TEXT;",
+            ],
+        });
+
+        let connection = Connection::open_in_memory()?;
+        assert!(monarch_db.history(&connection)?.is_empty());
+        let connection = monarch_db.migrate(connection)?;
+
+        // The failed migration is rolled back entirely, and the one after it still runs.
+        let partial: bool = connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'partial')",
+            [],
+            |row| row.get(0),
+        )?;
+        assert!(!partial);
+        connection.execute("UPDATE users SET name = 'alice'", [])?;
+
+        let history = monarch_db.history(&connection)?;
+        let statuses: Vec<_> = history
+            .iter()
+            .map(|entry| (entry.version, entry.status.as_str()))
+            .collect();
+        assert_eq!(statuses, [(1, "applied"), (2, "skipped"), (3, "applied")]);
+        assert!(history[1].status.to_string().contains("no such column"));
+        assert_eq!(
+            history[0].checksum,
+            monarch_db.checksum(monarch_db.migrations[0].sql()?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_descriptions_and_durations_recorded() -> Result<()> {
+        let monarch_db = MonarchDB::from_iter(
+            "history",
+            false,
+            [
+                ("001_create_users.sql", "CREATE TABLE users (id);"),
+                (
+                    "002_names.sql",
+                    "-- Give users names.\nALTER TABLE users ADD name;",
+                ),
+            ]
+            .map(|(name, sql)| (name.to_owned(), sql.to_owned())),
+        )
+        .unwrap();
+
+        // A history table from before descriptions and durations were recorded gains the columns.
+        let connection = Connection::open_in_memory()?;
+        connection.execute_batch(
+            "CREATE TABLE monarch_db_migration_history (
+                monarch_schema TEXT NOT NULL, version INTEGER NOT NULL, name TEXT NOT NULL,
+                checksum TEXT NOT NULL, status TEXT NOT NULL, error TEXT,
+                applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (monarch_schema, version)
+            );
+            INSERT INTO monarch_db_migration_history (monarch_schema, version, name, checksum, status)
+                VALUES ('old', 1, '1', '0', 'applied');",
+        )?;
+        assert_eq!(monarch_db.history(&connection)?, []);
+        let connection = monarch_db.migrate(connection)?;
+
+        let history = monarch_db.history(&connection)?;
+        assert!(history.iter().all(|entry| entry.duration.is_some()));
+        let descriptions: Vec<_> = history.into_iter().map(|entry| entry.description).collect();
+        assert_eq!(
+            descriptions,
+            [
+                Some("create users".to_owned()),
+                Some("Give users names.".to_owned())
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fail_fast_by_default() {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "history",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                "CREATE INDEX broken ON users (missing);",
+            ],
+        });
+        let error = monarch_db.open_in_memory().unwrap_err();
+        assert!(error.to_string().contains("no such column"), "{error}");
+    }
+}
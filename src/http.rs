@@ -0,0 +1,521 @@
+//! Loading migrations from a web server, for schema updates which ship separately from the
+//! application, such as on a fleet of kiosks or embedded devices.
+//!
+//! [`HttpSource`] fetches a manifest listing the migrations, checks its Ed25519 signature against
+//! a public key built into the application, then fetches each migration and checks it against the
+//! SHA-256 digest in the manifest. Nothing is returned unless everything checks out, so a
+//! compromised or misconfigured server cannot run SQL on the devices.
+//!
+//! The manifest is JSON, next to the migrations:
+//!
+//! ```json
+//! {
+//!   "name": "my_app",
+//!   "sequence": 42,
+//!   "migrations": [
+//!     { "name": "001_users.sql", "sha256": "7d2f…" },
+//!     { "name": "002_posts.sql", "sha256": "c3a9…" }
+//!   ]
+//! }
+//! ```
+//!
+//! Its signature is served at the manifest's URL with `.sig` appended, as the base64-encoded
+//! 64-byte Ed25519 signature of the manifest's exact bytes. The manifest's `name` must be the
+//! name of the schema, so a manifest signed for one application cannot be served to another.
+//!
+//! The `sequence` number must grow each time a new manifest is signed. A server could otherwise
+//! keep serving an old manifest, with its valid signature, to hold devices back. Given a file to
+//! keep it in with [`HttpSource::with_sequence_file`], the source records the sequence number of
+//! each manifest it accepts and rejects any manifest older than that.
+//!
+//! Requests are made over TLS for `https://` URLs, verified against the Mozilla root
+//! certificates, or in plain text for `http://` URLs. Up to five redirects are followed, since
+//! every file is checked against the signed manifest wherever it is served from. Responses
+//! larger than [`DEFAULT_MAX_RESPONSE_SIZE`] are refused, unless set otherwise with
+//! [`HttpSource::with_max_response_size`].
+
+use std::borrow::Cow;
+use std::io;
+use std::time::Duration;
+
+use base64::Engine;
+use camino::Utf8PathBuf;
+use ring::signature::{ED25519, UnparsedPublicKey};
+use ureq::Agent;
+use ureq::http::StatusCode;
+
+use crate::{ChecksumAlgorithm, Error, MigrationSource, Result, SourceMigration};
+
+/// How long to wait for a server before giving up, by default.
+pub const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The largest response, in bytes, to accept from a server by default.
+pub const DEFAULT_MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// How many redirects to follow for each request.
+const MAX_REDIRECTS: u32 = 5;
+
+/// Migrations served over HTTP with a signed manifest.
+///
+/// The manifest lists the migrations with their SHA-256 digests, and is signed with the Ed25519
+/// key whose public key the source is created with. Migrations are fetched from the manifest's
+/// directory, and fail with [`Error::Untrusted`] unless the signature, the schema name and every
+/// digest check out.
+///
+/// ```rust,no_run
+/// use monarch_db::{HttpSource, MonarchDB};
+///
+/// # const PUBLIC_KEY: [u8; 32] = [0; 32];
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let source = HttpSource::new(
+///     "kiosk",
+///     "https://updates.example.com/kiosk/manifest.json",
+///     PUBLIC_KEY,
+/// )
+/// .with_sequence_file("/var/lib/kiosk/manifest.sequence");
+/// let monarch_db = MonarchDB::from_source(source)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HttpSource {
+    name: Cow<'static, str>,
+    manifest_url: String,
+    public_key: [u8; 32],
+    enable_foreign_keys: bool,
+    timeout: Duration,
+    max_response_size: u64,
+    /// Where the sequence number of the last manifest accepted is kept.
+    sequence_file: Option<Utf8PathBuf>,
+}
+
+/// The list of migrations served by an [`HttpSource`].
+#[derive(Debug, serde::Deserialize)]
+struct Manifest {
+    name: String,
+    sequence: u64,
+    migrations: Vec<ManifestEntry>,
+}
+
+/// A migration listed in a [`Manifest`].
+#[derive(Debug, serde::Deserialize)]
+struct ManifestEntry {
+    name: String,
+    sha256: String,
+}
+
+impl HttpSource {
+    /// Creates a source for the schema `name` from the manifest at `manifest_url`, which must be
+    /// signed by the Ed25519 key whose raw 32-byte public key is `public_key`.
+    ///
+    /// Migrations are fetched from the same directory as the manifest.
+    pub fn new(
+        name: impl Into<Cow<'static, str>>,
+        manifest_url: impl Into<String>,
+        public_key: [u8; 32],
+    ) -> Self {
+        HttpSource {
+            name: name.into(),
+            manifest_url: manifest_url.into(),
+            public_key,
+            enable_foreign_keys: true,
+            timeout: DEFAULT_HTTP_TIMEOUT,
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            sequence_file: None,
+        }
+    }
+
+    /// Sets whether to enable foreign key constraints in SQLite. Defaults to `true`.
+    pub fn with_foreign_keys(mut self, enable_foreign_keys: bool) -> Self {
+        self.enable_foreign_keys = enable_foreign_keys;
+        self
+    }
+
+    /// Sets how long to wait for each request, from connecting to the server to reading the
+    /// whole response. Defaults to [`DEFAULT_HTTP_TIMEOUT`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the largest response, in bytes, to accept for the manifest, its signature or a
+    /// migration. A larger response fails with [`Error::Fetch`]. Defaults to
+    /// [`DEFAULT_MAX_RESPONSE_SIZE`].
+    pub fn with_max_response_size(mut self, max_response_size: u64) -> Self {
+        self.max_response_size = max_response_size;
+        self
+    }
+
+    /// Keeps the sequence number of the last manifest accepted in the file at `path`, and rejects
+    /// a manifest with a lower sequence number with [`Error::Untrusted`], so the server cannot
+    /// roll devices back to an older signed manifest.
+    ///
+    /// The file is written once every migration in a newer manifest checks out, and need not
+    /// exist before then. Without it, any manifest signed with the key is accepted.
+    pub fn with_sequence_file(mut self, path: impl Into<Utf8PathBuf>) -> Self {
+        self.sequence_file = Some(path.into());
+        self
+    }
+
+    /// An HTTP client with the source's timeout.
+    fn agent(&self) -> Agent {
+        Agent::config_builder()
+            .timeout_global(Some(self.timeout))
+            .max_redirects(MAX_REDIRECTS)
+            .http_status_as_error(false)
+            .user_agent(concat!("monarch-db/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .new_agent()
+    }
+
+    /// Fetches `url` with `agent` and returns the body of a `200 OK` response.
+    fn get(&self, agent: &Agent, url: &str) -> Result<Vec<u8>> {
+        let failed = |reason: String| Error::Fetch {
+            url: url.to_owned(),
+            reason,
+        };
+        let mut response = agent
+            .get(url)
+            .call()
+            .map_err(|error| failed(error.to_string()))?;
+        if response.status() != StatusCode::OK {
+            return Err(failed(format!(
+                "the server responded {}",
+                response.status()
+            )));
+        }
+        response
+            .body_mut()
+            .with_config()
+            .limit(self.max_response_size)
+            .read_to_vec()
+            .map_err(|error| match error {
+                ureq::Error::BodyExceedsLimit(limit) => {
+                    failed(format!("the response is larger than {limit} bytes"))
+                }
+                error => failed(error.to_string()),
+            })
+    }
+
+    /// Fetches the manifest and checks its signature, name and sequence number.
+    fn manifest(&self, agent: &Agent) -> Result<Manifest> {
+        let manifest = self.get(agent, &self.manifest_url)?;
+        let signature_url = format!("{}.sig", self.manifest_url);
+        let signature = self.get(agent, &signature_url)?;
+        let untrusted = |reason: String| Error::Untrusted {
+            url: self.manifest_url.clone(),
+            reason,
+        };
+
+        let signature = base64::engine::general_purpose::STANDARD
+            .decode(signature.trim_ascii())
+            .map_err(|error| untrusted(format!("the signature is not base64: {error}")))?;
+        UnparsedPublicKey::new(&ED25519, &self.public_key)
+            .verify(&manifest, &signature)
+            .map_err(|_| untrusted("the signature does not match the public key".to_owned()))?;
+
+        let manifest: Manifest = serde_json::from_slice(&manifest)
+            .map_err(|error| untrusted(format!("the manifest is invalid: {error}")))?;
+        if manifest.name != self.name {
+            return Err(untrusted(format!(
+                "the manifest is for {}, not {}",
+                manifest.name, self.name
+            )));
+        }
+        if let Some(accepted) = self.accepted_sequence()? {
+            if manifest.sequence < accepted {
+                return Err(untrusted(format!(
+                    "the manifest's sequence number {} is older than {accepted}, which was \
+                     already accepted",
+                    manifest.sequence
+                )));
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// The sequence number of the last manifest accepted, if it was recorded.
+    fn accepted_sequence(&self) -> Result<Option<u64>> {
+        let Some(path) = &self.sequence_file else {
+            return Ok(None);
+        };
+        match std::fs::read_to_string(path) {
+            Ok(sequence) => sequence.trim().parse().map(Some).map_err(|error| {
+                Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{path} does not hold a sequence number: {error}"),
+                ))
+            }),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Records `sequence` as the sequence number of the last manifest accepted, replacing the
+    /// sequence file at once so it is never left half written.
+    fn accept_sequence(&self, sequence: u64) -> Result<()> {
+        let Some(path) = &self.sequence_file else {
+            return Ok(());
+        };
+        if self.accepted_sequence()? == Some(sequence) {
+            return Ok(());
+        }
+        let partial = format!("{path}.partial");
+        std::fs::write(&partial, format!("{sequence}\n"))?;
+        std::fs::rename(&partial, path)?;
+        Ok(())
+    }
+}
+
+impl MigrationSource for HttpSource {
+    fn name(&self) -> Cow<'static, str> {
+        self.name.clone()
+    }
+
+    fn enable_foreign_keys(&self) -> bool {
+        self.enable_foreign_keys
+    }
+
+    fn migrations(&self) -> Result<Vec<SourceMigration>> {
+        let agent = self.agent();
+        let manifest = self.manifest(&agent)?;
+        let base = match self.manifest_url.rfind('/') {
+            Some(end) => &self.manifest_url[..=end],
+            None => "",
+        };
+
+        let mut migrations = Vec::with_capacity(manifest.migrations.len());
+        for entry in manifest.migrations {
+            let url = format!("{base}{}", entry.name);
+            let untrusted = |reason: &str| Error::Untrusted {
+                url: url.clone(),
+                reason: reason.to_owned(),
+            };
+            if entry.name.contains(['/', '\\', '?', '#']) || entry.name.starts_with('.') {
+                return Err(untrusted("the migration name is not a plain file name"));
+            }
+
+            tracing::debug!(%url, "Fetch migration");
+            let sql = String::from_utf8(self.get(&agent, &url)?)
+                .map_err(|_| untrusted("the migration is not UTF-8"))?;
+            if ChecksumAlgorithm::Sha256.checksum(&sql) != entry.sha256.to_ascii_lowercase() {
+                return Err(untrusted("the migration does not match its digest"));
+            }
+            migrations.push(SourceMigration::new(entry.name, sql));
+        }
+        self.accept_sequence(manifest.sequence)?;
+        Ok(migrations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use crate::MonarchDB;
+
+    use super::*;
+
+    /// Serves `files` over HTTP/1.0 until the test ends, returning the base URL. The same paths
+    /// under `/old/` redirect to it.
+    fn serve(files: HashMap<String, Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}/app/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let length = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..length]);
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                if let Some(file) = path.strip_prefix("/old/") {
+                    let response = format!(
+                        "HTTP/1.0 301 Moved Permanently\r\nLocation: /app/{file}\r\n\
+                         Content-Length: 0\r\n\r\n"
+                    );
+                    stream.write_all(response.as_bytes()).unwrap();
+                    continue;
+                }
+                let response = match files.get(path.trim_start_matches("/app/")) {
+                    Some(body) => {
+                        let mut response =
+                            format!("HTTP/1.0 200 OK\r\nContent-Length: {}\r\n\r\n", body.len())
+                                .into_bytes();
+                        response.extend_from_slice(body);
+                        response
+                    }
+                    None => b"HTTP/1.0 404 Not Found\r\n\r\n".to_vec(),
+                };
+                stream.write_all(&response).unwrap();
+            }
+        });
+        base
+    }
+
+    /// A signed manifest with `sequence` for `migrations`, and the files to serve it with.
+    fn signed(
+        key: &Ed25519KeyPair,
+        name: &str,
+        sequence: u64,
+        migrations: &[(&str, &str)],
+    ) -> HashMap<String, Vec<u8>> {
+        let entries: Vec<serde_json::Value> = migrations
+            .iter()
+            .map(|(name, sql)| {
+                serde_json::json!({
+                    "name": name,
+                    "sha256": ChecksumAlgorithm::Sha256.checksum(sql),
+                })
+            })
+            .collect();
+        let manifest = serde_json::to_vec(&serde_json::json!({
+            "name": name,
+            "sequence": sequence,
+            "migrations": entries,
+        }))
+        .unwrap();
+        let signature = base64::engine::general_purpose::STANDARD.encode(key.sign(&manifest));
+
+        let mut files: HashMap<String, Vec<u8>> = migrations
+            .iter()
+            .map(|(name, sql)| (name.to_string(), sql.as_bytes().to_vec()))
+            .collect();
+        files.insert("manifest.json".to_owned(), manifest);
+        files.insert("manifest.json.sig".to_owned(), signature.into_bytes());
+        files
+    }
+
+    fn key_pair() -> Ed25519KeyPair {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    fn source(base: &str, key: &Ed25519KeyPair) -> HttpSource {
+        let public_key = key.public_key().as_ref().try_into().unwrap();
+        HttpSource::new("kiosk", format!("{base}manifest.json"), public_key)
+            .with_timeout(Duration::from_secs(5))
+    }
+
+    #[test]
+    fn test_http_source() -> Result<()> {
+        let key = key_pair();
+        let migrations = [
+            (
+                "001_users.sql",
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+            ),
+            ("002_names.sql", "ALTER TABLE users ADD COLUMN name TEXT;"),
+        ];
+        let base = serve(signed(&key, "kiosk", 1, &migrations));
+
+        let monarch_db = MonarchDB::from_source(source(&base, &key))?;
+        assert_eq!(monarch_db.current_version(), 2);
+        monarch_db.open_in_memory()?;
+
+        let moved = source(&base.replace("/app/", "/old/"), &key).migrations()?;
+        assert_eq!(moved.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_source_rejects_untrusted_migrations() {
+        let key = key_pair();
+        let migrations = [(
+            "001_users.sql",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+        )];
+        let reason = |files: HashMap<String, Vec<u8>>, key: &Ed25519KeyPair| match source(
+            &serve(files),
+            key,
+        )
+        .migrations()
+        {
+            Err(Error::Untrusted { reason, .. }) => reason,
+            result => panic!("unexpected result {result:?}"),
+        };
+
+        let files = signed(&key, "kiosk", 1, &migrations);
+        assert_eq!(
+            reason(files.clone(), &key_pair()),
+            "the signature does not match the public key"
+        );
+
+        let mut tampered = files.clone();
+        tampered.insert("001_users.sql".to_owned(), b"DROP TABLE users;".to_vec());
+        assert_eq!(
+            reason(tampered, &key),
+            "the migration does not match its digest"
+        );
+
+        let other_app = signed(&key, "cashier", 1, &migrations);
+        assert_eq!(
+            reason(other_app, &key),
+            "the manifest is for cashier, not kiosk"
+        );
+
+        let mut missing = files;
+        missing.remove("001_users.sql");
+        let error = source(&serve(missing), &key).migrations().unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .ends_with("001_users.sql: the server responded 404 Not Found"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_http_source_rejects_older_manifests() -> Result<()> {
+        let key = key_pair();
+        let directory = tempfile::tempdir()?;
+        let sequence_file = Utf8PathBuf::try_from(directory.path().join("sequence")).unwrap();
+        let users = (
+            "001_users.sql",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+        );
+        let names = ("002_names.sql", "ALTER TABLE users ADD COLUMN name TEXT;");
+        let fetch = |files| {
+            source(&serve(files), &key)
+                .with_sequence_file(sequence_file.clone())
+                .migrations()
+        };
+
+        assert_eq!(fetch(signed(&key, "kiosk", 2, &[users, names]))?.len(), 2);
+        assert_eq!(std::fs::read_to_string(&sequence_file)?, "2\n");
+        // The same manifest is accepted again.
+        assert_eq!(fetch(signed(&key, "kiosk", 2, &[users, names]))?.len(), 2);
+
+        match fetch(signed(&key, "kiosk", 1, &[users])) {
+            Err(Error::Untrusted { reason, .. }) => assert_eq!(
+                reason,
+                "the manifest's sequence number 1 is older than 2, which was already accepted"
+            ),
+            result => panic!("unexpected result {result:?}"),
+        }
+        assert_eq!(std::fs::read_to_string(&sequence_file)?, "2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_source_limits_response_size() {
+        let key = key_pair();
+        let sql = format!("-- {}\nSELECT 1;", "x".repeat(4096));
+        let files = signed(&key, "kiosk", 1, &[("001_large.sql", &sql)]);
+        let error = source(&serve(files), &key)
+            .with_max_response_size(1024)
+            .migrations()
+            .unwrap_err();
+        match error {
+            Error::Fetch { url, reason } => {
+                assert!(url.ends_with("001_large.sql"), "{url}");
+                assert_eq!(reason, "the response is larger than 1024 bytes");
+            }
+            error => panic!("unexpected error {error}"),
+        }
+    }
+}
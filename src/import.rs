@@ -0,0 +1,526 @@
+//! Importing bundled CSV or Parquet files into tables.
+//!
+//! Reference datasets are awkward to ship as megabytes of `INSERT` statements. A migration can
+//! instead load them from a file with an import directive, run after the migration's SQL:
+//!
+//! ```sql
+//! -- monarch:import countries data/countries.csv
+//! CREATE TABLE countries (code TEXT PRIMARY KEY, name TEXT, population INTEGER);
+//! ```
+//!
+//! Paths are relative to the migration directory. [`TableImport`] does the work, and can also be
+//! used directly, for example to seed a database.
+//!
+//! Enable the `import` feature to import CSV files, and the `arrow` feature as well to import
+//! Parquet files.
+
+use std::fmt;
+
+use camino::Utf8Path;
+use rusqlite::Connection;
+use rusqlite::types::Value;
+
+use crate::sql::quote;
+
+/// The default number of rows inserted per statement.
+pub const DEFAULT_IMPORT_BATCH_SIZE: usize = 500;
+
+/// The number of parameters a statement may bind in every SQLite build.
+const MAX_VARIABLES: usize = 999;
+
+/// An error raised while importing a file.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ImportError {
+    /// Opening the file failed.
+    Io(std::io::Error),
+    /// Writing to the table failed.
+    Sqlite(rusqlite::Error),
+    /// Reading a CSV file failed.
+    Csv(csv::Error),
+    /// Reading Arrow data failed.
+    #[cfg(feature = "arrow")]
+    Arrow(arrow_schema::ArrowError),
+    /// Reading a Parquet file failed.
+    #[cfg(feature = "arrow")]
+    Parquet(parquet::errors::ParquetError),
+    /// A Parquet column has a type which cannot be stored in SQLite.
+    UnsupportedType {
+        /// The name of the column.
+        column: String,
+        /// The column's type.
+        data_type: String,
+    },
+    /// The file's extension is not a supported format.
+    UnknownFormat(String),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Io(error) => write!(f, "opening file: {error}"),
+            ImportError::Sqlite(error) => write!(f, "writing table: {error}"),
+            ImportError::Csv(error) => write!(f, "reading csv: {error}"),
+            #[cfg(feature = "arrow")]
+            ImportError::Arrow(error) => write!(f, "reading arrow data: {error}"),
+            #[cfg(feature = "arrow")]
+            ImportError::Parquet(error) => write!(f, "reading parquet: {error}"),
+            ImportError::UnsupportedType { column, data_type } => {
+                write!(f, "column {column} has unsupported type {data_type}")
+            }
+            ImportError::UnknownFormat(path) => {
+                write!(f, "cannot import {path}, expected a .csv or .parquet file")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImportError::Io(error) => Some(error),
+            ImportError::Sqlite(error) => Some(error),
+            ImportError::Csv(error) => Some(error),
+            #[cfg(feature = "arrow")]
+            ImportError::Arrow(error) => Some(error),
+            #[cfg(feature = "arrow")]
+            ImportError::Parquet(error) => Some(error),
+            ImportError::UnsupportedType { .. } | ImportError::UnknownFormat(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ImportError {
+    fn from(error: std::io::Error) -> Self {
+        ImportError::Io(error)
+    }
+}
+
+impl From<rusqlite::Error> for ImportError {
+    fn from(error: rusqlite::Error) -> Self {
+        ImportError::Sqlite(error)
+    }
+}
+
+impl From<csv::Error> for ImportError {
+    fn from(error: csv::Error) -> Self {
+        ImportError::Csv(error)
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<arrow_schema::ArrowError> for ImportError {
+    fn from(error: arrow_schema::ArrowError) -> Self {
+        ImportError::Arrow(error)
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<parquet::errors::ParquetError> for ImportError {
+    fn from(error: parquet::errors::ParquetError) -> Self {
+        ImportError::Parquet(error)
+    }
+}
+
+/// Loads the rows of a CSV or Parquet file into an existing table.
+///
+/// The format is chosen by the file's extension. A CSV file must start with a header row naming
+/// the columns to fill, while a Parquet file's columns are matched to the table's by name.
+/// Columns of the table which are not in the file take their defaults.
+///
+/// CSV fields are coerced to suit their column: empty fields become `NULL`, and in columns with
+/// `INTEGER`, `REAL` or `NUMERIC` affinity, `true` and `false` become `1` and `0` and numbers
+/// are stored as numbers. Other fields are stored as text. Parquet values keep their types.
+///
+/// Rows are inserted in batches of several rows per statement. Run the import inside a
+/// transaction, as migrations do, to keep it fast and atomic.
+///
+/// ```rust
+/// use camino::Utf8Path;
+/// use monarch_db::TableImport;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let directory = tempfile::tempdir()?;
+/// # let path = Utf8Path::from_path(directory.path()).unwrap().join("users.csv");
+/// std::fs::write(&path, "id,name,active\n1,alice,true\n2,bob,\n")?;
+///
+/// let connection = rusqlite::Connection::open_in_memory()?;
+/// connection.execute_batch("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, active BOOLEAN)")?;
+///
+/// let rows = TableImport::new("users", &path).run(&connection)?;
+/// assert_eq!(rows, 2);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TableImport<'a> {
+    table: &'a str,
+    path: &'a Utf8Path,
+    batch_size: usize,
+}
+
+impl<'a> TableImport<'a> {
+    /// Imports the file at `path` into `table`.
+    pub fn new(table: &'a str, path: &'a Utf8Path) -> Self {
+        TableImport {
+            table,
+            path,
+            batch_size: DEFAULT_IMPORT_BATCH_SIZE,
+        }
+    }
+
+    /// Sets the number of rows inserted per statement, [`DEFAULT_IMPORT_BATCH_SIZE`] by
+    /// default. Batches are made smaller where needed to stay within SQLite's limit of 999
+    /// parameters per statement.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is zero.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "import batch size must be positive");
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Inserts every row of the file, returning the number of rows inserted.
+    #[tracing::instrument(level = "debug", skip_all, fields(table = self.table, path = %self.path))]
+    pub fn run(&self, connection: &Connection) -> Result<u64, ImportError> {
+        match self.path.extension() {
+            Some("csv") => self.run_csv(connection),
+            #[cfg(feature = "arrow")]
+            Some("parquet") => self.run_parquet(connection),
+            _ => Err(ImportError::UnknownFormat(self.path.to_string())),
+        }
+    }
+
+    fn run_csv(&self, connection: &Connection) -> Result<u64, ImportError> {
+        let mut reader = csv::Reader::from_path(self.path)?;
+        let columns: Vec<String> = reader.headers()?.iter().map(str::to_owned).collect();
+        let numeric = numeric_columns(connection, self.table, &columns)?;
+
+        let mut inserter = Inserter::new(connection, self.table, columns, self.batch_size);
+        for record in reader.records() {
+            let record = record?;
+            inserter.push(
+                record
+                    .iter()
+                    .zip(&numeric)
+                    .map(|(field, numeric)| coerce(field, *numeric)),
+            )?;
+        }
+        Ok(inserter.finish()?)
+    }
+
+    #[cfg(feature = "arrow")]
+    fn run_parquet(&self, connection: &Connection) -> Result<u64, ImportError> {
+        use arrow_array::RecordBatchReader;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(self.path)?)?
+            .with_batch_size(self.batch_size)
+            .build()?;
+        let schema = reader.schema();
+        let columns: Vec<String> = schema.fields().iter().map(|f| f.name().clone()).collect();
+        for field in schema.fields() {
+            if !arrow::is_supported(field.data_type()) {
+                return Err(ImportError::UnsupportedType {
+                    column: field.name().clone(),
+                    data_type: field.data_type().to_string(),
+                });
+            }
+        }
+
+        let mut inserter = Inserter::new(connection, self.table, columns, self.batch_size);
+        for batch in reader {
+            let batch = batch?;
+            for row in 0..batch.num_rows() {
+                inserter.push(batch.columns().iter().map(|array| arrow::value(array, row)))?;
+            }
+        }
+        Ok(inserter.finish()?)
+    }
+}
+
+/// Buffers rows and inserts them several at a time.
+struct Inserter<'c> {
+    connection: &'c Connection,
+    table: &'c str,
+    columns: Vec<String>,
+    rows_per_statement: usize,
+    buffer: Vec<Value>,
+    total: u64,
+}
+
+impl<'c> Inserter<'c> {
+    fn new(
+        connection: &'c Connection,
+        table: &'c str,
+        columns: Vec<String>,
+        batch_size: usize,
+    ) -> Self {
+        let rows_per_statement = batch_size.min(MAX_VARIABLES / columns.len().max(1)).max(1);
+        Inserter {
+            connection,
+            table,
+            buffer: Vec::with_capacity(rows_per_statement * columns.len()),
+            columns,
+            rows_per_statement,
+            total: 0,
+        }
+    }
+
+    fn push(&mut self, row: impl IntoIterator<Item = Value>) -> rusqlite::Result<()> {
+        self.buffer.extend(row);
+        if self.buffer.len() >= self.rows_per_statement * self.columns.len() {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> rusqlite::Result<()> {
+        let rows = self.buffer.len() / self.columns.len().max(1);
+        if rows == 0 {
+            return Ok(());
+        }
+
+        let placeholders = format!("({})", vec!["?"; self.columns.len()].join(", "));
+        let sql = format!(
+            "INSERT INTO {} ({}) VALUES {}",
+            quote(self.table),
+            self.columns
+                .iter()
+                .map(|column| quote(column))
+                .collect::<Vec<_>>()
+                .join(", "),
+            vec![placeholders; rows].join(", "),
+        );
+        self.connection
+            .prepare_cached(&sql)?
+            .execute(rusqlite::params_from_iter(self.buffer.drain(..)))?;
+        self.total += rows as u64;
+        tracing::trace!(rows = self.total, "Imported batch");
+        Ok(())
+    }
+
+    fn finish(mut self) -> rusqlite::Result<u64> {
+        self.flush()?;
+        Ok(self.total)
+    }
+}
+
+/// Whether each of `columns` has `INTEGER`, `REAL` or `NUMERIC` affinity in `table`.
+fn numeric_columns(
+    connection: &Connection,
+    table: &str,
+    columns: &[String],
+) -> rusqlite::Result<Vec<bool>> {
+    let mut stmt = connection.prepare("SELECT type FROM pragma_table_info(?1) WHERE name = ?2")?;
+    columns
+        .iter()
+        .map(|column| {
+            let declared: Option<String> = stmt
+                .query_map((table, column), |row| row.get(0))?
+                .next()
+                .transpose()?;
+            Ok(declared.is_some_and(|declared| is_numeric(&declared)))
+        })
+        .collect()
+}
+
+/// Whether a declared column type has numeric affinity, following SQLite's affinity rules.
+fn is_numeric(declared: &str) -> bool {
+    let declared = declared.to_ascii_uppercase();
+    declared.contains("INT")
+        || !(declared.is_empty()
+            || ["CHAR", "CLOB", "TEXT", "BLOB"]
+                .iter()
+                .any(|name| declared.contains(name)))
+}
+
+/// Converts a CSV field to the value stored for it.
+fn coerce(field: &str, numeric: bool) -> Value {
+    if field.is_empty() {
+        return Value::Null;
+    }
+    if numeric {
+        if field.eq_ignore_ascii_case("true") {
+            return Value::Integer(1);
+        } else if field.eq_ignore_ascii_case("false") {
+            return Value::Integer(0);
+        } else if let Ok(integer) = field.parse() {
+            return Value::Integer(integer);
+        } else if let Ok(real) = field.parse() {
+            return Value::Real(real);
+        }
+    }
+    Value::Text(field.to_owned())
+}
+
+#[cfg(feature = "arrow")]
+mod arrow {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{
+        Float32Type, Float64Type, Int8Type, Int16Type, Int32Type, Int64Type, UInt8Type, UInt16Type,
+        UInt32Type, UInt64Type,
+    };
+    use arrow_array::{Array, ArrayRef};
+    use arrow_schema::DataType;
+    use rusqlite::types::Value;
+
+    pub(super) fn is_supported(data_type: &DataType) -> bool {
+        matches!(
+            data_type,
+            DataType::Null
+                | DataType::Boolean
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::Utf8
+                | DataType::LargeUtf8
+                | DataType::Binary
+                | DataType::LargeBinary
+        )
+    }
+
+    /// The value at `row`, for an array of a type accepted by [`is_supported`].
+    pub(super) fn value(array: &ArrayRef, row: usize) -> Value {
+        if array.is_null(row) {
+            return Value::Null;
+        }
+        match array.data_type() {
+            DataType::Boolean => Value::Integer(array.as_boolean().value(row).into()),
+            DataType::Int8 => Value::Integer(array.as_primitive::<Int8Type>().value(row).into()),
+            DataType::Int16 => Value::Integer(array.as_primitive::<Int16Type>().value(row).into()),
+            DataType::Int32 => Value::Integer(array.as_primitive::<Int32Type>().value(row).into()),
+            DataType::Int64 => Value::Integer(array.as_primitive::<Int64Type>().value(row)),
+            DataType::UInt8 => Value::Integer(array.as_primitive::<UInt8Type>().value(row).into()),
+            DataType::UInt16 => {
+                Value::Integer(array.as_primitive::<UInt16Type>().value(row).into())
+            }
+            DataType::UInt32 => {
+                Value::Integer(array.as_primitive::<UInt32Type>().value(row).into())
+            }
+            // Values beyond the range of SQLite's integers are stored as reals, as SQLite does
+            // with oversized integer literals.
+            DataType::UInt64 => {
+                let value = array.as_primitive::<UInt64Type>().value(row);
+                i64::try_from(value).map_or(Value::Real(value as f64), Value::Integer)
+            }
+            DataType::Float32 => Value::Real(array.as_primitive::<Float32Type>().value(row).into()),
+            DataType::Float64 => Value::Real(array.as_primitive::<Float64Type>().value(row)),
+            DataType::Utf8 => Value::Text(array.as_string::<i32>().value(row).to_owned()),
+            DataType::LargeUtf8 => Value::Text(array.as_string::<i64>().value(row).to_owned()),
+            DataType::Binary => Value::Blob(array.as_binary::<i32>().value(row).to_vec()),
+            DataType::LargeBinary => Value::Blob(array.as_binary::<i64>().value(row).to_vec()),
+            _ => Value::Null,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8PathBuf;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn file(directory: &TempDir, name: &str, contents: &[u8]) -> Utf8PathBuf {
+        let path = Utf8Path::from_path(directory.path()).unwrap().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_csv_import_coerces_fields() -> Result<(), Box<dyn std::error::Error>> {
+        let directory = tempfile::tempdir()?;
+        let path = file(
+            &directory,
+            "items.csv",
+            b"name,price,active,code\nwidget,1.5,true,007\ngadget,,FALSE,\n\"bolt, small\",3,1,42\n",
+        );
+
+        let connection = Connection::open_in_memory()?;
+        connection.execute_batch(
+            "CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT, price REAL, active BOOLEAN, code VARCHAR(3))",
+        )?;
+        let rows = TableImport::new("items", &path)
+            .with_batch_size(2)
+            .run(&connection)?;
+        assert_eq!(rows, 3);
+
+        let mut stmt =
+            connection.prepare("SELECT name, price, active, code FROM items ORDER BY id")?;
+        let items: Vec<Vec<Value>> = stmt
+            .query_map([], |row| (0..4).map(|i| row.get(i)).collect())?
+            .collect::<rusqlite::Result<_>>()?;
+        let text = |s: &str| Value::Text(s.to_owned());
+        assert_eq!(
+            items,
+            [
+                vec![
+                    text("widget"),
+                    Value::Real(1.5),
+                    Value::Integer(1),
+                    text("007")
+                ],
+                vec![text("gadget"), Value::Null, Value::Integer(0), Value::Null],
+                vec![
+                    text("bolt, small"),
+                    Value::Real(3.0),
+                    Value::Integer(1),
+                    text("42")
+                ],
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_format() -> crate::Result<()> {
+        let connection = Connection::open_in_memory()?;
+        let result = TableImport::new("items", Utf8Path::new("items.json")).run(&connection);
+        assert!(matches!(result, Err(ImportError::UnknownFormat(_))));
+        Ok(())
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_parquet_import() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::{ExportFormat, TableExport};
+
+        let source = Connection::open_in_memory()?;
+        source.execute_batch(
+            "CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT, price REAL, data BLOB);
+             INSERT INTO items (name, price, data) VALUES
+                ('widget', 1.5, x'0102'), (NULL, 2, NULL), ('bolt', NULL, x'');",
+        )?;
+        let mut contents = Vec::new();
+        TableExport::new("items", ExportFormat::Parquet).run(&source, &mut contents)?;
+
+        let directory = tempfile::tempdir()?;
+        let path = file(&directory, "items.parquet", &contents);
+        let target = Connection::open_in_memory()?;
+        target.execute_batch(
+            "CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT, price REAL, data BLOB)",
+        )?;
+        let rows = TableImport::new("items", &path)
+            .with_batch_size(2)
+            .run(&target)?;
+        assert_eq!(rows, 3);
+
+        let dump = |connection: &Connection| -> rusqlite::Result<Vec<Vec<Value>>> {
+            let mut stmt = connection.prepare("SELECT * FROM items ORDER BY id")?;
+            stmt.query_map([], |row| (0..4).map(|i| row.get(i)).collect())?
+                .collect()
+        };
+        assert_eq!(dump(&target)?, dump(&source)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,375 @@
+//! Advice on indexes which may no longer be worth keeping.
+//!
+//! Long-lived schemas accumulate indexes created by past migrations which queries no longer
+//! use. [`MonarchDB::advise_indexes`] inspects a live database and flags indexes created by the
+//! configured migrations which look redundant, unselective, or unused by a sample workload, so
+//! they can be dropped in a new migration.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use rusqlite::Connection;
+
+use crate::sql::quote;
+use crate::{MonarchDB, Result};
+
+/// The minimum number of rows `sqlite_stat1` must report for a table before its indexes are
+/// judged on selectivity. Small tables are cheap to scan either way.
+const MIN_STAT_ROWS: u64 = 1000;
+
+/// An index which is a candidate for removal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexAdvice {
+    /// The name of the index.
+    pub index: String,
+    /// The table the index belongs to.
+    pub table: String,
+    /// Why the index is flagged.
+    pub reason: IndexAdviceReason,
+}
+
+impl IndexAdvice {
+    /// The statement which drops the index, for use in a generated migration.
+    pub fn drop_sql(&self) -> String {
+        format!("DROP INDEX IF EXISTS {};", quote(&self.index))
+    }
+}
+
+/// The reason an index was flagged by [`MonarchDB::advise_indexes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexAdviceReason {
+    /// Another index on the same table starts with all of this index's columns, so it can
+    /// serve every lookup this index can.
+    Redundant {
+        /// The index which covers this one.
+        covered_by: String,
+    },
+    /// None of the workload queries use the index.
+    Unused,
+    /// `sqlite_stat1` reports that each key matches at least a tenth of the table, so the
+    /// query planner gains little from the index.
+    Unselective {
+        /// The number of rows in the table when it was last analyzed.
+        rows: u64,
+        /// The average number of rows matching each value of the first indexed column.
+        rows_per_key: u64,
+    },
+}
+
+impl fmt::Display for IndexAdviceReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexAdviceReason::Redundant { covered_by } => {
+                write!(f, "redundant, covered by {covered_by}")
+            }
+            IndexAdviceReason::Unused => write!(f, "not used by any workload query"),
+            IndexAdviceReason::Unselective { rows, rows_per_key } => {
+                write!(f, "unselective, {rows_per_key} of {rows} rows per key")
+            }
+        }
+    }
+}
+
+/// An index on a table in the live database.
+struct IndexInfo {
+    name: String,
+    unique: bool,
+    partial: bool,
+    /// Indexed column names, or `None` for expression indexes.
+    columns: Option<Vec<String>>,
+}
+
+impl MonarchDB {
+    /// Flags indexes created by this schema's migrations which may be worth dropping.
+    ///
+    /// Only indexes which the configured migrations create (determined by replaying them on a
+    /// scratch in-memory database) are considered, and `UNIQUE` or partial indexes are never
+    /// flagged since dropping them changes behavior. An index is flagged when:
+    ///
+    /// - another index on the same table starts with the same columns,
+    /// - `workload` is not empty and none of its queries use the index, according to
+    ///   `EXPLAIN QUERY PLAN`, or
+    /// - the statistics gathered by `ANALYZE` in `sqlite_stat1` show the index is unselective.
+    ///
+    /// Workload queries may contain parameters, which are left unbound. Each index is reported
+    /// at most once, for the first reason above which applies.
+    pub fn advise_indexes(
+        &self,
+        connection: &Connection,
+        workload: &[&str],
+    ) -> Result<Vec<IndexAdvice>> {
+        let owned = self.migration_indexes()?;
+        let used = workload_indexes(connection, workload)?;
+        let stats = index_stats(connection)?;
+
+        let mut advice = Vec::new();
+        for (table, indexes) in live_indexes(connection)? {
+            for index in &indexes {
+                if !owned.contains(&index.name) || index.unique || index.partial {
+                    continue;
+                }
+
+                let reason = if let Some(covered_by) = covering_index(index, &indexes) {
+                    IndexAdviceReason::Redundant {
+                        covered_by: covered_by.to_owned(),
+                    }
+                } else if !workload.is_empty() && !used.contains(&index.name) {
+                    IndexAdviceReason::Unused
+                } else if let Some(&(rows, rows_per_key)) = stats
+                    .get(&index.name)
+                    .filter(|(rows, per_key)| *rows >= MIN_STAT_ROWS && per_key * 10 >= *rows)
+                    .filter(|_| !used.contains(&index.name))
+                {
+                    IndexAdviceReason::Unselective { rows, rows_per_key }
+                } else {
+                    continue;
+                };
+
+                advice.push(IndexAdvice {
+                    index: index.name.clone(),
+                    table: table.clone(),
+                    reason,
+                });
+            }
+        }
+        Ok(advice)
+    }
+
+    /// Names of the explicitly created indexes left after replaying every migration, including
+    /// repeatable migrations.
+    fn migration_indexes(&self) -> Result<BTreeSet<String>> {
+        let connection = Connection::open_in_memory()?;
+        self.setup_connection(&connection)?;
+        let migrations = self.migrations.iter().chain(&self.repeatables);
+        for migration in migrations.filter(|m| self.applies(m)) {
+            migration.execute(&connection, |_| {})?;
+        }
+        let mut stmt = connection
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'index' AND sql IS NOT NULL")?;
+        Ok(stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+/// Finds another index on the same table whose leading columns are all of `index`'s columns.
+///
+/// Of two identical indexes, only the one whose name sorts last is reported as redundant, unless
+/// the other is unique.
+fn covering_index<'i>(index: &IndexInfo, indexes: &'i [IndexInfo]) -> Option<&'i str> {
+    let columns = index.columns.as_ref()?;
+    indexes
+        .iter()
+        .filter(|other| other.name != index.name && !other.partial)
+        .find(|other| {
+            let Some(other_columns) = &other.columns else {
+                return false;
+            };
+            other_columns.starts_with(columns)
+                && (other_columns.len() > columns.len() || other.unique || other.name < index.name)
+        })
+        .map(|other| other.name.as_str())
+}
+
+/// Indexes in the live database, grouped by table.
+fn live_indexes(connection: &Connection) -> rusqlite::Result<BTreeMap<String, Vec<IndexInfo>>> {
+    let mut stmt = connection.prepare(
+        "SELECT m.name, l.name, l.\"unique\", l.partial \
+         FROM sqlite_master AS m, pragma_index_list(m.name) AS l \
+         WHERE m.type = 'table' \
+         ORDER BY m.name, l.name",
+    )?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, bool>(2)?,
+                row.get::<_, bool>(3)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut columns_stmt =
+        connection.prepare("SELECT name FROM pragma_index_info(?1) ORDER BY seqno")?;
+    let mut tables: BTreeMap<String, Vec<IndexInfo>> = BTreeMap::new();
+    for (table, name, unique, partial) in rows {
+        let columns = columns_stmt
+            .query_map([&name], |row| row.get::<_, Option<String>>(0))?
+            .collect::<rusqlite::Result<Option<Vec<_>>>>()?;
+        tables.entry(table).or_default().push(IndexInfo {
+            name,
+            unique,
+            partial,
+            columns,
+        });
+    }
+    Ok(tables)
+}
+
+/// Names of the indexes the query planner chooses for any of the workload queries.
+fn workload_indexes(
+    connection: &Connection,
+    workload: &[&str],
+) -> rusqlite::Result<BTreeSet<String>> {
+    let mut used = BTreeSet::new();
+    for query in workload {
+        let mut stmt = connection.prepare(&format!("EXPLAIN QUERY PLAN {query}"))?;
+        // Parameters are left unbound, which `query` would reject.
+        let mut rows = stmt.raw_query();
+        while let Some(row) = rows.next()? {
+            let detail: String = row.get(3)?;
+            // e.g. "SEARCH users USING COVERING INDEX idx_users_name (name=?)"
+            let mut words = detail.split_whitespace();
+            if words.by_ref().any(|word| word == "INDEX") {
+                if let Some(name) = words.next() {
+                    used.insert(name.to_owned());
+                }
+            }
+        }
+    }
+    Ok(used)
+}
+
+/// Row counts and rows per key of the first indexed column, from `sqlite_stat1`.
+fn index_stats(connection: &Connection) -> rusqlite::Result<BTreeMap<String, (u64, u64)>> {
+    let analyzed: bool = connection.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'sqlite_stat1')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !analyzed {
+        return Ok(BTreeMap::new());
+    }
+
+    let mut stmt =
+        connection.prepare("SELECT idx, stat FROM sqlite_stat1 WHERE idx IS NOT NULL")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    let mut stats = BTreeMap::new();
+    for row in rows {
+        let (index, stat) = row?;
+        let mut numbers = stat.split_whitespace().map(str::parse::<u64>);
+        if let (Some(Ok(rows)), Some(Ok(per_key))) = (numbers.next(), numbers.next()) {
+            stats.insert(index, (rows, per_key));
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_redundant_prefix_index() -> Result<()> {
+        let monarch: MonarchDB = StaticMonarchConfiguration {
+            name: "indexes",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, email TEXT UNIQUE, team INTEGER);
+                 CREATE INDEX idx_users_name ON users(name);
+                 CREATE INDEX idx_users_name_team ON users(name, team);",
+                "CREATE INDEX idx_users_team ON users(team);
+                 CREATE UNIQUE INDEX idx_users_email_name ON users(email, name);",
+            ],
+        }
+        .into();
+        let connection = monarch.open_in_memory()?;
+        connection.execute_batch("CREATE INDEX local_users_team ON users(team);")?;
+
+        let advice = monarch.advise_indexes(&connection, &[])?;
+        assert_eq!(
+            advice,
+            [IndexAdvice {
+                index: "idx_users_name".into(),
+                table: "users".into(),
+                reason: IndexAdviceReason::Redundant {
+                    covered_by: "idx_users_name_team".into()
+                },
+            }]
+        );
+        assert_eq!(
+            advice[0].drop_sql(),
+            "DROP INDEX IF EXISTS \"idx_users_name\";"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unused_by_workload() -> Result<()> {
+        let monarch: MonarchDB = StaticMonarchConfiguration {
+            name: "indexes",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, email TEXT UNIQUE, team INTEGER);
+                 CREATE INDEX idx_users_name ON users(name);
+                 CREATE INDEX idx_users_name_team ON users(name, team);",
+                "CREATE INDEX idx_users_team ON users(team);
+                 CREATE UNIQUE INDEX idx_users_email_name ON users(email, name);",
+            ],
+        }
+        .into();
+        let connection = monarch.open_in_memory()?;
+
+        let workload = ["SELECT id FROM users WHERE name = ? AND team = ?"];
+        let advice = monarch.advise_indexes(&connection, &workload)?;
+        let flagged: Vec<_> = advice
+            .iter()
+            .map(|a| (a.index.as_str(), &a.reason))
+            .collect();
+        assert_eq!(
+            flagged,
+            [
+                (
+                    "idx_users_name",
+                    &IndexAdviceReason::Redundant {
+                        covered_by: "idx_users_name_team".into()
+                    }
+                ),
+                ("idx_users_team", &IndexAdviceReason::Unused),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unselective_index() -> Result<()> {
+        let monarch: MonarchDB = StaticMonarchConfiguration {
+            name: "indexes",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, email TEXT UNIQUE, team INTEGER);
+                 CREATE INDEX idx_users_name ON users(name);
+                 CREATE INDEX idx_users_name_team ON users(name, team);",
+                "CREATE INDEX idx_users_team ON users(team);
+                 CREATE UNIQUE INDEX idx_users_email_name ON users(email, name);",
+            ],
+        }
+        .into();
+        let connection = monarch.open_in_memory()?;
+        connection.execute_batch(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+             INSERT INTO users (name, email, team) SELECT 'user' || i, 'u' || i, i % 2 FROM n;
+             ANALYZE;",
+        )?;
+
+        let advice = monarch.advise_indexes(&connection, &[])?;
+        let team = advice
+            .iter()
+            .find(|a| a.index == "idx_users_team")
+            .expect("team index flagged");
+        assert_eq!(
+            team.reason,
+            IndexAdviceReason::Unselective {
+                rows: 2000,
+                rows_per_key: 1000
+            }
+        );
+        Ok(())
+    }
+}
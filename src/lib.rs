@@ -7,7 +7,7 @@
 //! ## Quick Start
 //!
 //! ```rust
-//! use monarch_db::{StaticMonarchConfiguration, MonarchDB, ConnectionConfiguration};
+//! use monarch_db::{StaticMonarchConfiguration, MonarchDB, ConnectionConfiguration, Database};
 //!
 //! # fn main() -> Result<(), Box<dyn std::error::Error>> {
 //! // Define your migrations at compile time
@@ -43,7 +43,8 @@
 //!
 //! // Create connection configuration
 //! let connection_config = ConnectionConfiguration {
-//!     database: None, // Use in-memory database for this example
+//!     database: Database::Memory, // Use in-memory database for this example
+//!     ..Default::default()
 //! };
 //!
 //! // Create database connection with migrations applied
@@ -63,19 +64,21 @@
 //! Use directory-based configuration when you want to manage migrations as separate files:
 //!
 //! ```rust,no_run
-//! use monarch_db::{MonarchConfiguration, MonarchDB, ConnectionConfiguration};
+//! use monarch_db::{MonarchConfiguration, MonarchDB, ConnectionConfiguration, Database};
 //!
 //! # fn main() -> Result<(), Box<dyn std::error::Error>> {
 //! let config = MonarchConfiguration {
 //!     name: "my_app".to_string(),
 //!     enable_foreign_keys: true,
 //!     migration_directory: "./migrations".into(),
+//!     ..Default::default()
 //! };
 //!
 //! let monarch_db = MonarchDB::from_configuration(config)?;
 //!
 //! let connection_config = ConnectionConfiguration {
-//!     database: Some("./my_app.db".into()),
+//!     database: Database::File("./my_app.db".into()),
+//!     ..Default::default()
 //! };
 //!
 //! let connection = monarch_db.create_connection(&connection_config)?;
@@ -89,44 +92,261 @@
 //!
 //! - [`StaticMonarchConfiguration`] - For compile-time embedded migrations
 //! - [`MonarchConfiguration`] - For runtime directory-based migrations
-//! - [`ConnectionConfiguration`] - For specifying database file paths
+//! - [`ConnectionConfiguration`] - For specifying database file paths and attached databases
 //!
 //! ## Core Types
 //!
 //! - [`MonarchDB`] - Main migration manager that applies schema changes
 //! - [`Migrations`] - Helper for applying migrations to database connections
+//! - [`MonarchSet`] - Applies several schemas to one connection in a single transaction
+//! - [`MigrationReport`] - Summary of the migrations applied for a schema
+//! - [`MigrationPlan`] - The migrations which would be applied for a schema
+//! - [`HistoryEntry`] - A migration recorded as run against a database
+//! - [`MonarchDescription`] - The migrations and settings of a [`MonarchDB`]
+//! - `VersionMetadata` - Tables and columns present at each schema version (`metadata` feature)
+//! - [`SchemaDiff`] - Differences between a database's schema and its migrations
+//! - [`Error`] - Errors raised while loading migrations
+//! - [`engine`] - The individual steps of migrating, for tools with their own frontend
+//!
+//! ## Feature Flags
+//!
+//! Without default features, only the core which applies migrations is built. The inspection
+//! APIs are gated by `blame` (schema blame and changelogs), `codegen` (Rust definitions of the
+//! schema), `generate` (migrations from a schema file), `indexes` (index advice), `metadata`
+//! (per-version table metadata) and `squash` (baselines), all of which the default `cli` feature
+//! enables. Optional dependencies are pulled in by `serde`, `testing`, `metrics`, `registry`,
+//! `import`, `arrow` and `libsql`. See the README for the full list.
 //!
 
-use std::{borrow::Cow, collections::BTreeMap, io};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
 
 use camino::Utf8PathBuf;
-use rusqlite::Connection;
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+use tracing::Instrument;
+
+mod adopt;
+mod ahead;
+mod assertion;
+mod backend;
+#[cfg(feature = "blame")]
+mod blame;
+#[cfg(feature = "blob")]
+mod blob;
+mod budget;
+mod busy;
+#[cfg(feature = "blame")]
+mod changelog;
+mod checkpoint;
+mod checksum;
+#[cfg(feature = "codegen")]
+mod codegen;
+mod compression;
+mod database;
+mod dependency;
+mod describe;
+mod determinism;
+mod digest;
+pub mod engine;
+mod error;
+#[cfg(feature = "arrow")]
+mod export;
+mod extension;
+mod filter;
+mod foreign_keys;
+#[cfg(feature = "generate")]
+mod generate;
+mod history;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "import")]
+mod import;
+#[cfg(feature = "indexes")]
+mod indexes;
+#[cfg(feature = "libsql")]
+mod libsql;
+#[cfg(feature = "metadata")]
+mod metadata;
+mod migration;
+mod out_of_order;
+mod plan;
+mod progress;
+mod quarantine;
+#[cfg(feature = "registry")]
+mod registry;
+mod repeatable;
+mod retry;
+mod sampling;
+mod schema;
+mod schema_file;
+mod set;
+mod setup;
+mod shutdown;
+mod source;
+mod sql;
+#[cfg(feature = "squash")]
+mod squash;
+mod telemetry;
+mod template;
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "registry")]
+#[doc(hidden)]
+pub mod __private {
+    pub use linkme;
+}
+mod timeout;
+mod transaction;
+mod transactional;
+mod tuning;
+mod validate;
+mod verify;
+mod versioning;
 
-type Migration = Cow<'static, str>;
+pub use adopt::MigrationTool;
+pub use ahead::VersionAheadPolicy;
+pub use backend::SqliteBackend;
+#[cfg(feature = "blame")]
+pub use blame::{MigrationRef, ObjectBlame};
+#[cfg(feature = "blob")]
+pub use blob::{BlobCopy, BlobProgress, DEFAULT_BLOB_CHUNK_SIZE};
+pub use budget::DeferredMigrations;
+pub use busy::BusyHandler;
+#[cfg(feature = "blame")]
+pub use changelog::{AlteredObject, ChangelogEntry};
+pub use checksum::{ChecksumAlgorithm, ChecksumPolicy};
+pub use compression::CompressedMonarchConfiguration;
+pub use database::Database;
+#[cfg(feature = "testing")]
+pub use database::TempDatabase;
+pub use describe::{MigrationDescription, MonarchDescription};
+pub use determinism::{DeterminismPolicy, Nondeterminism};
+pub use digest::TableDigest;
+pub use error::{Error, Result};
+#[cfg(feature = "arrow")]
+pub use export::{DEFAULT_EXPORT_BATCH_SIZE, ExportError, ExportFormat, TableExport};
+pub use extension::SqliteExtension;
+pub use history::{HistoryEntry, MigrationStatus};
+#[cfg(feature = "http")]
+pub use http::{DEFAULT_HTTP_TIMEOUT, DEFAULT_MAX_RESPONSE_SIZE, HttpSource};
+#[cfg(feature = "import")]
+pub use import::{DEFAULT_IMPORT_BATCH_SIZE, ImportError, TableImport};
+#[cfg(feature = "indexes")]
+pub use indexes::{IndexAdvice, IndexAdviceReason};
+#[cfg(feature = "metadata")]
+pub use metadata::{ColumnMetadata, TableMetadata, VersionMetadata};
+pub use plan::{MigrationPlan, PlannedMigration};
+pub use progress::{MigrationProgress, ProgressReporter};
+#[cfg(feature = "registry")]
+pub use registry::{REGISTERED_MIGRATIONS, RegisteredMigrations};
+pub use retry::RetryPolicy;
+pub use schema::{ModifiedObject, SchemaDiff, SchemaObject};
+pub use set::MonarchSet;
+pub use shutdown::ShutdownGuard;
+pub use source::{MigrationSource, SourceMigration};
+pub use transaction::TransactionMode;
+pub use transactional::{TransactionEffect, TransactionHazard};
+pub use tuning::TempStore;
+pub use versioning::VersionScheme;
+
+use backend::{Driver, Rusqlite, block_on};
+use migration::Migration;
+use progress::Reporter;
+use quarantine::Quarantine;
+use retry::is_busy;
+use sampling::LogSampler;
+use setup::ConnectionSetup;
 
 const VERSION_TABLE: &str = "monarch_db_schema_version";
 
+/// Default amount of time to wait for the migration lock, see [`MonarchDB::with_lock_timeout`].
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Initial pause between attempts to acquire the migration lock.
+const LOCK_POLL_INITIAL: Duration = Duration::from_millis(5);
+
+/// Upper bound on the pause between attempts to acquire the migration lock.
+const LOCK_POLL_MAX: Duration = Duration::from_millis(100);
+
 /// Configuration for opening a new SQLite database connection.
 ///
 /// This struct controls how a database connection is established, including
 /// whether to use a file-based database or an in-memory database.
-#[derive(Debug, Clone)]
-#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConnectionConfiguration {
-    /// Optional path to the database file.
+    /// The database to open.
+    ///
+    /// Defaults to [`Database::Memory`], a private in-memory database. Use
+    /// [`Database::File`] to persist the database to a file, or [`Database::SharedMemory`] to
+    /// share one in-memory database between connections.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub database: Database,
+
+    /// Secondary databases to `ATTACH` to the connection before migrations run.
+    ///
+    /// Migrations can refer to objects in these databases through their schema name
+    /// (e.g. `CREATE TABLE audit.events (...)`), and the connection returned by
+    /// [`MonarchDB::create_connection`] has them attached.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub attach: Vec<AttachedDatabase>,
+
+    /// SQLite extensions to load into the connection before migrations run.
+    ///
+    /// Migrations which create virtual tables from an extension (e.g. `spellfix1`) need it
+    /// loaded first. Loading extensions requires the `load_extension` feature; without it,
+    /// opening a connection with extensions configured fails.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub extensions: Vec<SqliteExtension>,
+
+    /// SQL run on every new connection, after extensions are loaded and databases attached, and
+    /// before migrations run.
     ///
-    /// If `None`, an in-memory database will be used. If `Some`, the database
-    /// will be persisted to the specified file path.
+    /// Use this for per-connection settings such as `PRAGMA journal_mode = WAL` or
+    /// `PRAGMA busy_timeout = 5000`. Each entry may contain several statements, and entries run
+    /// in order. Unlike migrations, init SQL is not versioned and runs every time.
     #[cfg_attr(feature = "serde", serde(default))]
-    pub database: Option<Utf8PathBuf>,
+    pub init_sql: Vec<String>,
+
+    /// The size of the page cache, set with `PRAGMA cache_size` before migrations run.
+    ///
+    /// A positive value is a number of pages, and a negative value a number of KiB, so
+    /// `-262144` asks for a 256 MiB cache. `None` leaves SQLite's default, about 2 MiB.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cache_size: Option<i64>,
+
+    /// The most bytes of the database to memory-map, set with `PRAGMA mmap_size` before
+    /// migrations run. SQLite caps this at its compile-time limit, and `0` turns memory-mapping
+    /// off. `None` leaves SQLite's default.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub mmap_size: Option<i64>,
+
+    /// Where temporary tables and indices are kept, set with `PRAGMA temp_store` before
+    /// migrations run. `None` leaves SQLite's default.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub temp_store: Option<TempStore>,
+}
+
+/// A secondary database attached to a connection with `ATTACH DATABASE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttachedDatabase {
+    /// The schema name the database is attached as, e.g. `audit` in `audit.events`.
+    pub schema: String,
+    /// Path to the database file, which is created if it does not exist.
+    pub path: Utf8PathBuf,
 }
 
 /// Configuration for MonarchDB that loads migrations from a directory at runtime.
 ///
 /// This configuration is used when migrations are stored as separate files in a
 /// directory and need to be loaded dynamically when the application starts.
-#[derive(Debug, Clone)]
-#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MonarchConfiguration {
     /// The name of the database schema, used for tracking migration versions.
     pub name: String,
@@ -134,6 +354,59 @@ pub struct MonarchConfiguration {
     pub enable_foreign_keys: bool,
     /// Path to the directory containing migration files.
     pub migration_directory: Utf8PathBuf,
+    /// Further directories whose migration files are merged with those in
+    /// [`migration_directory`](Self::migration_directory), e.g. from optional modules.
+    ///
+    /// With more than one directory, migrations are ordered by the number their file name
+    /// starts with, then by file name, so `core/002_users.sql` runs before
+    /// `billing/010_invoices.sql` whatever the width of their prefixes. A file name may only be
+    /// used once across all the directories.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub additional_migration_directories: Vec<Utf8PathBuf>,
+    /// Directories of migrations which only run in one environment, keyed by the environment,
+    /// e.g. `migrations-dev` for extra tables in local development.
+    ///
+    /// Their files are merged with the other directories' like
+    /// [`additional_migration_directories`](Self::additional_migration_directories), and each
+    /// migration is restricted to its directory's environment, as if it had a `-- monarch:env`
+    /// directive. Overlay migrations still occupy their versions in other environments, so
+    /// every environment agrees on what each schema version means.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub overlay_directories: BTreeMap<String, Utf8PathBuf>,
+    /// Whether migrations are also loaded from the subdirectories of the migration directories,
+    /// e.g. `2024/001_init.sql`.
+    ///
+    /// Versioned migrations are then named by their paths relative to their migration
+    /// directory, and ordered a path component at a time by the number each starts with, then by
+    /// name, so `2024/010_tags.sql` runs before `2025/001_labels.sql`. Hidden subdirectories are
+    /// skipped, and repeatable migrations are named by their file names wherever they are.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub recursive: bool,
+    /// Values for `{{name}}` placeholders in the migration files.
+    ///
+    /// Placeholders are replaced before migrations run, so the same migrations can be shipped
+    /// to deployments which need, for example, different table prefixes. The `app_name`
+    /// variable defaults to [`name`](Self::name). A placeholder without a value is an error.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub variables: BTreeMap<String, String>,
+    /// Patterns of the file names which are migrations, `*.sql` if empty.
+    ///
+    /// Patterns are matched against the file name, less any `.zst` compression suffix, where `*`
+    /// matches any run of characters and `?` a single character. Other files are skipped.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub include: Vec<String>,
+    /// Patterns of file names which are never migrations, e.g. `README.md`, even if they match
+    /// [`include`](Self::include). Hidden files, such as `.gitkeep`, are always skipped.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ignore: Vec<String>,
+    /// Whether a file which is neither included nor ignored is an error
+    /// ([`Error::UnexpectedFile`]) rather than skipped.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub strict: bool,
+    /// How the migrations are numbered. With [`VersionScheme::Timestamp`], migration file names
+    /// start with a timestamp, like `20240612093000_add_widgets.sql`, and run in order of it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub version_scheme: VersionScheme,
 }
 
 /// Configuration for MonarchDB with compile-time known migrations.
@@ -141,6 +414,9 @@ pub struct MonarchConfiguration {
 /// This configuration is used when all migrations are embedded in the binary
 /// at compile time, typically using `include_str!` or similar macros.
 /// This provides better performance and eliminates runtime file I/O.
+///
+/// With the `serde` feature, the configuration can be serialized, e.g. for debugging, but not
+/// deserialized, since its strings are borrowed for the life of the program.
 #[derive(Debug, Clone)]
 pub struct StaticMonarchConfiguration<const N: usize> {
     /// The name of the database schema, used for tracking migration versions.
@@ -151,16 +427,79 @@ pub struct StaticMonarchConfiguration<const N: usize> {
     pub migrations: [&'static str; N],
 }
 
+#[cfg(feature = "serde")]
+impl<const N: usize> serde::Serialize for StaticMonarchConfiguration<N> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("StaticMonarchConfiguration", 3)?;
+        state.serialize_field("name", self.name)?;
+        state.serialize_field("enable_foreign_keys", &self.enable_foreign_keys)?;
+        state.serialize_field("migrations", &self.migrations[..])?;
+        state.end()
+    }
+}
+
 impl<const N: usize> From<StaticMonarchConfiguration<N>> for MonarchDB {
     fn from(configuration: StaticMonarchConfiguration<N>) -> Self {
+        MonarchDB::from_static(
+            configuration.name,
+            configuration.enable_foreign_keys,
+            &configuration.migrations,
+        )
+    }
+}
+
+impl MonarchDB {
+    /// Creates a `MonarchDB` from migrations embedded in the binary, named by their versions.
+    fn from_static(
+        name: &'static str,
+        enable_foreign_keys: bool,
+        migrations: &[&'static str],
+    ) -> Self {
+        let migrations = migrations
+            .iter()
+            .enumerate()
+            .map(|(index, query)| Migration::new((index + 1).to_string(), *query))
+            .collect();
+        MonarchDB::new(name.into(), enable_foreign_keys, migrations, Vec::new())
+    }
+
+    /// Creates a `MonarchDB` with the given migrations and the default settings.
+    fn new(
+        name: Cow<'static, str>,
+        enable_foreign_keys: bool,
+        migrations: Vec<Migration>,
+        repeatables: Vec<Migration>,
+    ) -> Self {
         MonarchDB {
-            name: configuration.name.into(),
-            enable_foreign_keys: configuration.enable_foreign_keys,
-            migrations: configuration
-                .migrations
-                .iter()
-                .map(|q| Cow::Borrowed(*q))
-                .collect(),
+            name,
+            enable_foreign_keys,
+            migrations,
+            repeatables,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            retry_policy: RetryPolicy::NONE,
+            environment: None,
+            determinism_policy: DeterminismPolicy::Allow,
+            version_ahead_policy: VersionAheadPolicy::Ignore,
+            checksum_policy: ChecksumPolicy::Ignore,
+            checksum_algorithm: ChecksumAlgorithm::Fnv1a64,
+            connection_log: LogSampler::new(Duration::ZERO),
+            transaction_mode: TransactionMode::Single,
+            shutdown: None,
+            progress: None,
+            quarantine: Vec::new(),
+            connection_setup: Vec::new(),
+            after_open: Vec::new(),
+            schema_file: None,
+            wal_checkpoint: false,
+            busy_handler: None,
+            migration_timeout: None,
+            version_scheme: VersionScheme::Sequential,
+            out_of_order: false,
         }
     }
 }
@@ -171,6 +510,27 @@ pub struct MonarchDB {
     name: Cow<'static, str>,
     enable_foreign_keys: bool,
     migrations: Vec<Migration>,
+    repeatables: Vec<Migration>,
+    lock_timeout: Duration,
+    retry_policy: RetryPolicy,
+    environment: Option<Cow<'static, str>>,
+    determinism_policy: DeterminismPolicy,
+    version_ahead_policy: VersionAheadPolicy,
+    checksum_policy: ChecksumPolicy,
+    checksum_algorithm: ChecksumAlgorithm,
+    connection_log: LogSampler,
+    transaction_mode: TransactionMode,
+    shutdown: Option<ShutdownGuard>,
+    progress: Option<Reporter>,
+    quarantine: Vec<Quarantine>,
+    connection_setup: Vec<ConnectionSetup>,
+    after_open: Vec<ConnectionSetup>,
+    schema_file: Option<Utf8PathBuf>,
+    wal_checkpoint: bool,
+    busy_handler: Option<BusyHandler>,
+    migration_timeout: Option<Duration>,
+    version_scheme: VersionScheme,
+    out_of_order: bool,
 }
 
 impl MonarchDB {
@@ -181,8 +541,8 @@ impl MonarchDB {
     ///
     /// # Returns
     ///
-    /// Returns a `rusqlite::Result<Connection>` with migrations applied on success.
-    pub fn open_in_memory(&self) -> rusqlite::Result<Connection> {
+    /// Returns a `Result<Connection>` with migrations applied on success.
+    pub fn open_in_memory(&self) -> Result<Connection> {
         let connection = Connection::open_in_memory()?;
         self.migrate(connection)
     }
@@ -197,74 +557,255 @@ impl MonarchDB {
     /// * `configuration` - A MonarchConfiguration containing the migration directory path,
     ///   database name, and foreign key settings.
     ///
+    /// Template variables in the migrations are substituted as they are loaded, see
+    /// [`MonarchConfiguration::variables`].
+    ///
     /// # Returns
     ///
-    /// Returns a `io::Result<Self>` containing the configured MonarchDB instance.
+    /// Returns a `Result<Self>` containing the configured MonarchDB instance.
     ///
     /// # Errors
     ///
+    /// Only files with the `.sql` extension are loaded, and subdirectories are skipped.
+    ///
     /// This function will return an error if:
-    /// - The migration directory cannot be read
-    /// - Any migration file cannot be read
-    /// - File system operations fail
-    pub fn from_configuration(configuration: MonarchConfiguration) -> io::Result<Self> {
-        let mut migrations = BTreeMap::new();
-        for diritem in configuration.migration_directory.read_dir_utf8()? {
-            let entry = diritem?;
-
-            if entry.file_type()?.is_file() {
-                let query = std::fs::read_to_string(entry.path())?;
-                migrations.insert(entry.file_name().to_owned(), Cow::from(query));
-            }
-        }
+    /// - A migration directory cannot be read ([`Error::ReadDirectory`])
+    /// - An entry of a migration directory cannot be listed ([`Error::ReadEntry`]), e.g. because
+    ///   its name is not UTF-8
+    /// - A migration file cannot be read ([`Error::ReadMigration`])
+    /// - A migration file is not UTF-8 text ([`Error::InvalidEncoding`])
+    /// - A migration uses a template variable which has no value
+    /// - A migration other than the first is a baseline, see [`squash`](Self::squash)
+    pub fn from_configuration(configuration: MonarchConfiguration) -> Result<Self> {
+        MonarchDB::from_source(configuration)
+    }
 
-        Ok(MonarchDB {
-            name: configuration.name.into(),
-            enable_foreign_keys: configuration.enable_foreign_keys,
-            migrations: migrations.into_values().collect(),
-        })
+    /// Creates a `MonarchDB` from `(name, SQL)` pairs, for migrations which come from somewhere
+    /// other than a directory, such as generated code, another database or the network.
+    ///
+    /// Migrations run in the order the pairs are given. As with
+    /// [`from_configuration`](Self::from_configuration), a migration whose name starts with
+    /// `R__` is a repeatable migration, see
+    /// [`with_repeatable_migration`](Self::with_repeatable_migration). Template variables are not
+    /// substituted and import paths are used as given.
+    ///
+    /// ```rust
+    /// use monarch_db::MonarchDB;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let migrations = vec![
+    ///     ("001_users.sql".to_owned(), "CREATE TABLE users (id INTEGER PRIMARY KEY);".to_owned()),
+    ///     ("002_names.sql".to_owned(), "ALTER TABLE users ADD COLUMN name TEXT;".to_owned()),
+    /// ];
+    /// let monarch_db = MonarchDB::from_iter("my_app", true, migrations)?;
+    /// assert_eq!(monarch_db.current_version(), 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - Two migrations have the same name ([`Error::DuplicateMigration`])
+    /// - A migration other than the first is a baseline, see [`squash`](Self::squash)
+    pub fn from_iter(
+        name: impl Into<Cow<'static, str>>,
+        enable_foreign_keys: bool,
+        migrations: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self> {
+        let migrations = migrations
+            .into_iter()
+            .map(|(name, sql)| SourceMigration::new(name, sql));
+        MonarchDB::from_migrations(name.into(), enable_foreign_keys, migrations)
+    }
+
+    /// Sets how long to wait for the migration lock before giving up.
+    ///
+    /// Migrations run inside a `BEGIN IMMEDIATE` transaction, which takes the database
+    /// write lock up front. When several processes open the same database file at once
+    /// (e.g. the CLI and an application), only one of them can hold that lock; the others
+    /// retry with a short backoff until the lock is released or this timeout elapses, at
+    /// which point the `SQLITE_BUSY` error is returned. Once the lock is acquired, the
+    /// schema version is re-read, so a process which waited on another will find the
+    /// migrations already applied.
+    ///
+    /// Note that each attempt is also subject to the connection's own busy timeout
+    /// (five seconds for connections opened by `rusqlite`, unless set with
+    /// [`with_busy_handler`](Self::with_busy_handler)), so the total wait can exceed
+    /// this timeout by up to one busy timeout.
+    ///
+    /// Defaults to [`DEFAULT_LOCK_TIMEOUT`].
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    /// Sets the policy for retrying operations which fail because the database is locked.
+    ///
+    /// The policy applies to opening connections in [`create_connection`](Self::create_connection)
+    /// and to preparing a connection for migrations: setting pragmas and starting the
+    /// migration transaction. Defaults to [`RetryPolicy::NONE`].
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Sets the environment migrations are applied in, such as `dev`, `test` or `production`.
+    ///
+    /// A migration can be restricted to some environments with a `-- monarch:env dev, test`
+    /// directive before its first statement, or with an environment suffix on its file name,
+    /// like `004_fixtures.dev.sql`. Restricted migrations only run when one of their
+    /// environments is selected, so without an environment only unrestricted migrations run.
+    ///
+    /// A migration which does not apply still occupies its version, as an empty migration, so
+    /// every environment agrees on what each schema version means.
+    pub fn with_environment(mut self, environment: impl Into<Cow<'static, str>>) -> Self {
+        self.environment = Some(environment.into());
+        self
+    }
+
+    /// Returns the environment migrations are applied in, see
+    /// [`with_environment`](Self::with_environment).
+    pub fn environment(&self) -> Option<&str> {
+        self.environment.as_deref()
+    }
+
+    /// Returns the name of the database schema, used for tracking migration versions.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the number of versioned migrations, not counting repeatable migrations.
+    ///
+    /// This is the [`current_version`](Self::current_version) unless the first migration is a
+    /// baseline, see [`squash`](Self::squash), which counts as a single migration here.
+    pub fn len(&self) -> usize {
+        self.migrations.len()
+    }
+
+    /// Returns whether there are no versioned migrations.
+    pub fn is_empty(&self) -> bool {
+        self.migrations.is_empty()
+    }
+
+    /// Iterates over the versioned migrations in the order they run, with the version each one
+    /// upgrades the database to and its SQL, including any directives.
+    ///
+    /// Reading the SQL of a [compressed](CompressedMonarchConfiguration) migration decompresses
+    /// it, which fails with [`Error::CompressedMigration`] if the data is corrupt.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    ///         "ALTER TABLE users ADD COLUMN name TEXT;",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// assert_eq!(monarch_db.name(), "my_app");
+    /// assert_eq!(monarch_db.len(), 2);
+    /// let versions: Vec<u32> = monarch_db.iter().map(|(version, _)| version).collect();
+    /// assert_eq!(versions, [1, 2]);
+    /// assert_eq!(
+    ///     monarch_db.migration_sql(2)?,
+    ///     Some("ALTER TABLE users ADD COLUMN name TEXT;")
+    /// );
+    /// # Ok::<(), monarch_db::Error>(())
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = (u32, Result<&str>)> {
+        self.versions()
+            .map(|(version, migration)| (version, migration.sql()))
+    }
+
+    /// Returns the SQL of the migration which upgrades the database to `version`, or `None` if
+    /// no migration ends there, e.g. for a version replaced by a baseline.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::CompressedMigration`] if the migration is compressed and cannot be
+    /// decompressed.
+    pub fn migration_sql(&self, version: u32) -> Result<Option<&str>> {
+        self.versions()
+            .find(|(reached, _)| *reached == version)
+            .map(|(_, migration)| migration.sql())
+            .transpose()
     }
 
     /// Returns the current schema version, which is the number of migrations available.
     ///
-    /// This represents the latest version that the database schema can be migrated to.
+    /// This represents the latest version that the database schema can be migrated to. When the
+    /// first migration is a baseline, see [`squash`](Self::squash), it counts as every version it
+    /// replaces.
     ///
     /// # Returns
     ///
     /// Returns the number of migrations as a `u32`.
     pub fn current_version(&self) -> u32 {
-        self.migrations.len() as u32
+        match self.baseline() {
+            Some(baseline) => baseline + self.migrations.len() as u32 - 1,
+            None => self.migrations.len() as u32,
+        }
+    }
+
+    /// The last version replaced by the baseline migration, if the first migration is one.
+    pub(crate) fn baseline(&self) -> Option<u32> {
+        self.migrations
+            .first()
+            .and_then(|migration| migration.baseline)
+    }
+
+    /// The migration which upgrades a database from `version`, and the version it reaches.
+    fn get_migration(&self, version: u32) -> Result<(&Migration, u32)> {
+        let (index, next) = match self.baseline() {
+            Some(baseline) if version == 0 => (0, baseline),
+            Some(baseline) if version < baseline => {
+                return Err(Error::SquashedVersion { version, baseline });
+            }
+            Some(baseline) => (version - baseline + 1, version + 1),
+            None => (version, version + 1),
+        };
+        let migration = self
+            .migrations
+            .get(index as usize)
+            .expect("version <-> migration mismatch");
+        Ok((migration, next))
     }
 
-    fn get_migration(&self, version: u32) -> Option<&str> {
+    /// Each migration with the version it reaches, in order.
+    pub(crate) fn versions(&self) -> impl Iterator<Item = (u32, &Migration)> {
+        let first = self.baseline().unwrap_or(1);
         self.migrations
-            .get(version as usize)
-            .map(|query| query.as_ref())
+            .iter()
+            .enumerate()
+            .map(move |(index, migration)| (first + index as u32, migration))
+    }
+
+    /// Whether `migration` runs in the configured environment.
+    fn applies(&self, migration: &Migration) -> bool {
+        migration.applies_to(self.environment())
     }
 
     /// Creates a new SQLite database connection with migrations applied.
     ///
-    /// If a database path is specified in the configuration, opens that file.
-    /// Otherwise, creates an in-memory database. All migrations will be automatically
+    /// Opens the [`Database`] given in the configuration, by default a private in-memory
+    /// database. Any secondary databases listed in the
+    /// configuration are attached, and then all migrations will be automatically
     /// applied to ensure the schema is up to date.
     ///
     /// # Arguments
     ///
-    /// * `configuration` - A ConnectionConfiguration specifying the database path.
-    ///   If `database` is None, an in-memory database will be created.
+    /// * `configuration` - A ConnectionConfiguration specifying the database to open.
     ///
     /// # Returns
     ///
-    /// Returns a `rusqlite::Result<Connection>` with migrations applied on success.
-    pub fn create_connection(
-        &self,
-        configuration: &ConnectionConfiguration,
-    ) -> rusqlite::Result<Connection> {
-        let connection = if let Some(path) = configuration.database.as_deref() {
-            Connection::open(path)?
-        } else {
-            Connection::open_in_memory()?
-        };
+    /// Returns a `Result<Connection>` with migrations applied on success.
+    pub fn create_connection(&self, configuration: &ConnectionConfiguration) -> Result<Connection> {
+        let connection = open_connection(configuration, &self.retry_policy, self.busy_handler)?;
         self.migrate(connection)
     }
 
@@ -281,15 +822,80 @@ impl MonarchDB {
     /// # Returns
     ///
     /// Returns the connection with migrations applied on success.
-    pub fn migrate(&self, mut connection: Connection) -> rusqlite::Result<Connection> {
-        let migrations = Migrations {
-            connection: &mut connection,
-            monarch: self,
-        };
-        migrations.prepare()?;
+    pub fn migrate(&self, mut connection: Connection) -> Result<Connection> {
+        self.apply(&mut connection)?;
         Ok(connection)
     }
 
+    /// Applies all necessary migrations to a borrowed database connection.
+    ///
+    /// This is like [`migrate`](Self::migrate), including running the
+    /// [`with_after_open`](Self::with_after_open) hooks, for connections which cannot be moved,
+    /// such as one held in a connection pool's slot or another wrapper. It returns a report of
+    /// the migrations applied rather than the connection.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+    /// }
+    /// .into();
+    ///
+    /// let mut slot = Some(rusqlite::Connection::open_in_memory()?);
+    /// if let Some(connection) = slot.as_mut() {
+    ///     let report = monarch_db.apply(connection)?;
+    ///     assert_eq!(report.applied(), 1);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn apply(&self, connection: &mut Connection) -> Result<MigrationReport> {
+        let report = self.migrate_to(connection, self.current_version())?;
+        self.after_open(connection)?;
+        Ok(report)
+    }
+
+    /// Applies the migrations up to version `target` to an existing database connection.
+    ///
+    /// This is like [`migrate`](Self::migrate), but stops at an intermediate version, e.g. so
+    /// that the previous release of an application, which only understands that version, can
+    /// keep running against the database during a blue/green deploy. A database already at or
+    /// past `target` is left unchanged, since migrations cannot be undone. Repeatable
+    /// migrations only run when `target` is the [`current_version`](Self::current_version).
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    ///         "ALTER TABLE users ADD COLUMN name TEXT;",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// let mut connection = rusqlite::Connection::open_in_memory()?;
+    /// let report = monarch_db.migrate_to(&mut connection, 1)?;
+    /// assert_eq!((report.from_version, report.to_version), (0, 1));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::UnreachableVersion`] if `target` is past the current version or
+    /// inside the versions replaced by a baseline, see [`squash`](Self::squash).
+    pub fn migrate_to(&self, connection: &mut Connection, target: u32) -> Result<MigrationReport> {
+        self.migrations(connection).prepare_to(target)
+    }
+
     /// Create a migration manager for the given connection.
     ///
     /// This method initializes a new `Migrations` instance, which can be used to
@@ -298,6 +904,7 @@ impl MonarchDB {
         Migrations {
             connection,
             monarch: self,
+            refresh_schema_file: true,
         }
     }
 }
@@ -309,6 +916,7 @@ impl MonarchDB {
 pub struct Migrations<'c> {
     connection: &'c mut Connection,
     monarch: &'c MonarchDB,
+    refresh_schema_file: bool,
 }
 
 impl<'c> Migrations<'c> {
@@ -320,96 +928,528 @@ impl<'c> Migrations<'c> {
     ///
     /// # Returns
     ///
-    /// Returns `Ok(())` on success, or a `rusqlite::Error` if any operation fails.
-    #[tracing::instrument(level = "trace", skip_all, fields(monarch=%self.monarch.name))]
-    pub fn prepare(self) -> rusqlite::Result<()> {
-        if self.monarch.enable_foreign_keys {
+    /// Returns `Ok(())` on success, or an [`Error`] if any operation fails.
+    pub fn prepare(self) -> Result<()> {
+        let target = self.monarch.current_version();
+        self.prepare_to(target)?;
+        Ok(())
+    }
+
+    /// Prepares the database connection like [`prepare`](Self::prepare), but only applies
+    /// migrations up to version `target`, see [`MonarchDB::migrate_to`].
+    #[tracing::instrument(level = "trace", skip_all, fields(monarch=%self.monarch.name, target))]
+    pub fn prepare_to(self, target: u32) -> Result<MigrationReport> {
+        let sampled = self.monarch.connection_log.sample();
+        if self.monarch.enable_foreign_keys && sampled.is_some() {
             tracing::trace!("Set foreign keys");
-            self.connection.pragma_update(None, "foreign_keys", true)?;
         }
-        self.migrate()?;
+        engine::prepare_connection(self.monarch, self.connection)?;
+        if !self.monarch.relaxes_foreign_keys(self.connection, target)? {
+            return self.migrate(target, sampled);
+        }
+        // A migration relaxes foreign keys, which can only be turned off outside a transaction.
+        self.connection.pragma_update(None, "foreign_keys", false)?;
+        let report = self.migrate(target, sampled);
+        let enforced = self.connection.pragma_update(None, "foreign_keys", true);
+        let report = report?;
+        enforced?;
+        Ok(report)
+    }
+
+    /// Migrates through the migration core, logging the outcome if the connection was
+    /// `sampled` and nothing ran, see [`MonarchDB::with_connection_log_interval`].
+    fn migrate(&self, target: u32, sampled: Option<u64>) -> Result<MigrationReport> {
+        let interruptible = self.monarch.interruptible(self.connection);
+        let report = block_on(self.monarch.migrate_through(
+            &mut Rusqlite(self.connection),
+            target,
+            self.refresh_schema_file,
+        ));
+        drop(interruptible);
+        let report =
+            report.map_err(|error| self.monarch.check_cancelled(self.connection, error))?;
+        let idle = report.applied() == 0 && report.repeatable.is_empty();
+        if let Some(suppressed) = sampled.filter(|_| idle) {
+            tracing::debug!(
+                version = report.to_version,
+                suppressed,
+                "Schema is up to date"
+            );
+        }
+        Ok(report)
+    }
+}
+
+/// A summary of the migrations applied to a database for a single schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// The name of the schema, as given to [`MonarchDB`].
+    pub name: String,
+    /// The version recorded in the database before migrating.
+    pub from_version: u32,
+    /// The version recorded in the database after migrating.
+    pub to_version: u32,
+    /// The names of the best-effort migrations which failed and were skipped, see
+    /// [`MonarchDB::history`].
+    pub skipped: Vec<String>,
+    /// The names of the repeatable migrations which ran, see
+    /// [`MonarchDB::with_repeatable_migration`].
+    pub repeatable: Vec<String>,
+    /// The names of the migrations ordered before the database's version which ran because
+    /// they had not been applied, see [`MonarchDB::with_out_of_order`].
+    pub out_of_order: Vec<String>,
+}
+
+impl MigrationReport {
+    /// The number of migrations which were applied.
+    pub fn applied(&self) -> u32 {
+        self.to_version.saturating_sub(self.from_version)
+    }
+}
+
+impl MonarchDB {
+    /// Applies pending migrations inside a transaction which the caller has already started.
+    ///
+    /// The caller is responsible for committing (or rolling back) the transaction.
+    pub(crate) fn apply_pending(&self, tx: &Connection) -> Result<MigrationReport> {
+        self.apply_pending_to(tx, self.current_version())
+    }
+
+    /// Applies pending migrations up to `target`, like [`apply_pending`](Self::apply_pending).
+    pub(crate) fn apply_pending_to(&self, tx: &Connection, target: u32) -> Result<MigrationReport> {
+        block_on(self.apply_pending_through(&mut Rusqlite(tx), target))
+    }
+
+    /// Applies pending migrations up to `target` through `driver`, in the transactions the
+    /// [`TransactionMode`] calls for. If anything ran, the write-ahead log is then checkpointed,
+    /// and the schema file is refreshed if `refresh_schema_file` is set and `target` is the
+    /// current version.
+    pub(crate) async fn migrate_through<D: Driver>(
+        &self,
+        driver: &mut D,
+        target: u32,
+        refresh_schema_file: bool,
+    ) -> Result<MigrationReport, D::Error> {
+        let start = Instant::now();
+        let report = match self.transaction_mode {
+            TransactionMode::Single => self.transaction(driver, target).await?,
+            TransactionMode::PerMigration => self.steps(driver, target, false).await?,
+            TransactionMode::Savepoint => {
+                driver.begin(self).await?;
+                let report = self.steps(driver, target, true).await;
+                let committed = driver.execute_batch("COMMIT").await;
+                let report = report?;
+                committed?;
+                report
+            }
+        };
+        if report.applied() > 0 || !report.repeatable.is_empty() {
+            tracing::debug!(
+                from_version = report.from_version,
+                to_version = report.to_version,
+                applied = report.applied(),
+                repeatable = report.repeatable.len(),
+                duration_ms = start.elapsed().as_millis() as u64,
+                "Migrations complete"
+            );
+            self.checkpoint(driver).await;
+        }
+        if refresh_schema_file && report.to_version == self.current_version() {
+            self.refresh_schema_file(driver).await;
+        }
+        Ok(report)
+    }
+
+    /// Applies pending migrations up to `target` in one transaction.
+    async fn transaction<D: Driver>(
+        &self,
+        driver: &mut D,
+        target: u32,
+    ) -> Result<MigrationReport, D::Error> {
+        driver.begin(self).await?;
+        match self.apply_pending_through(driver, target).await {
+            Ok(report) => {
+                driver.execute_batch("COMMIT").await?;
+                Ok(report)
+            }
+            Err(error) => {
+                // SQLite may already have rolled back the transaction after an interrupt.
+                if let Err(rollback) = driver.execute_batch("ROLLBACK").await {
+                    tracing::debug!(error = %rollback, "Failed to roll back migrations");
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Applies pending migrations up to `target` one at a time, each in its own transaction,
+    /// or inside a savepoint of the transaction already started if `savepoints` is set,
+    /// merging their reports.
+    async fn steps<D: Driver>(
+        &self,
+        driver: &mut D,
+        target: u32,
+        savepoints: bool,
+    ) -> Result<MigrationReport, D::Error> {
+        self.check_target(target)?;
+        let mut report: Option<MigrationReport> = None;
+        loop {
+            let from = report.as_ref().map(|report| report.to_version);
+            let step = match from {
+                Some(version) if version >= target => break,
+                Some(version) => self.get_migration(version)?.1,
+                None => self.next_version(driver, target).await?,
+            };
+            let step = if savepoints {
+                self.savepoint(driver, step).await?
+            } else {
+                self.transaction(driver, step).await?
+            };
+            report = Some(match report {
+                Some(mut report) => {
+                    report.to_version = step.to_version;
+                    report.skipped.extend(step.skipped);
+                    report.repeatable = step.repeatable;
+                    report.out_of_order.extend(step.out_of_order);
+                    report
+                }
+                None => step,
+            });
+        }
+        Ok(report.expect("at least one step runs"))
+    }
+
+    /// Applies pending migrations up to `target` inside a savepoint, rolling back only their
+    /// changes if one fails.
+    async fn savepoint<D: Driver>(
+        &self,
+        driver: &mut D,
+        target: u32,
+    ) -> Result<MigrationReport, D::Error> {
+        driver.execute_batch("SAVEPOINT monarch_migration").await?;
+        match self.apply_pending_through(driver, target).await {
+            Ok(report) => {
+                driver.execute_batch("RELEASE monarch_migration").await?;
+                Ok(report)
+            }
+            Err(error) => {
+                driver
+                    .execute_batch("ROLLBACK TO monarch_migration; RELEASE monarch_migration")
+                    .await?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Applies pending migrations up to `target` through `driver`, inside a transaction which
+    /// has already started.
+    ///
+    /// Repeatable migrations only run when `target` is the current version, since they are
+    /// written against the latest schema.
+    pub(crate) async fn apply_pending_through<D: Driver>(
+        &self,
+        driver: &mut D,
+        target: u32,
+    ) -> Result<MigrationReport, D::Error> {
+        self.check_target(target)?;
+
+        let from_version = select_schema_version(driver, &self.name).await?;
+        self.check_version_ahead(from_version)?;
+        if !self.out_of_order {
+            self.check_identifier(driver, from_version).await?;
+        }
+        let pending = self.align_history(driver, from_version).await?;
+        self.check_checksums(driver).await?;
+        let mut skipped = Vec::new();
+        let mut out_of_order = Vec::new();
+        let version = if let Some(pending) = pending {
+            out_of_order = self
+                .apply_out_of_order(driver, from_version, &pending, target, &mut skipped)
+                .await?;
+            from_version.max(target)
+        } else if from_version < target {
+            self.apply_versions(driver, from_version, target, &mut skipped)
+                .await?
+        } else {
+            from_version
+        };
+        if version != from_version {
+            set_schema_version(driver, &self.name, version).await?;
+            self.record_identifier(driver, version).await?;
+        }
+
+        let repeatable = if target == self.current_version() {
+            self.apply_repeatables(driver).await?
+        } else {
+            Vec::new()
+        };
+        Ok(MigrationReport {
+            name: self.name.to_string(),
+            from_version,
+            to_version: version,
+            skipped,
+            repeatable,
+            out_of_order,
+        })
+    }
+
+    /// Fails with [`Error::UnreachableVersion`] unless migrating can stop at `target`.
+    fn check_target(&self, target: u32) -> Result<()> {
+        let reachable = target <= self.current_version()
+            && self
+                .baseline()
+                .is_none_or(|baseline| target == 0 || target >= baseline);
+        if !reachable {
+            return Err(Error::UnreachableVersion { version: target });
+        }
         Ok(())
     }
 
-    fn migrate(self) -> rusqlite::Result<()> {
-        let tx = self.connection.transaction()?;
-        let mut version = select_schema_version(&tx, &self.monarch.name)?;
+    /// The version reached by the first migration which migrating through `driver` towards
+    /// `target` would run, or `target` if none would.
+    async fn next_version<D: Driver>(&self, driver: &mut D, target: u32) -> Result<u32, D::Error> {
+        let from_version = plan::read_recorded_version(driver, &self.name).await?;
+        if from_version >= self.current_version() {
+            return Ok(target);
+        }
+        Ok(self.get_migration(from_version)?.1.min(target))
+    }
 
-        while version < self.monarch.current_version() {
-            let query = self
-                .monarch
-                .get_migration(version)
-                .expect("version <-> migration mismatch");
-            tracing::trace!("Running migration to version {}", version + 1);
-            tx.execute_batch(query)?;
-            version += 1;
+    /// Runs the versioned migrations from `version` up to `target`, returning the version
+    /// reached and adding the names of best-effort migrations which failed to `skipped`. The
+    /// schema version is not recorded.
+    pub(crate) async fn apply_versions<D: Driver>(
+        &self,
+        driver: &mut D,
+        mut version: u32,
+        target: u32,
+        skipped: &mut Vec<String>,
+    ) -> Result<u32, D::Error> {
+        let from_version = version;
+        while version < target {
+            self.check_shutdown(from_version)?;
+            let (migration, next) = self.get_migration(version)?;
+            self.apply_migration(driver, migration, next, target, skipped)
+                .await?;
+            version = next;
         }
+        Ok(version)
+    }
 
-        set_schema_version(&tx, &self.monarch.name, version)?;
-        tx.commit()?;
-        tracing::debug!("Migrations complete");
+    /// Runs `migration`, which upgrades the schema to `version`, if it applies in the configured
+    /// environment, recording it in the history and adding its name to `skipped` if it was a
+    /// best-effort migration which failed.
+    pub(crate) async fn apply_migration<D: Driver>(
+        &self,
+        driver: &mut D,
+        migration: &Migration,
+        version: u32,
+        target: u32,
+        skipped: &mut Vec<String>,
+    ) -> Result<(), D::Error> {
+        if !self.applies(migration) {
+            tracing::trace!(
+                migration = %migration.name,
+                "Skipping migration to version {} outside of its environments",
+                version
+            );
+            return Ok(());
+        }
+        let span = tracing::debug_span!(
+            "migration",
+            version,
+            name = %migration.name,
+            bytes = migration.sql()?.len(),
+            duration_ms = tracing::field::Empty,
+        );
+        let start = Instant::now();
+        let status = async {
+            self.check_determinism(migration)?;
+            self.check_transaction(migration)?;
+            tracing::trace!("Running migration to version {}", version);
+            if migration.best_effort {
+                self.execute_best_effort(driver, migration, version, target)
+                    .await
+            } else {
+                driver
+                    .run_migration(self, migration, version, target)
+                    .await
+                    .map(|()| MigrationStatus::Applied)
+            }
+        }
+        .instrument(span.clone())
+        .await;
+        let elapsed = start.elapsed();
+        span.record("duration_ms", elapsed.as_millis() as u64);
+        let status = status.inspect_err(|_| {
+            telemetry::migration_failed(&self.name, &migration.name, elapsed);
+        })?;
+        telemetry::migration_ran(&self.name, &migration.name, &status, elapsed);
+        self.record_history(driver, version, migration, &status, Some(elapsed))
+            .await?;
+        if let MigrationStatus::Skipped(_) = status {
+            skipped.push(migration.name.to_string());
+        }
         Ok(())
     }
+
+    /// Runs a best-effort migration inside a savepoint, rolling back only its changes if it fails.
+    /// A migration which was interrupted, cancelled or timed out fails migrating instead of
+    /// being skipped, so it runs again next time.
+    async fn execute_best_effort<D: Driver>(
+        &self,
+        driver: &mut D,
+        migration: &Migration,
+        version: u32,
+        target: u32,
+    ) -> Result<MigrationStatus, D::Error> {
+        driver
+            .execute_batch("SAVEPOINT monarch_best_effort")
+            .await?;
+        match driver.run_migration(self, migration, version, target).await {
+            Ok(()) => {
+                driver.execute_batch("RELEASE monarch_best_effort").await?;
+                Ok(MigrationStatus::Applied)
+            }
+            // SQLite may already have rolled back the whole transaction after an interrupt.
+            Err(error) if D::is_interruption(&error) => Err(error),
+            Err(error) => {
+                driver
+                    .execute_batch("ROLLBACK TO monarch_best_effort; RELEASE monarch_best_effort")
+                    .await?;
+                tracing::warn!(
+                    migration = %migration.name,
+                    %error,
+                    "Skipping best-effort migration to version {} which failed",
+                    version
+                );
+                Ok(MigrationStatus::Skipped(error.to_string()))
+            }
+        }
+    }
 }
 
-fn create_schema_version_table(connection: &Connection) -> rusqlite::Result<()> {
-    let mut stmt = connection.prepare(include_str!("00.versions.sql"))?;
-    stmt.execute([])?;
-    Ok(())
+/// Whether `error` stopped a migration from outside, through shutdown, cancellation or the
+/// migration timeout, rather than the migration failing by itself.
+fn is_interruption(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::Shutdown { .. } | Error::Cancelled { .. } | Error::MigrationTimedOut { .. }
+    ) || error.sqlite_error_code() == Some(rusqlite::ErrorCode::OperationInterrupted)
 }
 
-fn insert_initial_schema_version(connection: &Connection, name: &str) -> rusqlite::Result<()> {
-    let mut stmt = connection.prepare(&format!(
-        "INSERT INTO {VERSION_TABLE} (monarch_schema, version) VALUES (:name, 0)"
-    ))?;
-    stmt.execute(&[(":name", name)])?;
-    Ok(())
+/// Opens the database described by `configuration`, installs the busy handler, loads its
+/// extensions, attaches any secondary databases and runs its init SQL.
+fn open_connection(
+    configuration: &ConnectionConfiguration,
+    retry_policy: &RetryPolicy,
+    busy_handler: Option<BusyHandler>,
+) -> Result<Connection> {
+    let connection = configuration.database.open(retry_policy)?;
+    if let Some(busy_handler) = busy_handler {
+        busy_handler.install(&connection)?;
+    }
+    extension::load_extensions(&connection, &configuration.extensions)?;
+    for attached in &configuration.attach {
+        tracing::trace!(schema = %attached.schema, "Attach database");
+        connection.execute(
+            "ATTACH DATABASE :path AS :schema",
+            rusqlite::named_params! { ":path": attached.path.as_str(), ":schema": attached.schema },
+        )?;
+    }
+    tuning::apply_tuning(&connection, configuration)?;
+    for sql in &configuration.init_sql {
+        tracing::trace!(%sql, "Run init SQL");
+        connection.execute_batch(sql)?;
+    }
+    Ok(connection)
 }
 
-fn select_schema_version(connection: &Connection, name: &str) -> rusqlite::Result<u32> {
-    let mut stmt = connection.prepare("SELECT name FROM sqlite_master WHERE name = :table")?;
+/// The number a migration's file name starts with, or 0 if it starts with none.
+fn numeric_prefix(name: &str) -> u64 {
+    let digits = name.len() - name.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    name[..digits].parse().unwrap_or(0)
+}
 
-    let has_version_tbl: Option<Result<String, _>> = stmt
-        .query_map(&[(":table", VERSION_TABLE)], |row| row.get(0))?
-        .next();
+/// Starts a `BEGIN IMMEDIATE` transaction, retrying while another connection holds the lock.
+///
+/// This takes a shared reference so that it can be retried from a closure; callers must hold
+/// the exclusive borrow of the connection to guarantee the transaction is not nested.
+fn begin_immediate(
+    connection: &Connection,
+    timeout: Duration,
+) -> rusqlite::Result<Transaction<'_>> {
+    wait_for_lock(timeout, || {
+        Transaction::new_unchecked(connection, TransactionBehavior::Immediate)
+    })
+}
 
-    match has_version_tbl {
-        Some(Ok(_)) => {}
-        Some(Err(error)) => {
-            return Err(error);
-        }
-        None => {
-            tracing::trace!("Create schema version table {VERSION_TABLE}");
-            create_schema_version_table(connection)?;
-            insert_initial_schema_version(connection, name)?;
-            return Ok(0u32);
+/// Runs `attempt`, which takes the database's write lock, until it succeeds.
+///
+/// Attempts are retried with an exponential backoff (starting at [`LOCK_POLL_INITIAL`] and
+/// capped at [`LOCK_POLL_MAX`]) for as long as SQLite reports the database as busy or locked
+/// and the `timeout` has not elapsed. Any other error is returned immediately.
+fn wait_for_lock<T>(
+    timeout: Duration,
+    mut attempt: impl FnMut() -> rusqlite::Result<T>,
+) -> rusqlite::Result<T> {
+    let start = Instant::now();
+    let mut pause = LOCK_POLL_INITIAL;
+    loop {
+        match attempt() {
+            Ok(value) => return Ok(value),
+            Err(error) if is_busy(&error) && start.elapsed() < timeout => {
+                let remaining = timeout.saturating_sub(start.elapsed());
+                tracing::trace!(?pause, "Waiting for migration lock");
+                std::thread::sleep(pause.min(remaining));
+                pause = (pause * 2).min(LOCK_POLL_MAX);
+            }
+            Err(error) => return Err(error),
         }
-    };
-
-    let mut stmt = connection.prepare(&format!(
-        "SELECT version FROM {VERSION_TABLE} WHERE monarch_schema = :name"
-    ))?;
-    let version: Option<u32> = stmt
-        .query_map(&[(":name", name)], |row| row.get::<_, u32>(0))?
-        .next()
-        .transpose()?;
+    }
+}
+
+/// Reads the version recorded for `name`, creating the version table and the schema's row if
+/// they do not exist.
+async fn select_schema_version<D: Driver>(driver: &mut D, name: &str) -> Result<u32, D::Error> {
+    if !driver.table_exists(VERSION_TABLE).await? {
+        tracing::trace!("Create schema version table {VERSION_TABLE}");
+        driver
+            .execute_batch(include_str!("00.versions.sql"))
+            .await?;
+    }
+    let version = driver
+        .query_value(
+            &format!("SELECT version FROM {VERSION_TABLE} WHERE monarch_schema = ?1"),
+            &[name.to_owned().into()],
+        )
+        .await?;
     if let Some(version) = version {
         tracing::trace!(%version, "Get schema version");
-        Ok(version)
-    } else {
-        tracing::trace!("Insert new version for {name}");
-        insert_initial_schema_version(connection, name)?;
-        Ok(0)
+        return Ok(version);
     }
+    tracing::trace!("Insert new version for {name}");
+    driver
+        .execute(
+            &format!("INSERT INTO {VERSION_TABLE} (monarch_schema, version) VALUES (?1, 0)"),
+            &[name.to_owned().into()],
+        )
+        .await?;
+    Ok(0)
 }
 
-fn set_schema_version(connection: &Connection, name: &str, version: u32) -> rusqlite::Result<()> {
+async fn set_schema_version<D: Driver>(
+    driver: &mut D,
+    name: &str,
+    version: u32,
+) -> Result<(), D::Error> {
     tracing::trace!(%version, "Set schema version for {name}");
-    let mut stmt = connection.prepare(&format!(
-        "UPDATE {VERSION_TABLE} SET version = :version WHERE monarch_schema = :name"
-    ))?;
-    stmt.execute(rusqlite::named_params! { ":version": version, ":name": name})?;
-    Ok(())
+    driver
+        .execute(
+            &format!("UPDATE {VERSION_TABLE} SET version = ?1 WHERE monarch_schema = ?2"),
+            &[version.into(), name.to_owned().into()],
+        )
+        .await
 }
 
 #[cfg(test)]
@@ -447,7 +1487,7 @@ mod tests {
     }
 
     #[test]
-    fn test_open_in_memory_with_static_migrations() -> rusqlite::Result<()> {
+    fn test_open_in_memory_with_static_migrations() -> Result<()> {
         let config = StaticMonarchConfiguration {
             name: "test_memory_db",
             enable_foreign_keys: true,
@@ -477,7 +1517,7 @@ mod tests {
     }
 
     #[test]
-    fn test_create_connection_with_static_migrations() -> rusqlite::Result<()> {
+    fn test_create_connection_with_static_migrations() -> Result<()> {
         let config = StaticMonarchConfiguration {
             name: "test_file_db",
             enable_foreign_keys: false,
@@ -487,7 +1527,10 @@ mod tests {
         };
 
         let monarch_db: MonarchDB = config.into();
-        let connection_config = ConnectionConfiguration { database: None };
+        let connection_config = ConnectionConfiguration {
+            database: Database::Memory,
+            ..Default::default()
+        };
         let connection = monarch_db.create_connection(&connection_config)?;
 
         // Verify the table was created
@@ -511,7 +1554,59 @@ mod tests {
     }
 
     #[test]
-    fn test_migration_versioning() -> rusqlite::Result<()> {
+    fn test_init_sql_runs_before_migrations() -> Result<()> {
+        let monarch_db: MonarchDB = StaticMonarchConfiguration {
+            name: "init_sql",
+            enable_foreign_keys: false,
+            migrations: ["INSERT INTO temp.settings VALUES ('migrated');"],
+        }
+        .into();
+        let connection_config = ConnectionConfiguration {
+            init_sql: vec![
+                "PRAGMA user_version = 7;".to_owned(),
+                "CREATE TEMP TABLE settings (name TEXT);".to_owned(),
+            ],
+            ..Default::default()
+        };
+        let connection = monarch_db.create_connection(&connection_config)?;
+
+        let user_version: i64 =
+            connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        assert_eq!(user_version, 7);
+        let settings: String =
+            connection.query_row("SELECT name FROM temp.settings", [], |row| row.get(0))?;
+        assert_eq!(settings, "migrated");
+        Ok(())
+    }
+
+    #[test]
+    fn test_tuning_applies_before_migrations() -> Result<()> {
+        let monarch_db: MonarchDB = StaticMonarchConfiguration {
+            name: "tuning",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE tuning AS SELECT cache_size, temp_store FROM pragma_cache_size, pragma_temp_store;",
+            ],
+        }
+        .into();
+        let connection_config = ConnectionConfiguration {
+            cache_size: Some(-65536),
+            mmap_size: Some(0),
+            temp_store: Some(TempStore::Memory),
+            ..Default::default()
+        };
+        let connection = monarch_db.create_connection(&connection_config)?;
+
+        let (cache_size, temp_store): (i64, i64) =
+            connection.query_row("SELECT cache_size, temp_store FROM tuning", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+        assert_eq!((cache_size, temp_store), (-65536, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_migration_versioning() -> Result<()> {
         let config = StaticMonarchConfiguration {
             name: "versioning_test",
             enable_foreign_keys: false,
@@ -539,4 +1634,142 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_migrate_to_target_version() -> Result<()> {
+        let monarch_db: MonarchDB = StaticMonarchConfiguration {
+            name: "migrate_to",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE v1_table (id INTEGER PRIMARY KEY);",
+                "CREATE TABLE v2_table (id INTEGER PRIMARY KEY);",
+                "CREATE TABLE v3_table (id INTEGER PRIMARY KEY);",
+            ],
+        }
+        .into();
+        let mut connection = Connection::open_in_memory()?;
+
+        let report = monarch_db.migrate_to(&mut connection, 2)?;
+        assert_eq!((report.from_version, report.to_version), (0, 2));
+        let tables: i64 = connection.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name LIKE 'v%_table'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(tables, 2);
+
+        // Migrating to an older version leaves the database alone.
+        let report = monarch_db.migrate_to(&mut connection, 1)?;
+        assert_eq!((report.from_version, report.to_version), (2, 2));
+
+        let error = monarch_db.migrate_to(&mut connection, 4).unwrap_err();
+        assert!(matches!(error, Error::UnreachableVersion { version: 4 }));
+
+        let report = monarch_db.migrate_to(&mut connection, 3)?;
+        assert_eq!(report.applied(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_iter() -> Result<()> {
+        let migration = |name: &str, sql: &str| (name.to_owned(), sql.to_owned());
+        let monarch_db = MonarchDB::from_iter(
+            "from_iter",
+            true,
+            [
+                migration("b_users", "CREATE TABLE users (id INTEGER PRIMARY KEY);"),
+                migration(
+                    "R__names.sql",
+                    "CREATE VIEW IF NOT EXISTS names AS SELECT id FROM users;",
+                ),
+                migration("a_posts", "CREATE TABLE posts (id INTEGER PRIMARY KEY);"),
+            ],
+        )?;
+        assert_eq!(monarch_db.current_version(), 2);
+        let names: Vec<&str> = monarch_db.migrations.iter().map(|m| &*m.name).collect();
+        assert_eq!(names, ["b_users", "a_posts"]);
+        let connection = monarch_db.open_in_memory()?;
+        let names: i64 =
+            connection.query_row("SELECT COUNT(*) FROM names", [], |row| row.get(0))?;
+        assert_eq!(names, 0);
+
+        let duplicate = MonarchDB::from_iter(
+            "from_iter",
+            true,
+            [migration("a", "SELECT 1;"), migration("a", "SELECT 2;")],
+        );
+        assert!(matches!(
+            duplicate,
+            Err(Error::DuplicateMigration { migration }) if migration == "a"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_accessors() -> Result<()> {
+        let monarch_db = MonarchDB::from_iter(
+            "accessors",
+            true,
+            [
+                (
+                    "000_baseline.sql".to_owned(),
+                    "-- monarch:baseline 2\nCREATE TABLE users (id, name);".to_owned(),
+                ),
+                (
+                    "003_posts.sql".to_owned(),
+                    "CREATE TABLE posts (id);".to_owned(),
+                ),
+                ("R__view.sql".to_owned(), "SELECT 1;".to_owned()),
+            ],
+        )?;
+        assert_eq!(monarch_db.name(), "accessors");
+        assert_eq!(monarch_db.len(), 2);
+        assert!(!monarch_db.is_empty());
+        let versions: Vec<u32> = monarch_db.iter().map(|(version, _)| version).collect();
+        assert_eq!(versions, [2, 3]);
+        assert_eq!(monarch_db.migration_sql(1)?, None);
+        assert_eq!(
+            monarch_db.migration_sql(3)?,
+            Some("CREATE TABLE posts (id);")
+        );
+        assert_eq!(monarch_db.migration_sql(4)?, None);
+        Ok(())
+    }
+
+    #[cfg(all(feature = "serde", feature = "cli"))]
+    #[test]
+    fn test_configurations_round_trip() -> serde_json::Result<()> {
+        let connection: ConnectionConfiguration = serde_json::from_str(
+            r#"{
+                "database": {"shared_memory": "app"},
+                "attach": [{"schema": "audit", "path": "./audit.db"}],
+                "init_sql": ["PRAGMA busy_timeout = 5000;"],
+                "cache_size": -262144,
+                "temp_store": "memory"
+            }"#,
+        )?;
+        let json = serde_json::to_value(&connection)?;
+        assert_eq!(json["extensions"], serde_json::json!([]));
+        assert_eq!(connection.temp_store, Some(TempStore::Memory));
+        let copy: ConnectionConfiguration = serde_json::from_value(json.clone())?;
+        assert_eq!(serde_json::to_value(&copy)?, json);
+
+        let monarch: MonarchConfiguration = serde_json::from_str(
+            r#"{"name": "app", "enable_foreign_keys": true, "migration_directory": "./migrations"}"#,
+        )?;
+        let json = serde_json::to_value(&monarch)?;
+        let copy: MonarchConfiguration = serde_json::from_value(json.clone())?;
+        assert_eq!(serde_json::to_value(&copy)?, json);
+
+        let json = serde_json::to_value(StaticMonarchConfiguration {
+            name: "app",
+            enable_foreign_keys: false,
+            migrations: ["CREATE TABLE t (x);"],
+        })?;
+        assert_eq!(
+            json["migrations"],
+            serde_json::json!(["CREATE TABLE t (x);"])
+        );
+        Ok(())
+    }
 }
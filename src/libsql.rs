@@ -0,0 +1,113 @@
+//! Migrating databases through the `libsql` crate, such as Turso databases and embedded replicas.
+//!
+//! [`MonarchDB::migrate_libsql`] applies the same migrations as
+//! [`migrate`](MonarchDB::migrate) over a [`libsql::Connection`](::libsql::Connection), which may
+//! be a remote database reached by a `libsql://` or `https://` URL, an embedded replica, or a
+//! local libSQL file. It runs the same migration core as `rusqlite` and any
+//! [`SqliteBackend`](crate::SqliteBackend), so the version, history and repeatable migration
+//! tables are the same and a database can be migrated through either crate.
+//!
+//! The connection is asynchronous and is not a `rusqlite` connection, so a few features which
+//! work on one do not apply: connection setup hooks, progress reporters and quarantine
+//! remediations do not run, the lock timeout and retry policy are left to libSQL, and
+//! migrations with import directives or assertions fail.
+//!
+//! This crate enables libSQL's remote client only, which does not compile SQLite in. A local
+//! database or an embedded replica needs libSQL's `core` or `replication` feature in the
+//! application, which cannot be combined with the `bundled` feature since both link a copy of
+//! SQLite.
+
+use ::libsql::params::Params;
+use ::libsql::{Connection, Value as LibsqlValue};
+use rusqlite::types::Value;
+
+use crate::backend::Driver;
+use crate::{MigrationReport, MonarchDB, Result};
+
+/// Drives the migration core through a libSQL connection.
+struct Libsql<'c>(&'c Connection);
+
+impl Driver for Libsql<'_> {
+    type Error = crate::Error;
+
+    async fn execute_batch(&mut self, sql: &str) -> Result<()> {
+        self.0.execute_batch(sql).await?;
+        Ok(())
+    }
+
+    async fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Vec<Value>>> {
+        let params = params.iter().cloned().map(to_libsql).collect();
+        let mut rows = self.0.query(sql, Params::Positional(params)).await?;
+        let columns = rows.column_count();
+        let mut values = Vec::new();
+        while let Some(row) = rows.next().await? {
+            values.push(
+                (0..columns)
+                    .map(|index| row.get_value(index).map(from_libsql))
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+        Ok(values)
+    }
+}
+
+fn to_libsql(value: Value) -> LibsqlValue {
+    match value {
+        Value::Null => LibsqlValue::Null,
+        Value::Integer(value) => LibsqlValue::Integer(value),
+        Value::Real(value) => LibsqlValue::Real(value),
+        Value::Text(text) => LibsqlValue::Text(text),
+        Value::Blob(blob) => LibsqlValue::Blob(blob),
+    }
+}
+
+fn from_libsql(value: LibsqlValue) -> Value {
+    match value {
+        LibsqlValue::Null => Value::Null,
+        LibsqlValue::Integer(value) => Value::Integer(value),
+        LibsqlValue::Real(value) => Value::Real(value),
+        LibsqlValue::Text(text) => Value::Text(text),
+        LibsqlValue::Blob(blob) => Value::Blob(blob),
+    }
+}
+
+impl MonarchDB {
+    /// Applies pending migrations to a libSQL connection, in `BEGIN IMMEDIATE` transactions
+    /// following the configured [`TransactionMode`](crate::TransactionMode).
+    ///
+    /// ```rust,no_run
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);"],
+    /// }
+    /// .into();
+    ///
+    /// let database = libsql::Builder::new_remote(
+    ///     "libsql://my-app.turso.io".to_owned(),
+    ///     std::env::var("TURSO_AUTH_TOKEN")?,
+    /// )
+    /// .build()
+    /// .await?;
+    /// let connection = database.connect()?;
+    /// let report = monarch_db.migrate_libsql(&connection).await?;
+    /// println!("Migrated from {} to {}", report.from_version, report.to_version);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::Libsql`](crate::Error::Libsql) if a statement fails, in which case
+    /// its transaction is rolled back, and like [`migrate`](Self::migrate) otherwise.
+    pub async fn migrate_libsql(&self, connection: &Connection) -> Result<MigrationReport> {
+        if self.enable_foreign_keys {
+            connection.execute("PRAGMA foreign_keys = ON", ()).await?;
+        }
+        self.migrate_through(&mut Libsql(connection), self.current_version(), true)
+            .await
+    }
+}
@@ -0,0 +1,151 @@
+//! Schema metadata for each migration version.
+//!
+//! Editor tooling (SQL language servers, autocomplete plugins) needs to know which tables and
+//! columns exist at a given schema version. [`MonarchDB::schema_metadata`] replays the configured
+//! migrations against a scratch in-memory database and records the tables and views present
+//! after each one.
+
+use rusqlite::Connection;
+
+use crate::{MonarchDB, Result};
+
+/// The schema objects present after a single migration has been applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionMetadata {
+    /// The schema version reached after the migration ran.
+    pub version: u32,
+    /// Tables and views in the schema at this version, ordered by name.
+    pub tables: Vec<TableMetadata>,
+}
+
+/// A table or view in the schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableMetadata {
+    /// The name of the table or view.
+    pub name: String,
+    /// Either `table` or `view`, as reported by `sqlite_master`.
+    pub kind: String,
+    /// Columns in declaration order.
+    pub columns: Vec<ColumnMetadata>,
+}
+
+/// A column of a table or view.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnMetadata {
+    /// The name of the column.
+    pub name: String,
+    /// The declared type of the column, which may be empty.
+    pub declared_type: String,
+    /// Whether the column has a `NOT NULL` constraint.
+    pub not_null: bool,
+    /// Whether the column is part of the primary key.
+    pub primary_key: bool,
+    /// The SQL text of the column's default value, if any.
+    pub default: Option<String>,
+}
+
+impl MonarchDB {
+    /// Describes the tables and columns present after each migration.
+    ///
+    /// Migrations are applied one at a time to a scratch in-memory database, and the schema is
+    /// inspected after each step. The returned list has one entry per migration, in order, so
+    /// the entry at index `i` describes schema version `i + 1`, or `i + N` when the first
+    /// migration is a baseline replacing versions 1 to `N`. No real database is touched.
+    pub fn schema_metadata(&self) -> Result<Vec<VersionMetadata>> {
+        let connection = Connection::open_in_memory()?;
+        self.setup_connection(&connection)?;
+        let mut versions = Vec::with_capacity(self.migrations.len());
+        for (version, migration) in self.versions() {
+            if self.applies(migration) {
+                migration.execute(&connection, |_| {})?;
+            }
+            versions.push(VersionMetadata {
+                version,
+                tables: tables(&connection)?,
+            });
+        }
+        Ok(versions)
+    }
+}
+
+pub(crate) fn tables(connection: &Connection) -> rusqlite::Result<Vec<TableMetadata>> {
+    let mut stmt = connection.prepare(
+        "SELECT name, type FROM sqlite_master \
+         WHERE type IN ('table', 'view') AND name NOT LIKE 'sqlite_%' \
+         ORDER BY name",
+    )?;
+    let objects = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    objects
+        .into_iter()
+        .map(|(name, kind)| {
+            let columns = columns(connection, &name)?;
+            Ok(TableMetadata {
+                name,
+                kind,
+                columns,
+            })
+        })
+        .collect()
+}
+
+fn columns(connection: &Connection, table: &str) -> rusqlite::Result<Vec<ColumnMetadata>> {
+    let mut stmt = connection.prepare(
+        "SELECT name, type, \"notnull\", dflt_value, pk FROM pragma_table_info(:table) ORDER BY cid",
+    )?;
+    stmt.query_map(&[(":table", table)], |row| {
+        Ok(ColumnMetadata {
+            name: row.get(0)?,
+            declared_type: row.get(1)?,
+            not_null: row.get(2)?,
+            default: row.get(3)?,
+            primary_key: row.get::<_, i64>(4)? > 0,
+        })
+    })?
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_schema_metadata_per_version() -> Result<()> {
+        let monarch_db: MonarchDB = StaticMonarchConfiguration {
+            name: "metadata",
+            enable_foreign_keys: true,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL);",
+                "ALTER TABLE users ADD COLUMN email TEXT DEFAULT 'none';
+                 CREATE VIEW user_names AS SELECT name FROM users;",
+            ],
+        }
+        .into();
+
+        let versions = monarch_db.schema_metadata()?;
+        assert_eq!(versions.len(), 2);
+
+        let v1 = &versions[0];
+        assert_eq!(v1.version, 1);
+        assert_eq!(v1.tables.len(), 1);
+        let columns: Vec<_> = v1.tables[0].columns.iter().map(|c| &c.name).collect();
+        assert_eq!(columns, ["id", "name"]);
+        assert!(v1.tables[0].columns[0].primary_key);
+        assert!(v1.tables[0].columns[1].not_null);
+
+        let v2 = &versions[1];
+        assert_eq!(v2.version, 2);
+        let names: Vec<_> = v2.tables.iter().map(|t| (&*t.name, &*t.kind)).collect();
+        assert_eq!(names, [("user_names", "view"), ("users", "table")]);
+        let email = &v2.tables[1].columns[2];
+        assert_eq!(email.name, "email");
+        assert_eq!(email.declared_type, "TEXT");
+        assert_eq!(email.default.as_deref(), Some("'none'"));
+
+        Ok(())
+    }
+}
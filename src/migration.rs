@@ -0,0 +1,535 @@
+//! Individual migrations and the directives they carry.
+//!
+//! A migration may begin with directive comments of the form `-- monarch:<name> <args>`, which
+//! configure how Monarch treats it. Directives must appear before the first SQL statement;
+//! ordinary comments and blank lines may be interleaved with them. The supported directives are:
+//!
+//! - `-- monarch:env <environment>[, <environment>...]` restricts the migration to the listed
+//!   environments, see [`MonarchDB::with_environment`](crate::MonarchDB::with_environment).
+//! - `-- monarch:import <table> <path>` loads a CSV or Parquet file into a table after the
+//!   migration's SQL runs. Relative paths are resolved against the migration directory. This
+//!   requires the `import` feature.
+//! - `-- monarch:description <text>` describes the migration, e.g. for release notes. Several
+//!   description lines are joined with spaces. Without this directive, the first paragraph of
+//!   ordinary comments at the start of the migration describes it, and failing that its file
+//!   name, so `003_add_indexes.sql` is described as "add indexes".
+//! - `-- monarch:best-effort` lets migrating continue if the migration fails. Its changes are
+//!   rolled back and it is recorded as skipped in the migration history, see
+//!   [`MonarchDB::history`](crate::MonarchDB::history). Meant for optional optimizations, such
+//!   as an index, which must not block an upgrade.
+//! - `-- monarch:baseline <version>` marks the first migration as a baseline which replaces
+//!   versions 1 through `<version>`, see [`MonarchDB::squash`](crate::MonarchDB::squash).
+//! - `-- monarch:assert <query>` must return a true value after the migration runs, and
+//!   `-- monarch:assert-unchanged <query>` must return the same rows before and after it, or
+//!   the migration fails and is rolled back. A migration loaded from a directory may keep more
+//!   `assert` queries in a companion file, such as `003_rebuild.verify.sql` for
+//!   `003_rebuild.sql`.
+//! - `-- monarch:relax-foreign-keys` runs the migration without enforcing foreign keys, and checks
+//!   them once it has run, for migrations which rebuild a table other tables refer to.
+//! - `-- monarch:depends-on <migration>[, <migration>...]` runs the migration after the named
+//!   migrations, moving it later if it would otherwise run first.
+
+use std::borrow::Cow;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use rusqlite::fallible_iterator::FallibleIterator;
+
+use crate::assertion::{Assertion, assertion_directives};
+use crate::compression::CompressedSql;
+use crate::{Error, Result};
+
+/// A single migration, with the metadata gathered from its name and directives.
+#[derive(Debug, Clone)]
+pub(crate) struct Migration {
+    /// The file name of the migration, or its version for migrations without one.
+    pub(crate) name: Cow<'static, str>,
+    pub(crate) sql: Sql,
+    /// Environments the migration is restricted to. Empty if it applies everywhere.
+    pub(crate) environments: Vec<String>,
+    /// Files loaded into tables after the SQL runs.
+    pub(crate) imports: Vec<Import>,
+    /// For a baseline, the last version it replaces.
+    pub(crate) baseline: Option<u32>,
+    /// A description of the migration, from its directives, leading comment or file name.
+    pub(crate) description: Option<String>,
+    /// Whether a failure rolls back only this migration and migrating continues.
+    pub(crate) best_effort: bool,
+    /// Queries checked after the migration runs.
+    pub(crate) assertions: Vec<Assertion>,
+    /// Whether foreign keys are enforced only once the migration has run.
+    pub(crate) relax_foreign_keys: bool,
+    /// The names of migrations which must run before this one.
+    pub(crate) dependencies: Vec<String>,
+}
+
+/// The SQL of a migration, see [`Migration::sql`].
+#[derive(Debug, Clone)]
+pub(crate) enum Sql {
+    Text(Cow<'static, str>),
+    /// SQL which is only decompressed when it is needed.
+    Compressed(CompressedSql),
+}
+
+/// A file loaded into a table by an import directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Import {
+    pub(crate) table: String,
+    pub(crate) path: Utf8PathBuf,
+}
+
+impl Migration {
+    /// Creates a migration, reading its environments from its directives and, for files named
+    /// like `004_fixtures.dev.sql`, from its file name.
+    pub(crate) fn new(
+        name: impl Into<Cow<'static, str>>,
+        sql: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        let sql = sql.into();
+        let mut migration = Migration::parse(name.into(), &sql);
+        migration.sql = Sql::Text(sql);
+        migration
+    }
+
+    /// Creates a compressed migration, decompressing only the directives at the start of its SQL.
+    pub(crate) fn compressed(
+        name: impl Into<Cow<'static, str>>,
+        compressed: CompressedSql,
+    ) -> Result<Self> {
+        let name = name.into();
+        let header = compressed.header(&name)?;
+        let mut migration = Migration::parse(name, &header);
+        migration.sql = Sql::Compressed(compressed);
+        Ok(migration)
+    }
+
+    /// Reads the metadata of a migration from its name and the directives at the start of its
+    /// SQL, leaving the SQL itself empty.
+    fn parse(name: Cow<'static, str>, sql: &str) -> Self {
+        let mut environments: Vec<String> = directives(sql)
+            .filter(|(directive, _)| *directive == "env")
+            .flat_map(|(_, args)| args.split(',').map(str::trim))
+            .filter(|environment| !environment.is_empty())
+            .map(str::to_owned)
+            .collect();
+        if let Some(environment) = filename_environment(&name) {
+            environments.push(environment.to_owned());
+        }
+
+        let imports = directives(sql)
+            .filter(|(directive, _)| *directive == "import")
+            .filter_map(|(_, args)| {
+                let (table, path) = args.split_once(char::is_whitespace)?;
+                Some(Import {
+                    table: table.to_owned(),
+                    path: path.trim().into(),
+                })
+            })
+            .collect();
+
+        let baseline = directives(sql)
+            .filter(|(directive, _)| *directive == "baseline")
+            .find_map(|(_, args)| args.parse().ok());
+
+        let description: Vec<&str> = directives(sql)
+            .filter(|(directive, args)| *directive == "description" && !args.is_empty())
+            .map(|(_, args)| args)
+            .collect();
+        let description = if description.is_empty() {
+            leading_comment(sql).or_else(|| filename_description(&name))
+        } else {
+            Some(description.join(" "))
+        };
+
+        let best_effort = directives(sql).any(|(directive, _)| directive == "best-effort");
+        let assertions = assertion_directives(sql).collect();
+        let relax_foreign_keys =
+            directives(sql).any(|(directive, _)| directive == "relax-foreign-keys");
+        let dependencies = directives(sql)
+            .filter(|(directive, _)| *directive == "depends-on")
+            .flat_map(|(_, args)| args.split(',').map(str::trim))
+            .filter(|dependency| !dependency.is_empty())
+            .map(str::to_owned)
+            .collect();
+
+        Migration {
+            name,
+            sql: Sql::Text(Cow::Borrowed("")),
+            environments,
+            imports,
+            baseline,
+            description,
+            best_effort,
+            assertions,
+            relax_foreign_keys,
+            dependencies,
+        }
+    }
+
+    /// The SQL of the migration, decompressing it the first time it is read.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::CompressedMigration`] if compressed SQL cannot be decompressed.
+    pub(crate) fn sql(&self) -> Result<&str> {
+        match &self.sql {
+            Sql::Text(sql) => Ok(sql),
+            Sql::Compressed(compressed) => compressed.sql(&self.name),
+        }
+    }
+
+    /// Whether the migration runs in `environment`. Migrations without environments run
+    /// everywhere, while tagged migrations only run when one of their environments is selected.
+    pub(crate) fn applies_to(&self, environment: Option<&str>) -> bool {
+        self.environments.is_empty()
+            || environment
+                .is_some_and(|environment| self.environments.iter().any(|e| e == environment))
+    }
+
+    /// Resolves relative import paths against `directory`.
+    pub(crate) fn relative_to(mut self, directory: &Utf8Path) -> Self {
+        for import in &mut self.imports {
+            import.path = directory.join(&import.path);
+        }
+        self
+    }
+
+    /// Runs the migration's statements one at a time, calling `on_statement` with the index of
+    /// each before it runs.
+    ///
+    /// A failing statement is reported as [`Error::Statement`], which locates it in the
+    /// migration and keeps SQLite's error as its source.
+    pub(crate) fn execute(
+        &self,
+        connection: &rusqlite::Connection,
+        mut on_statement: impl FnMut(usize),
+    ) -> Result<()> {
+        let sql = self.sql()?;
+        let mut batch = rusqlite::Batch::new(connection, sql);
+        let mut index = 0;
+        loop {
+            let step = batch.next().and_then(|statement| {
+                let Some(mut statement) = statement else {
+                    return Ok(false);
+                };
+                on_statement(index);
+                // Step once, like `execute_batch`, since some statements such as pragmas return
+                // rows.
+                statement.raw_query().next()?;
+                Ok(true)
+            });
+            match step {
+                Ok(true) => index += 1,
+                Ok(false) => return Ok(()),
+                Err(source) => {
+                    // SQLite and the tokenizer both skip empty statements, so the statement which
+                    // failed to prepare or run is the tokenizer's statement at the same index.
+                    let line = crate::sql::statements(sql)
+                        .get(index)
+                        .map_or(1, |tokens| tokens[0].line);
+                    return Err(Error::Statement {
+                        migration: self.name.to_string(),
+                        statement: index + 1,
+                        line,
+                        source,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Loads the migration's imports into their tables.
+    #[cfg(feature = "import")]
+    pub(crate) fn run_imports(&self, connection: &rusqlite::Connection) -> Result<()> {
+        for import in &self.imports {
+            crate::TableImport::new(&import.table, &import.path)
+                .run(connection)
+                .map_err(|source| Error::Import {
+                    migration: self.name.to_string(),
+                    source,
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Fails if the migration has imports, which need the `import` feature.
+    #[cfg(not(feature = "import"))]
+    pub(crate) fn run_imports(&self, _: &rusqlite::Connection) -> Result<()> {
+        match self.imports.first() {
+            Some(import) => Err(Error::MissingFeature {
+                feature: "import",
+                needed_by: format!("migration {} imports {}", self.name, import.path),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Directives at the start of a migration, as `(name, arguments)` pairs.
+pub(crate) fn directives(sql: &str) -> impl Iterator<Item = (&str, &str)> {
+    sql.lines()
+        .map(str::trim)
+        .take_while(|line| line.is_empty() || line.starts_with("--"))
+        .filter_map(|line| line.strip_prefix("--")?.trim().strip_prefix("monarch:"))
+        .map(|directive| {
+            let (name, args) = directive
+                .split_once(char::is_whitespace)
+                .unwrap_or((directive, ""));
+            (name, args.trim())
+        })
+}
+
+/// The first paragraph of ordinary comments at the start of a migration, with its lines joined
+/// by spaces. Directives are skipped, and a blank line or empty comment ends the paragraph.
+fn leading_comment(sql: &str) -> Option<String> {
+    let mut lines = Vec::new();
+    let header = sql
+        .lines()
+        .map(str::trim)
+        .take_while(|line| line.is_empty() || line.starts_with("--"));
+    for line in header {
+        let text = line.strip_prefix("--").unwrap_or(line).trim();
+        if text.starts_with("monarch:") {
+            continue;
+        }
+        if text.is_empty() {
+            if lines.is_empty() {
+                continue;
+            }
+            break;
+        }
+        lines.push(text);
+    }
+    (!lines.is_empty()).then(|| lines.join(" "))
+}
+
+/// Describes a migration by its file name, without its version prefix, environment suffix and
+/// extension, and with underscores and dashes as spaces. Names which are only a version, such as
+/// those of embedded migrations, have no description.
+fn filename_description(name: &str) -> Option<String> {
+    let stem = name.strip_suffix(".sql").unwrap_or(name);
+    let stem = match filename_environment(name) {
+        Some(environment) => &stem[..stem.len() - environment.len() - 1],
+        None => stem,
+    };
+    let stem = stem
+        .strip_prefix(crate::repeatable::REPEATABLE_PREFIX)
+        .unwrap_or(stem);
+    let words: Vec<&str> = stem
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .split(['_', '-', ' '])
+        .filter(|word| !word.is_empty())
+        .collect();
+    (!words.is_empty()).then(|| words.join(" "))
+}
+
+/// Reads a migration file, dropping a UTF-8 byte order mark.
+///
+/// Files in other encodings fail with [`Error::InvalidEncoding`], naming the encoding if they start
+/// with a UTF-16 or UTF-32 byte order mark, or the offset of the first invalid byte otherwise.
+pub(crate) fn read_migration(path: &Utf8Path) -> Result<String, Error> {
+    let bytes = std::fs::read(path).map_err(|source| Error::ReadMigration {
+        path: path.to_owned(),
+        source,
+    })?;
+    let invalid = |reason: String| Error::InvalidEncoding {
+        path: path.to_owned(),
+        reason,
+    };
+
+    let encoding = match bytes.as_slice() {
+        [0xff, 0xfe, 0, 0, ..] | [0, 0, 0xfe, 0xff, ..] => Some("UTF-32"),
+        [0xff, 0xfe, ..] | [0xfe, 0xff, ..] => Some("UTF-16"),
+        _ => None,
+    };
+    if let Some(encoding) = encoding {
+        return Err(invalid(format!(
+            "the file is {encoding}, but migrations must be UTF-8"
+        )));
+    }
+
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&bytes);
+    String::from_utf8(bytes.to_vec()).map_err(|error| {
+        let offset = error.utf8_error().valid_up_to();
+        invalid(format!("invalid UTF-8 at byte {offset}"))
+    })
+}
+
+/// The environment in a file name like `004_fixtures.dev.sql`.
+fn filename_environment(name: &str) -> Option<&str> {
+    let stem = name.strip_suffix(".sql")?;
+    let (_, environment) = stem.rsplit_once('.')?;
+    (!environment.is_empty()).then_some(environment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directives_stop_at_first_statement() {
+        let sql = "-- Adds fixtures\n\n-- monarch:env dev, test\n--monarch:other\nCREATE TABLE t (id);\n-- monarch:env prod\n";
+        let directives: Vec<_> = directives(sql).collect();
+        assert_eq!(directives, [("env", "dev, test"), ("other", "")]);
+    }
+
+    #[test]
+    fn test_environments_from_directive_and_filename() {
+        let tagged = Migration::new("003_fixtures.sql", "-- monarch:env dev, test\nSELECT 1;");
+        assert_eq!(tagged.environments, ["dev", "test"]);
+        assert!(tagged.applies_to(Some("test")));
+        assert!(!tagged.applies_to(Some("production")));
+        assert!(!tagged.applies_to(None));
+
+        let suffixed = Migration::new("004_helpers.dev.sql", "SELECT 1;");
+        assert_eq!(suffixed.environments, ["dev"]);
+
+        let untagged = Migration::new("005_users.sql", "SELECT 1;");
+        assert!(untagged.environments.is_empty());
+        assert!(untagged.applies_to(None));
+        assert!(untagged.applies_to(Some("production")));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let checksum = |sql| crate::ChecksumAlgorithm::Fnv1a64.checksum(sql);
+        assert_eq!(checksum(""), "cbf29ce484222325");
+        assert_eq!(checksum("a"), "af63dc4c8601ec8c");
+        assert_ne!(checksum("SELECT 1;"), checksum("SELECT 2;"));
+    }
+
+    #[test]
+    fn test_import_directives() {
+        let migration = Migration::new(
+            "002_countries.sql",
+            "-- monarch:import countries data/countries.csv\n-- monarch:import regions /srv/regions.parquet\nCREATE TABLE countries (code);",
+        )
+        .relative_to(Utf8Path::new("migrations"));
+        assert_eq!(
+            migration.imports,
+            [
+                Import {
+                    table: "countries".into(),
+                    path: "migrations/data/countries.csv".into(),
+                },
+                Import {
+                    table: "regions".into(),
+                    path: "/srv/regions.parquet".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_description_directives() {
+        let migration = Migration::new(
+            "003_orders.sql",
+            "-- monarch:description Adds orders,\n-- monarch:description and their items.\nCREATE TABLE orders (id);",
+        );
+        assert_eq!(
+            migration.description.as_deref(),
+            Some("Adds orders, and their items.")
+        );
+        assert_eq!(Migration::new("1", "SELECT 1;").description, None);
+    }
+
+    #[test]
+    fn test_description_fallbacks() {
+        let description = |name, sql| Migration::new(name, sql).description;
+        assert_eq!(
+            description(
+                "004_fixtures.dev.sql",
+                "-- monarch:env dev\n-- Seed the users table\n-- for local testing.\n--\n-- Run after 003.\nINSERT INTO users VALUES (1);"
+            )
+            .as_deref(),
+            Some("Seed the users table for local testing.")
+        );
+        assert_eq!(
+            description("003_add_indexes.sql", "CREATE INDEX a ON t (x);").as_deref(),
+            Some("add indexes")
+        );
+        assert_eq!(
+            description("002_fixtures.dev.sql", "SELECT 1;").as_deref(),
+            Some("fixtures")
+        );
+        assert_eq!(
+            description("R__user-views.sql", "SELECT 1;").as_deref(),
+            Some("user views")
+        );
+        assert_eq!(description("012.sql", "SELECT 1;"), None);
+    }
+
+    #[test]
+    fn test_best_effort_directive() {
+        let migration = Migration::new(
+            "004_search.sql",
+            "-- monarch:best-effort
+CREATE INDEX users_email ON users (email);",
+        );
+        assert!(migration.best_effort);
+        assert!(!Migration::new("1", "SELECT 1;").best_effort);
+    }
+
+    #[test]
+    fn test_statement_error_location() {
+        let migration = Migration::new(
+            "002_posts.sql",
+            "-- Posts and their authors
+CREATE TABLE users (id INTEGER PRIMARY KEY);;
+
+CREATE TABLE posts (
+    id INTEGER PRIMARY KEY,
+    author INTEGER REFERENCES users (id),
+);
+SELECT 1;",
+        );
+        let connection = rusqlite::Connection::open_in_memory().unwrap();
+        let mut statements = Vec::new();
+        let error = migration
+            .execute(&connection, |index| statements.push(index))
+            .unwrap_err();
+        assert_eq!(statements, [0]);
+
+        let Error::Statement {
+            migration,
+            statement,
+            line,
+            source,
+        } = &error
+        else {
+            panic!("unexpected error {error:?}");
+        };
+        assert_eq!(migration, "002_posts.sql");
+        assert_eq!((*statement, *line), (2, 4));
+        assert!(source.to_string().contains("syntax error"), "{source}");
+        assert!(
+            error
+                .to_string()
+                .starts_with("migration 002_posts.sql, statement 2 at line 4: ")
+        );
+    }
+
+    #[test]
+    fn test_statement_error_keeps_sqlite_code() {
+        let migration = Migration::new(
+            "001_users.sql",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT UNIQUE);
+INSERT INTO users (email) VALUES ('a'), ('a');",
+        );
+        let connection = rusqlite::Connection::open_in_memory().unwrap();
+        let error = migration.execute(&connection, |_| {}).unwrap_err();
+        assert!(
+            matches!(
+                &error,
+                Error::Statement {
+                    source: rusqlite::Error::SqliteFailure(failure, _),
+                    ..
+                } if failure.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+            ),
+            "{error:?}"
+        );
+        assert_eq!(
+            error.sqlite_error_code(),
+            Some(rusqlite::ErrorCode::ConstraintViolation)
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Applying migrations which arrive out of order.
+//!
+//! When branches which each add a timestamped migration merge, a migration may be ordered before
+//! one a database already applied. Schema versions count the migrations applied, so the new
+//! migration would be taken as applied and never run; with timestamp versions, migrating fails
+//! with [`Error::IdentifierMismatch`](crate::Error::IdentifierMismatch) instead. With
+//! [`MonarchDB::with_out_of_order`],
+//! migrating instead looks each versioned migration up in the history by name, runs those which
+//! were never recorded, and renumbers the history to match the new order, like Flyway's
+//! `outOfOrder`.
+//!
+//! Migrations applied before Monarch kept a history have no record, so as many migrations as
+//! there are versions before the first one recorded are still taken as applied.
+
+use std::collections::BTreeMap;
+
+use crate::backend::Driver;
+use crate::history::HISTORY_TABLE;
+use crate::{MonarchDB, Result};
+
+impl MonarchDB {
+    /// Sets whether migrating runs versioned migrations ordered before the database's version
+    /// which were never applied, instead of skipping them. Defaults to `false`.
+    ///
+    /// Migrations applied this way are listed in
+    /// [`MigrationReport::out_of_order`](crate::MigrationReport::out_of_order).
+    ///
+    /// ```rust
+    /// use monarch_db::MonarchDB;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let users = (
+    ///     "20240612093000_users.sql".to_owned(),
+    ///     "CREATE TABLE users (id INTEGER PRIMARY KEY);".to_owned(),
+    /// );
+    /// let main = MonarchDB::from_iter("my_app", true, [users.clone()])?;
+    /// let mut connection = main.open_in_memory()?;
+    ///
+    /// // A branch merged a migration written before the one the database applied.
+    /// let widgets = (
+    ///     "20240601120000_widgets.sql".to_owned(),
+    ///     "CREATE TABLE widgets (id INTEGER PRIMARY KEY);".to_owned(),
+    /// );
+    /// let merged = MonarchDB::from_iter("my_app", true, [widgets, users])?.with_out_of_order(true);
+    /// let report = merged.apply(&mut connection)?;
+    /// assert_eq!(report.out_of_order, ["20240601120000_widgets.sql"]);
+    /// assert_eq!(report.to_version, 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_out_of_order(mut self, enabled: bool) -> Self {
+        self.out_of_order = enabled;
+        self
+    }
+
+    /// Renumbers the history of `tx` to the versions its migrations now reach, returning the
+    /// versions reached by the versioned migrations which were never recorded, or `None` if the
+    /// database is migrated as usual.
+    pub(crate) async fn align_history<D: Driver>(
+        &self,
+        driver: &mut D,
+        from_version: u32,
+    ) -> Result<Option<Vec<u32>>, D::Error> {
+        if !self.out_of_order || from_version == 0 {
+            return Ok(None);
+        }
+        let recorded: BTreeMap<String, u32> = self
+            .read_history(driver)
+            .await?
+            .into_iter()
+            .map(|entry| (entry.name, entry.version))
+            .collect();
+        let Some(&first) = recorded.values().min() else {
+            return Ok(None);
+        };
+
+        let mut moved = Vec::new();
+        let mut pending = Vec::new();
+        for (version, migration) in self.versions().filter(|(version, _)| *version >= first) {
+            match recorded.get(&*migration.name) {
+                Some(&previous) if previous != version => moved.push((&migration.name, version)),
+                Some(_) => {}
+                None => pending.push(version),
+            }
+        }
+
+        // Move records out of the way first, so none collides with one yet to move.
+        for (name, version) in &moved {
+            tracing::debug!(migration = %name, version, "Renumbering migration history");
+            driver
+                .execute(
+                    &format!(
+                        "UPDATE {HISTORY_TABLE} SET version = -?1 \
+                         WHERE monarch_schema = ?2 AND name = ?3"
+                    ),
+                    &[
+                        (*version).into(),
+                        self.name.to_string().into(),
+                        name.to_string().into(),
+                    ],
+                )
+                .await?;
+        }
+        if !moved.is_empty() {
+            driver
+                .execute(
+                    &format!(
+                        "UPDATE {HISTORY_TABLE} SET version = -version \
+                         WHERE monarch_schema = ?1 AND version < 0"
+                    ),
+                    &[self.name.to_string().into()],
+                )
+                .await?;
+        }
+        Ok(Some(pending))
+    }
+
+    /// Runs the migrations reaching each of `versions` up to `target`, returning the names of
+    /// those which ran out of order, before `from_version`.
+    pub(crate) async fn apply_out_of_order<D: Driver>(
+        &self,
+        driver: &mut D,
+        from_version: u32,
+        versions: &[u32],
+        target: u32,
+        skipped: &mut Vec<String>,
+    ) -> Result<Vec<String>, D::Error> {
+        let mut out_of_order = Vec::new();
+        for &version in versions.iter().filter(|&&version| version <= target) {
+            self.check_shutdown(from_version)?;
+            let Some((_, migration)) = self.versions().find(|(reached, _)| *reached == version)
+            else {
+                continue;
+            };
+            if version <= from_version && self.applies(migration) {
+                tracing::info!(
+                    migration = %migration.name,
+                    version,
+                    "Applying migration out of order"
+                );
+                out_of_order.push(migration.name.to_string());
+            }
+            self.apply_migration(driver, migration, version, target, skipped)
+                .await?;
+        }
+        Ok(out_of_order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::{ChecksumPolicy, Error, TransactionMode, VersionScheme};
+
+    use super::*;
+
+    #[test]
+    fn test_out_of_order() -> Result<()> {
+        let monarch = |names: &[&str]| {
+            let migrations = names
+                .iter()
+                .map(|name| (name.to_string(), format!("CREATE TABLE t_{name} (id);")));
+            let mut monarch_db = MonarchDB::from_iter("out_of_order", false, migrations)
+                .unwrap()
+                .with_checksum_policy(ChecksumPolicy::Error);
+            monarch_db.version_scheme = VersionScheme::Timestamp;
+            monarch_db
+        };
+        for mode in [TransactionMode::Single, TransactionMode::PerMigration] {
+            let mut connection = Connection::open_in_memory()?;
+            monarch(&["20240101", "20240301"]).apply(&mut connection)?;
+
+            let merged = || {
+                monarch(&["20240101", "20240201", "20240301", "20240401"])
+                    .with_transaction_mode(mode)
+            };
+            let error = merged().apply(&mut connection).unwrap_err();
+            assert!(
+                matches!(
+                    error,
+                    Error::IdentifierMismatch {
+                        version: 2,
+                        recorded: 20240301,
+                        current: 20240201,
+                    }
+                ),
+                "{error}"
+            );
+
+            let report = merged().with_out_of_order(true).apply(&mut connection)?;
+            assert_eq!((report.from_version, report.to_version), (2, 4));
+            assert_eq!(report.out_of_order, ["20240201"]);
+            assert_eq!(merged().apply(&mut connection)?.applied(), 0);
+
+            let history: Vec<(u32, String)> = monarch(&[])
+                .history(&connection)?
+                .into_iter()
+                .map(|entry| (entry.version, entry.name))
+                .collect();
+            assert_eq!(
+                history,
+                [
+                    (1, "20240101".to_owned()),
+                    (2, "20240201".to_owned()),
+                    (3, "20240301".to_owned()),
+                    (4, "20240401".to_owned()),
+                ]
+            );
+        }
+        Ok(())
+    }
+}
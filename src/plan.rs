@@ -0,0 +1,278 @@
+//! Listing the migrations which would run, without running them.
+//!
+//! [`MonarchDB::plan`] reads a database's schema version and works out which migrations
+//! migrating it would apply, so an application can log or display the upgrade before doing any
+//! work, e.g. "3 schema upgrades will run".
+
+use std::fmt;
+
+use rusqlite::Connection;
+
+use crate::backend::{Driver, Rusqlite, block_on};
+use crate::migration::directives;
+use crate::sql::statements;
+use crate::{MonarchDB, Result, VERSION_TABLE};
+
+/// The migrations which migrating a database would apply for a single schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationPlan {
+    /// The name of the schema, as given to [`MonarchDB`].
+    pub name: String,
+    /// The version recorded in the database.
+    pub from_version: u32,
+    /// The version the database would be at after migrating.
+    pub to_version: u32,
+    /// The pending versioned migrations, in the order they would run.
+    pub migrations: Vec<PlannedMigration>,
+    /// The names of the repeatable migrations which would run, see
+    /// [`MonarchDB::with_repeatable_migration`].
+    pub repeatable: Vec<String>,
+}
+
+impl MigrationPlan {
+    /// Whether migrating would change nothing.
+    pub fn is_empty(&self) -> bool {
+        self.migrations.is_empty() && self.repeatable.is_empty()
+    }
+}
+
+impl fmt::Display for MigrationPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(
+                f,
+                "{} is up to date at version {}",
+                self.name, self.from_version
+            );
+        }
+        write!(
+            f,
+            "{} migration(s) will run for {}, from version {} to {}",
+            self.migrations.len(),
+            self.name,
+            self.from_version,
+            self.to_version
+        )?;
+        if !self.repeatable.is_empty() {
+            write!(
+                f,
+                ", then {} repeatable migration(s)",
+                self.repeatable.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A versioned migration which has not been applied yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedMigration {
+    /// The schema version the migration upgrades the database to.
+    pub version: u32,
+    /// The name of the migration, its file name for migrations loaded from a directory.
+    pub name: String,
+    /// What the migration does, from its `-- monarch:description` directives, its leading
+    /// comment or its file name.
+    pub description: Option<String>,
+    /// The size of the migration's SQL, in bytes.
+    pub size: usize,
+    /// The `-- monarch:` directives at the start of the migration, as pairs of directive name
+    /// and arguments.
+    pub directives: Vec<(String, String)>,
+    /// Whether the migration is skipped because it is restricted to other environments, see
+    /// [`MonarchDB::with_environment`]. Only its version is recorded.
+    pub skipped: bool,
+    /// Whether the migration drops a table or column or deletes rows, losing data which cannot
+    /// be recovered without a backup.
+    pub destructive: bool,
+}
+
+impl MonarchDB {
+    /// Lists the migrations which migrating `connection` would apply, without applying them.
+    ///
+    /// Nothing is written to the database, not even Monarch's own tables, so this works on a
+    /// read-only connection. Another process may migrate the database between planning and
+    /// migrating, in which case fewer migrations run than were planned.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    ///         "ALTER TABLE users ADD COLUMN name TEXT;",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// let connection = rusqlite::Connection::open_in_memory()?;
+    /// let plan = monarch_db.plan(&connection)?;
+    /// assert_eq!(plan.migrations.len(), 2);
+    /// assert_eq!(plan.to_string(), "2 migration(s) will run for my_app, from version 0 to 2");
+    ///
+    /// let connection = monarch_db.migrate(connection)?;
+    /// assert!(monarch_db.plan(&connection)?.is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails like migrating would when the database's version falls within the versions
+    /// replaced by a baseline, see [`squash`](Self::squash).
+    pub fn plan(&self, connection: &Connection) -> Result<MigrationPlan> {
+        let from_version = recorded_version(connection, &self.name)?;
+
+        let mut migrations = Vec::new();
+        let mut version = from_version;
+        while version < self.current_version() {
+            let (migration, next) = self.get_migration(version)?;
+            let sql = migration.sql()?;
+            migrations.push(PlannedMigration {
+                version: next,
+                name: migration.name.to_string(),
+                description: migration.description.clone(),
+                size: sql.len(),
+                directives: directives(sql)
+                    .map(|(name, args)| (name.to_owned(), args.to_owned()))
+                    .collect(),
+                skipped: !self.applies(migration),
+                destructive: is_destructive(sql),
+            });
+            version = next;
+        }
+
+        let repeatable = block_on(self.pending_repeatables(&mut Rusqlite(connection)))?
+            .into_iter()
+            .map(|migration| migration.name.to_string())
+            .collect();
+
+        Ok(MigrationPlan {
+            name: self.name.to_string(),
+            from_version,
+            to_version: version,
+            migrations,
+            repeatable,
+        })
+    }
+}
+
+/// The schema version recorded for `name`, or 0 if none is, without creating the version table.
+pub(crate) fn recorded_version(connection: &Connection, name: &str) -> Result<u32> {
+    block_on(read_recorded_version(&mut Rusqlite(connection), name))
+}
+
+/// Reads the schema version recorded for `name` through `driver`, like [`recorded_version`].
+pub(crate) async fn read_recorded_version<D: Driver>(
+    driver: &mut D,
+    name: &str,
+) -> Result<u32, D::Error> {
+    if !driver.table_exists(VERSION_TABLE).await? {
+        return Ok(0);
+    }
+    let version = driver
+        .query_value(
+            &format!("SELECT version FROM {VERSION_TABLE} WHERE monarch_schema = ?1"),
+            &[name.to_owned().into()],
+        )
+        .await?;
+    Ok(version.unwrap_or(0))
+}
+
+/// Whether `sql` has a `DROP TABLE`, `ALTER TABLE ... DROP COLUMN` or `DELETE` statement.
+fn is_destructive(sql: &str) -> bool {
+    statements(sql)
+        .iter()
+        .any(|statement| match statement.as_slice() {
+            [drop, table, ..] if drop.is_keyword("DROP") && table.is_keyword("TABLE") => true,
+            [alter, table, ..] if alter.is_keyword("ALTER") && table.is_keyword("TABLE") => {
+                statement.iter().any(|token| token.is_keyword("DROP"))
+            }
+            [delete, ..] => delete.is_keyword("DELETE"),
+            _ => false,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_plan_lists_pending_migrations() -> Result<()> {
+        let all = MonarchDB::from(StaticMonarchConfiguration {
+            name: "plan",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                "-- monarch:env dev\nINSERT INTO users VALUES (1);",
+                "ALTER TABLE users ADD COLUMN name TEXT;",
+            ],
+        })
+        .with_repeatable_migration("R__views.sql", "CREATE VIEW IF NOT EXISTS v AS SELECT 1;");
+        let first = MonarchDB::from(StaticMonarchConfiguration {
+            name: "plan",
+            enable_foreign_keys: false,
+            migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+        });
+
+        let connection = first.open_in_memory()?;
+        let plan = all.plan(&connection)?;
+        assert_eq!((plan.from_version, plan.to_version), (1, 3));
+        assert_eq!(plan.repeatable, ["R__views.sql"]);
+        assert_eq!(
+            plan.migrations,
+            [
+                PlannedMigration {
+                    version: 2,
+                    name: "2".to_owned(),
+                    description: None,
+                    size: 48,
+                    directives: vec![("env".to_owned(), "dev".to_owned())],
+                    skipped: true,
+                    destructive: false,
+                },
+                PlannedMigration {
+                    version: 3,
+                    name: "3".to_owned(),
+                    description: None,
+                    size: 39,
+                    directives: Vec::new(),
+                    skipped: false,
+                    destructive: false,
+                },
+            ]
+        );
+        assert_eq!(
+            plan.to_string(),
+            "2 migration(s) will run for plan, from version 1 to 3, then 1 repeatable migration(s)"
+        );
+
+        // Planning leaves no trace in a fresh database.
+        let empty = Connection::open_in_memory()?;
+        assert_eq!(all.plan(&empty)?.migrations.len(), 3);
+        let tables: i64 =
+            empty.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0))?;
+        assert_eq!(tables, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_destructive_migrations() {
+        assert!(is_destructive(
+            "CREATE TABLE b (id);\nDROP TABLE IF EXISTS a;"
+        ));
+        assert!(is_destructive("ALTER TABLE users DROP COLUMN name;"));
+        assert!(is_destructive("DELETE FROM sessions WHERE expired;"));
+        assert!(!is_destructive(
+            "ALTER TABLE users ADD COLUMN name TEXT;\nDROP INDEX users_name;"
+        ));
+        assert!(!is_destructive(
+            "INSERT INTO log VALUES ('DROP TABLE users');"
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+//! Reporting progress while migrations run.
+//!
+//! A large data backfill can keep an application busy for minutes on first launch. A
+//! [`ProgressReporter`] registered with [`MonarchDB::with_progress_reporter`] is told as each
+//! migration starts and finishes, and before each of its statements runs, so the application can
+//! show a progress UI instead of looking hung.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+
+use crate::migration::Migration;
+use crate::{MonarchDB, Result};
+
+/// Receives progress events while versioned migrations run.
+///
+/// Every method does nothing by default, so implementations only override the events they
+/// need. Events are delivered on the thread running the migrations, inside the migration
+/// transaction, so they should return quickly.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use monarch_db::{MigrationProgress, MonarchDB, ProgressReporter, StaticMonarchConfiguration};
+///
+/// struct Log;
+///
+/// impl ProgressReporter for Log {
+///     fn on_migration_done(&self, migration: &MigrationProgress<'_>, elapsed: Duration) {
+///         println!("{} of {} ({elapsed:?})", migration.version, migration.target);
+///     }
+/// }
+///
+/// let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+///     name: "my_app",
+///     enable_foreign_keys: true,
+///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+/// })
+/// .with_progress_reporter(Log);
+/// ```
+pub trait ProgressReporter: Send + Sync {
+    /// Called before a migration runs.
+    fn on_migration_start(&self, migration: &MigrationProgress<'_>) {
+        let _ = migration;
+    }
+
+    /// Called before each statement of a migration runs, with the statement's 0-based index.
+    fn on_statement(&self, migration: &MigrationProgress<'_>, statement: usize) {
+        let _ = (migration, statement);
+    }
+
+    /// Called after a migration's statements and imports have run, with the time it took.
+    fn on_migration_done(&self, migration: &MigrationProgress<'_>, elapsed: Duration) {
+        let _ = (migration, elapsed);
+    }
+}
+
+/// The migration a [`ProgressReporter`] event is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationProgress<'a> {
+    /// The name of the migration, its file name for migrations loaded from a directory.
+    pub name: &'a str,
+    /// The schema version the migration upgrades the database to.
+    pub version: u32,
+    /// The version migrating stops at, usually the current version.
+    pub target: u32,
+}
+
+/// A registered [`ProgressReporter`].
+#[derive(Clone)]
+pub(crate) struct Reporter(Arc<dyn ProgressReporter>);
+
+impl fmt::Debug for Reporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressReporter")
+    }
+}
+
+impl MonarchDB {
+    /// Registers a reporter which is told about each versioned migration as it runs, see
+    /// [`ProgressReporter`].
+    pub fn with_progress_reporter(mut self, reporter: impl ProgressReporter + 'static) -> Self {
+        self.progress = Some(Reporter(Arc::new(reporter)));
+        self
+    }
+
+    /// Runs the SQL of `migration`, reporting progress if a reporter is registered.
+    pub(crate) fn execute_migration(
+        &self,
+        tx: &Connection,
+        migration: &Migration,
+        version: u32,
+        target: u32,
+    ) -> Result<()> {
+        self.relax_foreign_keys(tx, migration)?;
+        let Some(Reporter(reporter)) = &self.progress else {
+            self.run_within_timeout(tx, migration, || migration.run(tx, |_| {}))?;
+            return self.check_foreign_keys(tx, migration);
+        };
+
+        let progress = MigrationProgress {
+            name: &migration.name,
+            version,
+            target,
+        };
+        let start = Instant::now();
+        reporter.on_migration_start(&progress);
+        self.run_within_timeout(tx, migration, || {
+            migration.run(tx, |index| reporter.on_statement(&progress, index))
+        })?;
+        self.check_foreign_keys(tx, migration)?;
+        reporter.on_migration_done(&progress, start.elapsed());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl ProgressReporter for Arc<Recorder> {
+        fn on_migration_start(&self, migration: &MigrationProgress<'_>) {
+            let event = format!(
+                "start {} {}/{}",
+                migration.name, migration.version, migration.target
+            );
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_statement(&self, migration: &MigrationProgress<'_>, statement: usize) {
+            let event = format!("statement {} {statement}", migration.name);
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_migration_done(&self, migration: &MigrationProgress<'_>, _elapsed: Duration) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("done {}", migration.name));
+        }
+    }
+
+    #[test]
+    fn test_reports_migrations_and_statements() -> Result<()> {
+        let recorder = Arc::new(Recorder::default());
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "progress",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE t (x); PRAGMA user_version; INSERT INTO t VALUES (1);",
+                "-- monarch:env dev\nINSERT INTO t VALUES (2);",
+                "UPDATE t SET x = x + 1;",
+            ],
+        })
+        .with_progress_reporter(recorder.clone());
+
+        let connection = monarch_db.open_in_memory()?;
+        let x: i64 = connection.query_row("SELECT x FROM t", [], |row| row.get(0))?;
+        assert_eq!(x, 2);
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "start 1 1/3",
+                "statement 1 0",
+                "statement 1 1",
+                "statement 1 2",
+                "done 1",
+                "start 3 3/3",
+                "statement 3 0",
+                "done 3",
+            ]
+        );
+        Ok(())
+    }
+}
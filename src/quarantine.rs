@@ -0,0 +1,192 @@
+//! Repairing databases on which a released migration is known to fail.
+//!
+//! Once a migration has shipped it cannot be edited, since databases which already ran it would
+//! disagree with those which run the new version. When a migration turns out to fail on some
+//! databases in the field, e.g. because old data violates a new unique index, it can instead be
+//! quarantined with [`MonarchDB::with_quarantine`]: if it fails with an error matching a known
+//! signature, its changes are rolled back, a fix-up runs, and the migration is tried once more.
+
+use std::fmt;
+use std::sync::Arc;
+
+use rusqlite::Connection;
+
+use crate::migration::Migration;
+use crate::{MonarchDB, Result};
+
+/// A fix-up for a quarantined migration.
+type Remediation = Arc<dyn Fn(&Connection) -> rusqlite::Result<()> + Send + Sync>;
+
+/// A migration which is known to fail on some databases, and how to repair them.
+pub(crate) struct Quarantine {
+    migration: String,
+    signature: String,
+    remediation: Remediation,
+}
+
+impl fmt::Debug for Quarantine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Quarantine")
+            .field("migration", &self.migration)
+            .field("signature", &self.signature)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MonarchDB {
+    /// Quarantines the migration named `migration`: if it fails with an error whose message
+    /// contains `signature`, its changes are rolled back, `remediation` runs in the same
+    /// transaction, and the migration is retried once.
+    ///
+    /// Migrations loaded from a directory are named by their file name, and static migrations
+    /// by their version. Several remediations may be registered for one migration, and the
+    /// first whose signature matches is used. If the retry fails too, migrating fails with the
+    /// retry's error.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT);
+    ///          INSERT INTO users (email) VALUES ('a@example.com'), ('a@example.com');",
+    ///         "CREATE UNIQUE INDEX users_email ON users (email);",
+    ///     ],
+    /// })
+    /// .with_quarantine("2", "UNIQUE constraint failed", |connection| {
+    ///     connection.execute_batch(
+    ///         "DELETE FROM users WHERE id NOT IN (SELECT MIN(id) FROM users GROUP BY email);",
+    ///     )
+    /// });
+    ///
+    /// let connection = monarch_db.open_in_memory()?;
+    /// let users: i64 = connection.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?;
+    /// assert_eq!(users, 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_quarantine<F>(
+        mut self,
+        migration: impl Into<String>,
+        signature: impl Into<String>,
+        remediation: F,
+    ) -> Self
+    where
+        F: Fn(&Connection) -> rusqlite::Result<()> + Send + Sync + 'static,
+    {
+        self.quarantine.push(Quarantine {
+            migration: migration.into(),
+            signature: signature.into(),
+            remediation: Arc::new(remediation),
+        });
+        self
+    }
+
+    /// Runs `migration` like [`execute_migration`](Self::execute_migration), remediating and
+    /// retrying it once if it is quarantined and fails with a matching error.
+    pub(crate) fn execute_quarantined(
+        &self,
+        tx: &Connection,
+        migration: &Migration,
+        version: u32,
+        target: u32,
+    ) -> Result<()> {
+        if !self
+            .quarantine
+            .iter()
+            .any(|q| q.migration == migration.name)
+        {
+            return self.execute_migration(tx, migration, version, target);
+        }
+
+        tx.execute_batch("SAVEPOINT monarch_quarantine")?;
+        let error = match self.execute_migration(tx, migration, version, target) {
+            Ok(()) => return Ok(tx.execute_batch("RELEASE monarch_quarantine")?),
+            Err(error) => error,
+        };
+        tx.execute_batch("ROLLBACK TO monarch_quarantine; RELEASE monarch_quarantine")?;
+
+        let message = error.to_string();
+        let Some(quarantine) = self
+            .quarantine
+            .iter()
+            .find(|q| q.migration == migration.name && message.contains(&q.signature))
+        else {
+            return Err(error);
+        };
+        tracing::warn!(
+            migration = %migration.name,
+            %error,
+            "Remediating quarantined migration to version {} and retrying",
+            version
+        );
+        (quarantine.remediation)(tx)?;
+        self.execute_migration(tx, migration, version, target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    fn seeded() -> Result<Connection> {
+        let connection = Connection::open_in_memory()?;
+        let first = MonarchDB::from(StaticMonarchConfiguration {
+            name: "quarantine",
+            enable_foreign_keys: false,
+            migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT);"],
+        });
+        let connection = first.migrate(connection)?;
+        connection.execute_batch("INSERT INTO users (email) VALUES ('a'), ('a'), ('b');")?;
+        Ok(connection)
+    }
+
+    #[test]
+    fn test_matching_failure_is_remediated() -> Result<()> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "quarantine",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT);",
+                "CREATE TABLE audit (x); CREATE UNIQUE INDEX users_email ON users (email);",
+            ],
+        })
+        .with_quarantine("2", "no such table", |_| Ok(()))
+        .with_quarantine("2", "UNIQUE constraint failed", |connection| {
+            connection.execute_batch("DELETE FROM users WHERE id = 2")
+        });
+        let connection = monarch_db.migrate(seeded()?)?;
+        let emails: i64 =
+            connection.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?;
+        assert_eq!(emails, 2);
+        // The rolled back attempt left nothing behind for the retry to trip over.
+        connection.execute("INSERT INTO audit VALUES (1)", [])?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_other_failures_are_not_remediated() -> Result<()> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "quarantine",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT);",
+                "CREATE TABLE audit (x); CREATE UNIQUE INDEX users_email ON users (email);",
+            ],
+        })
+        .with_quarantine("2", "no such table", |_| {
+            panic!("remediation should not run")
+        });
+        let error = monarch_db.migrate(seeded()?).unwrap_err();
+        assert!(
+            error.to_string().contains("UNIQUE constraint failed"),
+            "{error}"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+//! Migration streams contributed by library crates.
+//!
+//! A library which owns some tables can ship its own migrations, tracked under its own schema
+//! name, with [`register_migrations!`](crate::register_migrations). The application then
+//! migrates every stream linked into the binary with [`MonarchSet::registered`], without
+//! listing them itself. Registration uses [`linkme`](https://docs.rs/linkme), and requires the
+//! `registry` feature.
+
+use crate::{MonarchDB, MonarchSet};
+
+/// A migration stream registered with [`register_migrations!`](crate::register_migrations).
+#[derive(Debug)]
+pub struct RegisteredMigrations {
+    /// The name of the schema, used for tracking migration versions.
+    pub name: &'static str,
+    /// Whether to enable foreign key constraints in SQLite.
+    pub enable_foreign_keys: bool,
+    /// The migration SQL strings, ordered from oldest to newest.
+    pub migrations: &'static [&'static str],
+}
+
+/// Every migration stream linked into the binary, in no particular order.
+#[linkme::distributed_slice]
+pub static REGISTERED_MIGRATIONS: [RegisteredMigrations];
+
+/// Registers a migration stream, to be migrated by [`MonarchSet::registered`].
+///
+/// The stream is tracked under its own schema name, like a [`StaticMonarchConfiguration`]
+/// with the same fields. Each name may only be registered once in a binary.
+///
+/// ```rust
+/// monarch_db::register_migrations! {
+///     name: "billing",
+///     enable_foreign_keys: true,
+///     migrations: [
+///         "CREATE TABLE invoices (id INTEGER PRIMARY KEY, total INTEGER NOT NULL);",
+///         "CREATE INDEX invoices_total ON invoices (total);",
+///     ],
+/// }
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut connection = rusqlite::Connection::open_in_memory()?;
+/// let reports = monarch_db::MonarchSet::registered().migrate(&mut connection)?;
+/// assert_eq!(reports[0].to_version, 2);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`StaticMonarchConfiguration`]: crate::StaticMonarchConfiguration
+#[macro_export]
+macro_rules! register_migrations {
+    (
+        name: $name:expr,
+        enable_foreign_keys: $enable_foreign_keys:expr,
+        migrations: [$($migration:expr),* $(,)?] $(,)?
+    ) => {
+        const _: () = {
+            #[$crate::__private::linkme::distributed_slice($crate::REGISTERED_MIGRATIONS)]
+            #[linkme(crate = $crate::__private::linkme)]
+            static REGISTRATION: $crate::RegisteredMigrations = $crate::RegisteredMigrations {
+                name: $name,
+                enable_foreign_keys: $enable_foreign_keys,
+                migrations: &[$($migration),*],
+            };
+        };
+    };
+}
+
+impl From<&RegisteredMigrations> for MonarchDB {
+    fn from(registered: &RegisteredMigrations) -> Self {
+        MonarchDB::from_static(
+            registered.name,
+            registered.enable_foreign_keys,
+            registered.migrations,
+        )
+    }
+}
+
+impl MonarchSet {
+    /// Creates a set of every migration stream registered with
+    /// [`register_migrations!`](crate::register_migrations) in the binary.
+    ///
+    /// Link order is not stable, so members are ordered by schema name. Streams which must run
+    /// after another should only depend on its tables from their own later migrations, or be
+    /// added to a [`MonarchSet`] explicitly instead.
+    ///
+    /// Foreign key enforcement applies to the whole connection, so unless the streams agree on
+    /// `enable_foreign_keys`, migrating fails with
+    /// [`Error::ConflictingSetting`](crate::Error::ConflictingSetting). An application
+    /// linking streams which disagree decides for all of them:
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let set = monarch_db::MonarchSet::registered().with_foreign_keys(true);
+    /// let mut connection = rusqlite::Connection::open_in_memory()?;
+    /// set.migrate(&mut connection)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if two streams are registered under the same name, see [`MonarchSet::with`].
+    pub fn registered() -> Self {
+        let mut registered: Vec<&RegisteredMigrations> = REGISTERED_MIGRATIONS.iter().collect();
+        registered.sort_by_key(|registered| registered.name);
+        registered
+            .into_iter()
+            .fold(MonarchSet::new(), |set, registered| {
+                set.with(registered.into())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    crate::register_migrations! {
+        name: "registry_b",
+        enable_foreign_keys: true,
+        migrations: ["CREATE TABLE b (id INTEGER PRIMARY KEY);"],
+    }
+
+    crate::register_migrations! {
+        name: "registry_a",
+        enable_foreign_keys: true,
+        migrations: [
+            "CREATE TABLE a (id INTEGER PRIMARY KEY);",
+            "ALTER TABLE a ADD COLUMN name TEXT;",
+        ],
+    }
+
+    use super::*;
+
+    #[test]
+    fn test_registered_streams_are_migrated() -> crate::Result<()> {
+        let set = MonarchSet::registered();
+        let mut connection = Connection::open_in_memory()?;
+        let reports = set.migrate(&mut connection)?;
+        let summary: Vec<_> = reports
+            .iter()
+            .map(|report| (report.name.as_str(), report.to_version))
+            .collect();
+        assert_eq!(summary, [("registry_a", 2), ("registry_b", 1)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+//! Repeatable migrations, which re-run whenever they change.
+//!
+//! Views and triggers are awkward to maintain as a chain of numbered migrations, since every
+//! change means copying the whole definition into a new file. A repeatable migration, loaded from
+//! a file named like `R__create_views.sql`, is instead tracked by name: it runs after the
+//! versioned migrations whenever its checksum differs from the one recorded when it last ran.
+//! Repeatable migrations should therefore be idempotent, e.g. `DROP VIEW IF EXISTS` followed by
+//! `CREATE VIEW`.
+
+use std::borrow::Cow;
+use std::time::Instant;
+
+use tracing::Instrument;
+
+use crate::backend::Driver;
+use crate::checksum::checksum_matches;
+use crate::migration::Migration;
+use crate::{MonarchDB, Result};
+
+/// The file name prefix which marks a migration as repeatable.
+pub(crate) const REPEATABLE_PREFIX: &str = "R__";
+
+pub(crate) const REPEATABLE_TABLE: &str = "monarch_db_repeatable_migrations";
+
+impl MonarchDB {
+    /// Adds a repeatable migration, which runs after the versioned migrations whenever its SQL
+    /// differs from the SQL last run under `name`.
+    ///
+    /// Migrations loaded by [`from_configuration`](Self::from_configuration) from files whose
+    /// names start with `R__` are added this way. Repeatable migrations run in order of name, and
+    /// should be safe to run more than once.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);"],
+    /// })
+    /// .with_repeatable_migration(
+    ///     "R__views.sql",
+    ///     "DROP VIEW IF EXISTS user_names; CREATE VIEW user_names AS SELECT name FROM users;",
+    /// );
+    ///
+    /// let connection = monarch_db.open_in_memory()?;
+    /// connection.execute("SELECT * FROM user_names", [])?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_repeatable_migration(
+        mut self,
+        name: impl Into<Cow<'static, str>>,
+        sql: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        let migration = Migration::new(name, sql);
+        let index = self
+            .repeatables
+            .partition_point(|existing| existing.name <= migration.name);
+        self.repeatables.insert(index, migration);
+        self
+    }
+
+    /// Runs the repeatable migrations which changed since they last ran, returning their names.
+    pub(crate) async fn apply_repeatables<D: Driver>(
+        &self,
+        driver: &mut D,
+    ) -> Result<Vec<String>, D::Error> {
+        let mut applied = Vec::new();
+        if self.repeatables.is_empty() {
+            return Ok(applied);
+        }
+
+        driver
+            .execute_batch(include_str!("01.repeatable.sql"))
+            .await?;
+        for migration in self.pending_repeatables(driver).await? {
+            let sql = migration.sql()?;
+            let checksum = self.checksum(sql);
+            let span = tracing::debug_span!(
+                "repeatable_migration",
+                name = %migration.name,
+                bytes = sql.len(),
+                duration_ms = tracing::field::Empty,
+            );
+            let start = Instant::now();
+            async {
+                self.check_determinism(migration)?;
+                self.check_transaction(migration)?;
+                tracing::trace!(migration = %migration.name, "Running repeatable migration");
+                driver.run_repeatable(self, migration).await
+            }
+            .instrument(span.clone())
+            .await?;
+            span.record("duration_ms", start.elapsed().as_millis() as u64);
+            driver
+                .execute(
+                    &format!(
+                        "INSERT INTO {REPEATABLE_TABLE} (monarch_schema, name, checksum) \
+                         VALUES (?1, ?2, ?3) \
+                         ON CONFLICT (monarch_schema, name) \
+                         DO UPDATE SET checksum = excluded.checksum"
+                    ),
+                    &[
+                        self.name.to_string().into(),
+                        migration.name.to_string().into(),
+                        checksum.into(),
+                    ],
+                )
+                .await?;
+            applied.push(migration.name.to_string());
+        }
+        Ok(applied)
+    }
+
+    /// The repeatable migrations which would run through `driver`: those in the configured
+    /// environment whose checksum differs from the one recorded when they last ran.
+    pub(crate) async fn pending_repeatables<D: Driver>(
+        &self,
+        driver: &mut D,
+    ) -> Result<Vec<&Migration>, D::Error> {
+        let exists = driver.table_exists(REPEATABLE_TABLE).await?;
+
+        let mut pending = Vec::new();
+        for migration in self.repeatables.iter().filter(|m| self.applies(m)) {
+            let recorded: Option<String> = if exists {
+                driver
+                    .query_value(
+                        &format!(
+                            "SELECT checksum FROM {REPEATABLE_TABLE} \
+                             WHERE monarch_schema = ?1 AND name = ?2"
+                        ),
+                        &[
+                            self.name.to_string().into(),
+                            migration.name.to_string().into(),
+                        ],
+                    )
+                    .await?
+            } else {
+                None
+            };
+            let sql = migration.sql()?;
+            if !recorded.is_some_and(|recorded| checksum_matches(&recorded, sql)) {
+                pending.push(migration);
+            }
+        }
+        Ok(pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_repeatables_rerun_on_change() -> Result<()> {
+        let monarch = |view| {
+            MonarchDB::from(StaticMonarchConfiguration {
+                name: "repeatable",
+                enable_foreign_keys: false,
+                migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, email TEXT);"],
+            })
+            .with_repeatable_migration("R__views.sql", view)
+            .with_repeatable_migration(
+                "R__audit.sql",
+                "CREATE TABLE IF NOT EXISTS audit (message TEXT);",
+            )
+        };
+        let mut connection = Connection::open_in_memory()?;
+
+        let first = monarch(
+            "DROP VIEW IF EXISTS contacts; CREATE VIEW contacts AS SELECT name FROM users;",
+        );
+        let tx = connection.transaction()?;
+        let report = first.apply_pending(&tx)?;
+        tx.commit()?;
+        assert_eq!(report.repeatable, ["R__audit.sql", "R__views.sql"]);
+
+        let tx = connection.transaction()?;
+        assert!(first.apply_pending(&tx)?.repeatable.is_empty());
+        tx.commit()?;
+
+        let second = monarch(
+            "DROP VIEW IF EXISTS contacts; CREATE VIEW contacts AS SELECT name, email FROM users;",
+        );
+        let tx = connection.transaction()?;
+        let report = second.apply_pending(&tx)?;
+        tx.commit()?;
+        assert_eq!(report.repeatable, ["R__views.sql"]);
+        assert_eq!(report.applied(), 0);
+
+        let columns: i64 = connection.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('contacts')",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(columns, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+//! Retrying operations which fail because the database is locked.
+
+use std::time::Duration;
+
+use rusqlite::ErrorCode;
+
+/// How to retry operations that fail because another connection holds a lock.
+///
+/// A [`MonarchDB`](crate::MonarchDB) applies this policy when opening a connection and when
+/// preparing a connection for migrations (setting pragmas and starting the migration
+/// transaction). Only `SQLITE_BUSY` and `SQLITE_LOCKED` failures are retried; any other error is
+/// returned immediately.
+///
+/// The pause between attempts starts at `backoff` and doubles after every failed attempt, up to
+/// `max_backoff`.
+///
+/// This complements [`MonarchDB::with_lock_timeout`](crate::MonarchDB::with_lock_timeout), which
+/// bounds how long a single attempt to start the migration transaction waits for the lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one. A value of `0` or `1` disables retries.
+    pub attempts: u32,
+    /// Pause before the first retry.
+    pub backoff: Duration,
+    /// Upper bound on the pause between attempts.
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy which never retries. This is the default.
+    pub const NONE: RetryPolicy = RetryPolicy {
+        attempts: 1,
+        backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+    };
+
+    /// Creates a policy making up to `attempts` attempts, pausing `backoff` before the first retry.
+    ///
+    /// The pause doubles after each failure, capped at eight times the initial `backoff`.
+    pub fn new(attempts: u32, backoff: Duration) -> Self {
+        RetryPolicy {
+            attempts,
+            backoff,
+            max_backoff: backoff * 8,
+        }
+    }
+
+    /// Runs `operation`, retrying it while it fails with a busy or locked error.
+    pub(crate) fn retry<T>(
+        &self,
+        mut operation: impl FnMut() -> rusqlite::Result<T>,
+    ) -> rusqlite::Result<T> {
+        let mut pause = self.backoff;
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Err(error) if is_busy(&error) && attempt < self.attempts => {
+                    tracing::debug!(attempt, ?pause, "Database is locked, retrying");
+                    std::thread::sleep(pause);
+                    pause = (pause * 2).min(self.max_backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::NONE
+    }
+}
+
+/// Whether an error indicates that another connection holds a conflicting lock.
+pub(crate) fn is_busy(error: &rusqlite::Error) -> bool {
+    matches!(
+        error.sqlite_error_code(),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::ffi;
+
+    use super::*;
+
+    fn failure(code: std::ffi::c_int) -> rusqlite::Error {
+        rusqlite::Error::SqliteFailure(ffi::Error::new(code), None)
+    }
+
+    #[test]
+    fn test_retry_until_success() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+        let mut calls = 0;
+        let result = policy.retry(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(failure(ffi::SQLITE_BUSY))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let policy = RetryPolicy::new(2, Duration::from_millis(1));
+        let mut calls = 0;
+        let result: rusqlite::Result<()> = policy.retry(|| {
+            calls += 1;
+            Err(failure(ffi::SQLITE_LOCKED))
+        });
+        assert_eq!(
+            result.unwrap_err().sqlite_error_code(),
+            Some(ErrorCode::DatabaseLocked)
+        );
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn test_retry_ignores_other_errors() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(1));
+        let mut calls = 0;
+        let result: rusqlite::Result<()> = policy.retry(|| {
+            calls += 1;
+            Err(failure(ffi::SQLITE_CORRUPT))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,90 @@
+//! Rate limiting the routine events logged for every connection.
+//!
+//! Services which call [`MonarchDB::create_connection`] per request check the schema version
+//! every time, and would otherwise log that it is up to date just as often.
+//! [`MonarchDB::with_connection_log_interval`] logs those routine events at most once per
+//! interval. Events about migrations which actually run are always logged.
+
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::MonarchDB;
+
+/// Decides which connections log their routine events.
+#[derive(Debug)]
+pub(crate) struct LogSampler {
+    interval: Duration,
+    state: Mutex<SamplerState>,
+}
+
+#[derive(Debug, Default)]
+struct SamplerState {
+    last: Option<Instant>,
+    suppressed: u64,
+}
+
+impl LogSampler {
+    /// A sampler which lets a connection log at most once per `interval`.
+    pub(crate) fn new(interval: Duration) -> Self {
+        LogSampler {
+            interval,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Whether the current connection should log, and if so how many connections were
+    /// suppressed since the last one which did.
+    pub(crate) fn sample(&self) -> Option<u64> {
+        self.sample_at(Instant::now())
+    }
+
+    fn sample_at(&self, now: Instant) -> Option<u64> {
+        if self.interval.is_zero() {
+            return Some(0);
+        }
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.last {
+            Some(last) if now.saturating_duration_since(last) < self.interval => {
+                state.suppressed += 1;
+                None
+            }
+            _ => {
+                state.last = Some(now);
+                Some(std::mem::take(&mut state.suppressed))
+            }
+        }
+    }
+}
+
+impl MonarchDB {
+    /// Logs the routine events for each connection, such as finding the schema up to date, at
+    /// most once per `interval` for this schema.
+    ///
+    /// The first connection after each interval logs as usual, with the number of connections
+    /// suppressed since the last one which logged. Migrations which run are always logged.
+    /// Defaults to [`Duration::ZERO`], which logs every connection.
+    pub fn with_connection_log_interval(mut self, interval: Duration) -> Self {
+        self.connection_log = LogSampler::new(interval);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampler_counts_suppressed_connections() {
+        let start = Instant::now();
+        let sampler = LogSampler::new(Duration::from_secs(60));
+        assert_eq!(sampler.sample_at(start), Some(0));
+        assert_eq!(sampler.sample_at(start + Duration::from_secs(1)), None);
+        assert_eq!(sampler.sample_at(start + Duration::from_secs(59)), None);
+        assert_eq!(sampler.sample_at(start + Duration::from_secs(60)), Some(2));
+        assert_eq!(sampler.sample_at(start + Duration::from_secs(61)), None);
+
+        let unlimited = LogSampler::new(Duration::ZERO);
+        assert_eq!(unlimited.sample_at(start), Some(0));
+        assert_eq!(unlimited.sample_at(start), Some(0));
+    }
+}
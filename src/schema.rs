@@ -0,0 +1,321 @@
+//! Dumping and comparing a database's schema.
+//!
+//! [`MonarchDB::schema_sql`] renders the DDL of every schema object in a stable order, suitable
+//! for snapshot tests which assert that migrations produce an expected schema, or for checking a
+//! schema file into a repository, and [`MonarchDB::schema_objects`] lists the objects themselves.
+//! [`MonarchDB::diff`] compares a live database's schema with the one its migrations produce, to
+//! catch changes made by other tools.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use rusqlite::Connection;
+
+use crate::backend::{Driver, Rusqlite, block_on, column};
+use crate::{MonarchDB, Result};
+
+impl MonarchDB {
+    /// Returns the SQL which creates every object in the connection's `main` schema.
+    ///
+    /// Objects are ordered tables first, then indexes, views and triggers, each group sorted by
+    /// name. Each statement has trailing whitespace removed from its lines, ends with a
+    /// semicolon, and is followed by a blank line, so the output is stable across databases
+    /// which reached the same schema. SQLite's internal objects and Monarch's own
+    /// `monarch_db_` bookkeeping tables are excluded.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY)   ;"],
+    /// }
+    /// .into();
+    ///
+    /// let connection = monarch_db.open_in_memory()?;
+    /// assert_eq!(
+    ///     MonarchDB::schema_sql(&connection)?,
+    ///     "CREATE TABLE users (id INTEGER PRIMARY KEY);\n"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn schema_sql(connection: &Connection) -> Result<String> {
+        block_on(MonarchDB::read_schema_sql(&mut Rusqlite(connection)))
+    }
+
+    /// Returns every object in the connection's `main` schema, in the order
+    /// [`schema_sql`](Self::schema_sql) writes them, with the SQL SQLite recorded for each.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT);",
+    ///         "CREATE INDEX users_email ON users (email);",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// let connection = monarch_db.open_in_memory()?;
+    /// let objects = MonarchDB::schema_objects(&connection)?;
+    /// let names: Vec<_> = objects
+    ///     .iter()
+    ///     .map(|object| (object.kind.as_str(), object.name.as_str()))
+    ///     .collect();
+    /// assert_eq!(names, [("table", "users"), ("index", "users_email")]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn schema_objects(connection: &Connection) -> Result<Vec<SchemaObject>> {
+        schema_objects(connection)
+    }
+
+    /// Dumps the schema through `driver`, like [`schema_sql`](Self::schema_sql).
+    pub(crate) async fn read_schema_sql<D: Driver>(driver: &mut D) -> Result<String, D::Error> {
+        let statements: Vec<_> = read_schema_objects(driver)
+            .await?
+            .iter()
+            .map(|object| normalize(&object.sql))
+            .collect();
+        Ok(statements.join("\n"))
+    }
+
+    /// Compares the schema of `connection` with the schema the configured migrations produce.
+    ///
+    /// The migrations are applied to a scratch in-memory database, and its tables, indexes,
+    /// views and triggers are compared by name and SQL with those of the connection's `main`
+    /// schema. Differences in whitespace are ignored. Objects created outside of migrations,
+    /// such as by another `MonarchDB` sharing the database, are reported as extra.
+    pub fn diff(&self, connection: &Connection) -> Result<SchemaDiff> {
+        let scratch = self.open_in_memory()?;
+        let expected = schema_objects(&scratch)?;
+        let mut actual: BTreeMap<_, _> = schema_objects(connection)?
+            .into_iter()
+            .map(|object| ((kind_order(&object.kind), object.name.clone()), object))
+            .collect();
+
+        let mut diff = SchemaDiff::default();
+        for object in expected {
+            match actual.remove(&(kind_order(&object.kind), object.name.clone())) {
+                None => diff.missing.push(object),
+                Some(found)
+                    if collapse_whitespace(&found.sql) != collapse_whitespace(&object.sql) =>
+                {
+                    diff.modified.push(ModifiedObject {
+                        kind: object.kind,
+                        name: object.name,
+                        expected: object.sql,
+                        actual: found.sql,
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        diff.extra = actual.into_values().collect();
+        Ok(diff)
+    }
+}
+
+/// A table, index, view or trigger in a database's schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaObject {
+    /// One of `table`, `index`, `view` or `trigger`, as reported by `sqlite_master`.
+    pub kind: String,
+    /// The name of the object.
+    pub name: String,
+    /// The SQL which created the object.
+    pub sql: String,
+}
+
+/// An object whose definition differs from the one the migrations produce.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModifiedObject {
+    /// One of `table`, `index`, `view` or `trigger`, as reported by `sqlite_master`.
+    pub kind: String,
+    /// The name of the object.
+    pub name: String,
+    /// The SQL the migrations produce.
+    pub expected: String,
+    /// The SQL found in the database.
+    pub actual: String,
+}
+
+/// Differences between a database's schema and the schema its migrations produce.
+///
+/// See [`MonarchDB::diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaDiff {
+    /// Objects the migrations create which the database lacks.
+    pub missing: Vec<SchemaObject>,
+    /// Objects in the database which the migrations do not create.
+    pub extra: Vec<SchemaObject>,
+    /// Objects whose definitions differ.
+    pub modified: Vec<ModifiedObject>,
+}
+
+impl SchemaDiff {
+    /// Whether the schemas match.
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for object in &self.missing {
+            writeln!(f, "missing {} {}", object.kind, object.name)?;
+        }
+        for object in &self.extra {
+            writeln!(f, "extra {} {}", object.kind, object.name)?;
+        }
+        for object in &self.modified {
+            writeln!(f, "modified {} {}", object.kind, object.name)?;
+            writeln!(f, "  expected: {}", collapse_whitespace(&object.expected))?;
+            writeln!(f, "  actual:   {}", collapse_whitespace(&object.actual))?;
+        }
+        Ok(())
+    }
+}
+
+/// Schema objects in the `main` schema, excluding SQLite's internal objects and Monarch's own
+/// tables, ordered tables first, then indexes, views and triggers, each sorted by name.
+pub(crate) fn schema_objects(connection: &Connection) -> Result<Vec<SchemaObject>> {
+    block_on(read_schema_objects(&mut Rusqlite(connection)))
+}
+
+/// Reads the objects in the `main` schema through `driver`, like [`schema_objects`].
+pub(crate) async fn read_schema_objects<D: Driver>(
+    driver: &mut D,
+) -> Result<Vec<SchemaObject>, D::Error> {
+    let rows = driver
+        .query(
+            "SELECT type, name, sql FROM sqlite_master \
+             WHERE sql IS NOT NULL \
+               AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' \
+               AND tbl_name NOT LIKE 'monarch\\_db\\_%' ESCAPE '\\'",
+            &[],
+        )
+        .await?;
+    let mut objects = rows
+        .iter()
+        .map(|row| {
+            Ok(SchemaObject {
+                kind: column(row, 0)?,
+                name: column(row, 1)?,
+                sql: column(row, 2)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    objects.sort_by(|a, b| (kind_order(&a.kind), &a.name).cmp(&(kind_order(&b.kind), &b.name)));
+    Ok(objects)
+}
+
+pub(crate) fn kind_order(kind: &str) -> u8 {
+    match kind {
+        "table" => 0,
+        "index" => 1,
+        "view" => 2,
+        _ => 3,
+    }
+}
+
+fn collapse_whitespace(sql: &str) -> String {
+    sql.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Strips trailing whitespace from each line and terminates the statement.
+pub(crate) fn normalize(sql: &str) -> String {
+    let mut normalized = sql
+        .trim()
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n");
+    normalized.push_str(";\n");
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_schema_sql_orders_objects() -> Result<()> {
+        let monarch_db: MonarchDB = StaticMonarchConfiguration {
+            name: "schema",
+            enable_foreign_keys: true,
+            migrations: [
+                "CREATE TABLE users (
+                    id INTEGER PRIMARY KEY,
+                    name TEXT UNIQUE
+                 );
+                 CREATE VIEW user_names AS SELECT name FROM users;",
+                "CREATE INDEX idx_users_name ON users(name);
+                 CREATE TABLE audit (id INTEGER PRIMARY KEY, message TEXT);
+                 CREATE TRIGGER users_audit AFTER INSERT ON users
+                 BEGIN INSERT INTO audit (message) VALUES (NEW.name); END;",
+            ],
+        }
+        .into();
+
+        let connection = monarch_db.open_in_memory()?;
+        let sql = MonarchDB::schema_sql(&connection)?;
+
+        let expected = "CREATE TABLE audit (id INTEGER PRIMARY KEY, message TEXT);\n\
+             \n\
+             CREATE TABLE users (\n                    id INTEGER PRIMARY KEY,\n                    name TEXT UNIQUE\n                 );\n\
+             \n\
+             CREATE INDEX idx_users_name ON users(name);\n\
+             \n\
+             CREATE VIEW user_names AS SELECT name FROM users;\n\
+             \n\
+             CREATE TRIGGER users_audit AFTER INSERT ON users\n                 BEGIN INSERT INTO audit (message) VALUES (NEW.name); END;\n";
+        assert_eq!(sql, expected);
+        assert!(!sql.contains("monarch_db_"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_reports_drift() -> Result<()> {
+        let monarch_db: MonarchDB = StaticMonarchConfiguration {
+            name: "drift",
+            enable_foreign_keys: true,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+                 CREATE INDEX idx_users_name ON users(name);",
+                "CREATE TRIGGER users_name AFTER UPDATE ON users BEGIN SELECT 1; END;",
+            ],
+        }
+        .into();
+
+        let connection = monarch_db.open_in_memory()?;
+        assert!(monarch_db.diff(&connection)?.is_empty());
+
+        connection.execute_batch(
+            "ALTER TABLE users ADD COLUMN email TEXT;
+             DROP INDEX idx_users_name;
+             CREATE TABLE notes (id INTEGER PRIMARY KEY);",
+        )?;
+        let diff = monarch_db.diff(&connection)?;
+
+        let missing: Vec<_> = diff.missing.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(missing, ["idx_users_name"]);
+        let extra: Vec<_> = diff.extra.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(extra, ["notes"]);
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.modified[0].name, "users");
+        assert!(diff.modified[0].actual.contains("email"));
+        assert!(!diff.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//! Keeping a `schema.sql` file in step with the migrations.
+//!
+//! Reviewing a migration is easier next to the schema it produces. With
+//! [`MonarchDB::with_schema_file`], migrating a database to the current version rewrites a file
+//! with [`MonarchDB::schema_sql`]'s dump of it, so the file can be checked into a repository, and
+//! [`MonarchDB::schema_file_is_stale`] catches a file which was not refreshed after the
+//! migrations changed.
+
+use std::io;
+
+use crate::backend::Driver;
+use crate::{Error, MonarchDB, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+
+impl MonarchDB {
+    /// Sets a file to write the schema to after migrating, e.g. `schema.sql` at the root of a
+    /// repository.
+    ///
+    /// Each time a connection is migrated to the current version, the file is rewritten with the
+    /// [`schema_sql`](Self::schema_sql) of the connection, unless it already holds that schema.
+    /// The dump includes every object in the `main` schema, so objects created by other
+    /// `MonarchDB`s sharing the database appear in it too. Migrating to an earlier version with
+    /// [`migrate_to`](Self::migrate_to) leaves the file alone, as do the scratch databases
+    /// migrated by [`schema_file_is_stale`](Self::schema_file_is_stale).
+    ///
+    /// The migrations have committed by the time the file is written, so failing to write it
+    /// is logged as a warning rather than failing the migration.
+    pub fn with_schema_file(mut self, path: impl Into<Utf8PathBuf>) -> Self {
+        self.schema_file = Some(path.into());
+        self
+    }
+
+    /// The file the schema is written to after migrating, see
+    /// [`with_schema_file`](Self::with_schema_file).
+    pub fn schema_file(&self) -> Option<&Utf8Path> {
+        self.schema_file.as_deref()
+    }
+
+    /// Returns whether the schema file at `path` differs from the schema the migrations
+    /// produce, or does not exist.
+    ///
+    /// The migrations are applied to a scratch in-memory database and its
+    /// [`schema_sql`](Self::schema_sql) is compared with the file, so this suits a CI check
+    /// that a checked-in `schema.sql` was refreshed along with the migrations.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let directory = tempfile::tempdir()?;
+    /// let path = camino::Utf8PathBuf::try_from(directory.path().join("schema.sql"))?;
+    ///
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+    /// }
+    /// .into();
+    /// assert!(monarch_db.schema_file_is_stale(&path)?);
+    ///
+    /// let monarch_db = monarch_db.with_schema_file(&path);
+    /// monarch_db.open_in_memory()?;
+    /// assert!(!monarch_db.schema_file_is_stale(&path)?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read, or the migrations fail.
+    pub fn schema_file_is_stale(&self, path: impl AsRef<Utf8Path>) -> Result<bool> {
+        let written = match std::fs::read_to_string(path.as_ref()) {
+            Ok(written) => written,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(error) => return Err(error.into()),
+        };
+        let expected = MonarchDB::schema_sql(&self.migrate_scratch()?)?;
+        Ok(written != expected)
+    }
+
+    /// Rewrites the schema file, if one is configured, with the schema read through `driver`.
+    pub(crate) async fn refresh_schema_file<D: Driver>(&self, driver: &mut D) {
+        let Some(path) = &self.schema_file else {
+            return;
+        };
+        let refresh = async {
+            let schema = MonarchDB::read_schema_sql(driver).await?;
+            match std::fs::read_to_string(path) {
+                Ok(written) if written == schema => return Ok(false),
+                Ok(_) => {}
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(Error::from(error).into()),
+            }
+            std::fs::write(path, schema).map_err(Error::from)?;
+            Ok::<_, D::Error>(true)
+        };
+        match refresh.await {
+            Ok(true) => tracing::debug!(path = %path, "Wrote schema file"),
+            Ok(false) => {}
+            Err(error) => tracing::warn!(path = %path, %error, "Failed to write schema file"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_schema_file_written_and_checked() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let path = Utf8PathBuf::try_from(directory.path().join("schema.sql")).unwrap();
+
+        let first = MonarchDB::from(StaticMonarchConfiguration {
+            name: "schema_file",
+            enable_foreign_keys: true,
+            migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+        })
+        .with_schema_file(&path);
+        assert_eq!(first.schema_file(), Some(path.as_path()));
+        assert!(first.schema_file_is_stale(&path)?);
+
+        let mut connection = first.open_in_memory()?;
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "CREATE TABLE users (id INTEGER PRIMARY KEY);\n"
+        );
+        assert!(!first.schema_file_is_stale(&path)?);
+
+        let second = MonarchDB::from(StaticMonarchConfiguration {
+            name: "schema_file",
+            enable_foreign_keys: true,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                "ALTER TABLE users ADD COLUMN email TEXT;",
+            ],
+        })
+        .with_schema_file(&path);
+        assert!(second.schema_file_is_stale(&path)?);
+
+        second.migrate_to(&mut connection, 1)?;
+        assert!(second.schema_file_is_stale(&path)?);
+
+        second.migrate(connection)?;
+        assert!(!second.schema_file_is_stale(&path)?);
+        assert!(std::fs::read_to_string(&path)?.contains("email TEXT"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unwritable_schema_file_does_not_fail_migration() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let path = Utf8PathBuf::try_from(directory.path().join("missing/schema.sql")).unwrap();
+
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "schema_file",
+            enable_foreign_keys: true,
+            migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+        })
+        .with_schema_file(&path);
+        monarch_db.open_in_memory()?;
+        assert!(!path.exists());
+        Ok(())
+    }
+}
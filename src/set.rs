@@ -0,0 +1,379 @@
+//! Applying several independently-versioned schemas to one database.
+
+use std::time::Duration;
+
+use rusqlite::Connection;
+
+use crate::{
+    BusyHandler, ConnectionConfiguration, DEFAULT_LOCK_TIMEOUT, Error, MigrationReport, MonarchDB,
+    Result, RetryPolicy, begin_immediate, open_connection,
+};
+
+/// Pragmas which a migration can change inside the migration transaction. Each is restored after
+/// a member's migrations, so one schema's settings do not carry over into the next one's.
+const SCOPED_PRAGMAS: [&str; 5] = [
+    "defer_foreign_keys",
+    "ignore_check_constraints",
+    "legacy_alter_table",
+    "recursive_triggers",
+    "reverse_unordered_selects",
+];
+
+/// A collection of [`MonarchDB`] schemas which share a single database.
+///
+/// Applications composed of several components, each with its own migrations tracked under a
+/// distinct name, can use a `MonarchSet` to bring all of them up to date on one connection.
+/// All members are migrated inside a single transaction, so either every schema is brought up
+/// to date or none of them are.
+///
+/// Members are migrated in the order they were added, which makes the ordering deterministic
+/// and lets later components depend on tables created by earlier ones.
+///
+/// Settings which apply to the whole connection belong to the set: members must agree on
+/// whether foreign keys are enforced unless [`with_foreign_keys`](Self::with_foreign_keys)
+/// decides it, and pragmas a member's migrations change are restored before the next member
+/// is migrated.
+///
+/// ```rust
+/// use monarch_db::{MonarchDB, MonarchSet, StaticMonarchConfiguration};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let accounts: MonarchDB = StaticMonarchConfiguration {
+///     name: "accounts",
+///     enable_foreign_keys: true,
+///     migrations: ["CREATE TABLE accounts (id INTEGER PRIMARY KEY);"],
+/// }
+/// .into();
+/// let billing: MonarchDB = StaticMonarchConfiguration {
+///     name: "billing",
+///     enable_foreign_keys: true,
+///     migrations: ["CREATE TABLE invoices (account_id INTEGER REFERENCES accounts(id));"],
+/// }
+/// .into();
+///
+/// let set = MonarchSet::new().with(accounts).with(billing);
+/// let mut connection = rusqlite::Connection::open_in_memory()?;
+/// let reports = set.migrate(&mut connection)?;
+/// assert_eq!(reports.len(), 2);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MonarchSet {
+    members: Vec<MonarchDB>,
+    lock_timeout: Duration,
+    retry_policy: RetryPolicy,
+    foreign_keys: Option<bool>,
+    busy_handler: Option<BusyHandler>,
+}
+
+impl Default for MonarchSet {
+    fn default() -> Self {
+        MonarchSet {
+            members: Vec::new(),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            retry_policy: RetryPolicy::NONE,
+            foreign_keys: None,
+            busy_handler: None,
+        }
+    }
+}
+
+impl MonarchSet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a schema to the set. Schemas are migrated in the order they are added.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the set already contains a schema with the same name, since both would
+    /// record their versions in the same row.
+    pub fn with(mut self, monarch: MonarchDB) -> Self {
+        assert!(
+            self.members.iter().all(|m| m.name != monarch.name),
+            "MonarchSet already contains a schema named {:?}",
+            monarch.name
+        );
+        self.members.push(monarch);
+        self
+    }
+
+    /// Sets how long to wait for the migration lock, see [`MonarchDB::with_lock_timeout`].
+    ///
+    /// The settings of individual members do not apply when they are migrated as part of a set.
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    /// Sets the policy for retrying locked operations, see [`MonarchDB::with_retry_policy`].
+    ///
+    /// The settings of individual members do not apply when they are migrated as part of a set.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Sets whether foreign keys are enforced on the set's connection, overriding the
+    /// `enable_foreign_keys` setting of each member.
+    ///
+    /// Without it, migrating fails with [`Error::ConflictingSetting`] if some members enable
+    /// foreign keys and others do not, rather than letting one member decide for all of them.
+    pub fn with_foreign_keys(mut self, enabled: bool) -> Self {
+        self.foreign_keys = Some(enabled);
+        self
+    }
+
+    /// Whether foreign keys are enforced, as the set or all of its members decide.
+    fn foreign_keys(&self) -> Result<bool, Error> {
+        if let Some(enabled) = self.foreign_keys {
+            return Ok(enabled);
+        }
+        let (enabled, disabled): (Vec<&MonarchDB>, Vec<&MonarchDB>) =
+            self.members.iter().partition(|m| m.enable_foreign_keys);
+        if !enabled.is_empty() && !disabled.is_empty() {
+            let names =
+                |members: Vec<&MonarchDB>| members.iter().map(|m| m.name.to_string()).collect();
+            return Err(Error::ConflictingSetting {
+                setting: "enable_foreign_keys",
+                enabled: names(enabled),
+                disabled: names(disabled),
+            });
+        }
+        Ok(!enabled.is_empty())
+    }
+
+    /// Sets the busy handler installed on the connections
+    /// [`create_connection`](Self::create_connection) opens, see
+    /// [`MonarchDB::with_busy_handler`].
+    ///
+    /// The settings of individual members do not apply when they are migrated as part of a set.
+    pub fn with_busy_handler(mut self, handler: BusyHandler) -> Self {
+        self.busy_handler = Some(handler);
+        self
+    }
+
+    /// Iterates over the schemas in the set, in migration order.
+    pub fn iter(&self) -> impl Iterator<Item = &MonarchDB> {
+        self.members.iter()
+    }
+
+    /// Creates a new SQLite database connection with every schema in the set migrated.
+    ///
+    /// See [`MonarchDB::create_connection`] for how the configuration is interpreted. Once every
+    /// schema is migrated, the [`with_after_open`](MonarchDB::with_after_open) hooks of each
+    /// member run, in migration order.
+    pub fn create_connection(&self, configuration: &ConnectionConfiguration) -> Result<Connection> {
+        let mut connection = open_connection(configuration, &self.retry_policy, self.busy_handler)?;
+        self.migrate(&mut connection)?;
+        for monarch in &self.members {
+            monarch.after_open(&connection)?;
+        }
+        Ok(connection)
+    }
+
+    /// Applies pending migrations for every schema in the set within one transaction.
+    ///
+    /// Foreign key enforcement is set on the connection as the set decides, see
+    /// [`with_foreign_keys`](Self::with_foreign_keys). Returns one report per member, in
+    /// migration order. If any migration fails, the transaction is rolled back and no schema is
+    /// changed.
+    #[tracing::instrument(level = "trace", skip_all, fields(members = self.members.len()))]
+    pub fn migrate(&self, connection: &mut Connection) -> Result<Vec<MigrationReport>> {
+        let foreign_keys = self.foreign_keys()?;
+        for monarch in &self.members {
+            monarch.setup_connection(connection)?;
+        }
+        tracing::trace!(foreign_keys, "Set foreign keys");
+        self.retry_policy
+            .retry(|| connection.pragma_update(None, "foreign_keys", foreign_keys))?;
+
+        let connection = &*connection;
+        let tx = self
+            .retry_policy
+            .retry(|| begin_immediate(connection, self.lock_timeout))?;
+        let mut reports = Vec::with_capacity(self.members.len());
+        for monarch in &self.members {
+            let pragmas = scoped_pragmas(&tx)?;
+            reports.push(monarch.apply_pending(&tx)?);
+            restore_pragmas(&tx, &monarch.name, &pragmas)?;
+        }
+        tx.commit()?;
+        tracing::debug!("Migrations complete");
+        Ok(reports)
+    }
+}
+
+/// The values of the [`SCOPED_PRAGMAS`] on `connection`.
+fn scoped_pragmas(connection: &Connection) -> rusqlite::Result<Vec<i64>> {
+    SCOPED_PRAGMAS
+        .iter()
+        .map(|pragma| connection.pragma_query_value(None, pragma, |row| row.get(0)))
+        .collect()
+}
+
+/// Restores the [`SCOPED_PRAGMAS`] to `values` after the migrations of the schema `name`.
+fn restore_pragmas(connection: &Connection, name: &str, values: &[i64]) -> rusqlite::Result<()> {
+    for (pragma, &value) in SCOPED_PRAGMAS.iter().zip(values) {
+        let current: i64 = connection.pragma_query_value(None, pragma, |row| row.get(0))?;
+        if current != value {
+            tracing::debug!(
+                schema = name,
+                pragma,
+                value,
+                "Restore pragma changed by migrations"
+            );
+            connection.pragma_update(None, pragma, value)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    fn component(name: &'static str, migrations: [&'static str; 2]) -> MonarchDB {
+        StaticMonarchConfiguration {
+            name,
+            enable_foreign_keys: false,
+            migrations,
+        }
+        .into()
+    }
+
+    fn version(connection: &Connection, name: &str) -> rusqlite::Result<Option<u32>> {
+        let mut stmt = connection
+            .prepare("SELECT version FROM monarch_db_schema_version WHERE monarch_schema = ?1")?;
+        let mut rows = stmt.query([name])?;
+        rows.next()?.map(|row| row.get(0)).transpose()
+    }
+
+    #[test]
+    fn test_set_migrates_all_members() -> Result<()> {
+        let set = MonarchSet::new()
+            .with(component(
+                "core",
+                [
+                    "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                    "ALTER TABLE users ADD COLUMN name TEXT;",
+                ],
+            ))
+            .with(component(
+                "billing",
+                [
+                    "CREATE TABLE invoices (id INTEGER PRIMARY KEY, user_id INTEGER);",
+                    "CREATE VIEW user_invoices AS SELECT * FROM users JOIN invoices ON users.id = invoices.user_id;",
+                ],
+            ));
+
+        let mut connection = Connection::open_in_memory()?;
+        let reports = set.migrate(&mut connection)?;
+        let summary: Vec<_> = reports
+            .iter()
+            .map(|r| (r.name.as_str(), r.from_version, r.to_version))
+            .collect();
+        assert_eq!(summary, [("core", 0, 2), ("billing", 0, 2)]);
+
+        assert_eq!(version(&connection, "core")?, Some(2));
+        assert_eq!(version(&connection, "billing")?, Some(2));
+
+        // Migrating again is a no-op.
+        let reports = set.migrate(&mut connection)?;
+        assert!(reports.iter().all(|r| r.applied() == 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_rolls_back_all_members_on_failure() -> Result<()> {
+        let set = MonarchSet::new()
+            .with(component(
+                "good",
+                [
+                    "CREATE TABLE a (id INTEGER PRIMARY KEY);",
+                    "CREATE TABLE b (id INTEGER PRIMARY KEY);",
+                ],
+            ))
+            .with(component(
+                "bad",
+                [
+                    "CREATE TABLE c (id INTEGER PRIMARY KEY);",
+                    "CREATE TABLE c (id INTEGER PRIMARY KEY);",
+                ],
+            ));
+
+        let mut connection = Connection::open_in_memory()?;
+        assert!(set.migrate(&mut connection).is_err());
+
+        let tables: i64 = connection.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(tables, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_scopes_connection_settings() -> Result<()> {
+        let strict = MonarchDB::from(StaticMonarchConfiguration {
+            name: "strict",
+            enable_foreign_keys: true,
+            migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+        });
+        let loose = || {
+            component(
+                "loose",
+                [
+                    "PRAGMA ignore_check_constraints = ON;",
+                    "CREATE TABLE tags (name TEXT CHECK (name <> ''));",
+                ],
+            )
+        };
+
+        let mut connection = Connection::open_in_memory()?;
+        let error = MonarchSet::new()
+            .with(strict)
+            .with(loose())
+            .migrate(&mut connection)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "enable_foreign_keys is enabled by strict but not by loose; decide it for the set"
+        );
+
+        let set = MonarchSet::new()
+            .with(loose())
+            .with(component(
+                "checked",
+                [
+                    "CREATE TABLE notes (body TEXT CHECK (body <> ''));",
+                    "SELECT 1;",
+                ],
+            ))
+            .with_foreign_keys(true);
+        set.migrate(&mut connection)?;
+        let foreign_keys: bool =
+            connection.pragma_query_value(None, "foreign_keys", |row| row.get(0))?;
+        assert!(foreign_keys);
+        let ignored: bool =
+            connection.pragma_query_value(None, "ignore_check_constraints", |row| row.get(0))?;
+        assert!(!ignored);
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "already contains a schema")]
+    fn test_set_rejects_duplicate_names() {
+        let _ = MonarchSet::new()
+            .with(component("dup", ["SELECT 1;", "SELECT 2;"]))
+            .with(component("dup", ["SELECT 1;", "SELECT 2;"]));
+    }
+}
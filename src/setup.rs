@@ -0,0 +1,233 @@
+//! Preparing connections before migrations run.
+//!
+//! Migrations may depend on things which only exist on a connection, not in the database file:
+//! an index on an expression calling an application-defined function, or a column using a custom
+//! collation. [`MonarchDB::with_connection_setup`] registers a hook which runs on every
+//! connection before its migrations do, so those can be registered first.
+//!
+//! Other settings only make sense once the schema is in place, such as an update hook watching
+//! the application's tables or an authorizer which must not get in the way of migrations.
+//! [`MonarchDB::with_after_open`] registers a hook for those, which runs after the migrations.
+
+use std::fmt;
+use std::sync::Arc;
+
+use rusqlite::Connection;
+
+use crate::{Migrations, MonarchDB, Result};
+
+type SetupFn = dyn Fn(&Connection) -> rusqlite::Result<()> + Send + Sync;
+
+/// A hook registered with [`MonarchDB::with_connection_setup`] or
+/// [`MonarchDB::with_after_open`].
+#[derive(Clone)]
+pub(crate) struct ConnectionSetup(Arc<SetupFn>);
+
+impl fmt::Debug for ConnectionSetup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ConnectionSetup")
+    }
+}
+
+impl MonarchDB {
+    /// Registers a hook which runs on each connection before its migrations, e.g. to register
+    /// the SQL functions and collations the migrations use.
+    ///
+    /// Hooks run in the order they were registered, before foreign keys are enabled, whenever
+    /// this `MonarchDB` migrates a connection. They also run on the scratch databases used to
+    /// inspect the migrations, such as by [`schema_metadata`](Self::schema_metadata). An error
+    /// from a hook fails migrating.
+    ///
+    /// Registering functions needs rusqlite's `functions` feature, and collations its
+    /// `collation` feature.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    /// use rusqlite::functions::FunctionFlags;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT);
+    ///          CREATE INDEX users_domain ON users (email_domain(email));",
+    ///     ],
+    /// })
+    /// .with_connection_setup(|connection| {
+    ///     connection.create_scalar_function(
+    ///         "email_domain",
+    ///         1,
+    ///         FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+    ///         |ctx| {
+    ///             let email: String = ctx.get(0)?;
+    ///             Ok(email.split_once('@').map(|(_, domain)| domain.to_owned()))
+    ///         },
+    ///     )
+    /// });
+    ///
+    /// let connection = monarch_db.open_in_memory()?;
+    /// connection.execute("INSERT INTO users (email) VALUES ('alice@example.com')", [])?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_connection_setup<F>(mut self, setup: F) -> Self
+    where
+        F: Fn(&Connection) -> rusqlite::Result<()> + Send + Sync + 'static,
+    {
+        self.connection_setup.push(ConnectionSetup(Arc::new(setup)));
+        self
+    }
+
+    /// Runs the hooks registered with [`with_connection_setup`](Self::with_connection_setup).
+    pub(crate) fn setup_connection(&self, connection: &Connection) -> Result<()> {
+        for ConnectionSetup(setup) in &self.connection_setup {
+            setup(connection)?;
+        }
+        Ok(())
+    }
+
+    /// Registers a hook which runs on each connection once its migrations are complete, e.g. to
+    /// install an update hook or an authorizer, or to change a setting the migrations depend on.
+    ///
+    /// Hooks run in the order they were registered, on the connections returned by
+    /// [`create_connection`](Self::create_connection),
+    /// [`create_connection_within`](Self::create_connection_within),
+    /// [`open_verified`](Self::open_verified) and [`migrate`](Self::migrate), and on those
+    /// migrated by [`apply`](Self::apply), but not on the connections migrated to a version by
+    /// [`migrate_to`](Self::migrate_to) or on scratch databases. An error from a hook fails
+    /// opening the connection, although the migrations have already committed.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+    /// })
+    /// // A read-only handle, which the migrations could not have run on.
+    /// .with_after_open(|connection| connection.pragma_update(None, "query_only", true));
+    ///
+    /// let connection = monarch_db.open_in_memory()?;
+    /// assert!(connection.execute("INSERT INTO users DEFAULT VALUES", []).is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_after_open<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Connection) -> rusqlite::Result<()> + Send + Sync + 'static,
+    {
+        self.after_open.push(ConnectionSetup(Arc::new(hook)));
+        self
+    }
+
+    /// Runs the hooks registered with [`with_after_open`](Self::with_after_open).
+    pub(crate) fn after_open(&self, connection: &Connection) -> Result<()> {
+        for ConnectionSetup(hook) in &self.after_open {
+            hook(connection)?;
+        }
+        Ok(())
+    }
+
+    /// An empty in-memory database configured like the databases this `MonarchDB` migrates.
+    pub(crate) fn scratch(&self) -> Result<Connection> {
+        let connection = Connection::open_in_memory()?;
+        self.setup_connection(&connection)?;
+        if self.enable_foreign_keys {
+            connection.pragma_update(None, "foreign_keys", true)?;
+        }
+        Ok(connection)
+    }
+
+    /// A [`scratch`](Self::scratch) database with the migrations applied, leaving the schema
+    /// file alone, see [`with_schema_file`](Self::with_schema_file).
+    pub(crate) fn migrate_scratch(&self) -> Result<Connection> {
+        let mut connection = self.scratch()?;
+        Migrations {
+            connection: &mut connection,
+            monarch: self,
+            refresh_schema_file: false,
+        }
+        .prepare()?;
+        Ok(connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rusqlite::functions::FunctionFlags;
+
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_setup_runs_before_migrations() -> Result<()> {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "setup",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE t (name TEXT); CREATE INDEX t_reversed ON t (reversed(name));",
+            ],
+        })
+        .with_connection_setup(move |connection| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            connection.create_scalar_function(
+                "reversed",
+                1,
+                FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+                |ctx| Ok(ctx.get::<String>(0)?.chars().rev().collect::<String>()),
+            )
+        });
+
+        let connection = monarch_db.open_in_memory()?;
+        connection.execute_batch("INSERT INTO t VALUES ('ab');")?;
+        let reversed: String =
+            connection.query_row("SELECT reversed(name) FROM t", [], |row| row.get(0))?;
+        assert_eq!(reversed, "ba");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Inspecting the migrations replays them on a scratch database, which is set up too.
+        #[cfg(feature = "metadata")]
+        {
+            assert_eq!(monarch_db.schema_metadata()?.len(), 1);
+            assert_eq!(calls.load(Ordering::SeqCst), 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_after_open_runs_after_migrations() -> Result<()> {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "after_open",
+            enable_foreign_keys: false,
+            migrations: ["CREATE TABLE t (name TEXT);"],
+        })
+        .with_after_open(move |connection| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            connection.execute_batch(
+                "CREATE TEMP TRIGGER t_upper AFTER INSERT ON main.t
+                 BEGIN UPDATE t SET name = upper(name) WHERE rowid = new.rowid; END;",
+            )
+        });
+
+        let connection = monarch_db.open_in_memory()?;
+        connection.execute_batch("INSERT INTO t VALUES ('ab');")?;
+        let name: String = connection.query_row("SELECT name FROM t", [], |row| row.get(0))?;
+        assert_eq!(name, "AB");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let mut connection = Connection::open_in_memory()?;
+        monarch_db.migrate_to(&mut connection, 1)?;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+}
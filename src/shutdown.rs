@@ -0,0 +1,278 @@
+//! Stopping migrations cleanly when the application shuts down.
+//!
+//! An application registers a [`ShutdownGuard`] with [`MonarchDB::with_shutdown_guard`] and
+//! calls [`ShutdownGuard::shutdown`] from its signal handler. Monarch checks the guard before
+//! each migration: a migration which is already running completes, and then either the
+//! migrations completed so far are kept ([`TransactionMode::PerMigration`] and
+//! [`TransactionMode::Savepoint`]) or the whole transaction is rolled back
+//! ([`TransactionMode::Single`]). Migrating then fails with [`Error::Shutdown`], which records
+//! the version the database was left at.
+//!
+//! An application which cannot wait for a long migration to finish calls
+//! [`ShutdownGuard::cancel`] instead, which also interrupts the running migration through its
+//! connection's [`InterruptHandle`]. The interrupted migration is rolled back like a failed one,
+//! and migrating fails with [`Error::Cancelled`].
+//!
+//! [`TransactionMode::PerMigration`]: crate::TransactionMode::PerMigration
+//! [`TransactionMode::Savepoint`]: crate::TransactionMode::Savepoint
+//! [`TransactionMode::Single`]: crate::TransactionMode::Single
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::{fmt, mem};
+
+use rusqlite::{Connection, InterruptHandle};
+
+use crate::{Error, MonarchDB, Result, plan};
+
+/// A flag which an application raises to stop migrations at the next safe point.
+///
+/// Clones share the same flag, so one clone can be moved into a signal handler while another is
+/// registered with [`MonarchDB::with_shutdown_guard`].
+///
+/// ```rust
+/// use monarch_db::{MonarchDB, ShutdownGuard, StaticMonarchConfiguration, TransactionMode};
+///
+/// let guard = ShutdownGuard::new();
+/// let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+///     name: "my_app",
+///     enable_foreign_keys: true,
+///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+/// })
+/// .with_transaction_mode(TransactionMode::PerMigration)
+/// .with_shutdown_guard(guard.clone());
+///
+/// // In the application's signal handler:
+/// guard.shutdown();
+///
+/// let error = monarch_db.open_in_memory().unwrap_err();
+/// assert!(error.to_string().contains("shutdown"));
+/// ```
+#[derive(Clone, Default)]
+pub struct ShutdownGuard(Arc<GuardState>);
+
+#[derive(Default)]
+struct GuardState {
+    shutdown: AtomicBool,
+    cancelled: AtomicBool,
+    next_connection: AtomicU64,
+    /// The connections migrating under the guard, to interrupt when it is cancelled.
+    migrating: Mutex<BTreeMap<u64, InterruptHandle>>,
+}
+
+impl fmt::Debug for ShutdownGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShutdownGuard")
+            .field("shutdown", &self.is_shutdown())
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl ShutdownGuard {
+    /// Creates a guard which has not been shut down.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks migrations to stop before the next migration starts.
+    pub fn shutdown(&self) {
+        self.0.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    /// Stops migrations now, interrupting any migration which is running.
+    ///
+    /// The interrupted migration is rolled back, along with the rest of its transaction, and
+    /// migrating fails with [`Error::Cancelled`]. This also counts as a
+    /// [`shutdown`](Self::shutdown), so no further migration starts.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.shutdown();
+        for handle in self.migrating().values() {
+            handle.interrupt();
+        }
+    }
+
+    /// Whether [`shutdown`](Self::shutdown) or [`cancel`](Self::cancel) has been called on this
+    /// guard or a clone of it.
+    pub fn is_shutdown(&self) -> bool {
+        self.0.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Whether [`cancel`](Self::cancel) has been called on this guard or a clone of it.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn migrating(&self) -> MutexGuard<'_, BTreeMap<u64, InterruptHandle>> {
+        // The map is only ever inserted into or removed from, so it is usable after a panic.
+        self.0
+            .migrating
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Keeps a connection interruptible by [`ShutdownGuard::cancel`] until it is dropped.
+pub(crate) struct Interruptible {
+    guard: Option<(ShutdownGuard, u64)>,
+}
+
+impl Drop for Interruptible {
+    fn drop(&mut self) {
+        if let Some((guard, id)) = mem::take(&mut self.guard) {
+            guard.migrating().remove(&id);
+        }
+    }
+}
+
+impl MonarchDB {
+    /// Registers a guard which stops migrating when it is shut down, see [`ShutdownGuard`].
+    pub fn with_shutdown_guard(mut self, guard: ShutdownGuard) -> Self {
+        self.shutdown = Some(guard);
+        self
+    }
+
+    /// Fails with [`Error::Shutdown`], or [`Error::Cancelled`], if shutdown was requested, for
+    /// a database left at `version`.
+    pub(crate) fn check_shutdown(&self, version: u32) -> Result<()> {
+        let Some(guard) = self.shutdown.as_ref().filter(|guard| guard.is_shutdown()) else {
+            return Ok(());
+        };
+        tracing::debug!(version, "Stopping migrations for shutdown");
+        let error = if guard.is_cancelled() {
+            Error::Cancelled { version }
+        } else {
+            Error::Shutdown { version }
+        };
+        Err(error)
+    }
+
+    /// Lets [`ShutdownGuard::cancel`] interrupt migrations on `connection` until the returned
+    /// registration is dropped.
+    pub(crate) fn interruptible(&self, connection: &Connection) -> Interruptible {
+        let guard = self.shutdown.as_ref().map(|guard| {
+            let id = guard.0.next_connection.fetch_add(1, Ordering::Relaxed);
+            guard
+                .migrating()
+                .insert(id, connection.get_interrupt_handle());
+            (guard.clone(), id)
+        });
+        Interruptible { guard }
+    }
+
+    /// Replaces the error of a migration which failed because it was cancelled with
+    /// [`Error::Cancelled`], recording the version `connection` was left at.
+    pub(crate) fn check_cancelled(&self, connection: &Connection, error: Error) -> Error {
+        if !self
+            .shutdown
+            .as_ref()
+            .is_some_and(ShutdownGuard::is_cancelled)
+        {
+            return error;
+        }
+        if let Error::Shutdown { .. } | Error::Cancelled { .. } = error {
+            return error;
+        }
+        match plan::recorded_version(connection, &self.name) {
+            Ok(version) => {
+                tracing::debug!(version, %error, "Migration cancelled");
+                Error::Cancelled { version }
+            }
+            Err(_) => error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::{StaticMonarchConfiguration, TransactionMode};
+
+    use super::*;
+
+    fn shutdown_version(error: Error) -> Option<u32> {
+        match error {
+            Error::Shutdown { version } => Some(version),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_shutdown_reports_version_reached() -> Result<()> {
+        let guard = ShutdownGuard::new();
+        let monarch = |mode| {
+            MonarchDB::from(StaticMonarchConfiguration {
+                name: "shutdown",
+                enable_foreign_keys: false,
+                migrations: ["CREATE TABLE a (id);", "CREATE TABLE b (id);"],
+            })
+            .with_transaction_mode(mode)
+            .with_shutdown_guard(guard.clone())
+        };
+
+        let mut connection = Connection::open_in_memory()?;
+        monarch(TransactionMode::PerMigration).migrate_to(&mut connection, 1)?;
+        guard.shutdown();
+        for mode in [
+            TransactionMode::Single,
+            TransactionMode::PerMigration,
+            TransactionMode::Savepoint,
+        ] {
+            let error = monarch(mode)
+                .migrations(&mut connection)
+                .prepare()
+                .unwrap_err();
+            assert_eq!(shutdown_version(error), Some(1));
+        }
+
+        // A database with nothing left to do migrates as usual.
+        let report = monarch(TransactionMode::Single).migrate_to(&mut connection, 1)?;
+        assert_eq!(report.to_version, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel_interrupts_running_migration() -> Result<()> {
+        let endless = "CREATE TABLE endless AS
+                       WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n)
+                       SELECT count(*) AS total FROM n;";
+        // A cancelled best-effort migration fails too, rather than being skipped for good.
+        let best_effort = format!("-- monarch:best-effort\n{endless}");
+        for endless in [endless.to_owned(), best_effort] {
+            let guard = ShutdownGuard::new();
+            let monarch_db = MonarchDB::from_iter(
+                "cancel",
+                false,
+                [
+                    ("001_a.sql".to_owned(), "CREATE TABLE a (id);".to_owned()),
+                    ("002_endless.sql".to_owned(), endless),
+                ],
+            )
+            .expect("valid migrations")
+            .with_transaction_mode(TransactionMode::PerMigration)
+            .with_shutdown_guard(guard.clone());
+
+            let migrating = std::thread::spawn(move || {
+                let mut connection = Connection::open_in_memory()?;
+                let result = monarch_db.migrations(&mut connection).prepare();
+                let tables: i64 = connection.query_row(
+                    "SELECT count(*) FROM sqlite_master WHERE name IN ('a', 'endless')",
+                    [],
+                    |row| row.get(0),
+                )?;
+                let history = monarch_db.history(&connection)?.len();
+                Ok::<_, Error>((result, tables, history))
+            });
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            guard.cancel();
+            let (result, tables, history) = migrating.join().expect("migration thread panicked")?;
+
+            assert!(matches!(result, Err(Error::Cancelled { version: 1 })));
+            assert_eq!((tables, history), (1, 1));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,408 @@
+//! Where migrations are loaded from.
+//!
+//! [`MigrationSource`] is implemented by the directory and static configurations, and can be
+//! implemented by applications whose migrations live somewhere else, such as generated code,
+//! another database, or a server. [`MonarchDB::from_source`] builds a `MonarchDB` from any of
+//! them, treating their migrations the same way.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::assertion::VERIFY_SUFFIX;
+use crate::compression::{COMPRESSED_SUFFIX, CompressedSql};
+use crate::migration::{self, Migration};
+use crate::{
+    Error, MonarchConfiguration, MonarchDB, Result, StaticMonarchConfiguration, VersionScheme,
+    dependency, numeric_prefix, repeatable, template, versioning,
+};
+
+/// A source of migrations, see [`MonarchDB::from_source`].
+///
+/// ```rust
+/// use std::borrow::Cow;
+///
+/// use monarch_db::{MigrationSource, MonarchDB, SourceMigration};
+///
+/// /// Migrations generated by the application.
+/// struct Generated {
+///     tables: Vec<&'static str>,
+/// }
+///
+/// impl MigrationSource for Generated {
+///     fn name(&self) -> Cow<'static, str> {
+///         "generated".into()
+///     }
+///
+///     fn migrations(&self) -> monarch_db::Result<Vec<SourceMigration>> {
+///         Ok(self
+///             .tables
+///             .iter()
+///             .map(|table| {
+///                 let sql = format!("CREATE TABLE {table} (id INTEGER PRIMARY KEY);");
+///                 SourceMigration::new(format!("create_{table}"), sql)
+///             })
+///             .collect())
+///     }
+/// }
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let monarch_db = MonarchDB::from_source(Generated {
+///     tables: vec!["users", "posts"],
+/// })?;
+/// assert_eq!(monarch_db.current_version(), 2);
+/// # Ok(())
+/// # }
+/// ```
+pub trait MigrationSource {
+    /// The name of the database schema, used for tracking migration versions.
+    fn name(&self) -> Cow<'static, str>;
+
+    /// Whether to enable foreign key constraints in SQLite. Defaults to `true`.
+    fn enable_foreign_keys(&self) -> bool {
+        true
+    }
+
+    /// Loads the migrations, in the order they run.
+    ///
+    /// Migrations whose names start with `R__` are repeatable migrations, see
+    /// [`MonarchDB::with_repeatable_migration`], and run after the others in order of their
+    /// names.
+    fn migrations(&self) -> Result<Vec<SourceMigration>>;
+
+    /// How the versioned migrations are numbered. Defaults to [`VersionScheme::Sequential`],
+    /// which runs them in the order [`migrations`](Self::migrations) returns them.
+    fn version_scheme(&self) -> VersionScheme {
+        VersionScheme::Sequential
+    }
+}
+
+/// A migration loaded by a [`MigrationSource`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMigration {
+    /// The name of the migration, e.g. its file name.
+    pub name: Cow<'static, str>,
+    /// The SQL of the migration, with any directives. Empty for a migration created with
+    /// [`compressed`](Self::compressed).
+    pub sql: Cow<'static, str>,
+    /// The directory relative import paths are resolved against, if any.
+    pub directory: Option<Utf8PathBuf>,
+    /// Assertion queries checked after the migration runs, like a companion `.verify.sql` file
+    /// of a migration directory.
+    pub verify: Option<Cow<'static, str>>,
+    /// Environments the migration is restricted to, in addition to any its directives or file
+    /// name select, see [`MonarchDB::with_environment`].
+    pub environments: Vec<String>,
+    /// The SQL of a migration created with [`compressed`](Self::compressed).
+    pub(crate) compressed: Option<CompressedSql>,
+}
+
+impl SourceMigration {
+    /// Creates a migration whose import paths are used as given.
+    pub fn new(name: impl Into<Cow<'static, str>>, sql: impl Into<Cow<'static, str>>) -> Self {
+        SourceMigration {
+            name: name.into(),
+            sql: sql.into(),
+            directory: None,
+            verify: None,
+            environments: Vec::new(),
+            compressed: None,
+        }
+    }
+
+    /// Resolves the migration's relative import paths against `directory`.
+    pub fn in_directory(mut self, directory: impl Into<Utf8PathBuf>) -> Self {
+        self.directory = Some(directory.into());
+        self
+    }
+
+    /// Sets queries which must hold after the migration runs, or the migration is rolled back.
+    /// Each statement must return a true value, and the SQL may start with
+    /// `-- monarch:assert-unchanged` directives, like a `.verify.sql` file.
+    pub fn with_verify_sql(mut self, sql: impl Into<Cow<'static, str>>) -> Self {
+        self.verify = Some(sql.into());
+        self
+    }
+
+    /// Restricts the migration to `environment`, like a `-- monarch:env` directive.
+    pub fn for_environment(mut self, environment: impl Into<String>) -> Self {
+        self.environments.push(environment.into());
+        self
+    }
+}
+
+impl MonarchDB {
+    /// Creates a `MonarchDB` from the migrations of `source`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The source fails to load its migrations
+    /// - Two migrations have the same name ([`Error::DuplicateMigration`])
+    /// - A migration other than the first is a baseline, see [`squash`](Self::squash)
+    /// - A migration depends on one which does not exist ([`Error::MissingDependency`]), or
+    ///   migrations depend on each other ([`Error::DependencyCycle`])
+    /// - With [`VersionScheme::Timestamp`], a migration has no timestamp
+    ///   ([`Error::MissingTimestamp`]) or shares it with another ([`Error::DuplicateVersion`])
+    pub fn from_source(source: impl MigrationSource) -> Result<Self> {
+        let mut migrations = source.migrations()?;
+        let scheme = source.version_scheme();
+        if scheme == VersionScheme::Timestamp {
+            versioning::order_by_timestamp(&mut migrations)?;
+        }
+        let mut monarch_db =
+            MonarchDB::from_migrations(source.name(), source.enable_foreign_keys(), migrations)?;
+        monarch_db.version_scheme = scheme;
+        Ok(monarch_db)
+    }
+
+    /// Creates a `MonarchDB` from loaded migrations, separating out the repeatable migrations.
+    pub(crate) fn from_migrations(
+        name: Cow<'static, str>,
+        enable_foreign_keys: bool,
+        migrations: impl IntoIterator<Item = SourceMigration>,
+    ) -> Result<Self> {
+        let mut names = BTreeSet::new();
+        let mut versioned = Vec::new();
+        let mut repeatables = BTreeMap::new();
+        for source in migrations {
+            let name = source.name.to_string();
+            if !names.insert(name.clone()) {
+                return Err(Error::DuplicateMigration { migration: name });
+            }
+            let mut migration = match source.compressed {
+                Some(compressed) => Migration::compressed(source.name, compressed)?,
+                None => Migration::new(source.name, source.sql),
+            };
+            if let Some(directory) = &source.directory {
+                migration = migration.relative_to(directory);
+            }
+            if let Some(verify) = &source.verify {
+                migration = migration.with_verification(verify);
+            }
+            migration.environments.extend(source.environments);
+            if name.starts_with(repeatable::REPEATABLE_PREFIX) {
+                repeatables.insert(name, migration);
+            } else {
+                versioned.push(migration);
+            }
+        }
+
+        let versioned = dependency::order_by_dependencies(versioned)?;
+        if let Some(misplaced) = versioned.iter().skip(1).find(|m| m.baseline.is_some()) {
+            return Err(Error::MisplacedBaseline {
+                migration: misplaced.name.to_string(),
+            });
+        }
+        Ok(MonarchDB::new(
+            name,
+            enable_foreign_keys,
+            versioned,
+            repeatables.into_values().collect(),
+        ))
+    }
+}
+
+/// Loads migrations from the configured directories, see
+/// [`MonarchDB::from_configuration`].
+impl MigrationSource for MonarchConfiguration {
+    fn name(&self) -> Cow<'static, str> {
+        self.name.clone().into()
+    }
+
+    fn enable_foreign_keys(&self) -> bool {
+        self.enable_foreign_keys
+    }
+
+    fn version_scheme(&self) -> VersionScheme {
+        self.version_scheme
+    }
+
+    fn migrations(&self) -> Result<Vec<SourceMigration>> {
+        let mut variables = self.variables.clone();
+        variables
+            .entry("app_name".to_owned())
+            .or_insert_with(|| self.name.clone());
+
+        let merged = !self.additional_migration_directories.is_empty()
+            || !self.overlay_directories.is_empty();
+        let directories = std::iter::once(&self.migration_directory)
+            .chain(&self.additional_migration_directories)
+            .map(|directory| (directory, None))
+            .chain(
+                self.overlay_directories
+                    .iter()
+                    .map(|(environment, directory)| (directory, Some(environment))),
+            );
+        let mut migrations = BTreeMap::new();
+        let mut repeatables = Vec::new();
+        for (root, environment) in directories {
+            // Directories still to read, with their paths relative to the root.
+            let mut pending = vec![(root.clone(), String::new())];
+            while let Some((directory, relative)) = pending.pop() {
+                let entries = directory
+                    .read_dir_utf8()
+                    .map_err(|source| Error::ReadDirectory {
+                        path: directory.clone(),
+                        source,
+                    })?;
+                for diritem in entries {
+                    let entry = diritem.map_err(|source| Error::ReadEntry {
+                        directory: directory.clone(),
+                        source,
+                    })?;
+                    let file_type = entry.file_type().map_err(|source| Error::ReadMigration {
+                        path: entry.path().to_owned(),
+                        source,
+                    })?;
+
+                    if file_type.is_dir() {
+                        if self.recursive && !entry.file_name().starts_with('.') {
+                            let nested = format!("{relative}{}/", entry.file_name());
+                            pending.push((entry.path().to_owned(), nested));
+                        }
+                        continue;
+                    }
+                    if !file_type.is_file() {
+                        continue;
+                    }
+
+                    // A compressed migration is named for the file it was compressed from.
+                    let file_name = entry.file_name();
+                    let file_name = file_name
+                        .strip_suffix(COMPRESSED_SUFFIX)
+                        .unwrap_or(file_name)
+                        .to_owned();
+                    if !self.is_migration(&file_name, entry.path())? {
+                        continue;
+                    }
+                    if file_name.ends_with(VERIFY_SUFFIX) {
+                        tracing::trace!(path = %entry.path(), "Skip assertions of a migration");
+                        continue;
+                    }
+                    // Repeatable migrations are named by their file names wherever they are,
+                    // versioned migrations by their paths relative to the root.
+                    let repeatable = file_name.starts_with(repeatable::REPEATABLE_PREFIX);
+                    let name = if repeatable {
+                        file_name.clone()
+                    } else {
+                        format!("{relative}{file_name}")
+                    };
+                    let render = |path: &Utf8Path| -> Result<String> {
+                        let query = migration::read_migration(path)?;
+                        template::render(&query, &variables).map_err(|variable| {
+                            Error::UndefinedVariable {
+                                migration: name.clone(),
+                                variable,
+                            }
+                        })
+                    };
+                    // Compressed migrations are rendered once they are decompressed.
+                    let mut migration = if entry.file_name().ends_with(COMPRESSED_SUFFIX) {
+                        let compressed =
+                            std::fs::read(entry.path()).map_err(|source| Error::ReadMigration {
+                                path: entry.path().to_owned(),
+                                source,
+                            })?;
+                        let compressed =
+                            CompressedSql::new(compressed.into()).with_variables(variables.clone());
+                        SourceMigration {
+                            compressed: Some(compressed),
+                            ..SourceMigration::new(name.clone(), "")
+                        }
+                    } else {
+                        SourceMigration::new(name.clone(), render(entry.path())?)
+                    }
+                    .in_directory(directory.clone());
+                    let stem = file_name.strip_suffix(".sql").unwrap_or(&file_name);
+                    let verify = entry
+                        .path()
+                        .with_file_name(format!("{stem}{VERIFY_SUFFIX}"));
+                    if verify.is_file() {
+                        migration = migration.with_verify_sql(render(&verify)?);
+                    }
+                    if let Some(environment) = environment {
+                        migration = migration.for_environment(environment.clone());
+                    }
+                    if repeatable {
+                        repeatables.push(migration);
+                    } else {
+                        let numbered = merged || self.recursive;
+                        let key: Vec<_> = name
+                            .split('/')
+                            .map(|part| (numbered.then(|| numeric_prefix(part)), part.to_owned()))
+                            .collect();
+                        if let Some(duplicate) = migrations.insert(key, migration) {
+                            return Err(Error::DuplicateMigration {
+                                migration: duplicate.name.into_owned(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(migrations.into_values().chain(repeatables).collect())
+    }
+}
+
+/// Uses the embedded migrations, named by their versions.
+impl<const N: usize> MigrationSource for StaticMonarchConfiguration<N> {
+    fn name(&self) -> Cow<'static, str> {
+        self.name.into()
+    }
+
+    fn enable_foreign_keys(&self) -> bool {
+        self.enable_foreign_keys
+    }
+
+    fn migrations(&self) -> Result<Vec<SourceMigration>> {
+        Ok(self
+            .migrations
+            .iter()
+            .enumerate()
+            .map(|(index, query)| SourceMigration::new((index + 1).to_string(), *query))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_static_source() -> Result<()> {
+        let configuration = StaticMonarchConfiguration {
+            name: "static_source",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                "ALTER TABLE users ADD COLUMN name TEXT;",
+            ],
+        };
+        let monarch_db = MonarchDB::from_source(configuration.clone())?;
+        assert_eq!(monarch_db.name, "static_source");
+        assert!(!monarch_db.enable_foreign_keys);
+        let names: Vec<&str> = monarch_db.migrations.iter().map(|m| &*m.name).collect();
+        assert_eq!(names, ["1", "2"]);
+        monarch_db.open_in_memory()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_source_migration_directory() -> Result<()> {
+        let monarch_db = MonarchDB::from_migrations(
+            "imports".into(),
+            true,
+            [
+                SourceMigration::new("001_users.sql", "CREATE TABLE users (id);"),
+                SourceMigration::new("002_seed.sql", "-- monarch:import users users.csv\n")
+                    .in_directory("seeds"),
+            ],
+        )?;
+        assert!(monarch_db.migrations[0].imports.is_empty());
+        assert_eq!(monarch_db.migrations[1].imports[0].path, "seeds/users.csv");
+        Ok(())
+    }
+}
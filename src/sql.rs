@@ -0,0 +1,244 @@
+//! A small SQL tokenizer, enough to split migrations into statements and inspect them.
+//!
+//! This is not a parser: it only knows SQLite's lexical rules (comments, string literals and
+//! quoted identifiers) and that semicolons inside a `CREATE TRIGGER` body do not end the
+//! statement.
+
+/// The kind of a [`Token`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+    /// A keyword or bare identifier.
+    Word,
+    /// A single-quoted string literal, including its quotes.
+    String,
+    /// An identifier quoted with `"`, `` ` `` or `[]`, including its quotes.
+    QuotedIdentifier,
+    /// A numeric literal.
+    Number,
+    /// Any other character, such as an operator, parenthesis or semicolon.
+    Punctuation,
+}
+
+/// A token in a SQL string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Token<'a> {
+    pub(crate) kind: TokenKind,
+    pub(crate) text: &'a str,
+    /// The byte offset of the token in the SQL.
+    pub(crate) offset: usize,
+    /// The 1-based line the token starts on.
+    pub(crate) line: usize,
+}
+
+impl Token<'_> {
+    /// Whether the token is the keyword (or bare identifier) `keyword`, ignoring case.
+    pub(crate) fn is_keyword(&self, keyword: &str) -> bool {
+        self.kind == TokenKind::Word && self.text.eq_ignore_ascii_case(keyword)
+    }
+
+    /// Whether the token is the punctuation character `c`.
+    pub(crate) fn is_punctuation(&self, c: char) -> bool {
+        self.kind == TokenKind::Punctuation && self.text.starts_with(c)
+    }
+}
+
+/// Splits `sql` into tokens, skipping whitespace and comments.
+///
+/// Unterminated strings, quoted identifiers and comments run to the end of the input.
+pub(crate) fn tokenize(sql: &str) -> Vec<Token<'_>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let start = offset;
+        let start_line = line;
+        let kind = match bytes[offset] {
+            b'\n' => {
+                line += 1;
+                offset += 1;
+                continue;
+            }
+            byte if byte.is_ascii_whitespace() => {
+                offset += 1;
+                continue;
+            }
+            b'-' if bytes.get(offset + 1) == Some(&b'-') => {
+                offset = sql[offset..]
+                    .find('\n')
+                    .map_or(bytes.len(), |end| offset + end);
+                continue;
+            }
+            b'/' if bytes.get(offset + 1) == Some(&b'*') => {
+                let end = sql[offset + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |end| offset + 2 + end + 2);
+                line += sql[offset..end].matches('\n').count();
+                offset = end;
+                continue;
+            }
+            b'\'' => {
+                offset = quoted_end(bytes, offset, b'\'');
+                TokenKind::String
+            }
+            quote @ (b'"' | b'`') => {
+                offset = quoted_end(bytes, offset, quote);
+                TokenKind::QuotedIdentifier
+            }
+            b'[' => {
+                offset = sql[offset..]
+                    .find(']')
+                    .map_or(bytes.len(), |end| offset + end + 1);
+                TokenKind::QuotedIdentifier
+            }
+            byte if byte.is_ascii_digit()
+                || (byte == b'.' && bytes.get(offset + 1).is_some_and(u8::is_ascii_digit)) =>
+            {
+                while offset < bytes.len()
+                    && (bytes[offset].is_ascii_alphanumeric() || bytes[offset] == b'.')
+                {
+                    offset += 1;
+                }
+                TokenKind::Number
+            }
+            byte if byte.is_ascii_alphabetic() || byte == b'_' || !byte.is_ascii() => {
+                while offset < bytes.len()
+                    && (bytes[offset].is_ascii_alphanumeric()
+                        || matches!(bytes[offset], b'_' | b'$')
+                        || !bytes[offset].is_ascii())
+                {
+                    offset += 1;
+                }
+                TokenKind::Word
+            }
+            _ => {
+                offset += 1;
+                TokenKind::Punctuation
+            }
+        };
+        line += sql[start..offset].matches('\n').count();
+        tokens.push(Token {
+            kind,
+            text: &sql[start..offset],
+            offset: start,
+            line: start_line,
+        });
+    }
+    tokens
+}
+
+/// Quotes `identifier`, such as a table or column name, for use in SQL.
+pub(crate) fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// The offset just past a quoted token starting at `start`, where a doubled quote is an escape.
+fn quoted_end(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut offset = start + 1;
+    while offset < bytes.len() {
+        if bytes[offset] == quote {
+            if bytes.get(offset + 1) == Some(&quote) {
+                offset += 2;
+                continue;
+            }
+            return offset + 1;
+        }
+        offset += 1;
+    }
+    bytes.len()
+}
+
+/// Splits `sql` into statements, each a non-empty list of tokens without its semicolon.
+pub(crate) fn statements(sql: &str) -> Vec<Vec<Token<'_>>> {
+    let mut statements = Vec::new();
+    let mut current: Vec<Token<'_>> = Vec::new();
+    // Nesting of BEGIN and CASE blocks within a trigger body, whose statements end in semicolons.
+    let mut depth = 0;
+
+    for token in tokenize(sql) {
+        if token.is_punctuation(';') && depth == 0 {
+            if !current.is_empty() {
+                statements.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+
+        if is_trigger(&current) {
+            if token.is_keyword("BEGIN") || (depth > 0 && token.is_keyword("CASE")) {
+                depth += 1;
+            } else if depth > 0 && token.is_keyword("END") {
+                depth -= 1;
+            }
+        }
+        current.push(token);
+    }
+    if !current.is_empty() {
+        statements.push(current);
+    }
+    statements
+}
+
+/// Whether a statement is a `CREATE TRIGGER`.
+fn is_trigger(statement: &[Token<'_>]) -> bool {
+    match statement {
+        [create, trigger, ..] if create.is_keyword("CREATE") && trigger.is_keyword("TRIGGER") => {
+            true
+        }
+        [create, temporary, trigger, ..] => {
+            create.is_keyword("CREATE")
+                && (temporary.is_keyword("TEMP") || temporary.is_keyword("TEMPORARY"))
+                && trigger.is_keyword("TRIGGER")
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts<'a>(tokens: &[Token<'a>]) -> Vec<&'a str> {
+        tokens.iter().map(|token| token.text).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let sql = "SELECT 'it''s', \"a\"\"b\", [c d], 1.5e3 -- comment\n/* multi\nline */ x;";
+        let tokens = tokenize(sql);
+        assert_eq!(
+            texts(&tokens),
+            [
+                "SELECT",
+                "'it''s'",
+                ",",
+                "\"a\"\"b\"",
+                ",",
+                "[c d]",
+                ",",
+                "1.5e3",
+                "x",
+                ";"
+            ]
+        );
+        assert_eq!(tokens[1].kind, TokenKind::String);
+        assert_eq!(tokens[3].kind, TokenKind::QuotedIdentifier);
+        assert_eq!(tokens[7].kind, TokenKind::Number);
+        assert_eq!(tokens[8].line, 3);
+    }
+
+    #[test]
+    fn test_statements_keep_trigger_bodies() {
+        let sql = "CREATE TABLE t (a); ;
+            CREATE TEMP TRIGGER t_insert AFTER INSERT ON t BEGIN
+                UPDATE t SET a = CASE WHEN a > 0 THEN a ELSE 0 END;
+                SELECT 1;
+            END;
+            INSERT INTO t VALUES (';')";
+        let statements = statements(sql);
+        assert_eq!(statements.len(), 3);
+        assert_eq!(statements[1].last().map(|t| t.text), Some("END"));
+        assert_eq!(texts(&statements[2])[..2], ["INSERT", "INTO"]);
+        assert_eq!(statements[2][0].line, 6);
+    }
+}
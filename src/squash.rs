@@ -0,0 +1,373 @@
+//! Squashing old migrations into a single baseline.
+//!
+//! A long-lived application accumulates hundreds of migrations which every new database replays
+//! in turn. [`MonarchDB::squash`] collapses the first migrations into one baseline script which
+//! creates the same schema and data directly. The baseline starts with a
+//! `-- monarch:baseline <version>` directive, so it takes the place of every version it replaces:
+//! new databases run it to jump straight to that version, and databases already past it carry on
+//! with the migrations which follow.
+
+use std::ops::RangeInclusive;
+
+use rusqlite::Connection;
+
+use crate::backend::{Rusqlite, block_on};
+use crate::schema::{normalize, schema_objects};
+use crate::sql::quote;
+use crate::{Error, MonarchDB, Result};
+
+impl MonarchDB {
+    /// Collapses the migrations for `versions` into a baseline script, returning its SQL.
+    ///
+    /// The migrations are applied to a scratch in-memory database, whose tables, rows, indexes,
+    /// views and triggers are then written out as a single script. Before it is returned, the
+    /// script is run on a second scratch database to check that it produces the same schema
+    /// (compared with [`schema_sql`](Self::schema_sql)) and the same data (compared with
+    /// [`content_digest`](Self::content_digest)).
+    ///
+    /// To use the baseline, save it as the first migration (e.g. `000_baseline.sql`) and remove
+    /// the migrations it replaces. Databases at version 0 run the baseline, databases at or past
+    /// the end of `versions` are unaffected, and databases in between fail to migrate with
+    /// [`Error::SquashedVersion`] until they have been upgraded with the original migrations.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);",
+    ///         "INSERT INTO users (name) VALUES ('admin');",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// let baseline = monarch_db.squash(1..=2)?;
+    /// assert!(baseline.starts_with("-- monarch:baseline 2\n"));
+    /// assert!(baseline.contains("INSERT INTO \"users\" (\"id\", \"name\") VALUES (1, 'admin');"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - `versions` does not start at 1, or ends past the last migration
+    /// - `versions` ends within a baseline which already exists
+    /// - A migration in `versions` is restricted to some environments
+    /// - A migration in `versions` contains a virtual table, whose contents cannot be copied
+    /// - A migration fails, or the baseline does not reproduce the migrated database
+    pub fn squash(&self, versions: RangeInclusive<u32>) -> Result<String> {
+        let (start, through) = versions.into_inner();
+        if start != 1 {
+            return Err(Error::Squash(format!(
+                "squashing must start at version 1, not {start}"
+            )));
+        }
+        if through == 0 || through > self.current_version() {
+            return Err(Error::Squash(format!(
+                "cannot squash through version {through}, migrations run from 1 to {}",
+                self.current_version()
+            )));
+        }
+        if let Some(baseline) = self.baseline().filter(|baseline| through <= *baseline) {
+            return Err(Error::Squash(format!(
+                "versions 1 to {baseline} are already squashed"
+            )));
+        }
+        if let Some((_, migration)) = self
+            .versions()
+            .take_while(|(version, _)| *version <= through)
+            .find(|(_, migration)| !migration.environments.is_empty())
+        {
+            return Err(Error::Squash(format!(
+                "migration {} only runs in some environments",
+                migration.name
+            )));
+        }
+
+        let scratch = self.scratch()?;
+        let tx = scratch.unchecked_transaction()?;
+        block_on(self.apply_versions(&mut Rusqlite(&tx), 0, through, &mut Vec::new()))?;
+        let baseline = self.baseline_sql(&tx, through)?;
+
+        let check = self.scratch()?;
+        let check_tx = check.unchecked_transaction()?;
+        check_tx
+            .execute_batch(&baseline)
+            .and_then(|()| check_tx.commit())
+            .map_err(|error| Error::Squash(format!("the baseline does not run: {error}")))?;
+        if MonarchDB::schema_sql(&check)? != MonarchDB::schema_sql(&tx)? {
+            return Err(Error::Squash(
+                "the baseline produces a different schema".to_owned(),
+            ));
+        }
+        if MonarchDB::content_digest(&check, &[])? != MonarchDB::content_digest(&tx, &[])? {
+            return Err(Error::Squash(
+                "the baseline produces different data".to_owned(),
+            ));
+        }
+        Ok(baseline)
+    }
+
+    /// Writes out the schema and data of `connection` as a baseline replacing versions 1 to
+    /// `through`.
+    fn baseline_sql(&self, connection: &Connection, through: u32) -> Result<String> {
+        let mut sql = format!(
+            "-- monarch:baseline {through}\n\
+             -- Replaces migrations 1 to {through} of {}.\n\n\
+             -- Rows are inserted in table order, so check foreign keys at the end.\n\
+             PRAGMA defer_foreign_keys = ON;\n\n",
+            self.name
+        );
+
+        let (tables, others): (Vec<_>, Vec<_>) = schema_objects(connection)?
+            .into_iter()
+            .partition(|object| object.kind == "table");
+        if let Some(table) = tables.iter().find(|table| {
+            table
+                .sql
+                .split_whitespace()
+                .nth(1)
+                .is_some_and(|word| word.eq_ignore_ascii_case("VIRTUAL"))
+        }) {
+            return Err(Error::Squash(format!(
+                "virtual table {} cannot be squashed",
+                table.name
+            )));
+        }
+
+        for table in &tables {
+            sql.push_str(&normalize(&table.sql));
+            sql.push('\n');
+        }
+        for table in &tables {
+            rows_sql(connection, &table.name, &mut sql)?;
+        }
+        sequences_sql(connection, &mut sql)?;
+        for object in &others {
+            sql.push_str(&normalize(&object.sql));
+            sql.push('\n');
+        }
+        Ok(sql)
+    }
+}
+
+/// Writes an `INSERT` for each row of `table`, ordered by all of its columns.
+fn rows_sql(connection: &Connection, table: &str, sql: &mut String) -> rusqlite::Result<()> {
+    let columns: Vec<String> = {
+        let mut stmt = connection.prepare("SELECT name FROM pragma_table_info(?1) ORDER BY cid")?;
+        stmt.query_map([table], |row| row.get::<_, String>(0))?
+            .map(|column| column.map(|column| quote(&column)))
+            .collect::<rusqlite::Result<_>>()?
+    };
+    let values: Vec<String> = columns
+        .iter()
+        .map(|column| format!("quote({column})"))
+        .collect();
+    let mut stmt = connection.prepare(&format!(
+        "SELECT {values} FROM {table} ORDER BY {columns}",
+        values = values.join(" || ', ' || "),
+        table = quote(table),
+        columns = columns.join(", "),
+    ))?;
+
+    let mut rows = stmt.query([])?;
+    let mut any = false;
+    while let Some(row) = rows.next()? {
+        let values: String = row.get(0)?;
+        sql.push_str(&format!(
+            "INSERT INTO {} ({}) VALUES ({values});\n",
+            quote(table),
+            columns.join(", ")
+        ));
+        any = true;
+    }
+    if any {
+        sql.push('\n');
+    }
+    Ok(())
+}
+
+/// Writes the `AUTOINCREMENT` counters, which may be ahead of the rows still in their tables.
+fn sequences_sql(connection: &Connection, sql: &mut String) -> rusqlite::Result<()> {
+    let exists: bool = connection.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'sqlite_sequence')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Ok(());
+    }
+
+    let mut stmt = connection.prepare(
+        "SELECT quote(name), seq FROM sqlite_sequence \
+         WHERE name NOT LIKE 'monarch\\_db\\_%' ESCAPE '\\' ORDER BY name",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut any = false;
+    while let Some(row) = rows.next()? {
+        let name: String = row.get(0)?;
+        let seq: i64 = row.get(1)?;
+        sql.push_str(&format!(
+            "DELETE FROM sqlite_sequence WHERE name = {name};\n\
+             INSERT INTO sqlite_sequence (name, seq) VALUES ({name}, {seq});\n"
+        ));
+        any = true;
+    }
+    if any {
+        sql.push('\n');
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+    use crate::migration::Migration;
+
+    use super::*;
+
+    /// `monarch_db` with its first `through` migrations replaced by `baseline`.
+    fn squashed(mut monarch_db: MonarchDB, through: usize, baseline: String) -> MonarchDB {
+        monarch_db
+            .migrations
+            .splice(..through, [Migration::new("000_baseline.sql", baseline)]);
+        monarch_db
+    }
+
+    #[test]
+    fn test_squash_reproduces_database() -> Result<()> {
+        let configuration = StaticMonarchConfiguration {
+            name: "squash",
+            enable_foreign_keys: true,
+            migrations: [
+                "CREATE TABLE teams (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT);
+                 CREATE TABLE users (id INTEGER PRIMARY KEY, team_id REFERENCES teams (id));",
+                "INSERT INTO teams (name) VALUES ('red'), ('blue'), ('gone');
+                 DELETE FROM teams WHERE name = 'gone';
+                 INSERT INTO users (team_id) VALUES (2);",
+                "CREATE INDEX users_team ON users (team_id);
+                 CREATE VIEW team_names AS SELECT name FROM teams;",
+                "ALTER TABLE users ADD COLUMN email TEXT;",
+            ],
+        };
+        let original = MonarchDB::from(configuration.clone());
+        let baseline = original.squash(1..=3)?;
+        assert!(
+            baseline.starts_with("-- monarch:baseline 3\n"),
+            "{baseline}"
+        );
+        assert!(
+            baseline.contains("INSERT INTO sqlite_sequence (name, seq) VALUES ('teams', 3);"),
+            "{baseline}"
+        );
+
+        let squashed = squashed(configuration.into(), 3, baseline);
+        assert_eq!(squashed.current_version(), 4);
+        #[cfg(feature = "metadata")]
+        assert_eq!(
+            squashed
+                .schema_metadata()?
+                .iter()
+                .map(|metadata| metadata.version)
+                .collect::<Vec<_>>(),
+            [3, 4]
+        );
+
+        let expected = original.open_in_memory()?;
+        let fresh = squashed.open_in_memory()?;
+        assert_eq!(
+            MonarchDB::schema_sql(&fresh)?,
+            MonarchDB::schema_sql(&expected)?
+        );
+        assert_eq!(
+            MonarchDB::content_digest(&fresh, &[])?,
+            MonarchDB::content_digest(&expected, &[])?
+        );
+
+        // Squashing again must extend the existing baseline.
+        assert!(matches!(squashed.squash(1..=2), Err(Error::Squash(_))));
+        assert!(
+            squashed
+                .squash(1..=4)?
+                .starts_with("-- monarch:baseline 4\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_existing_databases() -> Result<()> {
+        let configuration = StaticMonarchConfiguration {
+            name: "squash",
+            enable_foreign_keys: true,
+            migrations: [
+                "CREATE TABLE teams (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT);
+                 CREATE TABLE users (id INTEGER PRIMARY KEY, team_id REFERENCES teams (id));",
+                "INSERT INTO teams (name) VALUES ('red'), ('blue'), ('gone');
+                 DELETE FROM teams WHERE name = 'gone';
+                 INSERT INTO users (team_id) VALUES (2);",
+                "CREATE INDEX users_team ON users (team_id);
+                 CREATE VIEW team_names AS SELECT name FROM teams;",
+                "ALTER TABLE users ADD COLUMN email TEXT;",
+            ],
+        };
+        let baseline = MonarchDB::from(configuration.clone()).squash(1..=2)?;
+        let squashed = squashed(configuration.clone().into(), 2, baseline);
+
+        // Past the squashed range, only the later migrations run.
+        let mut migrated = MonarchDB::from(configuration);
+        migrated.migrations.truncate(2);
+        let mut connection = migrated.open_in_memory()?;
+        let tx = connection.transaction()?;
+        let report = squashed.apply_pending(&tx)?;
+        assert_eq!((report.from_version, report.to_version), (2, 4));
+
+        // Inside the squashed range, nothing can run.
+        migrated.migrations.truncate(1);
+        let mut connection = migrated.open_in_memory()?;
+        let tx = connection.transaction()?;
+        assert!(matches!(
+            squashed.apply_pending(&tx),
+            Err(Error::SquashedVersion {
+                version: 1,
+                baseline: 2
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_squash_rejects_invalid_ranges() {
+        let configuration = StaticMonarchConfiguration {
+            name: "squash",
+            enable_foreign_keys: true,
+            migrations: [
+                "CREATE TABLE teams (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT);
+                 CREATE TABLE users (id INTEGER PRIMARY KEY, team_id REFERENCES teams (id));",
+                "INSERT INTO teams (name) VALUES ('red'), ('blue'), ('gone');
+                 DELETE FROM teams WHERE name = 'gone';
+                 INSERT INTO users (team_id) VALUES (2);",
+                "CREATE INDEX users_team ON users (team_id);
+                 CREATE VIEW team_names AS SELECT name FROM teams;",
+                "ALTER TABLE users ADD COLUMN email TEXT;",
+            ],
+        };
+        let monarch_db = MonarchDB::from(configuration.clone());
+        for (start, through) in [(2, 3), (1, 5), (1, 0)] {
+            assert!(
+                matches!(monarch_db.squash(start..=through), Err(Error::Squash(_))),
+                "{start}..={through}"
+            );
+        }
+
+        let mut restricted = MonarchDB::from(configuration);
+        restricted.migrations[1] =
+            Migration::new("2.dev.sql", "INSERT INTO teams (name) VALUES ('fixture');");
+        let error = restricted.squash(1..=2).unwrap_err();
+        assert!(error.to_string().contains("2.dev.sql"), "{error}");
+        assert!(restricted.squash(1..=1).is_ok());
+    }
+}
@@ -0,0 +1,139 @@
+//! Metrics about migrations, emitted through the [`metrics`](https://docs.rs/metrics) facade.
+//!
+//! With the `metrics` feature, Monarch records the following metrics, each labelled with the
+//! `schema` name and the `migration` name, to whichever recorder the application installs
+//! (e.g. a Prometheus exporter):
+//!
+//! - `monarch_db.migrations_applied`: a counter of versioned migrations which ran successfully.
+//!   A migration run in a transaction which later rolls back is still counted.
+//! - `monarch_db.migration_duration_seconds`: a histogram of how long each versioned migration
+//!   took to run, whether or not it succeeded.
+//! - `monarch_db.migration_failures`: a counter of versioned migrations which failed, including
+//!   best-effort migrations which were skipped.
+//! - `monarch_db.migrations_skipped`: a counter of best-effort migrations which failed and were
+//!   skipped.
+//!
+//! Without the feature these functions do nothing.
+
+use std::time::Duration;
+
+use crate::MigrationStatus;
+
+/// Records a versioned migration of `schema` which ran for `elapsed` with `status`.
+#[cfg(feature = "metrics")]
+pub(crate) fn migration_ran(
+    schema: &str,
+    migration: &str,
+    status: &MigrationStatus,
+    elapsed: Duration,
+) {
+    let labels = [
+        ("schema", schema.to_owned()),
+        ("migration", migration.to_owned()),
+    ];
+    metrics::histogram!("monarch_db.migration_duration_seconds", &labels)
+        .record(elapsed.as_secs_f64());
+    match status {
+        MigrationStatus::Applied => {
+            metrics::counter!("monarch_db.migrations_applied", &labels).increment(1);
+        }
+        MigrationStatus::Skipped(_) => {
+            metrics::counter!("monarch_db.migration_failures", &labels).increment(1);
+            metrics::counter!("monarch_db.migrations_skipped", &labels).increment(1);
+        }
+    }
+}
+
+/// Records a versioned migration of `schema` which failed after running for `elapsed`.
+#[cfg(feature = "metrics")]
+pub(crate) fn migration_failed(schema: &str, migration: &str, elapsed: Duration) {
+    let labels = [
+        ("schema", schema.to_owned()),
+        ("migration", migration.to_owned()),
+    ];
+    metrics::histogram!("monarch_db.migration_duration_seconds", &labels)
+        .record(elapsed.as_secs_f64());
+    metrics::counter!("monarch_db.migration_failures", &labels).increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn migration_ran(_: &str, _: &str, _: &MigrationStatus, _: Duration) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn migration_failed(_: &str, _: &str, _: Duration) {}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use std::sync::Mutex;
+
+    use metrics::{
+        Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+    };
+
+    use crate::{MonarchDB, StaticMonarchConfiguration};
+
+    /// Records the name and migration label of each metric as it is registered.
+    #[derive(Default)]
+    struct Registrations(Mutex<Vec<String>>);
+
+    impl Registrations {
+        fn push(&self, key: &Key) {
+            let migration = key
+                .labels()
+                .find(|label| label.key() == "migration")
+                .map_or("", |label| label.value());
+            let name = format!("{} {migration}", key.name());
+            self.0.lock().unwrap().push(name);
+        }
+    }
+
+    impl Recorder for Registrations {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            self.push(key);
+            Counter::noop()
+        }
+
+        fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+            self.push(key);
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            self.push(key);
+            Histogram::noop()
+        }
+    }
+
+    #[test]
+    fn test_metrics_for_each_migration() {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "metrics",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                "-- monarch:best-effort\nCREATE INDEX broken ON users (missing);",
+                "CREATE TABLE broken (",
+            ],
+        });
+
+        let recorder = Registrations::default();
+        let result = metrics::with_local_recorder(&recorder, || monarch_db.open_in_memory());
+        assert!(result.is_err());
+        assert_eq!(
+            recorder.0.into_inner().unwrap(),
+            [
+                "monarch_db.migration_duration_seconds 1",
+                "monarch_db.migrations_applied 1",
+                "monarch_db.migration_duration_seconds 2",
+                "monarch_db.migration_failures 2",
+                "monarch_db.migrations_skipped 2",
+                "monarch_db.migration_duration_seconds 3",
+                "monarch_db.migration_failures 3",
+            ]
+        );
+    }
+}
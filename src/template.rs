@@ -0,0 +1,76 @@
+//! Placeholder substitution in migration SQL.
+//!
+//! Migrations loaded with [`MonarchConfiguration`](crate::MonarchConfiguration) may contain
+//! `{{name}}` placeholders, which are replaced with the configured variables before the
+//! migrations run. This lets deployments share migrations while varying, for example, a table
+//! prefix.
+
+use std::collections::BTreeMap;
+
+/// Replaces each `{{name}}` placeholder in `sql` with the value of the variable `name`.
+///
+/// Whitespace inside the braces is ignored. Braces which don't enclose an identifier (letters,
+/// digits and underscores) are left as they are. Returns the name of the first placeholder
+/// without a value as the error.
+pub(crate) fn render(sql: &str, variables: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut rendered = String::with_capacity(sql.len());
+    let mut rest = sql;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(length) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let name = rest[start + 2..start + 2 + length].trim();
+        rendered.push_str(&rest[..start]);
+
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            match variables.get(name) {
+                Some(value) => rendered.push_str(value),
+                None => return Err(name.to_owned()),
+            }
+            rest = &rest[start + 2 + length + 2..];
+        } else {
+            // Not a placeholder; keep the opening braces and carry on after them.
+            rendered.push_str("{{");
+            rest = &rest[start + 2..];
+        }
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> BTreeMap<String, String> {
+        [("table_prefix", "acme_"), ("app_name", "shop")]
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render_substitutes_variables() {
+        let sql = "CREATE TABLE {{table_prefix}}users (id); -- {{ app_name }}";
+        assert_eq!(
+            render(sql, &variables()).unwrap(),
+            "CREATE TABLE acme_users (id); -- shop"
+        );
+    }
+
+    #[test]
+    fn test_render_leaves_non_placeholders() {
+        let sql = "SELECT '{{not a name}}', '{{', '}}', json('{\"a\":{\"b\":1}}')";
+        assert_eq!(render(sql, &variables()).unwrap(), sql);
+    }
+
+    #[test]
+    fn test_render_undefined_variable() {
+        assert_eq!(
+            render("CREATE TABLE {{schema}}.t (id);", &variables()),
+            Err("schema".to_owned())
+        );
+    }
+}
@@ -0,0 +1,414 @@
+//! Helpers for testing code which uses a [`MonarchDB`].
+//!
+//! Enable the `testing` feature (typically only in `[dev-dependencies]`) to use these helpers:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! monarch-db = { version = "0.1", features = ["testing"] }
+//! ```
+//!
+//! [`TestDb`] creates a fresh, fully migrated database for each test and cleans it up
+//! afterwards, so test suites don't need to re-implement temporary file plumbing.
+//! [`MonarchDB::migrate_in_test_transaction`] instead isolates tests which share one database
+//! by rolling back everything each test does.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use rusqlite::{Connection, DropBehavior, OpenFlags, Savepoint};
+use tempfile::TempDir;
+
+use crate::{Error, MonarchDB, Result};
+
+/// Distinguishes shared in-memory databases created by this process.
+static SHARED_MEMORY_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A migrated database which only lives as long as a test.
+///
+/// `TestDb` dereferences to the underlying [`Connection`]. File-backed databases are created
+/// in a unique temporary directory which is removed, along with the database, when the
+/// `TestDb` is dropped. Shared in-memory databases live until the `TestDb` and every
+/// connection opened with [`TestDb::connect`] are dropped.
+///
+/// ```rust
+/// use monarch_db::{MonarchDB, StaticMonarchConfiguration, testing::TestDb};
+///
+/// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+///     name: "my_app",
+///     enable_foreign_keys: true,
+///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);"],
+/// }
+/// .into();
+///
+/// let db = TestDb::new(&monarch_db);
+/// db.execute("INSERT INTO users (name) VALUES ('alice')", []).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TestDb {
+    // Declared before `_directory` so the connection is closed before the files are removed.
+    connection: Connection,
+    location: Location,
+    _directory: Option<TempDir>,
+}
+
+/// Where a [`TestDb`] stores its data.
+#[derive(Debug)]
+enum Location {
+    File(Utf8PathBuf),
+    SharedMemory(String),
+    Private,
+}
+
+impl TestDb {
+    /// Creates a uniquely named temporary database file and applies all migrations to it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the temporary directory cannot be created or migrations fail, since this is
+    /// intended for use in tests.
+    pub fn new(monarch: &MonarchDB) -> Self {
+        let directory = tempfile::Builder::new()
+            .prefix("monarch-db-test-")
+            .tempdir()
+            .expect("create temporary directory for test database");
+        let path = Utf8Path::from_path(directory.path())
+            .expect("temporary directory path is not UTF-8")
+            .join(format!("{}.db", monarch.name));
+
+        let connection = Connection::open(&path)
+            .map_err(Error::from)
+            .and_then(|connection| monarch.migrate(connection))
+            .unwrap_or_else(|error| panic!("failed to create test database {path}: {error}"));
+
+        TestDb {
+            connection,
+            location: Location::File(path),
+            _directory: Some(directory),
+        }
+    }
+
+    /// Creates a private in-memory database and applies all migrations to it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if migrations fail.
+    pub fn in_memory(monarch: &MonarchDB) -> Self {
+        let connection = monarch
+            .open_in_memory()
+            .unwrap_or_else(|error| panic!("failed to create in-memory test database: {error}"));
+
+        TestDb {
+            connection,
+            location: Location::Private,
+            _directory: None,
+        }
+    }
+
+    /// Creates a uniquely named shared in-memory database and applies all migrations to it.
+    ///
+    /// Additional connections to the same database can be opened with [`TestDb::connect`], so
+    /// tests can exercise multi-connection behavior without touching the filesystem. Names are
+    /// unique within the process, which is as far as in-memory databases are visible, so tests
+    /// running in parallel never share a database.
+    ///
+    /// # Panics
+    ///
+    /// Panics if migrations fail.
+    pub fn shared_in_memory(monarch: &MonarchDB) -> Self {
+        let uri = format!(
+            "file:monarch-test-{}-{}-{}?mode=memory&cache=shared",
+            monarch.name,
+            std::process::id(),
+            SHARED_MEMORY_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let connection = open_uri(&uri)
+            .map_err(Error::from)
+            .and_then(|connection| monarch.migrate(connection))
+            .unwrap_or_else(|error| panic!("failed to create test database {uri}: {error}"));
+
+        TestDb {
+            connection,
+            location: Location::SharedMemory(uri),
+            _directory: None,
+        }
+    }
+
+    /// Creates a shared in-memory database, as [`TestDb::shared_in_memory`] does, and returns
+    /// it together with a second connection to the same database.
+    ///
+    /// # Panics
+    ///
+    /// Panics if migrations fail.
+    pub fn shared_pair(monarch: &MonarchDB) -> (Self, Connection) {
+        let db = Self::shared_in_memory(monarch);
+        let other = db.connect();
+        (db, other)
+    }
+
+    /// The path of the database file, or `None` for an in-memory database.
+    pub fn path(&self) -> Option<&Utf8Path> {
+        match &self.location {
+            Location::File(path) => Some(path),
+            Location::SharedMemory(_) | Location::Private => None,
+        }
+    }
+
+    /// The URI of a shared in-memory database, or `None` for other databases.
+    pub fn uri(&self) -> Option<&str> {
+        match &self.location {
+            Location::SharedMemory(uri) => Some(uri),
+            Location::File(_) | Location::Private => None,
+        }
+    }
+
+    /// The connection to the test database.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Opens an additional connection to a file-backed or shared in-memory test database.
+    ///
+    /// # Panics
+    ///
+    /// Panics for private in-memory databases, which cannot be shared, or if the database
+    /// can't be opened.
+    pub fn connect(&self) -> Connection {
+        match &self.location {
+            Location::File(path) => Connection::open(path)
+                .unwrap_or_else(|error| panic!("failed to open test database {path}: {error}")),
+            Location::SharedMemory(uri) => open_uri(uri)
+                .unwrap_or_else(|error| panic!("failed to open test database {uri}: {error}")),
+            Location::Private => panic!("private in-memory test databases cannot be reopened"),
+        }
+    }
+}
+
+impl MonarchDB {
+    /// Applies pending migrations inside a savepoint which is rolled back when the returned
+    /// guard is dropped.
+    ///
+    /// Tests can perform their setup and assertions through the guard, which dereferences to
+    /// the connection. Nothing they do, including the migrations themselves, outlives the
+    /// guard, so many tests can share one migrated database file without recreating the
+    /// schema for each test. Once the database has been migrated, for example by
+    /// [`MonarchDB::create_connection`], starting a test transaction only costs a savepoint.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);"],
+    /// }
+    /// .into();
+    ///
+    /// let mut connection = monarch_db.open_in_memory()?;
+    /// {
+    ///     let tx = monarch_db.migrate_in_test_transaction(&mut connection)?;
+    ///     tx.execute("INSERT INTO users (name) VALUES ('alice')", [])?;
+    /// }
+    /// let count: i64 = connection.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?;
+    /// assert_eq!(count, 0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn migrate_in_test_transaction<'c>(
+        &self,
+        connection: &'c mut Connection,
+    ) -> Result<TestTransaction<'c>> {
+        self.setup_connection(connection)?;
+        // The pragma is a no-op inside a transaction, so it must be set first.
+        if self.enable_foreign_keys {
+            connection.pragma_update(None, "foreign_keys", true)?;
+        }
+
+        let mut savepoint = connection.savepoint()?;
+        savepoint.set_drop_behavior(DropBehavior::Rollback);
+        self.apply_pending(&savepoint)?;
+        Ok(TestTransaction { savepoint })
+    }
+}
+
+/// A migrated connection whose changes are rolled back when dropped.
+///
+/// See [`MonarchDB::migrate_in_test_transaction`].
+#[derive(Debug)]
+pub struct TestTransaction<'c> {
+    savepoint: Savepoint<'c>,
+}
+
+impl Deref for TestTransaction<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.savepoint
+    }
+}
+
+fn open_uri(uri: &str) -> rusqlite::Result<Connection> {
+    Connection::open_with_flags(uri, OpenFlags::default() | OpenFlags::SQLITE_OPEN_URI)
+}
+
+impl Deref for TestDb {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+impl DerefMut for TestDb {
+    fn deref_mut(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_file_database_is_removed_on_drop() {
+        let monarch: MonarchDB = StaticMonarchConfiguration {
+            name: "testing",
+            enable_foreign_keys: true,
+            migrations: ["CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT);"],
+        }
+        .into();
+        let db = TestDb::new(&monarch);
+        let path = db.path().expect("file-backed database").to_owned();
+        assert!(path.exists());
+
+        db.execute("INSERT INTO items (name) VALUES ('widget')", [])
+            .unwrap();
+        let other = db.connect();
+        let count: i64 = other
+            .query_row("SELECT COUNT(*) FROM items", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+        drop(other);
+
+        drop(db);
+        assert!(!path.exists());
+        assert!(!path.parent().unwrap().exists());
+    }
+
+    #[test]
+    fn test_databases_are_unique() {
+        let monarch: MonarchDB = StaticMonarchConfiguration {
+            name: "testing",
+            enable_foreign_keys: true,
+            migrations: ["CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT);"],
+        }
+        .into();
+        let a = TestDb::new(&monarch);
+        let b = TestDb::new(&monarch);
+        assert_ne!(a.path(), b.path());
+    }
+
+    #[test]
+    fn test_in_memory_database() {
+        let monarch: MonarchDB = StaticMonarchConfiguration {
+            name: "testing",
+            enable_foreign_keys: true,
+            migrations: ["CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT);"],
+        }
+        .into();
+        let db = TestDb::in_memory(&monarch);
+        assert!(db.path().is_none());
+        db.execute("INSERT INTO items (name) VALUES ('widget')", [])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_test_transaction_rolls_back() -> Result<()> {
+        let monarch: MonarchDB = StaticMonarchConfiguration {
+            name: "testing",
+            enable_foreign_keys: true,
+            migrations: ["CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT);"],
+        }
+        .into();
+        let db = TestDb::new(&monarch);
+        let mut connection = db.connect();
+
+        {
+            let tx = monarch.migrate_in_test_transaction(&mut connection)?;
+            tx.execute("INSERT INTO items (name) VALUES ('widget')", [])?;
+            let count: i64 = tx.query_row("SELECT COUNT(*) FROM items", [], |row| row.get(0))?;
+            assert_eq!(count, 1);
+        }
+
+        let count: i64 =
+            connection.query_row("SELECT COUNT(*) FROM items", [], |row| row.get(0))?;
+        assert_eq!(count, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_test_transaction_rolls_back_migrations() -> Result<()> {
+        let monarch: MonarchDB = StaticMonarchConfiguration {
+            name: "testing",
+            enable_foreign_keys: true,
+            migrations: ["CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT);"],
+        }
+        .into();
+        let mut connection = Connection::open_in_memory()?;
+
+        {
+            let tx = monarch.migrate_in_test_transaction(&mut connection)?;
+            tx.execute("INSERT INTO items (name) VALUES ('widget')", [])?;
+        }
+
+        let tables: i64 = connection.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(tables, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_pair_sees_same_data() {
+        let monarch: MonarchDB = StaticMonarchConfiguration {
+            name: "testing",
+            enable_foreign_keys: true,
+            migrations: ["CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT);"],
+        }
+        .into();
+        let (db, other) = TestDb::shared_pair(&monarch);
+        assert!(db.path().is_none());
+        db.execute("INSERT INTO items (name) VALUES ('widget')", [])
+            .unwrap();
+        let count: i64 = other
+            .query_row("SELECT COUNT(*) FROM items", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_shared_databases_are_isolated() {
+        let monarch: MonarchDB = StaticMonarchConfiguration {
+            name: "testing",
+            enable_foreign_keys: true,
+            migrations: ["CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT);"],
+        }
+        .into();
+        let a = TestDb::shared_in_memory(&monarch);
+        let b = TestDb::shared_in_memory(&monarch);
+        assert_ne!(a.uri(), b.uri());
+
+        a.execute("INSERT INTO items (name) VALUES ('widget')", [])
+            .unwrap();
+        let count: i64 = b
+            .query_row("SELECT COUNT(*) FROM items", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,141 @@
+//! Limiting how long a single migration may run.
+//!
+//! A statement which scans a huge table, or a trigger which loops, can keep a migration running
+//! for far longer than an application can afford to wait at startup. With
+//! [`MonarchDB::with_migration_timeout`], SQLite's progress handler interrupts a migration which
+//! runs past its deadline, and the migration fails with [`Error::MigrationTimedOut`] and is
+//! rolled back like any other failure.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+
+use crate::migration::Migration;
+use crate::{Error, MonarchDB, Result};
+
+/// How many virtual machine instructions SQLite runs between checks of the deadline.
+const PROGRESS_INTERVAL: i32 = 1000;
+
+impl MonarchDB {
+    /// Sets the longest a single migration, versioned or repeatable, may run before it is
+    /// interrupted.
+    ///
+    /// A migration which runs past `timeout` fails with [`Error::MigrationTimedOut`], and is
+    /// rolled back along with the rest of its transaction. The deadline is checked by a progress
+    /// handler installed on the connection while the migration runs, replacing any progress
+    /// handler the application installed, which is not restored afterwards. By default
+    /// migrations may run for as long as they take.
+    pub fn with_migration_timeout(mut self, timeout: Duration) -> Self {
+        self.migration_timeout = Some(timeout);
+        self
+    }
+
+    /// Runs `migration` with `run`, interrupting it if it runs past the migration timeout.
+    pub(crate) fn run_within_timeout(
+        &self,
+        tx: &Connection,
+        migration: &Migration,
+        run: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        let Some(timeout) = self.migration_timeout else {
+            return run();
+        };
+        let deadline = Instant::now() + timeout;
+        let expired = Arc::new(AtomicBool::new(false));
+        let handler = {
+            let expired = Arc::clone(&expired);
+            move || {
+                let past = Instant::now() >= deadline;
+                if past {
+                    expired.store(true, Ordering::Relaxed);
+                }
+                past
+            }
+        };
+        tx.progress_handler(PROGRESS_INTERVAL, Some(handler));
+        let result = run();
+        tx.progress_handler(0, None::<fn() -> bool>);
+        match result {
+            Err(error) if expired.load(Ordering::Relaxed) => {
+                tracing::debug!(migration = %migration.name, %error, "Migration timed out");
+                Err(Error::MigrationTimedOut {
+                    migration: migration.name.to_string(),
+                    timeout,
+                })
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    #[test]
+    fn test_slow_migration_times_out() -> Result<()> {
+        let monarch = |timeout| {
+            MonarchDB::from(StaticMonarchConfiguration {
+                name: "timeout",
+                enable_foreign_keys: false,
+                migrations: [
+                    "CREATE TABLE a (id);",
+                    "CREATE TABLE b AS
+                     WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n)
+                     SELECT count(*) AS total FROM n;",
+                ],
+            })
+            .with_migration_timeout(timeout)
+        };
+        let mut connection = Connection::open_in_memory()?;
+        monarch(Duration::from_secs(60)).migrate_to(&mut connection, 1)?;
+
+        let error = monarch(Duration::from_millis(50))
+            .migrate_to(&mut connection, 2)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "migration 2 timed out after 50ms and was rolled back"
+        );
+        let version: u32 = connection.query_row(
+            "SELECT version FROM monarch_db_schema_version WHERE monarch_schema = 'timeout'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(version, 1);
+
+        // The handler is removed once the migration finishes.
+        connection.execute_batch(
+            "CREATE TEMP TABLE c AS
+             WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1000000)
+             SELECT count(*) AS total FROM n;",
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_best_effort_migration_times_out() -> Result<()> {
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "timeout",
+            enable_foreign_keys: false,
+            migrations: ["-- monarch:best-effort
+                 CREATE TABLE b AS
+                 WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n)
+                 SELECT count(*) AS total FROM n;"],
+        })
+        .with_transaction_mode(crate::TransactionMode::PerMigration)
+        .with_migration_timeout(Duration::from_millis(50));
+        let mut connection = Connection::open_in_memory()?;
+        let error = monarch_db.migrate_to(&mut connection, 1).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "migration 1 timed out after 50ms and was rolled back"
+        );
+        assert!(monarch_db.history(&connection)?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+//! How migrations are grouped into transactions.
+
+use crate::MonarchDB;
+
+/// Whether pending migrations run in one transaction or one transaction each.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum TransactionMode {
+    /// Run every pending migration in a single transaction, so a failure leaves the database at
+    /// the version it started from.
+    #[default]
+    Single,
+    /// Run each migration in its own transaction, recording its version as it commits, so a
+    /// failure or shutdown keeps the migrations which completed before it.
+    PerMigration,
+    /// Run every pending migration in a single transaction, each inside a savepoint. A failure
+    /// rolls back only the failing migration, and the migrations which completed before it are
+    /// committed, like [`PerMigration`](Self::PerMigration), but the lock is held throughout
+    /// and the database is synced once rather than after every migration.
+    Savepoint,
+}
+
+impl MonarchDB {
+    /// Sets whether pending migrations run in one transaction or one transaction each, see
+    /// [`TransactionMode`]. Defaults to [`TransactionMode::Single`].
+    ///
+    /// With [`TransactionMode::PerMigration`], the migration lock is released between
+    /// migrations, so other connections may use the database at intermediate versions.
+    /// Repeatable migrations run in the transaction of the last versioned migration, or in a
+    /// transaction of their own when no versioned migration is pending. With
+    /// [`TransactionMode::Savepoint`], they run in the savepoint of the last versioned migration
+    /// in the same way.
+    pub fn with_transaction_mode(mut self, mode: TransactionMode) -> Self {
+        self.transaction_mode = mode;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    fn tables(connection: &Connection) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = connection.prepare(
+            "SELECT name FROM sqlite_master WHERE tbl_name NOT LIKE 'monarch%' ORDER BY name",
+        )?;
+        stmt.query_map([], |row| row.get(0))?.collect()
+    }
+
+    #[test]
+    fn test_failure_keeps_committed_migrations() -> crate::Result<()> {
+        let configuration = StaticMonarchConfiguration {
+            name: "transaction",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE a (id);",
+                "CREATE TABLE b (id);",
+                "CREATE TABLE c (id); INSERT INTO missing VALUES (1);",
+            ],
+        };
+        let mut connection = Connection::open_in_memory()?;
+        assert!(
+            MonarchDB::from(configuration.clone())
+                .with_transaction_mode(TransactionMode::Single)
+                .migrations(&mut connection)
+                .prepare()
+                .is_err()
+        );
+        assert!(tables(&connection)?.is_empty());
+
+        let per_migration =
+            MonarchDB::from(configuration).with_transaction_mode(TransactionMode::PerMigration);
+        assert!(per_migration.migrations(&mut connection).prepare().is_err());
+        assert_eq!(tables(&connection)?, ["a", "b"]);
+        assert_eq!(per_migration.plan(&connection)?.from_version, 2);
+
+        let report = per_migration.migrate_to(&mut connection, 2)?;
+        assert_eq!((report.from_version, report.to_version), (2, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_savepoint_keeps_completed_migrations() -> crate::Result<()> {
+        let mut connection = Connection::open_in_memory()?;
+        let savepoint = MonarchDB::from(StaticMonarchConfiguration {
+            name: "transaction",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE a (id);",
+                "CREATE TABLE b (id);",
+                "CREATE TABLE c (id); INSERT INTO missing VALUES (1);",
+            ],
+        })
+        .with_transaction_mode(TransactionMode::Savepoint);
+        let error = savepoint.migrations(&mut connection).prepare().unwrap_err();
+        assert!(
+            error.to_string().contains("no such table: missing"),
+            "{error}"
+        );
+        assert_eq!(tables(&connection)?, ["a", "b"]);
+        assert_eq!(savepoint.plan(&connection)?.from_version, 2);
+        assert!(connection.is_autocommit());
+
+        let history: Vec<_> = savepoint
+            .history(&connection)?
+            .into_iter()
+            .map(|entry| entry.version)
+            .collect();
+        assert_eq!(history, [1, 2]);
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+//! Linting migrations for statements which do not belong in a transaction.
+//!
+//! Migrations run inside a transaction, so a failure leaves the database at the version it
+//! started from. A few statements cannot run in a transaction, or quietly undermine it: `VACUUM`,
+//! `ATTACH` and changing the journal mode fail, `BEGIN` fails because a transaction is already
+//! open, `COMMIT` and `ROLLBACK` end Monarch's transaction partway through a migration, and
+//! `PRAGMA foreign_keys` is ignored. [`MonarchDB::lint_transactions`] finds them, and migrating
+//! refuses to start a migration with a statement which would fail or end the transaction, rather
+//! than failing partway through it.
+
+use std::fmt;
+
+use crate::migration::Migration;
+use crate::sql::{Token, statements};
+use crate::{Error, MonarchDB, Result};
+
+/// What a statement does when it runs inside the migration's transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionEffect {
+    /// The statement fails, e.g. `VACUUM` or `BEGIN`.
+    Fails,
+    /// The statement commits or rolls back the migration's transaction before the migration
+    /// finishes, e.g. `COMMIT`.
+    EndsTransaction,
+    /// The statement is silently ignored, e.g. `PRAGMA foreign_keys = OFF`.
+    Ignored,
+}
+
+/// A statement which cannot run as intended inside the migration's transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionHazard {
+    /// The name of the migration, its file name for migrations loaded from a directory.
+    pub migration: String,
+    /// The 1-based line of the statement within the migration.
+    pub line: usize,
+    /// The statement's leading keywords, e.g. `VACUUM` or `PRAGMA journal_mode`.
+    pub statement: String,
+    /// What the statement does inside the transaction.
+    pub effect: TransactionEffect,
+}
+
+impl fmt::Display for TransactionHazard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let effect = match self.effect {
+            TransactionEffect::Fails => "cannot run inside the migration's transaction",
+            TransactionEffect::EndsTransaction => "would end the migration's transaction early",
+            TransactionEffect::Ignored => "has no effect inside the migration's transaction",
+        };
+        write!(
+            f,
+            "migration {} line {}: {} {effect}",
+            self.migration, self.line, self.statement
+        )
+    }
+}
+
+impl std::error::Error for TransactionHazard {}
+
+impl MonarchDB {
+    /// Finds statements which cannot run as intended inside the migration's transaction, in
+    /// every migration including repeatable migrations and those outside the configured
+    /// environment.
+    ///
+    /// ```rust
+    /// use monarch_db::{MonarchDB, StaticMonarchConfiguration, TransactionEffect};
+    ///
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    ///         "DELETE FROM users;\nVACUUM;",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// let hazards = monarch_db.lint_transactions()?;
+    /// assert_eq!(hazards.len(), 1);
+    /// assert_eq!((hazards[0].line, hazards[0].statement.as_str()), (2, "VACUUM"));
+    /// assert_eq!(hazards[0].effect, TransactionEffect::Fails);
+    /// # Ok::<(), monarch_db::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::CompressedMigration`] if a compressed migration cannot be
+    /// decompressed.
+    pub fn lint_transactions(&self) -> Result<Vec<TransactionHazard>> {
+        let mut hazards = Vec::new();
+        for migration in self.migrations.iter().chain(&self.repeatables) {
+            hazards.extend(lint(migration)?);
+        }
+        Ok(hazards)
+    }
+
+    /// Refuses a migration which is about to run if one of its statements would fail or end
+    /// the transaction, and warns about statements which would be ignored.
+    pub(crate) fn check_transaction(&self, migration: &Migration) -> Result<()> {
+        for hazard in lint(migration)? {
+            if hazard.effect != TransactionEffect::Ignored {
+                return Err(Error::TransactionHazard(hazard));
+            }
+            tracing::warn!(%hazard, "Statement ignored in a migration");
+        }
+        Ok(())
+    }
+}
+
+/// Finds the statements of `migration` which do not belong in a transaction.
+fn lint(migration: &Migration) -> Result<Vec<TransactionHazard>> {
+    let sql = migration.sql()?;
+    let hazards = statements(sql)
+        .iter()
+        .filter_map(|statement| {
+            let (end, effect) = hazard(statement)?;
+            let (first, last) = (&statement[0], &statement[end]);
+            Some(TransactionHazard {
+                migration: migration.name.to_string(),
+                line: first.line,
+                statement: sql[first.offset..last.offset + last.text.len()].to_owned(),
+                effect,
+            })
+        })
+        .collect();
+    Ok(hazards)
+}
+
+/// If `statement` does not belong in a transaction, the index of the last of its leading
+/// keywords and what it does.
+fn hazard(statement: &[Token<'_>]) -> Option<(usize, TransactionEffect)> {
+    let first = &statement[0];
+    if ["VACUUM", "ATTACH", "DETACH", "BEGIN"]
+        .iter()
+        .any(|keyword| first.is_keyword(keyword))
+    {
+        return Some((0, TransactionEffect::Fails));
+    }
+    if first.is_keyword("COMMIT") || first.is_keyword("END") {
+        return Some((0, TransactionEffect::EndsTransaction));
+    }
+    if first.is_keyword("ROLLBACK") {
+        // ROLLBACK TO a savepoint leaves the transaction open.
+        let to_savepoint = statement.iter().any(|token| token.is_keyword("TO"));
+        return (!to_savepoint).then_some((0, TransactionEffect::EndsTransaction));
+    }
+    if first.is_keyword("PRAGMA") {
+        // Only setting a pragma matters, and its name may be qualified by a schema.
+        let assigns = statement
+            .iter()
+            .position(|token| token.is_punctuation('=') || token.is_punctuation('('))?;
+        let name = assigns.checked_sub(1)?;
+        if statement[name].is_keyword("journal_mode") {
+            return Some((name, TransactionEffect::Fails));
+        }
+        if statement[name].is_keyword("foreign_keys") {
+            return Some((name, TransactionEffect::Ignored));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::StaticMonarchConfiguration;
+
+    use super::*;
+
+    fn hazards(sql: &'static str) -> Vec<(usize, String, TransactionEffect)> {
+        lint(&Migration::new("test.sql", sql))
+            .unwrap()
+            .into_iter()
+            .map(|hazard| (hazard.line, hazard.statement, hazard.effect))
+            .collect()
+    }
+
+    #[test]
+    fn test_flags_statements_which_break_transactions() {
+        let sql = "PRAGMA foreign_keys = OFF;
+            CREATE TRIGGER t AFTER INSERT ON a BEGIN SELECT 1; END;
+            SAVEPOINT rebuild;
+            ROLLBACK TO rebuild;
+            RELEASE rebuild;
+            PRAGMA journal_mode;
+            PRAGMA main.journal_mode = WAL;
+            ATTACH 'other.db' AS other;
+            BEGIN;
+            COMMIT;
+            ROLLBACK TRANSACTION;
+            VACUUM;";
+        assert_eq!(
+            hazards(sql),
+            [
+                (
+                    1,
+                    "PRAGMA foreign_keys".to_owned(),
+                    TransactionEffect::Ignored
+                ),
+                (
+                    7,
+                    "PRAGMA main.journal_mode".to_owned(),
+                    TransactionEffect::Fails
+                ),
+                (8, "ATTACH".to_owned(), TransactionEffect::Fails),
+                (9, "BEGIN".to_owned(), TransactionEffect::Fails),
+                (10, "COMMIT".to_owned(), TransactionEffect::EndsTransaction),
+                (
+                    11,
+                    "ROLLBACK".to_owned(),
+                    TransactionEffect::EndsTransaction
+                ),
+                (12, "VACUUM".to_owned(), TransactionEffect::Fails),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_migration_refused_before_it_runs() -> Result<()> {
+        let monarch_db: MonarchDB = StaticMonarchConfiguration {
+            name: "transactional",
+            enable_foreign_keys: true,
+            migrations: [
+                "PRAGMA foreign_keys = OFF; CREATE TABLE a (id);",
+                "CREATE TABLE b (id); COMMIT; CREATE TABLE c (id);",
+            ],
+        }
+        .into();
+
+        let mut connection = Connection::open_in_memory()?;
+        monarch_db.migrate_to(&mut connection, 1)?;
+        let Err(Error::TransactionHazard(hazard)) = monarch_db.migrate(connection) else {
+            panic!("migration with COMMIT ran");
+        };
+        assert_eq!(
+            (hazard.line, hazard.effect),
+            (1, TransactionEffect::EndsTransaction)
+        );
+        assert_eq!(
+            hazard.to_string(),
+            "migration 2 line 1: COMMIT would end the migration's transaction early"
+        );
+        Ok(())
+    }
+}
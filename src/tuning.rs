@@ -0,0 +1,58 @@
+//! Performance pragmas applied to new connections before migrations run.
+//!
+//! A data migration which rebuilds a large table spends most of its time paging the table in and
+//! out of SQLite's page cache and spilling its sorts to temporary files. A larger cache, a
+//! memory-mapped database and in-memory temporary storage make those migrations much faster, and
+//! the [`ConnectionConfiguration`] fields which set them take effect before any migration runs.
+
+use rusqlite::Connection;
+
+use crate::ConnectionConfiguration;
+
+/// Where SQLite keeps temporary tables and indices, e.g. for sorting, set by `PRAGMA temp_store`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum TempStore {
+    /// Use the default chosen when SQLite was compiled, usually a file.
+    #[default]
+    Default,
+    /// Keep temporary storage in a file.
+    File,
+    /// Keep temporary storage in memory.
+    Memory,
+}
+
+impl TempStore {
+    /// The value of `PRAGMA temp_store` which selects this storage.
+    fn pragma_value(self) -> &'static str {
+        match self {
+            TempStore::Default => "DEFAULT",
+            TempStore::File => "FILE",
+            TempStore::Memory => "MEMORY",
+        }
+    }
+}
+
+/// Sets the performance pragmas of `configuration` which are present on `connection`.
+pub(crate) fn apply_tuning(
+    connection: &Connection,
+    configuration: &ConnectionConfiguration,
+) -> rusqlite::Result<()> {
+    if let Some(cache_size) = configuration.cache_size {
+        tracing::trace!(cache_size, "Set cache size");
+        connection.pragma_update(None, "cache_size", cache_size)?;
+    }
+    if let Some(mmap_size) = configuration.mmap_size {
+        tracing::trace!(mmap_size, "Set memory map size");
+        connection.pragma_update(None, "mmap_size", mmap_size)?;
+    }
+    if let Some(temp_store) = configuration.temp_store {
+        tracing::trace!(?temp_store, "Set temporary storage");
+        connection.pragma_update(None, "temp_store", temp_store.pragma_value())?;
+    }
+    Ok(())
+}
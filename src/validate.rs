@@ -0,0 +1,112 @@
+//! Checking migrations for errors without touching a real database.
+//!
+//! [`MonarchDB::validate`] replays the migrations on a scratch in-memory database, so syntax
+//! errors and references to tables or columns which do not exist are found in CI, before a
+//! migration reaches a user's database.
+
+use rusqlite::Connection;
+
+use crate::{MonarchDB, Result};
+
+impl MonarchDB {
+    /// Checks that every statement of every migration, including repeatable migrations, can be
+    /// prepared against the schema built by the statements before it.
+    ///
+    /// Each statement is prepared and then run against a scratch in-memory database which starts
+    /// empty, so later statements see the tables the earlier ones created. No real database is
+    /// touched. Imports are not loaded, so statements which read imported data see empty tables.
+    /// SQLite checks the body of a view or trigger only when it is used, so a view referring to a
+    /// missing column is reported at the first statement reading from it.
+    ///
+    /// ```rust
+    /// use monarch_db::{Error, MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: [
+    ///         "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);",
+    ///         "CREATE INDEX users_email ON users (email);",
+    ///     ],
+    /// }
+    /// .into();
+    ///
+    /// let Err(Error::Statement { migration, .. }) = monarch_db.validate() else {
+    ///     panic!("the index refers to a missing column");
+    /// };
+    /// assert_eq!(migration, "2");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::Statement`](crate::Error::Statement) for the first statement which
+    /// cannot be prepared or run, or if a connection setup hook fails.
+    pub fn validate(&self) -> Result<()> {
+        let connection = Connection::open_in_memory()?;
+        self.setup_connection(&connection)?;
+        let migrations = self.migrations.iter().chain(&self.repeatables);
+        for migration in migrations.filter(|m| self.applies(m)) {
+            tracing::trace!(migration = %migration.name, "Validate migration");
+            migration.execute(&connection, |_| {})?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, StaticMonarchConfiguration};
+
+    use super::*;
+
+    fn statement_error(monarch_db: &MonarchDB) -> (String, usize, usize) {
+        match monarch_db.validate() {
+            Err(Error::Statement {
+                migration,
+                statement,
+                line,
+                ..
+            }) => (migration, statement, line),
+            result => panic!("unexpected result {result:?}"),
+        }
+    }
+
+    fn users() -> MonarchDB {
+        StaticMonarchConfiguration {
+            name: "app",
+            enable_foreign_keys: true,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);",
+                "ALTER TABLE users ADD COLUMN email TEXT;\nCREATE INDEX users_email ON users (email);",
+            ],
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_validate() {
+        users().validate().unwrap();
+
+        let missing_column = users().with_repeatable_migration(
+            "users_view.sql",
+            "DROP VIEW IF EXISTS user_names;
+CREATE VIEW user_names AS SELECT nickname FROM users;
+INSERT INTO users (name) SELECT 'admin' WHERE NOT EXISTS (SELECT * FROM user_names);",
+        );
+        assert_eq!(
+            statement_error(&missing_column),
+            ("users_view.sql".to_owned(), 3, 3)
+        );
+
+        let syntax: MonarchDB = StaticMonarchConfiguration {
+            name: "app",
+            enable_foreign_keys: true,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                "-- Posts\nCREATE TABLE posts (id INTEGER PRIMARY KEY,);",
+            ],
+        }
+        .into();
+        assert_eq!(statement_error(&syntax), ("2".to_owned(), 1, 2));
+    }
+}
@@ -0,0 +1,149 @@
+//! Opening databases which another process migrates.
+//!
+//! Read replicas and secondary processes must never change the schema, but still need to know
+//! that the database they open matches the schema they were built against.
+//! [`MonarchDB::open_verified`] checks the recorded version instead of migrating.
+
+use rusqlite::Connection;
+
+use crate::{ConnectionConfiguration, Error, MonarchDB, Result, engine, open_connection};
+
+impl MonarchDB {
+    /// Opens a connection like [`create_connection`](Self::create_connection), but fails
+    /// instead of running any migrations.
+    ///
+    /// The version recorded in the database must be exactly the
+    /// [`current_version`](Self::current_version), and no repeatable migrations may be pending.
+    /// Nothing is written to the database, not even Monarch's own tables, so the database may
+    /// be opened read-only, e.g. with a `file:data.db?mode=ro` URI filename. Connection setup
+    /// hooks still run, and foreign keys are enabled if the schema asks for them.
+    ///
+    /// ```rust
+    /// use monarch_db::{ConnectionConfiguration, Database, MonarchDB, StaticMonarchConfiguration};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let monarch_db: MonarchDB = StaticMonarchConfiguration {
+    ///     name: "my_app",
+    ///     enable_foreign_keys: true,
+    ///     migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+    /// }
+    /// .into();
+    /// let configuration = ConnectionConfiguration {
+    ///     database: Database::SharedMemory("open_verified_example".into()),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// // Nothing has migrated the database yet.
+    /// assert!(monarch_db.open_verified(&configuration).is_err());
+    ///
+    /// let _primary = monarch_db.create_connection(&configuration)?;
+    /// let replica = monarch_db.open_verified(&configuration)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::VersionMismatch`] if the database is behind or ahead of the
+    /// migrations, or with [`Error::PendingRepeatable`] if repeatable migrations would run.
+    pub fn open_verified(&self, configuration: &ConnectionConfiguration) -> Result<Connection> {
+        let connection = open_connection(configuration, &self.retry_policy, self.busy_handler)?;
+        engine::prepare_connection(self, &connection)?;
+        self.verify(&connection)?;
+        self.after_open(&connection)?;
+        Ok(connection)
+    }
+
+    /// Fails unless `connection` is fully migrated, without writing to it.
+    fn verify(&self, connection: &Connection) -> Result<()> {
+        let expected = self.current_version();
+        let found = engine::recorded_version(connection, &self.name)?;
+        let error = if found != expected {
+            Error::VersionMismatch { expected, found }
+        } else {
+            let migrations = self.plan(connection)?.repeatable;
+            if migrations.is_empty() {
+                return Ok(());
+            }
+            Error::PendingRepeatable { migrations }
+        };
+        tracing::warn!(%error, "Refusing to open database");
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Database, StaticMonarchConfiguration};
+
+    use super::*;
+
+    fn verify_error(monarch_db: &MonarchDB, configuration: &ConnectionConfiguration) -> String {
+        monarch_db
+            .open_verified(configuration)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn test_open_verified_requires_exact_version() -> Result<()> {
+        let configuration = ConnectionConfiguration {
+            database: Database::SharedMemory("verify_exact_version".to_owned()),
+            ..Default::default()
+        };
+        let old = MonarchDB::from(StaticMonarchConfiguration {
+            name: "verify",
+            enable_foreign_keys: false,
+            migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+        });
+        let new = MonarchDB::from(StaticMonarchConfiguration {
+            name: "verify",
+            enable_foreign_keys: false,
+            migrations: [
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+                "ALTER TABLE users ADD COLUMN name TEXT;",
+            ],
+        });
+
+        let error = verify_error(&old, &configuration);
+        assert!(error.contains("is at version 0, but version 1"), "{error}");
+
+        let _primary = old.create_connection(&configuration)?;
+        let replica = old.open_verified(&configuration)?;
+        replica.execute("INSERT INTO users (id) VALUES (1)", [])?;
+
+        let error = verify_error(&new, &configuration);
+        assert!(error.contains("is at version 1, but version 2"), "{error}");
+        let columns: i64 = replica.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('users')",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(columns, 1);
+
+        let _primary = new.create_connection(&configuration)?;
+        let error = verify_error(&old, &configuration);
+        assert!(error.contains("is at version 2, but version 1"), "{error}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_verified_refuses_pending_repeatable() -> Result<()> {
+        let configuration = ConnectionConfiguration {
+            database: Database::SharedMemory("verify_pending_repeatable".to_owned()),
+            ..Default::default()
+        };
+        let monarch_db = MonarchDB::from(StaticMonarchConfiguration {
+            name: "verify",
+            enable_foreign_keys: false,
+            migrations: ["CREATE TABLE users (id INTEGER PRIMARY KEY);"],
+        });
+        let _primary = monarch_db.create_connection(&configuration)?;
+
+        let monarch_db = monarch_db
+            .with_repeatable_migration("R__views.sql", "CREATE VIEW v AS SELECT id FROM users;");
+        let error = verify_error(&monarch_db, &configuration);
+        assert!(error.contains("R__views.sql"), "{error}");
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+//! Timestamp-based migration versions.
+//!
+//! Numbering migrations `001`, `002`, ... means two feature branches which each add a migration
+//! both pick the next number, and one has to be renumbered when they merge. With
+//! [`VersionScheme::Timestamp`], migrations are instead named for when they were written, like
+//! `20240612093000_add_widgets.sql`, and run in order of those timestamps, so branches no longer
+//! collide. Two migrations with the same timestamp are an error.
+//!
+//! Schema versions still count the migrations applied, so every other part of Monarch works the
+//! same way, but the version table also records the timestamp of the latest migration applied in
+//! its `identifier` column. When a branch merges a migration with an earlier timestamp than one
+//! already applied, the count no longer lands on the recorded timestamp, and migrating fails with
+//! [`Error::IdentifierMismatch`] rather than skip the new migration and rerun an applied one,
+//! unless [`MonarchDB::with_out_of_order`] is set.
+
+use crate::backend::Driver;
+use crate::source::SourceMigration;
+use crate::{Error, MonarchDB, Result, VERSION_TABLE, repeatable};
+
+/// How versioned migrations are numbered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum VersionScheme {
+    /// Migrations run in the order their source lists them, e.g. by file name. The default.
+    #[default]
+    Sequential,
+    /// Migrations are named starting with a timestamp, such as `20240612093000`, and run in
+    /// order of it, so migrations added on different branches do not collide.
+    ///
+    /// The version table also records the timestamp of the latest migration applied, and
+    /// migrating fails with [`Error::IdentifierMismatch`] when a migration was added before it,
+    /// unless [`MonarchDB::with_out_of_order`] is set.
+    Timestamp,
+}
+
+/// A query returning a row if the version table has an `identifier` column.
+const IDENTIFIER_COLUMN: &str =
+    "SELECT name FROM pragma_table_info('monarch_db_schema_version') WHERE name = 'identifier'";
+
+/// Adds the `identifier` column to a version table created before timestamp versions.
+const IDENTIFIER_UPGRADE: &str =
+    "ALTER TABLE monarch_db_schema_version ADD COLUMN identifier INTEGER";
+
+/// The timestamp a migration name starts with, ignoring any directories before its file name.
+fn timestamp(name: &str) -> Option<u64> {
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    let digits = file_name.len()
+        - file_name
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    file_name[..digits].parse().ok()
+}
+
+/// Orders the versioned migrations of `migrations` by timestamp, leaving repeatable migrations
+/// where they are.
+///
+/// Fails with [`Error::MissingTimestamp`] for a versioned migration without a timestamp, and
+/// with [`Error::DuplicateVersion`] for two migrations with the same one.
+pub(crate) fn order_by_timestamp(migrations: &mut [SourceMigration]) -> Result<()> {
+    let mut versioned = Vec::new();
+    for (index, migration) in migrations.iter().enumerate() {
+        if migration.name.starts_with(repeatable::REPEATABLE_PREFIX) {
+            continue;
+        }
+        let timestamp = timestamp(&migration.name).ok_or_else(|| Error::MissingTimestamp {
+            migration: migration.name.to_string(),
+        })?;
+        versioned.push((timestamp, index));
+    }
+    versioned.sort();
+    if let Some(pair) = versioned.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(Error::DuplicateVersion {
+            version: pair[0].0,
+            first: migrations[pair[0].1].name.to_string(),
+            second: migrations[pair[1].1].name.to_string(),
+        });
+    }
+
+    let mut ordered: Vec<SourceMigration> = versioned
+        .iter()
+        .map(|&(_, index)| migrations[index].clone())
+        .collect();
+    let slots = migrations
+        .iter_mut()
+        .filter(|migration| !migration.name.starts_with(repeatable::REPEATABLE_PREFIX));
+    for (slot, migration) in slots.zip(ordered.drain(..)) {
+        *slot = migration;
+    }
+    Ok(())
+}
+
+impl MonarchDB {
+    /// Returns how versioned migrations are numbered, see [`VersionScheme`].
+    pub fn version_scheme(&self) -> VersionScheme {
+        self.version_scheme
+    }
+
+    /// The identifier recorded with schema version `version`: with timestamp versions, the
+    /// timestamp of the migration which upgrades the database to it.
+    pub(crate) fn version_identifier(&self, version: u32) -> Option<u64> {
+        if self.version_scheme != VersionScheme::Timestamp {
+            return None;
+        }
+        let (_, migration) = self.versions().find(|(next, _)| *next == version)?;
+        timestamp(&migration.name)
+    }
+
+    /// Fails with [`Error::IdentifierMismatch`] if the identifier recorded with `version` is not
+    /// the one of the migration which now reaches it, with timestamp versions.
+    ///
+    /// Databases migrated before identifiers were recorded are not checked.
+    pub(crate) async fn check_identifier<D: Driver>(
+        &self,
+        driver: &mut D,
+        version: u32,
+    ) -> Result<(), D::Error> {
+        let Some(current) = self.version_identifier(version) else {
+            return Ok(());
+        };
+        if driver.query(IDENTIFIER_COLUMN, &[]).await?.is_empty() {
+            return Ok(());
+        }
+        let recorded: Option<u64> = driver
+            .query_value(
+                &format!("SELECT identifier FROM {VERSION_TABLE} WHERE monarch_schema = ?1"),
+                &[self.name.to_string().into()],
+            )
+            .await?
+            .flatten();
+        match recorded {
+            Some(recorded) if recorded != current => Err(Error::IdentifierMismatch {
+                version,
+                recorded,
+                current,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records the identifier of `version` in the version table, with timestamp versions.
+    pub(crate) async fn record_identifier<D: Driver>(
+        &self,
+        driver: &mut D,
+        version: u32,
+    ) -> Result<(), D::Error> {
+        if self.version_scheme != VersionScheme::Timestamp {
+            return Ok(());
+        }
+        if driver.query(IDENTIFIER_COLUMN, &[]).await?.is_empty() {
+            driver.execute_batch(IDENTIFIER_UPGRADE).await?;
+        }
+        let identifier = self
+            .version_identifier(version)
+            .map(|identifier| identifier as i64);
+        driver
+            .execute(
+                &format!("UPDATE {VERSION_TABLE} SET identifier = ?1 WHERE monarch_schema = ?2"),
+                &[identifier.into(), self.name.to_string().into()],
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::MigrationSource;
+
+    use super::*;
+
+    struct Timestamped(Vec<(&'static str, &'static str)>);
+
+    impl MigrationSource for Timestamped {
+        fn name(&self) -> std::borrow::Cow<'static, str> {
+            "timestamped".into()
+        }
+
+        fn version_scheme(&self) -> VersionScheme {
+            VersionScheme::Timestamp
+        }
+
+        fn migrations(&self) -> Result<Vec<SourceMigration>> {
+            Ok(self
+                .0
+                .iter()
+                .map(|(name, sql)| SourceMigration::new(*name, *sql))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_timestamp_versions() -> Result<()> {
+        let monarch_db = MonarchDB::from_source(Timestamped(vec![
+            (
+                "20240612093000_widgets.sql",
+                "CREATE TABLE widgets (id INTEGER PRIMARY KEY);",
+            ),
+            (
+                "R__views.sql",
+                "CREATE VIEW IF NOT EXISTS widget_ids AS SELECT id FROM widgets;",
+            ),
+            ("20240101120000_users.sql", "CREATE TABLE users (id);"),
+        ]))?;
+        let names: Vec<&str> = monarch_db.migrations.iter().map(|m| &*m.name).collect();
+        assert_eq!(
+            names,
+            ["20240101120000_users.sql", "20240612093000_widgets.sql"]
+        );
+
+        let connection = monarch_db.open_in_memory()?;
+        let (version, identifier): (u32, i64) = connection.query_row(
+            "SELECT version, identifier FROM monarch_db_schema_version",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!((version, identifier), (2, 20240612093000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_timestamp_errors() {
+        let error = MonarchDB::from_source(Timestamped(vec![
+            ("20240612093000_widgets.sql", "SELECT 1;"),
+            ("20240612093000_gadgets.sql", "SELECT 2;"),
+        ]))
+        .unwrap_err();
+        assert!(
+            matches!(
+                error,
+                Error::DuplicateVersion {
+                    version: 20240612093000,
+                    ..
+                }
+            ),
+            "{error}"
+        );
+
+        let error =
+            MonarchDB::from_source(Timestamped(vec![("widgets.sql", "SELECT 1;")])).unwrap_err();
+        assert!(matches!(error, Error::MissingTimestamp { .. }), "{error}");
+    }
+}
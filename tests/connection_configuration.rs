@@ -0,0 +1,142 @@
+use monarch_db::{
+    AttachedDatabase, ConnectionConfiguration, Database, MonarchDB, StaticMonarchConfiguration,
+};
+use rusqlite::Connection;
+use tempfile::TempDir;
+
+#[test]
+fn test_attached_databases() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let audit_path = temp_dir.path().join("audit.db");
+
+    let monarch_db: MonarchDB = StaticMonarchConfiguration {
+        name: "attached",
+        enable_foreign_keys: true,
+        migrations: [
+            include_str!("migrations/001_create_users.sql"),
+            "CREATE TABLE audit.events (id INTEGER PRIMARY KEY, user_id INTEGER, action TEXT);",
+        ],
+    }
+    .into();
+
+    let connection_config = ConnectionConfiguration {
+        database: Database::File(temp_dir.path().join("main.db").try_into()?),
+        attach: vec![AttachedDatabase {
+            schema: "audit".into(),
+            path: audit_path.clone().try_into()?,
+        }],
+        ..Default::default()
+    };
+
+    let connection = monarch_db.create_connection(&connection_config)?;
+
+    // The returned connection can query across both databases.
+    connection.execute(
+        "INSERT INTO users (username, email) VALUES ('alice', 'alice@example.com')",
+        [],
+    )?;
+    connection.execute(
+        "INSERT INTO audit.events (user_id, action) SELECT id, 'signup' FROM users",
+        [],
+    )?;
+    connection.execute_batch(
+        "CREATE TEMP VIEW user_actions AS
+         SELECT u.username, e.action FROM users u JOIN audit.events e ON e.user_id = u.id",
+    )?;
+    let action: String = connection.query_row(
+        "SELECT action FROM user_actions WHERE username = 'alice'",
+        [],
+        |row| row.get(0),
+    )?;
+    assert_eq!(action, "signup");
+    drop(connection);
+
+    // The migration created the table in the attached file, not the main database.
+    let audit = Connection::open(&audit_path)?;
+    let count: i64 = audit.query_row("SELECT COUNT(*) FROM events", [], |row| row.get(0))?;
+    assert_eq!(count, 1);
+
+    // Reconnecting re-attaches the database without re-running migrations.
+    let connection = monarch_db.create_connection(&connection_config)?;
+    let count: i64 =
+        connection.query_row("SELECT COUNT(*) FROM audit.events", [], |row| row.get(0))?;
+    assert_eq!(count, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_shared_memory_database() -> Result<(), Box<dyn std::error::Error>> {
+    let monarch_db: MonarchDB = StaticMonarchConfiguration {
+        name: "shared_memory_test",
+        enable_foreign_keys: true,
+        migrations: [include_str!("migrations/001_create_users.sql")],
+    }
+    .into();
+    let connection_config = ConnectionConfiguration {
+        database: Database::SharedMemory("shared_memory_test".into()),
+        ..Default::default()
+    };
+
+    let first = monarch_db.create_connection(&connection_config)?;
+    first.execute(
+        "INSERT INTO users (username, email) VALUES ('alice', 'alice@example.com')",
+        [],
+    )?;
+
+    // A second connection finds the database already migrated, with the first one's data.
+    let second = monarch_db.create_connection(&connection_config)?;
+    let count: i64 = second.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?;
+    assert_eq!(count, 1);
+
+    // Once every connection closes, the database is gone.
+    drop((first, second));
+    let fresh = Connection::open("file:shared_memory_test?mode=memory&cache=shared")?;
+    let tables: i64 =
+        fresh.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0))?;
+    assert_eq!(tables, 0);
+    Ok(())
+}
+
+#[test]
+fn test_uri_filenames() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().join("data.db");
+    // e.g. `file:///tmp/.../data.db`, or `file:///C:/.../data.db` on Windows.
+    let uri = format!(
+        "file:///{}",
+        path.display()
+            .to_string()
+            .replace('\\', "/")
+            .trim_start_matches('/')
+    );
+    let monarch_db: MonarchDB = StaticMonarchConfiguration {
+        name: "uri_test",
+        enable_foreign_keys: true,
+        migrations: [include_str!("migrations/001_create_users.sql")],
+    }
+    .into();
+
+    let read_write = ConnectionConfiguration {
+        database: Database::File(format!("{uri}?mode=rwc").into()),
+        ..Default::default()
+    };
+    drop(monarch_db.create_connection(&read_write)?);
+    // The query is an option, not part of the file name.
+    assert!(path.exists());
+
+    // A migrated database can be opened read-only, and rejects writes.
+    let read_only = ConnectionConfiguration {
+        database: Database::File(format!("{uri}?mode=ro&immutable=1").into()),
+        ..Default::default()
+    };
+    let connection = monarch_db.create_connection(&read_only)?;
+    let error = connection
+        .execute(
+            "INSERT INTO users (username, email) VALUES ('alice', 'alice@example.com')",
+            [],
+        )
+        .unwrap_err();
+    assert!(error.to_string().contains("readonly"), "{error}");
+    Ok(())
+}
@@ -1,5 +1,5 @@
 use camino::Utf8PathBuf;
-use monarch_db::{ConnectionConfiguration, MonarchConfiguration, MonarchDB};
+use monarch_db::{ConnectionConfiguration, Database, MonarchConfiguration, MonarchDB};
 use rusqlite::Connection;
 use std::fs;
 use tempfile::TempDir;
@@ -19,11 +19,15 @@ fn test_directory_configuration_with_file_database() -> Result<(), Box<dyn std::
         enable_foreign_keys: true,
         migration_directory: Utf8PathBuf::from_path_buf(migrations_dir.to_path_buf())
             .map_err(|_| "Invalid UTF-8 path")?,
+        ..Default::default()
     };
 
     let monarch_db = MonarchDB::from_configuration(config)?;
     let connection_config = ConnectionConfiguration {
-        database: Some(Utf8PathBuf::from_path_buf(db_path).map_err(|_| "Invalid UTF-8 path")?),
+        database: Database::File(
+            Utf8PathBuf::from_path_buf(db_path).map_err(|_| "Invalid UTF-8 path")?,
+        ),
+        ..Default::default()
     };
 
     let connection = monarch_db.create_connection(&connection_config)?;
@@ -52,13 +56,17 @@ fn test_directory_configuration_partial_migrations() -> Result<(), Box<dyn std::
         enable_foreign_keys: false,
         migration_directory: Utf8PathBuf::from_path_buf(migrations_dir.to_path_buf())
             .map_err(|_| "Invalid UTF-8 path")?,
+        ..Default::default()
     };
 
     let monarch_db = MonarchDB::from_configuration(config)?;
     assert_eq!(monarch_db.current_version(), 2);
 
     let connection_config = ConnectionConfiguration {
-        database: Some(Utf8PathBuf::from_path_buf(db_path).map_err(|_| "Invalid UTF-8 path")?),
+        database: Database::File(
+            Utf8PathBuf::from_path_buf(db_path).map_err(|_| "Invalid UTF-8 path")?,
+        ),
+        ..Default::default()
     };
 
     let connection = monarch_db.create_connection(&connection_config)?;
@@ -85,12 +93,14 @@ fn test_directory_configuration_incremental_migration() -> Result<(), Box<dyn st
         enable_foreign_keys: false,
         migration_directory: Utf8PathBuf::from_path_buf(migrations_dir.to_path_buf())
             .map_err(|_| "Invalid UTF-8 path")?,
+        ..Default::default()
     };
 
     let connection_config = ConnectionConfiguration {
-        database: Some(
+        database: Database::File(
             Utf8PathBuf::from_path_buf(db_path.to_path_buf()).map_err(|_| "Invalid UTF-8 path")?,
         ),
+        ..Default::default()
     };
 
     // Create initial database with just users table
@@ -144,13 +154,17 @@ fn test_directory_configuration_empty_directory() -> Result<(), Box<dyn std::err
         enable_foreign_keys: false,
         migration_directory: Utf8PathBuf::from_path_buf(migrations_dir.to_path_buf())
             .map_err(|_| "Invalid UTF-8 path")?,
+        ..Default::default()
     };
 
     let monarch_db = MonarchDB::from_configuration(config)?;
     assert_eq!(monarch_db.current_version(), 0);
 
     let connection_config = ConnectionConfiguration {
-        database: Some(Utf8PathBuf::from_path_buf(db_path).map_err(|_| "Invalid UTF-8 path")?),
+        database: Database::File(
+            Utf8PathBuf::from_path_buf(db_path).map_err(|_| "Invalid UTF-8 path")?,
+        ),
+        ..Default::default()
     };
 
     let connection = monarch_db.create_connection(&connection_config)?;
@@ -163,6 +177,111 @@ fn test_directory_configuration_empty_directory() -> Result<(), Box<dyn std::err
     Ok(())
 }
 
+#[test]
+fn test_directory_configuration_template_variables() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let migrations_dir = Utf8PathBuf::from_path_buf(temp_dir.path().join("migrations"))
+        .map_err(|_| "Invalid UTF-8 path")?;
+    fs::create_dir_all(&migrations_dir)?;
+    fs::write(
+        migrations_dir.join("001_create_users.sql"),
+        "CREATE TABLE {{table_prefix}}users (id INTEGER PRIMARY KEY);
+         CREATE TABLE {{ app_name }}_settings (key TEXT PRIMARY KEY);",
+    )?;
+
+    let mut config = MonarchConfiguration {
+        name: "tenant".to_string(),
+        enable_foreign_keys: false,
+        migration_directory: migrations_dir,
+        ..Default::default()
+    };
+
+    // Without a value for `table_prefix`, loading fails and names the migration.
+    let error = MonarchDB::from_configuration(config.clone()).unwrap_err();
+    assert!(matches!(
+        &error,
+        monarch_db::Error::UndefinedVariable { migration, variable }
+            if migration == "001_create_users.sql" && variable == "table_prefix"
+    ));
+
+    config
+        .variables
+        .insert("table_prefix".to_string(), "acme_".to_string());
+    let monarch_db = MonarchDB::from_configuration(config)?;
+    let connection = monarch_db.open_in_memory()?;
+
+    let mut stmt = connection.prepare(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'monarch_db_%' ORDER BY name",
+    )?;
+    let tables: Vec<String> = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    assert_eq!(tables, ["acme_users", "tenant_settings"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_directory_configuration_environments() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let migrations_dir = Utf8PathBuf::from_path_buf(temp_dir.path().join("migrations"))
+        .map_err(|_| "Invalid UTF-8 path")?;
+    fs::create_dir_all(&migrations_dir)?;
+    fs::write(
+        migrations_dir.join("001_create_users.sql"),
+        "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    )?;
+    fs::write(
+        migrations_dir.join("002_fixtures.sql"),
+        "-- Sample data for local development and tests.
+         -- monarch:env dev, test
+         CREATE TABLE fixtures (id INTEGER PRIMARY KEY);",
+    )?;
+    fs::write(
+        migrations_dir.join("003_helpers.test.sql"),
+        "CREATE TABLE test_helpers (id INTEGER PRIMARY KEY);",
+    )?;
+    fs::write(
+        migrations_dir.join("004_create_posts.sql"),
+        "CREATE TABLE posts (id INTEGER PRIMARY KEY);",
+    )?;
+
+    let config = MonarchConfiguration {
+        name: "environments".to_string(),
+        enable_foreign_keys: false,
+        migration_directory: migrations_dir,
+        ..Default::default()
+    };
+
+    let tables = |monarch_db: &MonarchDB| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let connection = monarch_db.open_in_memory()?;
+        let mut stmt = connection.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'monarch_db_%' ORDER BY name",
+        )?;
+        let tables = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(tables)
+    };
+
+    // Without an environment, restricted migrations are skipped but keep their versions.
+    let production = MonarchDB::from_configuration(config.clone())?;
+    assert_eq!(production.current_version(), 4);
+    assert_eq!(tables(&production)?, ["posts", "users"]);
+
+    let dev = MonarchDB::from_configuration(config.clone())?.with_environment("dev");
+    assert_eq!(dev.environment(), Some("dev"));
+    assert_eq!(tables(&dev)?, ["fixtures", "posts", "users"]);
+
+    let test = MonarchDB::from_configuration(config)?.with_environment("test");
+    assert_eq!(
+        tables(&test)?,
+        ["fixtures", "posts", "test_helpers", "users"]
+    );
+
+    Ok(())
+}
+
 fn copy_migration_files(
     migrations_dir: &std::path::Path,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -324,3 +443,402 @@ fn test_foreign_key_constraints(connection: &Connection) -> rusqlite::Result<()>
 
     Ok(())
 }
+
+#[cfg(feature = "import")]
+#[test]
+fn test_directory_configuration_import() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let migrations_dir = Utf8PathBuf::from_path_buf(temp_dir.path().join("migrations"))
+        .map_err(|_| "Invalid UTF-8 path")?;
+    fs::create_dir_all(migrations_dir.join("data"))?;
+    fs::write(
+        migrations_dir.join("001_create_countries.sql"),
+        "-- monarch:import countries data/countries.csv
+         CREATE TABLE countries (code TEXT PRIMARY KEY, name TEXT NOT NULL, population INTEGER);",
+    )?;
+    fs::write(
+        migrations_dir.join("data/countries.csv"),
+        "code,name,population\nFR,France,68000000\nAQ,Antarctica,\n",
+    )?;
+
+    let config = MonarchConfiguration {
+        name: "import".to_string(),
+        enable_foreign_keys: false,
+        migration_directory: migrations_dir,
+        ..Default::default()
+    };
+    let monarch_db = MonarchDB::from_configuration(config)?;
+    let connection = monarch_db.open_in_memory()?;
+
+    let mut stmt = connection.prepare("SELECT code, population FROM countries ORDER BY code")?;
+    let countries: Vec<(String, Option<i64>)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    assert_eq!(
+        countries,
+        [("AQ".to_string(), None), ("FR".to_string(), Some(68000000))]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_directory_configuration_repeatable_migrations() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let migrations_dir = Utf8PathBuf::from_path_buf(temp_dir.path().join("migrations"))
+        .map_err(|_| "Invalid UTF-8 path")?;
+    let db_path = Utf8PathBuf::from_path_buf(temp_dir.path().join("repeatable.db"))
+        .map_err(|_| "Invalid UTF-8 path")?;
+    fs::create_dir_all(&migrations_dir)?;
+    fs::write(
+        migrations_dir.join("001_create_users.sql"),
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, active INTEGER);",
+    )?;
+    fs::write(
+        migrations_dir.join("R__views.sql"),
+        "DROP VIEW IF EXISTS user_names;
+         CREATE VIEW user_names AS SELECT name FROM users;",
+    )?;
+
+    let config = MonarchConfiguration {
+        name: "repeatable".to_string(),
+        enable_foreign_keys: false,
+        migration_directory: migrations_dir.clone(),
+        ..Default::default()
+    };
+    let connection_config = ConnectionConfiguration {
+        database: Database::File(db_path),
+        ..Default::default()
+    };
+
+    let monarch_db = MonarchDB::from_configuration(config.clone())?;
+    // Repeatable migrations do not count towards the schema version.
+    assert_eq!(monarch_db.current_version(), 1);
+    monarch_db.create_connection(&connection_config)?;
+
+    fs::write(
+        migrations_dir.join("R__views.sql"),
+        "DROP VIEW IF EXISTS user_names;
+         CREATE VIEW user_names AS SELECT name FROM users WHERE active;",
+    )?;
+    let monarch_db = MonarchDB::from_configuration(config)?;
+    let connection = monarch_db.create_connection(&connection_config)?;
+
+    let sql: String = connection.query_row(
+        "SELECT sql FROM sqlite_master WHERE name = 'user_names'",
+        [],
+        |row| row.get(0),
+    )?;
+    assert!(sql.contains("WHERE active"));
+
+    Ok(())
+}
+
+#[test]
+fn test_directory_configuration_verify_files() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let migrations_dir = Utf8PathBuf::from_path_buf(temp_dir.path().join("migrations"))
+        .map_err(|_| "Invalid UTF-8 path")?;
+    fs::create_dir_all(&migrations_dir)?;
+    fs::write(
+        migrations_dir.join("001_create_users.sql"),
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+         INSERT INTO users (name) VALUES ('alice'), ('bob');",
+    )?;
+    fs::write(
+        migrations_dir.join("002_dedupe_users.sql"),
+        "DELETE FROM users WHERE name = 'alice';",
+    )?;
+    fs::write(
+        migrations_dir.join("002_dedupe_users.verify.sql"),
+        "SELECT count(*) = 2 FROM users;",
+    )?;
+
+    let config = MonarchConfiguration {
+        name: "verify".to_string(),
+        enable_foreign_keys: false,
+        migration_directory: migrations_dir.clone(),
+        ..Default::default()
+    };
+    let monarch_db = MonarchDB::from_configuration(config.clone())?;
+    // The verify file is not a migration of its own.
+    assert_eq!(monarch_db.current_version(), 2);
+
+    let mut connection = Connection::open_in_memory()?;
+    monarch_db.migrate_to(&mut connection, 1)?;
+    let error = monarch_db.migrate_to(&mut connection, 2).unwrap_err();
+    assert!(
+        error.to_string().contains("002_dedupe_users.sql"),
+        "{error}"
+    );
+    let count: i64 = connection.query_row("SELECT count(*) FROM users", [], |row| row.get(0))?;
+    assert_eq!(count, 2);
+
+    fs::write(
+        migrations_dir.join("002_dedupe_users.verify.sql"),
+        "SELECT count(*) = 1 FROM users;",
+    )?;
+    let monarch_db = MonarchDB::from_configuration(config)?;
+    monarch_db.migrate(connection)?;
+    Ok(())
+}
+
+#[test]
+fn test_directory_configuration_multiple_directories() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let root = Utf8PathBuf::from_path_buf(temp_dir.path().to_path_buf())
+        .map_err(|_| "Invalid UTF-8 path")?;
+    let core = root.join("core");
+    let billing = root.join("billing");
+    fs::create_dir_all(&core)?;
+    fs::create_dir_all(&billing)?;
+    fs::write(
+        core.join("001_users.sql"),
+        "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    )?;
+    fs::write(
+        core.join("010_user_names.sql"),
+        "ALTER TABLE users ADD COLUMN name TEXT;",
+    )?;
+    fs::write(
+        billing.join("5_invoices.sql"),
+        "CREATE TABLE invoices (id INTEGER PRIMARY KEY, user_id INTEGER REFERENCES users (id));",
+    )?;
+
+    let mut config = MonarchConfiguration {
+        name: "workspace".to_string(),
+        enable_foreign_keys: true,
+        migration_directory: core.clone(),
+        additional_migration_directories: vec![billing.clone()],
+        ..Default::default()
+    };
+    let monarch_db = MonarchDB::from_configuration(config.clone())?;
+    let connection = monarch_db.open_in_memory()?;
+    let names: Vec<String> = monarch_db
+        .plan(&Connection::open_in_memory()?)?
+        .migrations
+        .into_iter()
+        .map(|migration| migration.name)
+        .collect();
+    assert_eq!(
+        names,
+        ["001_users.sql", "5_invoices.sql", "010_user_names.sql"]
+    );
+    connection.execute("INSERT INTO users (id, name) VALUES (1, 'alice')", [])?;
+    connection.execute("INSERT INTO invoices (user_id) VALUES (1)", [])?;
+
+    fs::write(billing.join("001_users.sql"), "SELECT 1;")?;
+    let error = MonarchDB::from_configuration(config.clone()).unwrap_err();
+    assert!(matches!(
+        &error,
+        monarch_db::Error::DuplicateMigration { migration } if migration == "001_users.sql"
+    ));
+
+    config.additional_migration_directories.clear();
+    assert_eq!(MonarchDB::from_configuration(config)?.current_version(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_directory_configuration_loading_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let migrations_dir = Utf8PathBuf::from_path_buf(temp_dir.path().join("migrations"))
+        .map_err(|_| "Invalid UTF-8 path")?;
+
+    let mut config = MonarchConfiguration {
+        name: "loading".to_string(),
+        enable_foreign_keys: true,
+        migration_directory: migrations_dir.clone(),
+        ..Default::default()
+    };
+    let error = MonarchDB::from_configuration(config.clone()).unwrap_err();
+    assert!(matches!(
+        &error,
+        monarch_db::Error::ReadDirectory { path, .. } if *path == migrations_dir
+    ));
+    assert!(error.to_string().contains(migrations_dir.as_str()));
+
+    // Files which are not SQL are ignored, and a UTF-8 byte order mark is stripped
+    fs::create_dir_all(&migrations_dir)?;
+    fs::write(migrations_dir.join("notes.txt"), "Not a migration")?;
+    fs::write(
+        migrations_dir.join("001_users.sql"),
+        "\u{feff}CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    )?;
+    let monarch_db = MonarchDB::from_configuration(config.clone())?;
+    assert_eq!(monarch_db.current_version(), 1);
+    monarch_db.open_in_memory()?;
+
+    let invalid = migrations_dir.join("002_names.sql");
+    fs::write(&invalid, [0xff, 0xfe, b'S', 0, b'E', 0])?;
+    let error = MonarchDB::from_configuration(config.clone()).unwrap_err();
+    assert!(matches!(
+        &error,
+        monarch_db::Error::InvalidEncoding { path, .. } if *path == invalid
+    ));
+    assert!(error.to_string().contains("UTF-16"), "{error}");
+
+    fs::write(&invalid, b"SELECT '\xc3\x28';")?;
+    let error = MonarchDB::from_configuration(config.clone()).unwrap_err();
+    assert!(
+        error.to_string().contains("invalid UTF-8 at byte 8"),
+        "{error}"
+    );
+
+    fs::remove_file(&invalid)?;
+    config.additional_migration_directories = vec![migrations_dir.join("missing")];
+    let error = MonarchDB::from_configuration(config).unwrap_err();
+    assert!(matches!(
+        &error,
+        monarch_db::Error::ReadDirectory { path, .. } if path.ends_with("missing")
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_directory_configuration_strict_filtering() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let migrations_dir = Utf8PathBuf::from_path_buf(temp_dir.path().to_path_buf())
+        .map_err(|_| "Invalid UTF-8 path")?;
+    fs::write(
+        migrations_dir.join("001_users.sql"),
+        "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    )?;
+    fs::write(migrations_dir.join(".gitkeep"), "")?;
+    fs::write(migrations_dir.join("README.md"), "# Migrations")?;
+    fs::write(
+        migrations_dir.join("002_posts.sq"),
+        "CREATE TABLE posts (id);",
+    )?;
+
+    let mut config = MonarchConfiguration {
+        name: "filtering".to_string(),
+        enable_foreign_keys: true,
+        migration_directory: migrations_dir.clone(),
+        ignore: vec!["*.md".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(
+        MonarchDB::from_configuration(config.clone())?.current_version(),
+        1
+    );
+
+    config.strict = true;
+    let error = MonarchDB::from_configuration(config.clone()).unwrap_err();
+    assert!(error.to_string().contains("002_posts.sq"), "{error}");
+
+    fs::rename(
+        migrations_dir.join("002_posts.sq"),
+        migrations_dir.join("002_posts.sql"),
+    )?;
+    assert_eq!(MonarchDB::from_configuration(config)?.current_version(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_directory_configuration_recursive() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let migrations_dir = Utf8PathBuf::from_path_buf(temp_dir.path().to_path_buf())
+        .map_err(|_| "Invalid UTF-8 path")?;
+    for year in ["2024", "2025", ".drafts"] {
+        fs::create_dir_all(migrations_dir.join(year))?;
+    }
+    fs::write(
+        migrations_dir.join("2024/001_users.sql"),
+        "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    )?;
+    fs::write(
+        migrations_dir.join("2024/010_user_names.sql"),
+        "ALTER TABLE users ADD COLUMN name TEXT;",
+    )?;
+    fs::write(
+        migrations_dir.join("2025/001_labels.sql"),
+        "CREATE TABLE labels (user_id INTEGER REFERENCES users (id), name TEXT);",
+    )?;
+    fs::write(
+        migrations_dir.join("2025/R__views.sql"),
+        "CREATE VIEW IF NOT EXISTS user_labels AS SELECT users.name, labels.name AS label \
+         FROM users JOIN labels ON labels.user_id = users.id;",
+    )?;
+    fs::write(
+        migrations_dir.join(".drafts/002_wip.sql"),
+        "SELECT nonsense;",
+    )?;
+
+    let mut config = MonarchConfiguration {
+        name: "recursive".to_string(),
+        enable_foreign_keys: true,
+        migration_directory: migrations_dir.clone(),
+        ..Default::default()
+    };
+    // Subdirectories are only read when asked to.
+    assert_eq!(
+        MonarchDB::from_configuration(config.clone())?.current_version(),
+        0
+    );
+
+    config.recursive = true;
+    let monarch_db = MonarchDB::from_configuration(config)?;
+    let description = monarch_db.describe()?;
+    let names: Vec<&str> = description.migrations.iter().map(|m| &*m.name).collect();
+    assert_eq!(
+        names,
+        [
+            "2024/001_users.sql",
+            "2024/010_user_names.sql",
+            "2025/001_labels.sql"
+        ]
+    );
+    assert_eq!(description.repeatable[0].name, "R__views.sql");
+    let connection = monarch_db.open_in_memory()?;
+    connection.execute("SELECT * FROM user_labels", [])?;
+    Ok(())
+}
+
+#[test]
+fn test_directory_configuration_overlays() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let root = Utf8PathBuf::from_path_buf(temp_dir.path().to_path_buf())
+        .map_err(|_| "Invalid UTF-8 path")?;
+    let base = root.join("migrations");
+    let dev = root.join("migrations-dev");
+    fs::create_dir_all(&base)?;
+    fs::create_dir_all(&dev)?;
+    fs::write(
+        base.join("001_users.sql"),
+        "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    )?;
+    fs::write(
+        base.join("003_posts.sql"),
+        "CREATE TABLE posts (id INTEGER PRIMARY KEY);",
+    )?;
+    fs::write(
+        dev.join("002_debug_log.sql"),
+        "CREATE TABLE debug_log (message TEXT);",
+    )?;
+
+    let config = MonarchConfiguration {
+        name: "overlays".to_string(),
+        enable_foreign_keys: true,
+        migration_directory: base,
+        overlay_directories: [("dev".to_string(), dev)].into(),
+        ..Default::default()
+    };
+    let tables = |connection: &Connection| -> rusqlite::Result<Vec<String>> {
+        let mut statement = connection.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' \
+             AND name NOT LIKE 'monarch_db_%' ORDER BY name",
+        )?;
+        statement.query_map([], |row| row.get(0))?.collect()
+    };
+
+    let production = MonarchDB::from_configuration(config.clone())?;
+    assert_eq!(production.current_version(), 3);
+    let connection = production.open_in_memory()?;
+    assert_eq!(tables(&connection)?, ["posts", "users"]);
+
+    let development = MonarchDB::from_configuration(config)?.with_environment("dev");
+    let connection = development.open_in_memory()?;
+    assert_eq!(tables(&connection)?, ["debug_log", "posts", "users"]);
+    Ok(())
+}
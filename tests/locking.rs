@@ -0,0 +1,161 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::{thread, time::Duration};
+
+use monarch_db::{
+    BusyHandler, ConnectionConfiguration, Database, MonarchDB, RetryPolicy,
+    StaticMonarchConfiguration,
+};
+use rusqlite::{Connection, ErrorCode, TransactionBehavior};
+use tempfile::TempDir;
+
+fn blog_migrations() -> MonarchDB {
+    StaticMonarchConfiguration {
+        name: "locking",
+        enable_foreign_keys: true,
+        migrations: [
+            include_str!("migrations/001_create_users.sql"),
+            include_str!("migrations/002_create_posts.sql"),
+            include_str!("migrations/003_add_indexes.sql"),
+        ],
+    }
+    .into()
+}
+
+fn open_without_busy_timeout(path: &std::path::Path) -> rusqlite::Result<Connection> {
+    let connection = Connection::open(path)?;
+    connection.busy_timeout(Duration::ZERO)?;
+    Ok(connection)
+}
+
+#[test]
+fn test_migration_waits_for_lock() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let db_path = temp_dir.path().join("waits.db");
+
+    let mut holder = Connection::open(&db_path)?;
+    let tx = holder.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let monarch_db = blog_migrations().with_lock_timeout(Duration::from_secs(10));
+    let connection = open_without_busy_timeout(&db_path)?;
+    let migrator = thread::spawn(move || monarch_db.migrate(connection));
+
+    // Hold the write lock long enough that the migrator has to retry.
+    thread::sleep(Duration::from_millis(200));
+    tx.commit()?;
+
+    let connection = migrator.join().expect("migration thread panicked")?;
+    let version: u32 = connection.query_row(
+        "SELECT version FROM monarch_db_schema_version WHERE monarch_schema = 'locking'",
+        [],
+        |row| row.get(0),
+    )?;
+    assert_eq!(version, 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_migration_lock_timeout() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let db_path = temp_dir.path().join("timeout.db");
+
+    let mut holder = Connection::open(&db_path)?;
+    let _tx = holder.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let monarch_db = blog_migrations().with_lock_timeout(Duration::from_millis(50));
+    let error = monarch_db
+        .migrate(open_without_busy_timeout(&db_path)?)
+        .expect_err("migration should not acquire a held lock");
+    assert_eq!(error.sqlite_error_code(), Some(ErrorCode::DatabaseBusy));
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_first_open() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let connection_config = ConnectionConfiguration {
+        database: Database::File(temp_dir.path().join("concurrent.db").try_into()?),
+        ..Default::default()
+    };
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let connection_config = connection_config.clone();
+            thread::spawn(move || {
+                blog_migrations()
+                    .create_connection(&connection_config)
+                    .map(|_| ())
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().expect("migration thread panicked")?;
+    }
+
+    let connection = blog_migrations().create_connection(&connection_config)?;
+    let count: i64 = connection.query_row(
+        "SELECT COUNT(*) FROM monarch_db_schema_version WHERE monarch_schema = 'locking'",
+        [],
+        |row| row.get(0),
+    )?;
+    assert_eq!(count, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_retry_policy_absorbs_transient_lock() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let db_path = temp_dir.path().join("retry.db");
+
+    let mut holder = Connection::open(&db_path)?;
+    let tx = holder.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    // With no lock timeout, only the retry policy can absorb the held lock.
+    let monarch_db = blog_migrations()
+        .with_lock_timeout(Duration::ZERO)
+        .with_retry_policy(RetryPolicy::new(20, Duration::from_millis(25)));
+    let connection = open_without_busy_timeout(&db_path)?;
+    let migrator = thread::spawn(move || monarch_db.migrate(connection));
+
+    thread::sleep(Duration::from_millis(100));
+    tx.commit()?;
+
+    migrator.join().expect("migration thread panicked")?;
+
+    Ok(())
+}
+
+static BUSY_CALLS: AtomicI32 = AtomicI32::new(0);
+
+fn give_up_after_three(attempts: i32) -> bool {
+    BUSY_CALLS.fetch_max(attempts + 1, Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(5));
+    attempts < 2
+}
+
+#[test]
+fn test_custom_busy_handler() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = TempDir::new()?;
+    let db_path = temp_dir.path().join("busy.db");
+    let connection_config = ConnectionConfiguration {
+        database: Database::File(db_path.clone().try_into()?),
+        ..Default::default()
+    };
+
+    let mut holder = Connection::open(&db_path)?;
+    let _tx = holder.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let monarch_db = blog_migrations()
+        .with_lock_timeout(Duration::ZERO)
+        .with_busy_handler(BusyHandler::Custom(give_up_after_three));
+    let error = monarch_db
+        .create_connection(&connection_config)
+        .expect_err("migration should not acquire a held lock");
+    assert_eq!(error.sqlite_error_code(), Some(ErrorCode::DatabaseBusy));
+    assert_eq!(BUSY_CALLS.load(Ordering::SeqCst), 3);
+
+    Ok(())
+}
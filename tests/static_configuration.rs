@@ -1,4 +1,4 @@
-use monarch_db::{ConnectionConfiguration, MonarchDB, StaticMonarchConfiguration};
+use monarch_db::{ConnectionConfiguration, Database, MonarchDB, StaticMonarchConfiguration};
 use rusqlite::Connection;
 use tempfile::TempDir;
 
@@ -19,7 +19,8 @@ fn test_static_configuration_with_file_database() -> Result<(), Box<dyn std::err
 
     let monarch_db: MonarchDB = config.into();
     let connection_config = ConnectionConfiguration {
-        database: Some(db_path.try_into()?),
+        database: Database::File(db_path.try_into()?),
+        ..Default::default()
     };
 
     let connection = monarch_db.create_connection(&connection_config)?;
@@ -49,7 +50,8 @@ fn test_static_configuration_multiple_connections() -> Result<(), Box<dyn std::e
 
     let monarch_db: MonarchDB = config.into();
     let connection_config = ConnectionConfiguration {
-        database: Some(db_path.try_into()?),
+        database: Database::File(db_path.try_into()?),
+        ..Default::default()
     };
 
     // Create first connection and add data
@@ -90,7 +92,8 @@ fn test_static_configuration_migration_versioning() -> Result<(), Box<dyn std::e
 
     let monarch_db_v1: MonarchDB = config_v1.into();
     let connection_config = ConnectionConfiguration {
-        database: Some(db_path.try_into()?),
+        database: Database::File(db_path.try_into()?),
+        ..Default::default()
     };
 
     {